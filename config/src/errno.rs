@@ -0,0 +1,36 @@
+//! Error numbers returned (negated) by syscalls, following Linux values
+
+/// No such file or directory
+pub const ENOENT: isize = 2;
+/// Input/output error, like a block which does not match its checksum
+pub const EIO: isize = 5;
+/// Exec format error
+pub const ENOEXEC: isize = 8;
+/// Bad file descriptor
+pub const EBADF: isize = 9;
+/// Resource temporarily unavailable, try again
+pub const EAGAIN: isize = 11;
+/// Cannot allocate memory
+pub const ENOMEM: isize = 12;
+/// Bad address
+pub const EFAULT: isize = 14;
+/// Device or resource busy
+pub const EBUSY: isize = 16;
+/// File exists
+pub const EEXIST: isize = 17;
+/// No such device
+pub const ENODEV: isize = 19;
+/// Not a directory
+pub const ENOTDIR: isize = 20;
+/// Is a directory
+pub const EISDIR: isize = 21;
+/// Invalid argument
+pub const EINVAL: isize = 22;
+/// Too many open files
+pub const EMFILE: isize = 24;
+/// No space left on device
+pub const ENOSPC: isize = 28;
+/// Illegal seek, like on a pipe
+pub const ESPIPE: isize = 29;
+/// File name too long
+pub const ENAMETOOLONG: isize = 36;
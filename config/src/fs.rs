@@ -1,15 +1,36 @@
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
 pub const BLOCK_BITS: usize = BLOCK_SZ * 8;
-/// Use a block cache of 16 blocks
+/// Default number of blocks in the block cache
 pub const BLOCK_CACHE_SIZE: usize = 16;
+/// Max number of file pages kept in the page cache
+pub const PAGE_CACHE_PAGES: usize = 1024;
+/// Write dirty cached blocks back to disk at this interval
+pub const BLOCK_CACHE_FLUSH_INTERVAL_MS: usize = 1000;
+/// Max number of file descriptors of a task, like `RLIMIT_NOFILE`
+pub const FD_LIMIT: usize = 1024;
 
 /// Magic number for sanity check
 pub const EFS_MAGIC: u32 = 0x94740454;
+/// Version of the on-disk format written by easy-fs. Version 0 has fixed-size
+/// directory entries, version 1 has variable-length ones, version 2 maps the
+/// blocks of new inodes by extents, version 3 checksums the metadata blocks
+/// and keeps a backup of the super block in the last block
+pub const EFS_VERSION: u32 = 3;
+/// Magic number of a committed journal transaction
+pub const JOURNAL_MAGIC: u32 = 0x4a524e4c;
+/// The number of checksums in a checksum block
+pub const BLOCK_CHECKSUM_COUNT: usize = BLOCK_SZ / 4;
+/// The max number of blocks changed by one journal transaction
+pub const JOURNAL_CAPACITY: usize = BLOCK_SZ / 4 - 2;
+/// The journal area holds a header and the copies of changed blocks
+pub const JOURNAL_BLOCKS: usize = 1 + JOURNAL_CAPACITY;
 /// The max number of direct inodes
 pub const INODE_DIRECT_COUNT: usize = 28;
 /// The max length of inode name
-pub const NAME_LENGTH_LIMIT: usize = 27;
+pub const NAME_LENGTH_LIMIT: usize = 255;
+/// The max length of inode name in images of version 0
+pub const NAME_LENGTH_LIMIT_V0: usize = 27;
 /// The max number of indirect inodes
 pub const INODE_INDIRECT_COUNT: usize = BLOCK_SZ / 4;
 /// The max number of indirect1 inodes
@@ -23,6 +44,12 @@ pub const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 /// The upper bound of indirect2 inode indexs
 #[allow(unused)]
 pub const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// The max number of extents in an inode, which take the place of direct inodes
+pub const INODE_EXTENT_COUNT: usize = INODE_DIRECT_COUNT / 2;
+/// The number of extents in an extent block
+pub const EXTENT_BLOCK_COUNT: usize = BLOCK_SZ / 8;
+/// The upper bound of extent index in the extent block of indirect1
+pub const EXTENT_INDIRECT1_BOUND: usize = INODE_EXTENT_COUNT + EXTENT_BLOCK_COUNT;
 
 pub mod fd {
     pub const STDIN: usize = 0;
@@ -30,27 +57,177 @@ pub mod fd {
     pub const STDERR: usize = 2;
 }
 
+/// Commands and flags of `fcntl`
+pub mod fcntl {
+    /// Duplicate to the lowest free fd not less than the argument
+    pub const F_DUPFD: usize = 0;
+    /// Get the descriptor flags
+    pub const F_GETFD: usize = 1;
+    /// Set the descriptor flags
+    pub const F_SETFD: usize = 2;
+    /// Get the access mode and status flags of the opened file
+    pub const F_GETFL: usize = 3;
+    /// Set the status flags of the opened file, only `APPEND` and `NONBLOCK` change
+    pub const F_SETFL: usize = 4;
+    /// Like `F_DUPFD`, and set close-on-exec on the new descriptor
+    pub const F_DUPFD_CLOEXEC: usize = 1030;
+    /// Descriptor flag of close-on-exec
+    pub const FD_CLOEXEC: usize = 1;
+}
+
+/// Where `lseek` counts its offset from
+pub mod seek {
+    /// The start of the file
+    pub const SEEK_SET: usize = 0;
+    /// The current offset
+    pub const SEEK_CUR: usize = 1;
+    /// The end of the file
+    pub const SEEK_END: usize = 2;
+}
+
 bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct OpenFlag: usize{
         const RDONLY = 0;
         const WRONLY = 1 << 0;
         const RDWR = 1 << 1;
+        /// Fail with `CREATE` if the file already exists
+        const EXCL = 1 << 7;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// Every write goes to the end of file
+        const APPEND = 1 << 11;
+        /// Read and write return `EAGAIN` instead of blocking
+        const NONBLOCK = 1 << 12;
+        /// Fail if the path is not a directory
+        const DIRECTORY = 1 << 16;
+        /// Close the file descriptor on `exec`
+        const CLOEXEC = 1 << 19;
     }
 }
 impl OpenFlag {
     /// Do not check validity for simplicity
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
-            (true, false)
-        } else if self.contains(Self::WRONLY) {
+        if self.contains(Self::WRONLY) {
             (false, true)
-        } else {
+        } else if self.contains(Self::RDWR) {
             (true, true)
+        } else {
+            (true, false)
+        }
+    }
+    /// Flags kept by the opened file rather than used up by `open`
+    pub fn status(&self) -> Self {
+        *self & (Self::APPEND | Self::NONBLOCK)
+    }
+    /// Access mode flags from (readable, writable)
+    pub fn from_read_write(readable: bool, writable: bool) -> Self {
+        match (readable, writable) {
+            (true, true) => Self::RDWR,
+            (false, true) => Self::WRONLY,
+            _ => Self::RDONLY,
         }
     }
 }
 
-pub const PIPE_BUFFER_SIZE: usize = 32;
\ No newline at end of file
+pub const PIPE_BUFFER_SIZE: usize = 32;
+
+/// The max number of pages of file data held by a tmpfs with no `size=` option
+pub const TMPFS_PAGE_LIMIT: usize = 512;
+
+/// Records returned by `getdents`, laid out like `linux_dirent64`
+pub mod dirent {
+    /// Type of a character device
+    pub const DT_CHR: u8 = 2;
+    /// Type of a directory
+    pub const DT_DIR: u8 = 4;
+    /// Type of a block device
+    pub const DT_BLK: u8 = 6;
+    /// Type of a regular file
+    pub const DT_REG: u8 = 8;
+    /// The name starts after `ino: u64, off: i64, reclen: u16, type: u8`
+    const NAME_OFFSET: usize = 19;
+
+    /// Length of a record with a name of `name_len` bytes, NUL terminated and padded to 8 bytes
+    pub const fn reclen(name_len: usize) -> usize {
+        (NAME_OFFSET + name_len + 1).next_multiple_of(8)
+    }
+
+    /// A directory entry as seen by user space
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Dirent<'a> {
+        pub ino: u64,
+        /// Offset to continue reading the directory after this entry
+        pub off: i64,
+        pub r#type: u8,
+        pub name: &'a str,
+    }
+
+    impl<'a> Dirent<'a> {
+        /// Length of the record
+        pub fn reclen(&self) -> usize {
+            reclen(self.name.len())
+        }
+        /// Write the record to the head of `buf`, return `None` if it does not fit
+        pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+            let reclen = self.reclen();
+            let record = buf.get_mut(..reclen)?;
+            record.fill(0);
+            record[0..8].copy_from_slice(&self.ino.to_ne_bytes());
+            record[8..16].copy_from_slice(&self.off.to_ne_bytes());
+            record[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+            record[18] = self.r#type;
+            record[NAME_OFFSET..NAME_OFFSET + self.name.len()]
+                .copy_from_slice(self.name.as_bytes());
+            Some(reclen)
+        }
+        /// Read the record at the head of `buf`, return it with its length
+        pub fn decode(buf: &'a [u8]) -> Option<(Self, usize)> {
+            let header = buf.get(..NAME_OFFSET)?;
+            let reclen = u16::from_ne_bytes([header[16], header[17]]) as usize;
+            let name = buf.get(NAME_OFFSET..reclen)?;
+            let name_len = name.iter().position(|&b| b == 0)?;
+            let dirent = Self {
+                ino: u64::from_ne_bytes(header[0..8].try_into().unwrap()),
+                off: i64::from_ne_bytes(header[8..16].try_into().unwrap()),
+                r#type: header[18],
+                name: core::str::from_utf8(&name[..name_len]).ok()?,
+            };
+            Some((dirent, reclen))
+        }
+    }
+
+    #[test]
+    fn test_dirent() {
+        let mut buf = [0xffu8; 64];
+        let entries = [
+            Dirent {
+                ino: 0,
+                off: 1,
+                r#type: DT_DIR,
+                name: ".",
+            },
+            Dirent {
+                ino: 7,
+                off: 2,
+                r#type: DT_REG,
+                name: "hello_world",
+            },
+        ];
+        let mut len = 0;
+        for dirent in entries.iter() {
+            len += dirent.encode(&mut buf[len..]).unwrap();
+            assert_eq!(len % 8, 0);
+        }
+        assert!(entries[1].encode(&mut buf[len..]).is_none());
+        let mut pos = 0;
+        for dirent in entries.iter() {
+            let (decoded, reclen) = Dirent::decode(&buf[pos..len]).unwrap();
+            assert_eq!(&decoded, dirent);
+            pos += reclen;
+        }
+        assert_eq!(pos, len);
+        assert!(Dirent::decode(&buf[pos..len]).is_none());
+    }
+}
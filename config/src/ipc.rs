@@ -0,0 +1,19 @@
+//! Keys, flags and commands of the System V shared memory syscalls, following Linux values
+
+/// The key of a segment nobody else can get by its key
+pub const IPC_PRIVATE: usize = 0;
+
+/// The command of `shmctl` removing a segment
+pub const IPC_RMID: usize = 0;
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ShmFlag: usize {
+        /// Create the segment of the key if there is none
+        const CREAT = 0o1000;
+        /// Fail with `CREAT` if the segment of the key exists already
+        const EXCL = 0o2000;
+        /// Attach the segment read-only
+        const RDONLY = 0o10000;
+    }
+}
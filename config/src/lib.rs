@@ -1,6 +1,8 @@
 #![no_std]
 #![feature(default_field_values)]
+pub mod errno;
 pub mod fs;
+pub mod ipc;
 pub mod memory;
 mod qemu;
 pub mod signal;
@@ -10,12 +10,26 @@ pub const VPN_WIDTH: usize = VA_WIDTH - PAGE_SIZE_BITS;
 pub const MEMORY_END: usize = super::qemu::MEMORY_END;
 
 pub const KERNEL_HEAP_SIZE: usize = 0xF0_000;
+/// The kernel heap grows by at least `2^KERNEL_HEAP_GROW_ORDER` frames at a time
+pub const KERNEL_HEAP_GROW_ORDER: usize = 6;
+/// Frames left for the kernel heap and slab caches when pages of programs fill the memory
+pub const KERNEL_RESERVED_FRAMES: usize = 256;
 
 pub const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS;
 pub const PAGE_SIZE_BITS: usize = 12;
 
 pub const MMIO: &[(usize, usize)] = super::qemu::MMIO;
 
+/// The block device pages are swapped to, if it is there
+pub const SWAP_DEVICE: &str = "vdc";
+
+/// Shared memory is attached from here up when the program does not choose where
+pub const SHM_BASE: usize = 0x20_0000_0000;
+/// The end of the user addresses shared memory may be attached to
+pub const SHM_END: usize = 0x40_0000_0000;
+/// The largest shared memory segment
+pub const SHM_MAX_SIZE: usize = 16 * 1024 * 1024;
+
 pub const USER_STACK_SIZE: usize = PAGE_SIZE * 2;
 pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE * 2;
 
@@ -4,4 +4,6 @@ pub const MEMORY_END: usize = 0x8800_0000;
 pub const MMIO: &[(usize, usize)] = &[
     (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
     (0x1000_1000, 0x00_1000), // Virtio Block in virt machine
+    (0x1000_2000, 0x00_1000), // the second Virtio Block
+    (0x1000_3000, 0x00_1000), // the third Virtio Block, for swap
 ];
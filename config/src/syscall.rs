@@ -2,11 +2,21 @@
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SyscallID {
     Dup = 24,
+    Fcntl = 25,
+    Dup3 = 26,
+    Mkdir = 34,
+    Unlink = 35,
+    Umount = 39,
+    Mount = 40,
     Open = 56,
     Close = 57,
     Pipe = 59,
+    GetDents = 61,
+    Lseek = 62,
     Read = 63,
     Write = 64,
+    Sync = 81,
+    Fsync = 82,
     Exit = 93,
     Yield = 124,
     Kill = 129,
@@ -15,10 +25,15 @@ pub enum SyscallID {
     SigReturn = 139,
     GetTime = 169,
     GetPid = 172,
+    ShmGet = 194,
+    ShmCtl = 195,
+    ShmAt = 196,
+    ShmDt = 197,
     Sbrk = 214,
     Fork = 220,
     Exec = 221,
     WaitPid = 260,
+    SyncFs = 267,
     PowerOff = 114514,
 }
 // impl From<usize> for SyscallID {
@@ -0,0 +1,245 @@
+//! Corrupt images by hand, then check fsck finds and repairs the damage
+
+use crate::journal_test::CrashDevice;
+use easy_fs::fsck::{self, Problem};
+use easy_fs::{Corrupted, EasyFileSystem, Inode};
+use std::sync::Arc;
+
+const BLOCK_SZ: usize = 512;
+const TOTAL_BLOCKS: usize = 4096;
+
+/// First blocks of the areas of an image
+struct Layout {
+    inode_bitmap: usize,
+    inode_area: usize,
+    data_bitmap: usize,
+    data_area: usize,
+}
+
+fn word(image: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(image[pos..pos + 4].try_into().unwrap())
+}
+
+fn set_word(image: &mut [u8], pos: usize, value: u32) {
+    image[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn layout(image: &[u8]) -> Layout {
+    let [
+        inode_bitmap_blocks,
+        inode_area_blocks,
+        data_bitmap_blocks,
+        journal_blocks,
+        checksum_blocks,
+    ] = [2, 3, 4, 6, 8].map(|i| word(image, i * 4) as usize);
+    let inode_bitmap = 1 + journal_blocks;
+    let inode_area = inode_bitmap + inode_bitmap_blocks;
+    let data_bitmap = inode_area + inode_area_blocks;
+    Layout {
+        inode_bitmap,
+        inode_area,
+        data_bitmap,
+        data_area: data_bitmap + data_bitmap_blocks + checksum_blocks,
+    }
+}
+
+impl Layout {
+    /// Byte position of a field of a disk inode
+    fn inode(&self, inode: usize, field: usize) -> usize {
+        self.inode_area * BLOCK_SZ + inode * 128 + field
+    }
+    /// Byte position of an extent, its first block then its length
+    fn extent(&self, inode: usize, i: usize) -> usize {
+        self.inode(inode, 4 + i * 8)
+    }
+    /// Flip a bit of a bitmap
+    fn flip(image: &mut [u8], bitmap: usize, bit: usize) {
+        image[bitmap * BLOCK_SZ + bit / 8] ^= 1 << (bit % 8);
+    }
+}
+
+/// An image with a small file "a" (inode 1) and a larger file "b" (inode 2),
+/// each of a single extent
+fn sample() -> Vec<u8> {
+    let device = Arc::new(CrashDevice::new(vec![0; TOTAL_BLOCKS * BLOCK_SZ], None));
+    let efs = EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+    let root = EasyFileSystem::root_inode(&efs);
+    let a = root.create("a").unwrap().unwrap();
+    a.write_at(0, &[1; 3 * BLOCK_SZ]).unwrap();
+    let b = root.create("b").unwrap().unwrap();
+    b.write_at(0, &[2; 40 * BLOCK_SZ]).unwrap();
+    easy_fs::block_cache_sync_all();
+    device.image.lock().unwrap().clone()
+}
+
+/// Run fsck over a copy of an image, return its result and the image after it
+fn check(image: &[u8], repair: bool) -> (Option<Vec<Problem>>, Vec<u8>) {
+    let device = Arc::new(CrashDevice::new(image.to_vec(), None));
+    let problems = fsck::check(device.clone(), repair);
+    let image = device.image.lock().unwrap().clone();
+    (problems, image)
+}
+
+#[test]
+fn fsck_repair_test() {
+    let mut image = sample();
+    assert_eq!(check(&image, false).0, Some(vec![]));
+    let layout = layout(&image);
+
+    let a0 = word(&image, layout.extent(1, 0));
+    let [a1, a2] = [a0 + 1, a0 + 2];
+    // the second block of "a" is in an extent from the super block
+    set_word(&mut image, layout.extent(1, 0) + 4, 1);
+    set_word(&mut image, layout.extent(1, 1), 1);
+    set_word(&mut image, layout.extent(1, 1) + 4, 2);
+    // the first block of "a" is free in the bitmap
+    Layout::flip(
+        &mut image,
+        layout.data_bitmap,
+        a0 as usize - layout.data_area,
+    );
+    // a block nobody uses is allocated
+    Layout::flip(&mut image, layout.data_bitmap, 2000);
+    // an inode nobody links is allocated
+    Layout::flip(&mut image, layout.inode_bitmap, 5);
+    // the name of "b" holds a '/', its record follows the 12 bytes of "a"
+    let root_block = word(&image, layout.extent(0, 0)) as usize;
+    image[root_block * BLOCK_SZ + 12 + 8] = b'/';
+
+    let (problems, repaired) = check(&image, true);
+    let problems = problems.unwrap();
+    let data_area = layout.data_area as u32;
+    for expected in [
+        Problem::BadBlockPointer {
+            inode: 1,
+            block: 1,
+            size: BLOCK_SZ as u32,
+        },
+        Problem::UnallocatedBlock { block: a0 },
+        Problem::LeakedBlock { block: a1 },
+        Problem::LeakedBlock { block: a2 },
+        Problem::LeakedBlock {
+            block: data_area + 2000,
+        },
+        Problem::OrphanInode { inode: 5 },
+        Problem::InvalidName {
+            dir: 0,
+            offset: 12,
+            inode: 2,
+        },
+    ] {
+        assert!(problems.contains(&expected), "{expected} not found");
+    }
+    assert!(problems.iter().all(Problem::repairable));
+    assert_eq!(check(&repaired, false).0, Some(vec![]));
+
+    // the repaired image is usable
+    let device = Arc::new(CrashDevice::new(repaired, None));
+    let efs = EasyFileSystem::open(device).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.ls().unwrap(), ["a", "#2", "#5"]);
+    assert_eq!(root.find("a").unwrap().unwrap().size().unwrap(), BLOCK_SZ);
+    let b = root.find("#2").unwrap().unwrap();
+    let mut buf = vec![0; 40 * BLOCK_SZ];
+    assert_eq!(b.read_at(0, &mut buf).unwrap(), buf.len());
+    assert!(buf.iter().all(|&byte| byte == 2));
+}
+
+#[test]
+fn fsck_bad_record_test() {
+    let mut image = sample();
+    let layout = layout(&image);
+    // the record of "a" has a length beyond its block, hiding "b" after it
+    let root_block = word(&image, layout.extent(0, 0)) as usize;
+    image[root_block * BLOCK_SZ + 4..][..2].copy_from_slice(&1000u16.to_le_bytes());
+
+    let (problems, repaired) = check(&image, true);
+    let problems = problems.unwrap();
+    for expected in [
+        Problem::BadRecord { dir: 0, offset: 0 },
+        Problem::OrphanInode { inode: 1 },
+        Problem::OrphanInode { inode: 2 },
+    ] {
+        assert!(problems.contains(&expected), "{expected} not found");
+    }
+    assert_eq!(check(&repaired, false).0, Some(vec![]));
+    let device = Arc::new(CrashDevice::new(repaired, None));
+    let efs = EasyFileSystem::open(device).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.ls().unwrap(), ["#1", "#2"]);
+    assert_eq!(
+        root.find("#2").unwrap().unwrap().size().unwrap(),
+        40 * BLOCK_SZ
+    );
+}
+
+#[test]
+fn fsck_double_allocated_test() {
+    let mut image = sample();
+    let layout = layout(&image);
+    // "b" shares the first block of "a"
+    let a0 = word(&image, layout.extent(1, 0));
+    let b0 = word(&image, layout.extent(2, 0));
+    set_word(&mut image, layout.extent(2, 0), a0);
+    set_word(&mut image, layout.extent(2, 0) + 4, 1);
+    set_word(&mut image, layout.extent(2, 1), b0 + 1);
+    set_word(&mut image, layout.extent(2, 1) + 4, 39);
+
+    let (problems, repaired) = check(&image, true);
+    let problems = problems.unwrap();
+    assert!(problems.contains(&Problem::LeakedBlock { block: b0 }));
+    let unrepairable: Vec<_> = problems.iter().filter(|p| !p.repairable()).collect();
+    assert!(matches!(
+        unrepairable[..],
+        [Problem::DoubleAllocated { block, .. }] if *block == a0
+    ));
+    // only what cannot be repaired is left
+    let (problems, _) = check(&repaired, false);
+    assert_eq!(problems.unwrap().len(), 1);
+}
+
+#[test]
+fn fsck_invalid_image_test() {
+    let image = vec![0; TOTAL_BLOCKS * BLOCK_SZ];
+    assert_eq!(check(&image, false).0, None);
+}
+
+#[test]
+fn fsck_checksum_test() {
+    let mut image = sample();
+    let layout = layout(&image);
+    // the super block is overwritten, and so is the free inode slot 9
+    image[..BLOCK_SZ].fill(0xff);
+    let inode_pos = layout.inode(9, 0);
+    image[inode_pos] ^= 1;
+
+    // the backup super block is used until the image is repaired
+    let device = Arc::new(CrashDevice::new(image.clone(), None));
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    assert_eq!(EasyFileSystem::root_inode(&efs).ls().unwrap(), ["a", "b"]);
+    // and the block of the damaged inode fails to load
+    let (block_id, block_offset) = efs.lock().get_disk_inode_pos(9);
+    let inode = Inode::new(block_id, block_offset, Arc::clone(&efs), device);
+    let block_id = block_id as usize;
+    assert_eq!(inode.size(), Err(Corrupted { block_id }));
+    drop(efs);
+
+    let (problems, repaired) = check(&image, true);
+    let problems = problems.unwrap();
+    for expected in [
+        Problem::BadSuperBlock { block: 0 },
+        Problem::BadChecksum {
+            block: (inode_pos / BLOCK_SZ) as u32,
+        },
+    ] {
+        assert!(problems.contains(&expected), "{expected} not found");
+    }
+    assert_eq!(check(&repaired, false).0, Some(vec![]));
+    assert_eq!(repaired[..BLOCK_SZ], image[(TOTAL_BLOCKS - 1) * BLOCK_SZ..]);
+
+    // without a valid copy of the super block the image cannot be opened
+    image[(TOTAL_BLOCKS - 1) * BLOCK_SZ..].fill(0xff);
+    let device = Arc::new(CrashDevice::new(image.clone(), None));
+    assert!(EasyFileSystem::open(device).is_err());
+    assert_eq!(check(&image, false).0, None);
+}
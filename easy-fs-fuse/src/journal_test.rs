@@ -0,0 +1,295 @@
+//! Cut the writes of a workload at random points, then check the image is
+//! consistent once the journal is replayed
+
+use easy_fs::{BlockDevice, EasyFileSystem, Inode};
+use rand::prelude::*;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+const BLOCK_SZ: usize = 512;
+const TOTAL_BLOCKS: usize = 4096;
+
+/// An in-memory disk which loses every write after `budget` of them
+pub(crate) struct CrashDevice {
+    pub(crate) image: Mutex<Vec<u8>>,
+    /// remaining writes before the crash, `None` for a disk never crashing
+    budget: Mutex<Option<usize>>,
+    writes: Mutex<usize>,
+}
+
+impl CrashDevice {
+    pub(crate) fn new(image: Vec<u8>, budget: Option<usize>) -> Self {
+        Self {
+            image: Mutex::new(image),
+            budget: Mutex::new(budget),
+            writes: Mutex::new(0),
+        }
+    }
+    fn crashed(&self) -> bool {
+        *self.budget.lock().unwrap() == Some(0)
+    }
+}
+
+impl BlockDevice for CrashDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let image = self.image.lock().unwrap();
+        buf.copy_from_slice(&image[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        match &mut *self.budget.lock().unwrap() {
+            Some(0) => return,
+            Some(budget) => *budget -= 1,
+            None => {}
+        }
+        *self.writes.lock().unwrap() += 1;
+        let mut image = self.image.lock().unwrap();
+        image[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ].copy_from_slice(buf);
+    }
+
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.image.lock().unwrap().len() / BLOCK_SZ)
+    }
+}
+
+/// Create, grow, overwrite, truncate and remove files, stopping once the disk crashes
+fn workload(root: &Inode, device: &CrashDevice, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..40 {
+        if device.crashed() {
+            return;
+        }
+        let name = format!("file{}", rng.random_range(0..8));
+        let file = match root.find(&name).unwrap() {
+            Some(file) => file,
+            None => {
+                root.create(&name).unwrap().unwrap();
+                continue;
+            }
+        };
+        match rng.random_range(0..6) {
+            0 => file.clear().unwrap(),
+            1 => assert!(root.unlink(&name).unwrap()),
+            2 => {
+                let size = rng.random_range(0..file.size().unwrap() + 200 * BLOCK_SZ);
+                file.truncate(size).unwrap();
+            }
+            _ => {
+                // large enough to reach into indirect2
+                let offset = rng.random_range(0..=file.size().unwrap());
+                let len = rng.random_range(1..100 * BLOCK_SZ);
+                let data: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+                file.write_at(offset, &data).unwrap();
+            }
+        }
+    }
+}
+
+/// Read consecutive little-endian `u32`s
+fn words(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes
+        .as_chunks::<4>()
+        .0
+        .iter()
+        .map(|&word| u32::from_le_bytes(word))
+}
+
+/// Check the on-disk structures against each other, independently of easy-fs
+fn check_image(image: &[u8]) -> Result<(), String> {
+    let block = |id: u32| &image[id as usize * BLOCK_SZ..(id as usize + 1) * BLOCK_SZ];
+    let super_block: Vec<u32> = words(&block(0)[..36]).collect();
+    let [
+        _,
+        _,
+        inode_bitmap_blocks,
+        inode_area_blocks,
+        data_bitmap_blocks,
+        data_area_blocks,
+        journal_blocks,
+        version,
+        checksum_blocks,
+    ] = super_block[..]
+    else {
+        unreachable!()
+    };
+    if version != 3 {
+        return Err(format!("unexpected version {version}"));
+    }
+    let inode_bitmap_start = 1 + journal_blocks;
+    let inode_area_start = inode_bitmap_start + inode_bitmap_blocks;
+    let data_bitmap_start = inode_area_start + inode_area_blocks;
+    let data_area_start = data_bitmap_start + data_bitmap_blocks + checksum_blocks;
+    let data_area_end = data_area_start + data_area_blocks;
+    let allocated = |bitmap_start: u32, bit: u32| {
+        let bits = block(bitmap_start + bit / 4096);
+        bits[(bit % 4096 / 8) as usize] & (1 << (bit % 8)) != 0
+    };
+
+    let mut inodes = HashSet::new();
+    let mut blocks = HashSet::new();
+    let mut claim_block = |block_id: u32, owner: u32| {
+        if block_id < data_area_start
+            || block_id >= data_area_end
+            || !allocated(data_bitmap_start, block_id - data_area_start)
+            || !blocks.insert(block_id)
+        {
+            return Err(format!("inode {owner} owns bad block {block_id}"));
+        }
+        Ok(())
+    };
+    // walk every inode reached from the root
+    let mut pending = vec![0u32];
+    inodes.insert(0);
+    while let Some(inode_id) = pending.pop() {
+        let pos = inode_area_start as usize * BLOCK_SZ + inode_id as usize * 128;
+        let disk_inode = &image[pos..pos + 128];
+        let fields: Vec<u32> = words(&disk_inode[..124]).collect();
+        let (size, direct, indirect1, indirect2) =
+            (fields[0] as usize, &fields[1..29], fields[29], fields[30]);
+        let data_blocks = size.div_ceil(BLOCK_SZ);
+        let mut data: Vec<u32> = Vec::new();
+        if disk_inode[125] == 1 {
+            // extents, 14 in the inode, 64 in the block of indirect1 and in each one under indirect2
+            let covers = |extents: &[u32]| {
+                let blocks: usize = extents
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|e| e[1] as usize)
+                    .sum();
+                blocks >= data_blocks
+            };
+            let mut extents: Vec<u32> = direct.to_vec();
+            if !covers(&extents) {
+                extents.extend(words(block(indirect1)));
+            }
+            if !covers(&extents) {
+                for extent_block in words(block(indirect2)) {
+                    extents.extend(words(block(extent_block)));
+                    if covers(&extents) {
+                        break;
+                    }
+                }
+            }
+            let mut count = 0usize;
+            for extent in extents.as_chunks::<2>().0 {
+                if data.len() == data_blocks {
+                    break;
+                }
+                let len = (extent[1] as usize).min(data_blocks - data.len());
+                data.extend(extent[0]..extent[0] + len as u32);
+                count += 1;
+            }
+            if count > 14 {
+                claim_block(indirect1, inode_id)?;
+            }
+            if count > 14 + 64 {
+                claim_block(indirect2, inode_id)?;
+                for extent_block in words(block(indirect2)).take((count - 14 - 64).div_ceil(64)) {
+                    claim_block(extent_block, inode_id)?;
+                }
+            }
+        } else {
+            data.extend(direct.iter().copied().take(data_blocks));
+            if data_blocks > 28 {
+                claim_block(indirect1, inode_id)?;
+                data.extend(words(block(indirect1)).take(data_blocks - 28));
+            }
+            if data_blocks > 28 + 128 {
+                claim_block(indirect2, inode_id)?;
+                let rest = data_blocks - 28 - 128;
+                for (i, indirect) in words(block(indirect2)).take(rest.div_ceil(128)).enumerate() {
+                    claim_block(indirect, inode_id)?;
+                    data.extend(words(block(indirect)).take(rest - i * 128));
+                }
+            }
+        }
+        for &block_id in data.iter() {
+            claim_block(block_id, inode_id)?;
+        }
+        if disk_inode[124] != 1 {
+            continue;
+        }
+        // a directory of variable-length records, which never cross blocks
+        if size % BLOCK_SZ != 0 {
+            return Err(format!("directory {inode_id} has size {size}"));
+        }
+        for &block_id in data.iter() {
+            let records = block(block_id);
+            let mut pos = 0;
+            while pos < BLOCK_SZ {
+                let record = &records[pos..];
+                let child = words(&record[..4]).next().unwrap();
+                let rec_len = u16::from_le_bytes([record[4], record[5]]) as usize;
+                let name_len = record[6] as usize;
+                if rec_len < 8 + name_len || pos + rec_len > BLOCK_SZ {
+                    return Err(format!("bad record in block {block_id} at {pos}"));
+                }
+                pos += rec_len;
+                if name_len == 0 {
+                    continue;
+                }
+                let name = String::from_utf8_lossy(&record[8..8 + name_len]);
+                if !allocated(inode_bitmap_start, child) || !inodes.insert(child) {
+                    return Err(format!("entry {name} refers to bad inode {child}"));
+                }
+                pending.push(child);
+            }
+        }
+    }
+
+    let inode_count = inode_area_blocks * (BLOCK_SZ as u32 / 128);
+    if let Some(leaked) =
+        (0..inode_count).find(|&id| allocated(inode_bitmap_start, id) && !inodes.contains(&id))
+    {
+        return Err(format!("inode {leaked} is allocated but unreachable"));
+    }
+    if let Some(leaked) = (data_area_start..data_area_end)
+        .find(|&id| allocated(data_bitmap_start, id - data_area_start) && !blocks.contains(&id))
+    {
+        return Err(format!("block {leaked} is allocated but unused"));
+    }
+    Ok(())
+}
+
+#[test]
+fn journal_crash_test() {
+    let seed = 0x5eed;
+    let fresh = {
+        let device = Arc::new(CrashDevice::new(vec![0; TOTAL_BLOCKS * BLOCK_SZ], None));
+        EasyFileSystem::create(device.clone(), TOTAL_BLOCKS as u32, 1);
+        device.image.lock().unwrap().clone()
+    };
+    // count the writes of the whole workload
+    let device = Arc::new(CrashDevice::new(fresh.clone(), None));
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    workload(&EasyFileSystem::root_inode(&efs), &device, seed);
+    easy_fs::block_cache_sync_all();
+    let total_writes = *device.writes.lock().unwrap();
+    check_image(&device.image.lock().unwrap()).unwrap();
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..64 {
+        let cut = rng.random_range(0..total_writes);
+        let device = Arc::new(CrashDevice::new(fresh.clone(), Some(cut)));
+        let efs = EasyFileSystem::open(device.clone()).unwrap();
+        workload(&EasyFileSystem::root_inode(&efs), &device, seed);
+        easy_fs::block_cache_sync_all();
+        let image = device.image.lock().unwrap().clone();
+        // reboot
+        let device = Arc::new(CrashDevice::new(image, None));
+        let efs = EasyFileSystem::open(device.clone()).unwrap();
+        easy_fs::block_cache_sync_all();
+        if let Err(err) = check_image(&device.image.lock().unwrap()) {
+            panic!("inconsistent after crashing at write {cut}: {err}");
+        }
+        assert_eq!(easy_fs::fsck::check(device.clone(), false), Some(vec![]));
+        // the filesystem stays usable
+        let root = EasyFileSystem::root_inode(&efs);
+        for name in root.ls().unwrap() {
+            let file = root.find(&name).unwrap().unwrap();
+            let mut buf = vec![0u8; file.size().unwrap()];
+            assert_eq!(file.read_at(0, &mut buf).unwrap(), buf.len());
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use easy_fs::{BlockDevice, EasyFileSystem};
+use config::fs::BLOCK_CACHE_SIZE;
+use easy_fs::fsck::{self, Problem};
+use easy_fs::{BlockDevice, EasyFileSystem, block_cache_sync_all, set_block_cache_capacity};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
@@ -6,6 +8,16 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use toml::Value;
 
+mod mount;
+mod pack;
+
+#[cfg(test)]
+mod fsck_test;
+#[cfg(test)]
+mod journal_test;
+#[cfg(test)]
+mod pack_test;
+
 const BLOCK_SZ: usize = 512;
 
 struct BlockFile(Mutex<File>);
@@ -24,14 +36,129 @@ impl BlockDevice for BlockFile {
             .expect("Error when seeking!");
         assert_eq!(file.write(buf).unwrap(), BLOCK_SZ, "Not a complete block!");
     }
+
+    fn num_blocks(&self) -> Option<usize> {
+        let len = self.0.lock().unwrap().metadata().ok()?.len();
+        Some(len as usize / BLOCK_SZ)
+    }
+}
+
+const USAGE: &str = "Usage: easy-fs-fuse <source> <target>
+       easy-fs-fuse pack <dir> <image> [--size <bytes>[K|M|G]] [--inodes <count>]
+       easy-fs-fuse unpack <image> <dir>
+       easy-fs-fuse check <image> [--repair]
+       easy-fs-fuse mount <image> <mountpoint> [--cache <blocks>]";
+
+/// Print the usage to stderr and exit with 16, the status of bad arguments
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(16);
+}
+
+/// Parse a size like `16M`
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, shift) = match size.as_bytes().last()? {
+        b'K' | b'k' => (&size[..size.len() - 1], 10),
+        b'M' | b'm' => (&size[..size.len() - 1], 20),
+        b'G' | b'g' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// Pack a host directory, 16MiB with 4096 inodes by default
+fn pack(args: &[String]) -> std::io::Result<()> {
+    let (mut size, mut inodes) = (Some(16 << 20), Some(4096));
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = args.next().and_then(|arg| parse_size(arg)),
+            "--inodes" => inodes = args.next().and_then(|arg| arg.parse().ok()),
+            _ => paths.push(arg),
+        }
+    }
+    let (Some(size), Some(inodes), [source, image]) = (size, inodes, &paths[..]) else {
+        usage();
+    };
+    pack::pack(source.as_ref(), image.as_ref(), size, inodes)
+}
+
+/// Mount an image, keeping `BLOCK_CACHE_SIZE` blocks in the block cache by default
+fn mount(args: &[String]) -> std::io::Result<()> {
+    let mut cache = Some(BLOCK_CACHE_SIZE);
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cache" => {
+                cache = args
+                    .next()
+                    .and_then(|arg| arg.parse().ok())
+                    .filter(|&blocks| blocks > 0)
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let (Some(cache), [image, mountpoint]) = (cache, &paths[..]) else {
+        usage();
+    };
+    set_block_cache_capacity(cache);
+    mount::mount(image.as_ref(), mountpoint.as_ref())
+}
+
+/// Check an image like fsck, exiting with 0 if it is clean,
+/// 1 if all errors are repaired, 4 if errors are left and 8 if it is not easy-fs
+fn check(args: &[String]) -> std::io::Result<()> {
+    let (image, repair) = match args {
+        [image] => (image, false),
+        [image, flag] if flag == "--repair" => (image, true),
+        _ => usage(),
+    };
+    // opened writable as the journal may be replayed
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image)?,
+    )));
+    let Some(problems) = fsck::check(block_file, repair) else {
+        println!("{image}: not an easy-fs image");
+        std::process::exit(8);
+    };
+    for problem in problems.iter() {
+        let state = match (repair, problem.repairable()) {
+            (true, true) => "fixed",
+            (false, true) => "repairable",
+            (_, false) => "unrepairable",
+        };
+        println!("{problem} ({state})");
+    }
+    let code = if problems.is_empty() {
+        0
+    } else if repair && problems.iter().all(Problem::repairable) {
+        1
+    } else {
+        4
+    };
+    println!("{image}: {} problems", problems.len());
+    std::process::exit(code);
 }
 
 fn main() -> std::io::Result<()> {
     // let App { source, target } = App::parse();
     let args: Vec<_> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("pack") => return pack(&args[1..]),
+        Some("unpack") => {
+            let [_, image, target] = &args[..] else {
+                usage();
+            };
+            return pack::unpack(image.as_ref(), target.as_ref());
+        }
+        Some("check") => return check(&args[1..]),
+        Some("mount") => return mount(&args[1..]),
+        _ => {}
+    }
     if args.len() != 2 {
-        println!("Usage: {} <source> <target>", args[0]);
-        return Ok(());
+        usage();
     }
     let source = PathBuf::from(args[0].clone());
     let target = PathBuf::from(args[1].clone());
@@ -78,56 +205,49 @@ fn main() -> std::io::Result<()> {
         let mut all_data: Vec<u8> = Vec::new();
         host_file.read_to_end(&mut all_data).unwrap();
         // create a file in easy-fs
-        let inode = root_inode.create(&app.as_str()).unwrap();
+        let inode = root_inode.create(&app.as_str()).unwrap().unwrap();
         // write data to easy-fs
-        inode.write_at(0, all_data.as_slice());
+        inode.write_at(0, all_data.as_slice()).unwrap();
     });
+    // the block cache writes back lazily
+    block_cache_sync_all();
     Ok(())
 }
 
 #[test]
 fn efs_test() -> std::io::Result<()> {
-    let block_file = Arc::new(BlockFile(Mutex::new({
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open("fs.img")?;
-        f.set_len(8192 * 512).unwrap();
-        f
-    })));
-    easy_fs::init();
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
-    let efs = EasyFileSystem::open(block_file.clone());
+    let device = Arc::new(easy_fs::RamBlockDevice::new(4096));
+    EasyFileSystem::create(device.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(device).unwrap();
     let root_inode = EasyFileSystem::root_inode(&efs);
-    root_inode.create("filea");
-    root_inode.create("fileb");
-    for name in root_inode.ls() {
+    root_inode.create("filea").unwrap();
+    root_inode.create("fileb").unwrap();
+    for name in root_inode.ls().unwrap() {
         println!("{}", name);
     }
-    let filea = root_inode.find("filea").unwrap();
+    let filea = root_inode.find("filea").unwrap().unwrap();
     let greet_str = "Hello, world!";
-    filea.write_at(0, greet_str.as_bytes());
+    filea.write_at(0, greet_str.as_bytes()).unwrap();
     //let mut buffer = [0u8; 512];
     let mut buffer = [0u8; 233];
-    let len = filea.read_at(0, &mut buffer);
+    let len = filea.read_at(0, &mut buffer).unwrap();
     assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap(),);
 
     let mut random_str_test = |len: usize| {
-        filea.clear();
-        assert_eq!(filea.read_at(0, &mut buffer), 0,);
+        filea.clear().unwrap();
+        assert_eq!(filea.read_at(0, &mut buffer).unwrap(), 0,);
         let mut str = String::new();
         use rand;
         // random digit
         for _ in 0..len {
             str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
         }
-        filea.write_at(0, str.as_bytes());
+        filea.write_at(0, str.as_bytes()).unwrap();
         let mut read_buffer = [0u8; 127];
         let mut offset = 0usize;
         let mut read_str = String::new();
         loop {
-            let len = filea.read_at(offset, &mut read_buffer);
+            let len = filea.read_at(offset, &mut read_buffer).unwrap();
             if len == 0 {
                 break;
             }
@@ -0,0 +1,310 @@
+//! Serve an easy-fs image through FUSE
+
+use crate::BlockFile;
+use easy_fs::{Corrupted, EasyFileSystem, Inode, WriteError, block_cache_sync_all};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyWrite, Request, TimeOrNow,
+};
+use spin::Mutex;
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How long the kernel may cache entries and attributes,
+/// nobody else changes the image while it is mounted
+const TTL: Duration = Duration::from_secs(1);
+
+/// A block of the image does not match its checksum
+fn eio(err: Corrupted) -> i32 {
+    eprintln!("easy-fs: {err}");
+    libc::EIO
+}
+
+/// An allocation failed for want of space or on a corrupted block
+fn write_error(err: WriteError) -> i32 {
+    match err {
+        WriteError::NoSpace => libc::ENOSPC,
+        WriteError::Corrupted(block_id) => eio(Corrupted { block_id }),
+    }
+}
+
+/// An easy-fs image as a FUSE filesystem. FUSE inode numbers are easy-fs
+/// inode ids plus one, as FUSE numbers its root 1
+struct EasyFuse {
+    efs: Arc<Mutex<EasyFileSystem>>,
+    /// Owner and times of the image, shown for every file
+    uid: u32,
+    gid: u32,
+    time: SystemTime,
+}
+
+impl EasyFuse {
+    fn inode(&self, ino: u64) -> Inode {
+        let efs = self.efs.lock();
+        let (block_id, block_offset) = efs.get_disk_inode_pos(ino as u32 - 1);
+        Inode::new(
+            block_id,
+            block_offset,
+            Arc::clone(&self.efs),
+            Arc::clone(&efs.block_device),
+        )
+    }
+    fn attr(&self, inode: &Inode) -> Result<FileAttr, i32> {
+        let size = inode.size().map_err(eio)? as u64;
+        let (kind, perm, nlink) = if inode.is_dir().map_err(eio)? {
+            (FileType::Directory, 0o755, 2)
+        } else {
+            (FileType::RegularFile, 0o644, 1)
+        };
+        Ok(FileAttr {
+            ino: inode.inode_id() as u64 + 1,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.time,
+            mtime: self.time,
+            ctime: self.time,
+            crtime: self.time,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+    /// Find the directory `parent`, then `name` under it
+    fn find(&self, parent: u64, name: &OsStr) -> Result<Arc<Inode>, i32> {
+        let dir = self.inode(parent);
+        if !dir.is_dir().map_err(eio)? {
+            return Err(libc::ENOTDIR);
+        }
+        let name = name.to_str().ok_or(libc::ENOENT)?;
+        if name.len() > dir.name_length_limit() {
+            return Err(libc::ENAMETOOLONG);
+        }
+        dir.find(name).map_err(eio)?.ok_or(libc::ENOENT)
+    }
+}
+
+impl Filesystem for EasyFuse {
+    fn destroy(&mut self) {
+        block_cache_sync_all();
+    }
+
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.find(parent, name).and_then(|inode| self.attr(&inode)) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(&self.inode(ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let inode = self.inode(ino);
+        // only the size is stored
+        let result = (|| {
+            if let Some(size) = size {
+                if inode.is_dir().map_err(eio)? {
+                    return Err(libc::EISDIR);
+                }
+                inode.truncate(size as usize).map_err(write_error)?;
+            }
+            self.attr(&inode)
+        })();
+        match result {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let inode = self.inode(ino);
+        let mut buf = vec![0; size as usize];
+        match inode.read_at(offset as usize, &mut buf) {
+            Ok(len) => reply.data(&buf[..len]),
+            Err(err) => reply.error(eio(err)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.inode(ino).write_at(offset as usize, data) {
+            Ok(len) => reply.written(len as u32),
+            Err(err) => reply.error(write_error(err)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let dir = self.inode(parent);
+        let Some(name) = name.to_str() else {
+            return reply.error(libc::EINVAL);
+        };
+        if name.len() > dir.name_length_limit() {
+            return reply.error(libc::ENAMETOOLONG);
+        }
+        let created = dir
+            .create(name)
+            .map_err(write_error)
+            .and_then(|inode| match inode {
+                Some(inode) => self.attr(&inode),
+                None => Err(libc::EEXIST),
+            });
+        match created {
+            Ok(attr) => reply.created(&TTL, &attr, 0, 0, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.find(parent, name).and_then(|inode| {
+            if inode.is_dir().map_err(eio)? {
+                return Err(libc::EISDIR);
+            }
+            self.inode(parent)
+                .unlink(name.to_str().unwrap())
+                .map_err(eio)
+        });
+        match result {
+            Ok(_) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let dir = self.inode(ino);
+        match dir.is_dir() {
+            Ok(true) => {}
+            Ok(false) => return reply.error(libc::ENOTDIR),
+            Err(err) => return reply.error(eio(err)),
+        }
+        // the offset of an entry is where to read the next one from,
+        // which is its position in the directory past "." and ".."
+        let mut offset = offset as usize;
+        loop {
+            let (name, ino, kind, next) = match offset {
+                // there is no parent link on disk, which is only right for the root
+                0 => (".".into(), ino, FileType::Directory, 1),
+                1 => ("..".into(), 1, FileType::Directory, 2),
+                _ => match dir.read_dir(offset - 2) {
+                    Ok(Some((name, inode, next))) => {
+                        let kind = match inode.is_dir() {
+                            Ok(true) => FileType::Directory,
+                            Ok(false) => FileType::RegularFile,
+                            Err(err) => return reply.error(eio(err)),
+                        };
+                        (name, inode.inode_id() as u64 + 1, kind, next + 2)
+                    }
+                    Ok(None) => break,
+                    Err(err) => return reply.error(eio(err)),
+                },
+            };
+            offset = next;
+            if reply.add(ino, offset as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.inode(ino).sync() {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(eio(err)),
+        }
+    }
+}
+
+/// Mount an image at `mountpoint` until it is unmounted
+pub fn mount(image: &Path, mountpoint: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(image)?;
+    let metadata = file.metadata()?;
+    let block_file = Arc::new(BlockFile(std::sync::Mutex::new(file)));
+    let efs = EasyFileSystem::open(block_file)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    let fs = EasyFuse {
+        efs,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        time: metadata.modified()?,
+    };
+    let options = [
+        MountOption::FSName("easy-fs".into()),
+        MountOption::DefaultPermissions,
+    ];
+    fuser::mount2(fs, mountpoint, &options)?;
+    // unmounted
+    block_cache_sync_all();
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! Pack a host directory tree into an image, and unpack an image back
+
+use crate::BlockFile;
+use config::fs::{
+    BLOCK_BITS, BLOCK_SZ, EXTENT_BLOCK_COUNT, EXTENT_INDIRECT1_BOUND, INODE_EXTENT_COUNT,
+    NAME_LENGTH_LIMIT,
+};
+use easy_fs::{Corrupted, EasyFileSystem, Inode, WriteError, block_cache_sync_all, record_len};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Bytes copied at a time between the host and the image
+const COPY_CHUNK_SZ: usize = 64 * 1024;
+
+/// A block of the image does not match its checksum
+fn corrupted(err: Corrupted) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+/// The image is full, or a block of it does not match its checksum
+fn write_error(err: WriteError) -> Error {
+    match err {
+        WriteError::NoSpace => Error::new(ErrorKind::StorageFull, err.to_string()),
+        WriteError::Corrupted(block_id) => corrupted(Corrupted { block_id }),
+    }
+}
+
+/// A host file or directory to pack
+enum Node {
+    File { path: PathBuf, size: usize },
+    Dir(Vec<(String, Node)>),
+}
+
+/// Read a host directory recursively with its entries sorted by name,
+/// so the same tree always gives the same image
+fn scan(path: &Path) -> io::Result<Vec<(String, Node)>> {
+    let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    let mut nodes = Vec::new();
+    for entry in entries {
+        let path = entry.path();
+        let Ok(name) = entry.file_name().into_string() else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: name is not UTF-8", path.display()),
+            ));
+        };
+        if name.len() > NAME_LENGTH_LIMIT {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{}: name longer than {NAME_LENGTH_LIMIT} bytes",
+                    path.display()
+                ),
+            ));
+        }
+        // symlinks are not followed
+        let file_type = entry.file_type()?;
+        let node = if file_type.is_dir() {
+            Node::Dir(scan(&path)?)
+        } else if file_type.is_file() {
+            let size = entry.metadata()?.len() as usize;
+            Node::File { path, size }
+        } else {
+            // easy-fs only has regular files and directories
+            eprintln!("skip {}: not a regular file or directory", path.display());
+            continue;
+        };
+        nodes.push((name, node));
+    }
+    Ok(nodes)
+}
+
+/// Blocks taken at most by an inode of `size` bytes, counting the extent blocks
+/// it needs if none of its data blocks are contiguous
+fn blocks_for(size: usize) -> usize {
+    let data_blocks = size.div_ceil(BLOCK_SZ);
+    let mut total = data_blocks;
+    if data_blocks > INODE_EXTENT_COUNT {
+        total += 1;
+    }
+    if data_blocks > EXTENT_INDIRECT1_BOUND {
+        total += 1 + (data_blocks - EXTENT_INDIRECT1_BOUND).div_ceil(EXTENT_BLOCK_COUNT);
+    }
+    total
+}
+
+/// Blocks taken by a directory with these entries. Each entry goes
+/// into the first block with room for its record, or a new block
+fn dir_blocks(nodes: &[(String, Node)]) -> usize {
+    let mut free = Vec::new();
+    for (name, _) in nodes {
+        let record_len = record_len(name.len());
+        match free.iter_mut().find(|free| **free >= record_len) {
+            Some(free) => *free -= record_len,
+            None => free.push(BLOCK_SZ - record_len),
+        }
+    }
+    blocks_for(free.len() * BLOCK_SZ)
+}
+
+/// Count the inodes and data blocks needed by the entries of a directory,
+/// not counting the directory itself
+fn usage(nodes: &[(String, Node)]) -> (usize, usize) {
+    nodes
+        .iter()
+        .map(|(_, node)| match node {
+            Node::File { size, .. } => (1, blocks_for(*size)),
+            Node::Dir(nodes) => {
+                let (inodes, blocks) = usage(nodes);
+                (1 + inodes, dir_blocks(nodes) + blocks)
+            }
+        })
+        .fold((0, 0), |(inodes, blocks), (i, b)| (inodes + i, blocks + b))
+}
+
+fn write_tree(dir: &Inode, nodes: &[(String, Node)]) -> io::Result<()> {
+    let mut buf = vec![0; COPY_CHUNK_SZ];
+    for (name, node) in nodes {
+        match node {
+            Node::File { path, .. } => {
+                let inode = dir.create(name).map_err(write_error)?.unwrap();
+                let mut file = File::open(path)?;
+                let mut offset = 0;
+                loop {
+                    let len = file.read(&mut buf)?;
+                    if len == 0 {
+                        break;
+                    }
+                    // a short write only happens once the image is full
+                    if inode.write_at(offset, &buf[..len]).map_err(write_error)? < len {
+                        return Err(write_error(WriteError::NoSpace));
+                    }
+                    offset += len;
+                }
+            }
+            Node::Dir(nodes) => {
+                write_tree(&dir.create_dir(name).map_err(write_error)?.unwrap(), nodes)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pack the tree under `source` into a new image of `size` bytes with room for `inodes` inodes,
+/// which is rounded up to a multiple of the inodes tracked by a bitmap block
+pub fn pack(source: &Path, image: &Path, size: usize, inodes: usize) -> io::Result<()> {
+    let nodes = scan(source)?;
+    let (used_inodes, used_blocks) = usage(&nodes);
+    let used_blocks = used_blocks + dir_blocks(&nodes);
+    // the root takes an inode too
+    if used_inodes + 1 > inodes {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} inodes needed, only {inodes} requested", used_inodes + 1),
+        ));
+    }
+    let inode_bitmap_blocks = inodes.div_ceil(BLOCK_BITS);
+    let total_blocks = size / BLOCK_SZ;
+    if EasyFileSystem::data_area_blocks(total_blocks as u32, inode_bitmap_blocks as u32).is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{size} bytes too small for the metadata of {inodes} inodes"),
+        ));
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)?;
+    file.set_len((total_blocks * BLOCK_SZ) as u64)?;
+    let block_file = Arc::new(BlockFile(Mutex::new(file)));
+    let efs = EasyFileSystem::create(block_file, total_blocks as u32, inode_bitmap_blocks as u32);
+    let free_blocks = efs.lock().free_data_blocks().map_err(corrupted)? as usize;
+    if used_blocks > free_blocks {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{used_blocks} data blocks needed, only {free_blocks} in the image"),
+        ));
+    }
+    write_tree(&EasyFileSystem::root_inode(&efs), &nodes)?;
+    block_cache_sync_all();
+    Ok(())
+}
+
+fn read_tree(dir: &Inode, path: &Path) -> io::Result<()> {
+    let mut buf = vec![0; COPY_CHUNK_SZ];
+    let mut pos = 0;
+    while let Some((name, inode, next)) = dir.read_dir(pos).map_err(corrupted)? {
+        pos = next;
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            eprintln!("skip entry {name:?} under {}", path.display());
+            continue;
+        }
+        let path = path.join(name);
+        if inode.is_dir().map_err(corrupted)? {
+            fs::create_dir_all(&path)?;
+            read_tree(&inode, &path)?;
+            continue;
+        }
+        let mut file = File::create(&path)?;
+        let mut offset = 0;
+        loop {
+            let len = inode.read_at(offset, &mut buf).map_err(corrupted)?;
+            if len == 0 {
+                break;
+            }
+            file.write_all(&buf[..len])?;
+            offset += len;
+        }
+    }
+    Ok(())
+}
+
+/// Extract every file and directory of an image under `target`
+pub fn unpack(image: &Path, target: &Path) -> io::Result<()> {
+    // opened writable as the journal may be replayed
+    let file = OpenOptions::new().read(true).write(true).open(image)?;
+    let efs = EasyFileSystem::open(Arc::new(BlockFile(Mutex::new(file))))
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+    fs::create_dir_all(target)?;
+    read_tree(&EasyFileSystem::root_inode(&efs), target)?;
+    block_cache_sync_all();
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Pack host trees into images and unpack them back
+
+use crate::BlockFile;
+use crate::pack::{pack, unpack};
+use easy_fs::fsck;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A fresh directory for a test under the host temp dir
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("easy-fs-fuse-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Contents of a host tree, with `None` for directories
+fn read_tree(dir: &Path, prefix: &str, tree: &mut BTreeMap<String, Option<Vec<u8>>>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let name = format!("{prefix}/{}", entry.file_name().into_string().unwrap());
+        let file_type = entry.file_type().unwrap();
+        if file_type.is_dir() {
+            tree.insert(name.clone(), None);
+            read_tree(&entry.path(), &name, tree);
+        } else if file_type.is_file() {
+            tree.insert(name, Some(fs::read(entry.path()).unwrap()));
+        }
+    }
+}
+
+fn make_source(dir: &Path) {
+    fs::create_dir_all(dir.join("bin")).unwrap();
+    fs::create_dir_all(dir.join("home/user/empty")).unwrap();
+    fs::write(dir.join("README"), b"easy-fs").unwrap();
+    fs::write(dir.join("bin/empty"), b"").unwrap();
+    // large enough to reach into indirect2
+    let big: Vec<u8> = (0..300 * 1024).map(|i| (i % 253) as u8).collect();
+    fs::write(dir.join("bin/big"), big).unwrap();
+    fs::write(dir.join("home/user/note"), b"hello").unwrap();
+    std::os::unix::fs::symlink("README", dir.join("link")).unwrap();
+}
+
+#[test]
+fn pack_unpack_test() {
+    let tmp = temp_dir("pack");
+    let source = tmp.join("source");
+    make_source(&source);
+    fs::write(source.join("home").join("l".repeat(255)), b"a long name").unwrap();
+    let (image, image2) = (tmp.join("a.img"), tmp.join("b.img"));
+    pack(&source, &image, 4 << 20, 5000).unwrap();
+    pack(&source, &image2, 4 << 20, 5000).unwrap();
+    // reproducible
+    assert_eq!(fs::read(&image).unwrap(), fs::read(&image2).unwrap());
+    assert_eq!(fs::metadata(&image).unwrap().len(), 4 << 20);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&image)
+        .unwrap();
+    let block_file = Arc::new(BlockFile(Mutex::new(file)));
+    assert_eq!(fsck::check(block_file, false), Some(vec![]));
+
+    let target = tmp.join("target");
+    unpack(&image, &target).unwrap();
+    let (mut expected, mut unpacked) = (BTreeMap::new(), BTreeMap::new());
+    read_tree(&source, "", &mut expected);
+    read_tree(&target, "", &mut unpacked);
+    // the symlink is skipped
+    assert!(!target.join("link").exists());
+    assert_eq!(unpacked, expected);
+    fs::remove_dir_all(tmp).unwrap();
+}
+
+#[test]
+fn pack_limits_test() {
+    let tmp = temp_dir("limits");
+    let source = tmp.join("source");
+    make_source(&source);
+    let image = tmp.join("fs.img");
+    // 9 inodes are needed with the root
+    let err = pack(&source, &image, 4 << 20, 8).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    pack(&source, &image, 4 << 20, 9).unwrap();
+    // no room for the data
+    let err = pack(&source, &image, 700 << 10, 9).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    // no room for the metadata
+    let err = pack(&source, &image, 64 << 10, 9).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    // a name of 255 bytes is the longest the host has too
+    fs::write(source.join("n".repeat(255)), b"").unwrap();
+    pack(&source, &image, 4 << 20, 100).unwrap();
+    fs::remove_dir_all(tmp).unwrap();
+}
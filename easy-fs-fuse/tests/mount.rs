@@ -0,0 +1,205 @@
+//! Mount an image with the `mount` subcommand, then check ordinary file
+//! operations on it give the same results as on a host directory
+
+use easy_fs::{BlockDevice, EasyFileSystem, fsck};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const BLOCK_SZ: usize = 512;
+
+struct BlockFile(Mutex<File>);
+
+impl BlockDevice for BlockFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let file = self.0.lock().unwrap();
+        file.read_exact_at(buf, (block_id * BLOCK_SZ) as u64)
+            .unwrap();
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let file = self.0.lock().unwrap();
+        file.write_all_at(buf, (block_id * BLOCK_SZ) as u64)
+            .unwrap();
+    }
+
+    fn num_blocks(&self) -> Option<usize> {
+        let len = self.0.lock().unwrap().metadata().ok()?.len();
+        Some(len as usize / BLOCK_SZ)
+    }
+}
+
+fn open_image(path: &Path) -> Arc<BlockFile> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .unwrap();
+    Arc::new(BlockFile(Mutex::new(file)))
+}
+
+/// Whether FUSE can be mounted on this host
+fn fuse_available() -> bool {
+    Path::new("/dev/fuse").exists()
+        && ["fusermount3", "fusermount"]
+            .iter()
+            .any(|cmd| Command::new(cmd).arg("-V").output().is_ok())
+}
+
+fn is_mounted(mountpoint: &Path) -> bool {
+    let mountpoint = mountpoint.to_str().unwrap();
+    fs::read_to_string("/proc/self/mountinfo")
+        .unwrap()
+        .lines()
+        .any(|line| line.split(' ').nth(4) == Some(mountpoint))
+}
+
+/// An image mounted by easy-fs-fuse, unmounted when dropped
+struct Mount {
+    child: Child,
+    mountpoint: PathBuf,
+}
+
+impl Mount {
+    fn new(image: &Path, mountpoint: &Path) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+            .arg("mount")
+            .arg(image)
+            .arg(mountpoint)
+            .spawn()
+            .unwrap();
+        let start = Instant::now();
+        while !is_mounted(mountpoint) {
+            if let Some(status) = child.try_wait().unwrap() {
+                panic!("easy-fs-fuse exited with {status} before mounting");
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "mount timed out");
+            sleep(Duration::from_millis(10));
+        }
+        Self {
+            child,
+            mountpoint: mountpoint.to_path_buf(),
+        }
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let unmounted = ["fusermount3", "fusermount"].iter().any(|cmd| {
+            Command::new(cmd)
+                .arg("-u")
+                .arg(&self.mountpoint)
+                .status()
+                .is_ok_and(|status| status.success())
+        });
+        if !unmounted {
+            self.child.kill().unwrap();
+        }
+        // the image is written back once the session ends
+        self.child.wait().unwrap();
+    }
+}
+
+/// Run file operations under `dir`, then return the name, size and data of each file
+fn exercise(dir: &Path) -> Vec<(String, u64, Vec<u8>)> {
+    fs::write(dir.join("hello"), b"Hello, world!").unwrap();
+    // large enough to reach into indirect2
+    let big: Vec<u8> = (0..200 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(dir.join("big"), &big).unwrap();
+    // overwrite in the middle, append, then shrink and grow
+    let file = OpenOptions::new()
+        .write(true)
+        .open(dir.join("big"))
+        .unwrap();
+    file.write_all_at(b"patched", 70 * 1024).unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(dir.join("hello"))
+        .unwrap()
+        .write_all(b" Again!")
+        .unwrap();
+    file.set_len(100 * 1024 + 3).unwrap();
+    fs::write(dir.join("empty"), b"").unwrap();
+    File::create(dir.join("sparse"))
+        .unwrap()
+        .set_len(3000)
+        .unwrap();
+    // names up to 255 bytes
+    fs::write(dir.join("l".repeat(255)), b"a long name").unwrap();
+    assert_eq!(
+        fs::write(dir.join("l".repeat(256)), b"")
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidFilename
+    );
+    // create and remove
+    fs::write(dir.join("gone"), b"removed soon").unwrap();
+    fs::remove_file(dir.join("gone")).unwrap();
+    assert_eq!(
+        fs::remove_file(dir.join("gone")).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert!(!dir.join("gone").exists());
+
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let name = entry.file_name().into_string().unwrap();
+            let size = entry.metadata().unwrap().len();
+            (name, size, fs::read(entry.path()).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn mount_test() {
+    if !fuse_available() {
+        eprintln!("skip mount_test: FUSE is not available");
+        return;
+    }
+    let tmp = std::env::temp_dir().join(format!("easy-fs-fuse-{}", std::process::id()));
+    let image = tmp.join("fs.img");
+    let (host, mountpoint) = (tmp.join("host"), tmp.join("mnt"));
+    fs::create_dir_all(&host).unwrap();
+    fs::create_dir(&mountpoint).unwrap();
+    let total_blocks = 8192;
+    {
+        let block_file = open_image(&image);
+        block_file
+            .0
+            .lock()
+            .unwrap()
+            .set_len((total_blocks * BLOCK_SZ) as u64)
+            .unwrap();
+        EasyFileSystem::create(block_file, total_blocks as u32, 1);
+    }
+
+    let expected = exercise(&host);
+    let mount = Mount::new(&image, &mountpoint);
+    assert_eq!(exercise(&mountpoint), expected);
+    drop(mount);
+
+    // the changes reached the image, which stays consistent
+    let block_file = open_image(&image);
+    assert_eq!(fsck::check(block_file.clone(), false), Some(vec![]));
+    let efs = EasyFileSystem::open(block_file).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    for (name, size, data) in expected {
+        let inode = root.find(&name).unwrap().unwrap();
+        assert_eq!(inode.size().unwrap() as u64, size);
+        let mut buf = vec![0; data.len()];
+        inode.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+    fs::remove_dir_all(tmp).unwrap();
+}
@@ -1,30 +1,46 @@
 use crate::config::*;
-use crate::{BlockDevice, get_block_cache};
+use crate::{BlockDevice, Corrupted, get_block_cache};
 use alloc::sync::Arc;
+use core::ops::Range;
 
 /// A bitmap block
 type BitmapBlock = [u64; 64];
+/// Decompose bits into (block_pos, bits64_pos, inner_pos)
+fn decomposition(mut bit: usize) -> (usize, usize, usize) {
+    let block_pos = bit / BLOCK_BITS;
+    bit %= BLOCK_BITS;
+    (block_pos, bit / 64, bit % 64)
+}
+
 /// A bitmap
 pub struct Bitmap {
     start_block_id: usize,
     blocks: usize,
+    /// Bits past it in the last block have nothing to allocate
+    bits: usize,
 }
 
 impl Bitmap {
-    /// A new bitmap from start block id and number of blocks
-    pub fn new(start_block_id: usize, blocks: usize) -> Self {
+    /// A new bitmap of `bits` bits from start block id and number of blocks
+    pub fn new(start_block_id: usize, blocks: usize, bits: usize) -> Self {
+        assert!(bits <= blocks * BLOCK_BITS);
         Self {
             start_block_id,
             blocks,
+            bits,
         }
     }
-    /// Allocate a new block from a block device
-    pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+    /// Ids of the blocks holding the bitmap
+    pub fn block_ids(&self) -> impl Iterator<Item = usize> {
+        self.start_block_id..self.start_block_id + self.blocks
+    }
+    /// Allocate a new block from a block device, `None` if all are allocated
+    pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Result<Option<usize>, Corrupted> {
         for block_id in 0..self.blocks {
             let pos = get_block_cache(
                 block_id + self.start_block_id as usize,
                 Arc::clone(block_device),
-            )
+            )?
             .lock()
             .modify(0, |bitmap_block: &mut BitmapBlock| {
                 if let Some((bits64_pos, inner_pos)) = bitmap_block
@@ -32,6 +48,9 @@ impl Bitmap {
                     .enumerate()
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     .map(|(bits64_pos, bits64)| (bits64_pos, bits64.trailing_ones() as usize))
+                    .filter(|(bits64_pos, inner_pos)| {
+                        block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos < self.bits
+                    })
                 {
                     // modify cache
                     bitmap_block[bits64_pos] ^= 1 << inner_pos;
@@ -41,29 +60,151 @@ impl Bitmap {
                 }
             });
             if pos.is_some() {
-                return pos;
+                return Ok(pos);
             }
         }
-        None
+        Ok(None)
     }
-    /// Deallocate a block
-    pub fn dealloc(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
-        /// Decompose bits into (block_pos, bits64_pos, inner_pos)
-        fn decomposition(mut bit: usize) -> (usize, usize, usize) {
-            let block_pos = bit / BLOCK_BITS;
-            bit %= BLOCK_BITS;
-            (block_pos, bit / 64, bit % 64)
+    /// Allocate up to `count` contiguous bits, return the first of them and how many they are.
+    /// The free bits right at `goal` are taken first, as they extend what ends there,
+    /// then the first `count` free bits in a row from `goal` on, and at last any free bits
+    pub fn alloc_contiguous(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        goal: usize,
+        count: usize,
+    ) -> Result<Option<(usize, usize)>, Corrupted> {
+        assert!(count > 0);
+        let goal = goal.min(self.bits);
+        let mut run = self.find_free_run(block_device, goal..self.bits, |start, len| {
+            start == goal || len >= count
+        })?;
+        if run.is_none() {
+            run = self.find_free_run(block_device, 0..goal, |_, len| len >= count)?;
+        }
+        if run.is_none() {
+            run = self.find_free_run(block_device, 0..self.bits, |_, _| true)?;
+        }
+        let Some((start, len)) = run else {
+            return Ok(None);
+        };
+        let len = len.min(count);
+        for bit in start..start + len {
+            self.set(block_device, bit)?;
+        }
+        Ok(Some((start, len)))
+    }
+    /// The first run of free bits in `range` accepted by `accept`, as its first bit and length
+    fn find_free_run(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        range: Range<usize>,
+        mut accept: impl FnMut(usize, usize) -> bool,
+    ) -> Result<Option<(usize, usize)>, Corrupted> {
+        let mut bitmap_block: Option<(usize, BitmapBlock)> = None;
+        let mut run_start = None;
+        for bit in range.start..=range.end {
+            let free = bit < range.end && {
+                let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+                let (_, bits) = match bitmap_block {
+                    Some((pos, _)) if pos == block_pos => bitmap_block.as_ref().unwrap(),
+                    _ => bitmap_block.insert((
+                        block_pos,
+                        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))?
+                            .lock()
+                            .read(0, |bitmap_block: &BitmapBlock| *bitmap_block),
+                    )),
+                };
+                bits[bits64_pos] & (1 << inner_pos) == 0
+            };
+            match (free, run_start) {
+                (true, None) => run_start = Some(bit),
+                (false, Some(start)) => {
+                    if accept(start, bit - start) {
+                        return Ok(Some((start, bit - start)));
+                    }
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+    /// Whether at least `count` bits are free
+    pub fn has_free(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        count: usize,
+    ) -> Result<bool, Corrupted> {
+        let mut free = 0;
+        for block_pos in 0..self.blocks {
+            if free >= count {
+                return Ok(true);
+            }
+            let bits = (self.bits - block_pos * BLOCK_BITS).min(BLOCK_BITS);
+            free += get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))?
+                .lock()
+                .read(0, |bitmap_block: &BitmapBlock| {
+                    bitmap_block
+                        .iter()
+                        .enumerate()
+                        .map(|(bits64_pos, bits64)| {
+                            // only the bits below `bits` can be allocated
+                            let valid = bits.saturating_sub(bits64_pos * 64).min(64);
+                            let mask = if valid == 64 {
+                                u64::MAX
+                            } else {
+                                (1 << valid) - 1
+                            };
+                            (!bits64 & mask).count_ones() as usize
+                        })
+                        .sum::<usize>()
+                });
         }
+        Ok(free >= count)
+    }
+    /// Deallocate a block
+    pub fn dealloc(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        bit: usize,
+    ) -> Result<(), Corrupted> {
         let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
-        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))?
             .lock()
             .modify(0, |bitmap_block: &mut BitmapBlock| {
                 assert!(bitmap_block[bits64_pos] & (1 << inner_pos) > 0);
                 bitmap_block[bits64_pos] ^= 1 << inner_pos;
             });
+        Ok(())
+    }
+    /// Whether a bit is allocated
+    pub fn is_allocated(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        bit: usize,
+    ) -> Result<bool, Corrupted> {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        Ok(
+            get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))?
+                .lock()
+                .read(0, |bitmap_block: &BitmapBlock| {
+                    bitmap_block[bits64_pos] & (1 << inner_pos) > 0
+                }),
+        )
+    }
+    /// Mark a bit as allocated
+    pub fn set(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> Result<(), Corrupted> {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))?
+            .lock()
+            .modify(0, |bitmap_block: &mut BitmapBlock| {
+                bitmap_block[bits64_pos] |= 1 << inner_pos;
+            });
+        Ok(())
     }
     /// Get the max number of allocatable blocks
     pub fn maximum(&self) -> usize {
-        self.blocks * BLOCK_BITS
+        self.bits
     }
 }
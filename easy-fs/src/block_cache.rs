@@ -1,9 +1,12 @@
+use super::checksum::{ChecksumArea, crc32c};
 use super::config::{BLOCK_CACHE_SIZE, BLOCK_SZ};
 use crate::BlockDevice;
 use alloc::boxed::Box;
-use alloc::sync::Arc;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::alloc::Layout;
+use core::fmt;
 use core::mem::ManuallyDrop;
 use core::ptr::{addr_of, addr_of_mut};
 use spin::Mutex;
@@ -45,6 +48,18 @@ impl AsMut<[u8; BLOCK_SZ]> for CacheData {
     }
 }
 
+/// A block which does not match its checksum as it is loaded, the image is corrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corrupted {
+    pub block_id: usize,
+}
+
+impl fmt::Display for Corrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {} does not match its checksum", self.block_id)
+    }
+}
+
 /// Cached block inside memory
 pub struct BlockCache {
     /// cached block data
@@ -55,20 +70,31 @@ pub struct BlockCache {
     block_device: Arc<dyn BlockDevice>,
     /// whether the block is dirty
     modified: bool,
+    /// whether the block holds metadata changed by the running transaction,
+    /// which must reach the journal before its home location
+    journaled: bool,
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
-    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+    /// Load a new BlockCache from disk, and check it against its checksum if it has one
+    pub fn new(
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+        checksum: Option<u32>,
+    ) -> Result<Self, Corrupted> {
         // for alignment and move effciency
         let mut cache = CacheData::new();
         block_device.read_block(block_id, cache.as_mut());
-        Self {
+        if checksum.is_some_and(|checksum| crc32c(cache.as_ref()) != checksum) {
+            return Err(Corrupted { block_id });
+        }
+        Ok(Self {
             cache,
             block_id,
             block_device,
             modified: false,
-        }
+            journaled: false,
+        })
     }
     /// Get the address of an offset inside the cached block data
     fn addr_of_offset(&self, offset: usize) -> *const u8 {
@@ -89,7 +115,17 @@ impl BlockCache {
         unsafe { &*addr }
     }
 
+    /// Get a mutable reference to metadata, which is journaled
     pub fn as_mut<T>(&mut self, offset: usize) -> &mut T
+    where
+        T: Sized,
+    {
+        self.journaled = true;
+        self.as_data_mut(offset)
+    }
+
+    /// Get a mutable reference to file data, which skips the journal
+    pub fn as_data_mut<T>(&mut self, offset: usize) -> &mut T
     where
         T: Sized,
     {
@@ -108,13 +144,34 @@ impl BlockCache {
         f(self.as_mut(offset))
     }
 
+    pub fn modify_data<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        f(self.as_data_mut(offset))
+    }
+
+    /// Id of the cached block
+    pub fn block_id(&self) -> usize {
+        self.block_id
+    }
+
+    /// Data of the whole block
+    pub fn data(&self) -> &[u8; BLOCK_SZ] {
+        self.cache.as_ref()
+    }
+
+    /// Write the block back, unless it waits for the journal
     pub fn sync(&mut self) {
-        if self.modified {
+        if self.modified && !self.journaled {
             self.modified = false;
             self.block_device
                 .write_block(self.block_id, self.cache.as_ref());
         }
     }
+
+    /// Write the block back after the journal has committed it
+    pub fn checkpoint(&mut self) {
+        self.journaled = false;
+        self.sync();
+    }
 }
 impl Drop for BlockCache {
     fn drop(&mut self) {
@@ -122,69 +179,225 @@ impl Drop for BlockCache {
     }
 }
 
+/// A cached block with the time it was last used
+struct CacheEntry {
+    cache: Arc<Mutex<BlockCache>>,
+    last_used: u64,
+}
+
+/// Blocks are identified by the address of their device and the block id
+type CacheKey = (usize, usize);
+
+fn device_key(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+/// LRU block cache, indexed by device and block id.
+///
+/// Dirty blocks are only written back on eviction or `sync_all`. When every
+/// block is in use, the cache grows beyond its capacity rather than failing,
+/// and shrinks back once blocks are released.
 pub struct BlockCacheManager {
-    queue: Vec<(usize, Arc<Mutex<BlockCache>>)>,
+    capacity: usize,
+    /// increases on every access, orders entries from least recently used
+    clock: u64,
+    entries: BTreeMap<CacheKey, CacheEntry>,
+    /// keys of entries by their `last_used`
+    lru: BTreeMap<u64, CacheKey>,
+    /// checksums to check blocks against when they are loaded, by device
+    checksums: BTreeMap<usize, (Weak<dyn BlockDevice>, ChecksumArea)>,
 }
 
 impl BlockCacheManager {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
         Self {
-            queue: Vec::with_capacity(BLOCK_CACHE_SIZE),
+            capacity,
+            clock: 0,
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            checksums: BTreeMap::new(),
         }
     }
 
+    /// The cache of a block, loaded if it is not cached. A block which does
+    /// not match its checksum is not cached, so loading it fails again
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.swap_remove(idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
+    ) -> Result<Arc<Mutex<BlockCache>>, Corrupted> {
+        let key = (device_key(&block_device), block_id);
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.lru.insert(self.clock, key);
+            return Ok(Arc::clone(&entry.cache));
+        }
+        self.shrink_to(self.capacity - 1);
+        // load block into mem
+        let checksum = self.checksum(&block_device, block_id);
+        let cache = Arc::new(Mutex::new(BlockCache::new(
+            block_id,
+            block_device,
+            checksum,
+        )?));
+        self.entries.insert(
+            key,
+            CacheEntry {
+                cache: Arc::clone(&cache),
+                last_used: self.clock,
+            },
+        );
+        self.lru.insert(self.clock, key);
+        Ok(cache)
+    }
+
+    /// The checksum of a block to check it against, from the cache or else from the disk.
+    /// The copy on disk is up to date for any block which is not cached
+    fn checksum(&self, block_device: &Arc<dyn BlockDevice>, block_id: usize) -> Option<u32> {
+        let device = device_key(block_device);
+        let (_, area) = self.checksums.get(&device)?;
+        let (checksum_block, index) = area.slot(block_id)?;
+        let mut data = [0u8; BLOCK_SZ];
+        match self.entries.get(&(device, checksum_block)) {
+            Some(entry) => data.copy_from_slice(entry.cache.lock().data()),
+            None => block_device.read_block(checksum_block, &mut data),
+        }
+        Some(u32::from_ne_bytes(
+            data[index * 4..][..4].try_into().unwrap(),
+        ))
+    }
+
+    /// Check the blocks of a device against `checksums` when they are loaded,
+    /// or stop checking them with `None`
+    pub fn set_checksums(
+        &mut self,
+        block_device: &Arc<dyn BlockDevice>,
+        checksums: Option<ChecksumArea>,
+    ) {
+        // a device which is gone keeps its address from being reused until its entry goes
+        self.checksums
+            .retain(|_, (device, _)| device.strong_count() > 0);
+        let device = device_key(block_device);
+        match checksums {
+            Some(area) => {
+                self.checksums
+                    .insert(device, (Arc::downgrade(block_device), area));
+            }
+            None => {
+                self.checksums.remove(&device);
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push((block_id, Arc::clone(&block_cache)));
-            block_cache
         }
     }
+
+    /// Evict least recently used blocks until at most `len` are cached,
+    /// skipping blocks somebody holds and blocks waiting for the journal
+    fn shrink_to(&mut self, len: usize) {
+        let excess = self.entries.len().saturating_sub(len);
+        let victims: Vec<(u64, CacheKey)> = self
+            .lru
+            .iter()
+            .filter(|(_, key)| {
+                let cache = &self.entries[*key].cache;
+                Arc::strong_count(cache) == 1 && cache.try_lock().is_some_and(|c| !c.journaled)
+            })
+            .take(excess)
+            .map(|(&last_used, &key)| (last_used, key))
+            .collect();
+        for (last_used, key) in victims {
+            self.lru.remove(&last_used);
+            // dirty data is written back when the cache drops
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Change the number of cached blocks, evicting blocks if it shrinks
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0);
+        self.capacity = capacity;
+        self.shrink_to(capacity);
+    }
+
+    /// Write the given blocks of a device back if they are cached and dirty
+    pub fn sync_blocks(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        block_ids: impl Iterator<Item = usize>,
+    ) {
+        let device = device_key(block_device);
+        for block_id in block_ids {
+            if let Some(entry) = self.entries.get(&(device, block_id)) {
+                entry.cache.lock().sync();
+            }
+        }
+    }
+
+    /// Write all dirty blocks back to their devices
+    pub fn sync_all(&self) {
+        for entry in self.entries.values() {
+            entry.cache.lock().sync();
+        }
+    }
+
+    /// Blocks of a device changed by the running transaction
+    pub fn journaled_blocks(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<Arc<Mutex<BlockCache>>> {
+        let device = device_key(block_device);
+        self.entries
+            .range((device, 0)..=(device, usize::MAX))
+            .map(|(_, entry)| &entry.cache)
+            .filter(|cache| cache.lock().journaled)
+            .cloned()
+            .collect()
+    }
 }
 
 /// The global block cache manager
 pub static BLOCK_CACHE_MANAGER: UpSafeLazyCell<Mutex<BlockCacheManager>> =
-    unsafe { UpSafeLazyCell::new(|| Mutex::new(BlockCacheManager::new())) };
+    unsafe { UpSafeLazyCell::new(|| Mutex::new(BlockCacheManager::new(BLOCK_CACHE_SIZE))) };
 
-/// Get the block cache corresponding to the given block id and block device
+/// Get the block cache corresponding to the given block id and block device,
+/// fail if the block is loaded and does not match its checksum
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
-) -> Arc<Mutex<BlockCache>> {
-    #[allow(static_mut_refs)]
+) -> Result<Arc<Mutex<BlockCache>>, Corrupted> {
     BLOCK_CACHE_MANAGER
         .lock()
         .get_block_cache(block_id, block_device)
 }
 /// Sync all block cache to block device
 pub fn block_cache_sync_all() {
-    #[allow(static_mut_refs)]
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
-    }
+    BLOCK_CACHE_MANAGER.lock().sync_all();
+}
+/// Sync the given blocks to block device
+pub fn block_cache_sync(
+    block_device: &Arc<dyn BlockDevice>,
+    block_ids: impl Iterator<Item = usize>,
+) {
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .sync_blocks(block_device, block_ids);
+}
+/// Get the blocks of a device changed by the running transaction
+pub fn block_cache_journaled(block_device: &Arc<dyn BlockDevice>) -> Vec<Arc<Mutex<BlockCache>>> {
+    BLOCK_CACHE_MANAGER.lock().journaled_blocks(block_device)
+}
+/// Check the blocks of a device against `checksums` when they are loaded,
+/// or stop checking them with `None`
+pub fn set_block_cache_checksums(
+    block_device: &Arc<dyn BlockDevice>,
+    checksums: Option<ChecksumArea>,
+) {
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .set_checksums(block_device, checksums);
+}
+/// Set the number of blocks kept in the block cache
+pub fn set_block_cache_capacity(capacity: usize) {
+    BLOCK_CACHE_MANAGER.lock().set_capacity(capacity);
 }
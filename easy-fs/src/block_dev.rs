@@ -1,3 +1,5 @@
+#[cfg(any(test, feature = "std"))]
+use crate::config::BLOCK_SZ;
 use core::any::Any;
 /// Trait for block devices
 /// which reads and writes data in the unit of blocks
@@ -6,4 +8,53 @@ pub trait BlockDevice: Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     ///Write data from buffer to block
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Number of blocks of the device, if known, to find the backup super block
+    fn num_blocks(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A block device in memory, for tests on the host
+#[cfg(any(test, feature = "std"))]
+pub struct RamBlockDevice(std::sync::Mutex<Vec<u8>>);
+
+#[cfg(any(test, feature = "std"))]
+impl RamBlockDevice {
+    /// Create a device of `blocks` zeroed blocks
+    pub fn new(blocks: usize) -> Self {
+        Self::from_image(vec![0; blocks * BLOCK_SZ])
+    }
+    /// Create a device holding an image
+    pub fn from_image(image: Vec<u8>) -> Self {
+        assert_eq!(image.len() % BLOCK_SZ, 0, "Not a whole number of blocks!");
+        Self(std::sync::Mutex::new(image))
+    }
+    /// Copy the contents of the device out
+    pub fn image(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Data of a file for tests on the host, different for each `seed`.
+/// It does not repeat every 256 bytes, so reading the wrong block shows
+#[cfg(any(test, feature = "std"))]
+pub fn test_data(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 251 + seed) as u8).collect()
+}
+
+#[cfg(any(test, feature = "std"))]
+impl BlockDevice for RamBlockDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let image = self.0.lock().unwrap();
+        buf.copy_from_slice(&image[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut image = self.0.lock().unwrap();
+        image[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ].copy_from_slice(buf);
+    }
+
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.0.lock().unwrap().len() / BLOCK_SZ)
+    }
 }
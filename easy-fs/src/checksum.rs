@@ -0,0 +1,131 @@
+//! CRC32C checksums of metadata blocks.
+//!
+//! From version 3, the checksum area after the data bitmap holds a checksum
+//! for every block of the inode bitmap, the inode area and the data bitmap.
+//! A commit updates the checksums of the blocks it changes, so they reach
+//! the disk in the same transaction. Blocks are checked when the block cache
+//! loads them. File data and directories are not covered: data skips the
+//! journal, so its checksums could not stay in step across a crash.
+
+use super::config::{BLOCK_CHECKSUM_COUNT, BLOCK_SZ};
+use super::{BlockDevice, Corrupted, block_cache_journaled, get_block_cache};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// A block of checksums
+type ChecksumBlock = [u32; BLOCK_CHECKSUM_COUNT];
+type DataBlock = [u8; BLOCK_SZ];
+
+/// Lookup table of CRC32C, with the reversed Castagnoli polynomial
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32C of some bytes
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The checksums of the metadata blocks of an image
+#[derive(Clone)]
+pub struct ChecksumArea {
+    /// Blocks which have a checksum
+    covered: Range<usize>,
+    start_block: usize,
+}
+
+impl ChecksumArea {
+    /// Checksums of the `covered` blocks, kept from `start_block`
+    pub fn new(covered: Range<usize>, start_block: usize) -> Self {
+        Self {
+            covered,
+            start_block,
+        }
+    }
+    /// Number of blocks taken by the checksums of `covered` blocks
+    pub fn blocks(covered: usize) -> usize {
+        covered.div_ceil(BLOCK_CHECKSUM_COUNT)
+    }
+    /// Blocks which have a checksum
+    pub fn covered(&self) -> Range<usize> {
+        self.covered.clone()
+    }
+    /// The block holding the checksum of a block and its index there, if it has one
+    pub fn slot(&self, block_id: usize) -> Option<(usize, usize)> {
+        self.covered.contains(&block_id).then(|| {
+            let i = block_id - self.covered.start;
+            (
+                self.start_block + i / BLOCK_CHECKSUM_COUNT,
+                i % BLOCK_CHECKSUM_COUNT,
+            )
+        })
+    }
+    /// The checksum of a block with its current data
+    fn compute(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        block_id: usize,
+    ) -> Result<u32, Corrupted> {
+        Ok(get_block_cache(block_id, Arc::clone(block_device))?
+            .lock()
+            .read(0, |data_block: &DataBlock| crc32c(data_block)))
+    }
+    /// Whether the data of a covered block matches its checksum
+    pub fn verify(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        block_id: usize,
+    ) -> Result<bool, Corrupted> {
+        let (checksum_block, index) = self.slot(block_id).unwrap();
+        let checksum = get_block_cache(checksum_block, Arc::clone(block_device))?
+            .lock()
+            .read(0, |checksums: &ChecksumBlock| checksums[index]);
+        Ok(self.compute(block_device, block_id)? == checksum)
+    }
+    /// Record the checksum of a covered block with its current data
+    pub fn write(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        block_id: usize,
+    ) -> Result<(), Corrupted> {
+        let checksum = self.compute(block_device, block_id)?;
+        let (checksum_block, index) = self.slot(block_id).unwrap();
+        get_block_cache(checksum_block, Arc::clone(block_device))?
+            .lock()
+            .modify(0, |checksums: &mut ChecksumBlock| {
+                checksums[index] = checksum
+            });
+        Ok(())
+    }
+    /// Record the checksums of the covered blocks changed by the running transaction
+    pub fn update(&self, block_device: &Arc<dyn BlockDevice>) -> Result<(), Corrupted> {
+        let block_ids: Vec<usize> = block_cache_journaled(block_device)
+            .iter()
+            .map(|cache| cache.lock().block_id())
+            .filter(|block_id| self.covered.contains(block_id))
+            .collect();
+        for block_id in block_ids {
+            self.write(block_device, block_id)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+//! Entries of a directory
+//!
+//! In images of version 0 a directory is an array of fixed-size `DirEntry`s, and removing
+//! an entry moves the last one into its place. From version 1 each block of a directory is
+//! split into variable-length records like ext2: a record runs up to the next one, a record
+//! with an empty name is free space, and a removed record is merged into the one before it.
+//! Records never cross blocks nor move, so the position of an entry stays valid while
+//! other entries come and go
+
+use super::config::{BLOCK_SZ, NAME_LENGTH_LIMIT_V0};
+use super::{Corrupted, DIRENT_SZ, DirEntry, DiskInode, EasyFileSystem, WriteError};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::iter;
+
+/// Bytes before the name of a record: inode number, record length, name length and a pad
+const RECORD_HEADER_SZ: usize = 8;
+
+/// A data block
+type DataBlock = [u8; BLOCK_SZ];
+
+/// Length of a record holding a name of `name_len` bytes, records are 4-byte aligned
+pub fn record_len(name_len: usize) -> usize {
+    (RECORD_HEADER_SZ + name_len).next_multiple_of(4)
+}
+
+/// The header of a record
+struct Record {
+    inode_number: u32,
+    rec_len: usize,
+    /// 0 for free space
+    name_len: usize,
+}
+
+impl Record {
+    /// Parse the record at `pos` of a block, return `None` if it is malformed
+    fn parse(block: &DataBlock, pos: usize) -> Option<Self> {
+        let header = block.get(pos..pos + RECORD_HEADER_SZ)?;
+        let record = Self {
+            inode_number: u32::from_le_bytes(header[0..4].try_into().unwrap()),
+            rec_len: u16::from_le_bytes([header[4], header[5]]) as usize,
+            name_len: header[6] as usize,
+        };
+        (record.rec_len.is_multiple_of(4)
+            && record.rec_len >= record.used().max(RECORD_HEADER_SZ)
+            && pos + record.rec_len <= BLOCK_SZ)
+            .then_some(record)
+    }
+    /// Write the header at `pos` of a block
+    fn write(&self, block: &mut DataBlock, pos: usize) {
+        block[pos..pos + 4].copy_from_slice(&self.inode_number.to_le_bytes());
+        block[pos + 4..pos + 6].copy_from_slice(&(self.rec_len as u16).to_le_bytes());
+        block[pos + 6] = self.name_len as u8;
+        block[pos + 7] = 0;
+    }
+    /// Bytes taken by the entry, the rest of the record is free
+    fn used(&self) -> usize {
+        if self.name_len == 0 {
+            0
+        } else {
+            record_len(self.name_len)
+        }
+    }
+}
+
+/// Positions of the records of a block, ending with the first malformed one if any
+fn record_positions(block: &DataBlock) -> impl Iterator<Item = usize> + '_ {
+    iter::successors(Some(0), |&pos| {
+        Some(pos + Record::parse(block, pos)?.rec_len)
+    })
+    .take_while(|&pos| pos < BLOCK_SZ)
+}
+
+/// An entry of a directory
+pub struct Entry {
+    /// Position of the entry in the directory
+    pub offset: usize,
+    /// Position to look for the entry after it
+    pub next: usize,
+    pub inode_number: u32,
+    /// `None` if the name is empty, not UTF-8, or holds '/' or NUL
+    pub name: Option<String>,
+}
+
+/// Get a name if it is a valid one
+fn checked_name(bytes: &[u8]) -> Option<String> {
+    let name = core::str::from_utf8(bytes).ok()?;
+    (!name.is_empty() && !name.contains(['/', '\0'])).then(|| String::from(name))
+}
+
+/// Parse the entries of the directory block at `block_offset` of an image of version 1.
+/// Also return the position of the first malformed record, where parsing stops
+pub fn block_entries(block: &DataBlock, block_offset: usize) -> (Vec<Entry>, Option<usize>) {
+    let mut entries = Vec::new();
+    for pos in record_positions(block) {
+        let Some(record) = Record::parse(block, pos) else {
+            return (entries, Some(block_offset + pos));
+        };
+        if record.name_len == 0 {
+            continue;
+        }
+        let name = &block[pos + RECORD_HEADER_SZ..][..record.name_len];
+        entries.push(Entry {
+            offset: block_offset + pos,
+            next: block_offset + pos + record.rec_len,
+            inode_number: record.inode_number,
+            name: checked_name(name),
+        });
+    }
+    (entries, None)
+}
+
+/// Read the first entry of a directory at or after `offset`
+pub fn read_entry(
+    fs: &EasyFileSystem,
+    dir: &DiskInode,
+    offset: usize,
+) -> Result<Option<Entry>, Corrupted> {
+    let block_device = &fs.block_device;
+    if fs.version == 0 {
+        let offset = offset.next_multiple_of(DIRENT_SZ);
+        let mut dirent = DirEntry::empty();
+        if dir.read_at(offset, dirent.as_bytes_mut(), block_device)? != DIRENT_SZ {
+            return Ok(None);
+        }
+        return Ok(Some(Entry {
+            offset,
+            next: offset + DIRENT_SZ,
+            inode_number: dirent.inode_number(),
+            name: dirent.checked_name().map(String::from),
+        }));
+    }
+    // the block is parsed from its head, as `offset` may be inside a merged record
+    let mut block = [0; BLOCK_SZ];
+    let mut block_offset = offset / BLOCK_SZ * BLOCK_SZ;
+    while dir.read_at(block_offset, &mut block, block_device)? == BLOCK_SZ {
+        let (entries, _) = block_entries(&block, block_offset);
+        if let Some(entry) = entries.into_iter().find(|entry| entry.offset >= offset) {
+            return Ok(Some(entry));
+        }
+        block_offset += BLOCK_SZ;
+    }
+    Ok(None)
+}
+
+/// Iterate over the entries of a directory from `offset`, stopping after an error
+pub fn entries<'a>(
+    fs: &'a EasyFileSystem,
+    dir: &'a DiskInode,
+    offset: usize,
+) -> impl Iterator<Item = Result<Entry, Corrupted>> + 'a {
+    // a file has no entries
+    let mut next = dir.is_dir().then_some(offset);
+    iter::from_fn(move || {
+        let entry = read_entry(fs, dir, next?).transpose()?;
+        next = entry.as_ref().ok().map(|entry| entry.next);
+        Some(entry)
+    })
+}
+
+/// Add an entry to a directory, return false if the name is longer than
+/// `EasyFileSystem::name_length_limit`
+pub fn insert_entry(
+    fs: &mut EasyFileSystem,
+    dir: &mut DiskInode,
+    name: &str,
+    inode_number: u32,
+) -> Result<bool, WriteError> {
+    if name.len() > fs.name_length_limit() {
+        return Ok(false);
+    }
+    let block_device = Arc::clone(&fs.block_device);
+    if fs.version == 0 {
+        let offset = dir.size as usize;
+        fs.increase_size(dir, (offset + DIRENT_SZ) as u32)?;
+        let dirent = DirEntry::new(name, inode_number);
+        dir.write_at(offset, dirent.as_bytes(), &block_device)?;
+        return Ok(true);
+    }
+    // the first record with enough free space
+    let need = record_len(name.len());
+    let mut block = [0; BLOCK_SZ];
+    let mut found = None;
+    'blocks: for block_offset in (0..dir.size as usize).step_by(BLOCK_SZ) {
+        dir.read_at(block_offset, &mut block, &block_device)?;
+        for pos in record_positions(&block) {
+            let Some(record) = Record::parse(&block, pos) else {
+                break;
+            };
+            if record.rec_len - record.used() >= need {
+                found = Some((block_offset, pos, record));
+                break 'blocks;
+            }
+        }
+    }
+    let (block_offset, pos, record) = match found {
+        Some(found) => found,
+        None => {
+            // a new block of free space
+            let block_offset = dir.size as usize;
+            fs.increase_size(dir, (block_offset + BLOCK_SZ) as u32)?;
+            block.fill(0);
+            let record = Record {
+                inode_number: 0,
+                rec_len: BLOCK_SZ,
+                name_len: 0,
+            };
+            (block_offset, 0, record)
+        }
+    };
+    // the new record takes the free space of the one found
+    let used = record.used();
+    if used > 0 {
+        Record {
+            rec_len: used,
+            ..record
+        }
+        .write(&mut block, pos);
+    }
+    let pos = pos + used;
+    Record {
+        inode_number,
+        rec_len: record.rec_len - used,
+        name_len: name.len(),
+    }
+    .write(&mut block, pos);
+    block[pos + RECORD_HEADER_SZ..][..name.len()].copy_from_slice(name.as_bytes());
+    dir.write_at(block_offset, &block, &block_device)?;
+    Ok(true)
+}
+
+/// Remove the entry at `offset` of a directory, and free the blocks it no longer needs
+pub fn remove_entry(
+    fs: &mut EasyFileSystem,
+    dir: &mut DiskInode,
+    offset: usize,
+) -> Result<(), Corrupted> {
+    let block_device = Arc::clone(&fs.block_device);
+    let new_size = if fs.version == 0 {
+        // move the last entry into its place
+        let last = dir.size as usize - DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        dir.read_at(last, dirent.as_bytes_mut(), &block_device)?;
+        dir.write_at(offset, dirent.as_bytes(), &block_device)?;
+        last
+    } else {
+        let mut block = [0; BLOCK_SZ];
+        let (block_offset, pos) = (offset / BLOCK_SZ * BLOCK_SZ, offset % BLOCK_SZ);
+        dir.read_at(block_offset, &mut block, &block_device)?;
+        let Some(mut record) = record_positions(&block)
+            .any(|start| start == pos)
+            .then(|| Record::parse(&block, pos))
+            .flatten()
+        else {
+            return Ok(());
+        };
+        let prev = record_positions(&block)
+            .take_while(|&start| start < pos)
+            .last();
+        match prev.and_then(|prev| Some((prev, Record::parse(&block, prev)?))) {
+            // merged into the record before it
+            Some((prev, mut prev_record)) => {
+                prev_record.rec_len += record.rec_len;
+                prev_record.write(&mut block, prev);
+            }
+            // or left as free space at the head of the block
+            None => {
+                record.inode_number = 0;
+                record.name_len = 0;
+                record.write(&mut block, pos);
+            }
+        }
+        dir.write_at(block_offset, &block, &block_device)?;
+        // drop the blocks at the end which are all free space
+        let mut size = dir.size as usize;
+        while size > 0 {
+            dir.read_at(size - BLOCK_SZ, &mut block, &block_device)?;
+            match Record::parse(&block, 0) {
+                Some(record) if record.name_len == 0 && record.rec_len == BLOCK_SZ => {
+                    size -= BLOCK_SZ
+                }
+                _ => break,
+            }
+        }
+        size
+    };
+    for data_block in dir.decrease_size(new_size as u32, &block_device)? {
+        fs.dealloc_data(data_block)?;
+    }
+    Ok(())
+}
+
+/// Rename the entry at `offset` of a directory in place, return false if the name does not fit
+pub fn rename_entry(
+    fs: &EasyFileSystem,
+    dir: &mut DiskInode,
+    offset: usize,
+    name: &str,
+) -> Result<bool, Corrupted> {
+    let block_device = &fs.block_device;
+    if fs.version == 0 {
+        if name.len() > NAME_LENGTH_LIMIT_V0 {
+            return Ok(false);
+        }
+        let mut dirent = DirEntry::empty();
+        dir.read_at(offset, dirent.as_bytes_mut(), block_device)?;
+        let dirent = DirEntry::new(name, dirent.inode_number());
+        dir.write_at(offset, dirent.as_bytes(), block_device)?;
+        return Ok(true);
+    }
+    let mut block = [0; BLOCK_SZ];
+    let (block_offset, pos) = (offset / BLOCK_SZ * BLOCK_SZ, offset % BLOCK_SZ);
+    dir.read_at(block_offset, &mut block, block_device)?;
+    let Some(mut record) = Record::parse(&block, pos) else {
+        return Ok(false);
+    };
+    if name.is_empty() || record_len(name.len()) > record.rec_len {
+        return Ok(false);
+    }
+    record.name_len = name.len();
+    record.write(&mut block, pos);
+    block[pos + RECORD_HEADER_SZ..][..name.len()].copy_from_slice(name.as_bytes());
+    dir.write_at(block_offset, &block, block_device)?;
+    Ok(true)
+}
+
+/// Turn the malformed record at `offset` of a directory and everything after it
+/// in its block into free space
+pub fn clear_records(
+    fs: &EasyFileSystem,
+    dir: &mut DiskInode,
+    offset: usize,
+) -> Result<(), Corrupted> {
+    let block_device = &fs.block_device;
+    let mut block = [0; BLOCK_SZ];
+    let (block_offset, pos) = (offset / BLOCK_SZ * BLOCK_SZ, offset % BLOCK_SZ);
+    dir.read_at(block_offset, &mut block, block_device)?;
+    let prev = record_positions(&block)
+        .take_while(|&start| start < pos)
+        .last();
+    match prev.and_then(|prev| Some((prev, Record::parse(&block, prev)?))) {
+        // the record before it runs to the end of the block
+        Some((prev, mut prev_record)) => {
+            prev_record.rec_len = BLOCK_SZ - prev;
+            prev_record.write(&mut block, prev);
+        }
+        None => Record {
+            inode_number: 0,
+            rec_len: BLOCK_SZ,
+            name_len: 0,
+        }
+        .write(&mut block, 0),
+    }
+    dir.write_at(block_offset, &block, block_device)?;
+    Ok(())
+}
@@ -1,10 +1,16 @@
-use super::config::BLOCK_SZ;
+use super::checksum::ChecksumArea;
+use super::config::{
+    BLOCK_BITS, BLOCK_SZ, EFS_VERSION, EXTENT_BLOCK_COUNT, JOURNAL_BLOCKS, NAME_LENGTH_LIMIT,
+    NAME_LENGTH_LIMIT_V0,
+};
 use super::{
-    Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode, SuperBlock, block_cache_sync_all,
-    get_block_cache,
+    Bitmap, BlockDevice, BlockMapping, Corrupted, DiskInode, DiskInodeType, Inode, Journal,
+    SuperBlock, block_cache_journaled, block_cache_sync_all, get_block_cache,
+    set_block_cache_checksums,
 };
 
 use alloc::sync::Arc;
+use core::fmt;
 use spin::Mutex;
 
 /// An easy file system on block
@@ -16,93 +22,309 @@ pub struct EasyFileSystem {
     pub inode_bitmap: Bitmap,
     ///Data bitmap
     pub data_bitmap: Bitmap,
+    /// The journal, `None` on images made before it, whose metadata is written in place
+    journal: Option<Journal>,
+    /// Version of the on-disk format, which decides the format of directories
+    /// and how new inodes map their blocks
+    pub version: u32,
+    /// The super block the filesystem was opened with
+    pub super_block: SuperBlock,
+    /// Checksums of the metadata blocks, from version 3
+    pub(crate) checksums: Option<ChecksumArea>,
     inode_area_start_block: u32,
     data_area_start_block: u32,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
 
+/// Why an image cannot be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    /// Neither the super block nor its backup is valid
+    BadSuperBlock,
+    /// The image has a version of the format newer than `EFS_VERSION`
+    UnsupportedVersion(u32),
+    /// A block does not match its checksum as the journal is replayed
+    Corrupted(usize),
+}
+
+impl From<Corrupted> for OpenError {
+    fn from(err: Corrupted) -> Self {
+        Self::Corrupted(err.block_id)
+    }
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::BadSuperBlock => write!(f, "no valid easy-fs super block"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported easy-fs version {version}")
+            }
+            Self::Corrupted(block_id) => {
+                write!(f, "block {block_id} does not match its checksum")
+            }
+        }
+    }
+}
+
+/// Why an operation which allocates inodes or blocks failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteError {
+    /// No free inode or data block is left
+    NoSpace,
+    /// A block does not match its checksum
+    Corrupted(usize),
+}
+
+impl From<Corrupted> for WriteError {
+    fn from(err: Corrupted) -> Self {
+        Self::Corrupted(err.block_id)
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::NoSpace => write!(f, "no space left"),
+            Self::Corrupted(block_id) => {
+                write!(f, "block {block_id} does not match its checksum")
+            }
+        }
+    }
+}
+
+/// Sizes in blocks of the areas of a new image
+struct Areas {
+    inode_area_blocks: u32,
+    data_bitmap_blocks: u32,
+    checksum_blocks: u32,
+    data_area_blocks: u32,
+    backup_blocks: u32,
+}
+
+impl Areas {
+    /// Split `total_blocks` into areas, `None` if the metadata leaves no data block
+    fn new(total_blocks: u32, inode_bitmap_blocks: u32, version: u32) -> Option<Self> {
+        let inode_area_blocks =
+            (inode_bitmap_blocks as usize * BLOCK_BITS * core::mem::size_of::<DiskInode>())
+                .div_ceil(BLOCK_SZ) as u32;
+        let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
+        let backup_blocks = (version >= 3) as u32;
+        let data_total_blocks = total_blocks
+            .checked_sub(1 + JOURNAL_BLOCKS as u32 + inode_total_blocks + backup_blocks)?;
+        let data_bitmap_blocks = data_total_blocks.div_ceil(BLOCK_BITS as u32 + 1);
+        let checksum_blocks = if version >= 3 {
+            ChecksumArea::blocks((inode_total_blocks + data_bitmap_blocks) as usize) as u32
+        } else {
+            0
+        };
+        let data_area_blocks = data_total_blocks
+            .checked_sub(data_bitmap_blocks + checksum_blocks)
+            .filter(|&blocks| blocks > 0)?;
+        Some(Self {
+            inode_area_blocks,
+            data_bitmap_blocks,
+            checksum_blocks,
+            data_area_blocks,
+            backup_blocks,
+        })
+    }
+}
+
 impl EasyFileSystem {
+    /// Data blocks of a new image of `total_blocks` with `inode_bitmap_blocks`,
+    /// `None` if its metadata leaves no room for data
+    pub fn data_area_blocks(total_blocks: u32, inode_bitmap_blocks: u32) -> Option<u32> {
+        Areas::new(total_blocks, inode_bitmap_blocks, EFS_VERSION)
+            .map(|areas| areas.data_area_blocks)
+    }
     /// A data block of block size
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
     ) -> Arc<Mutex<Self>> {
-        // calculate block size of areas & create bitmaps
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
-        let inode_num = inode_bitmap.maximum();
-        let inode_area_blocks =
-            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
-        let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
-        let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
-        let data_area_blocks = data_total_blocks - data_bitmap_blocks;
-        let data_bitmap = Bitmap::new(
-            (1 + inode_total_blocks) as usize,
-            data_bitmap_blocks as usize,
-        );
-        let mut efs = Self {
-            block_device: Arc::clone(&block_device),
-            inode_bitmap,
-            data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
-        };
+        Self::create_version(block_device, total_blocks, inode_bitmap_blocks, EFS_VERSION)
+    }
+    /// Create an image of an older `version` of the format
+    pub(crate) fn create_version(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        version: u32,
+    ) -> Arc<Mutex<Self>> {
+        // blocks left from an earlier image are not checked against its checksums
+        set_block_cache_checksums(&block_device, None);
+        let efs = Self::format(block_device, total_blocks, inode_bitmap_blocks, version)
+            .expect("a new image has room for the root, and is not checked against checksums");
+        // write back immediately
+        block_cache_sync_all();
+        set_block_cache_checksums(&efs.block_device, efs.checksums.clone());
+        Arc::new(Mutex::new(efs))
+    }
+    /// Write the areas of a new image
+    fn format(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        version: u32,
+    ) -> Result<Self, WriteError> {
+        let Areas {
+            inode_area_blocks,
+            data_bitmap_blocks,
+            checksum_blocks,
+            data_area_blocks,
+            backup_blocks,
+        } = Areas::new(total_blocks, inode_bitmap_blocks, version)
+            .expect("Image too small for its metadata!");
         // clear all blocks
         for i in 0..total_blocks {
-            get_block_cache(i as usize, Arc::clone(&block_device))
+            get_block_cache(i as usize, Arc::clone(&block_device))?
                 .lock()
-                .modify(0, |data_block: &mut DataBlock| {
+                .modify_data(0, |data_block: &mut DataBlock| {
                     data_block.fill(0);
                 });
         }
-        // initialize SuperBlock
-        get_block_cache(0, Arc::clone(&block_device)).lock().modify(
-            0,
-            |super_block: &mut SuperBlock| {
+        // initialize SuperBlock, and its backup
+        let super_block = get_block_cache(0, Arc::clone(&block_device))?
+            .lock()
+            .modify(0, |super_block: &mut SuperBlock| {
                 super_block.initialize(
                     total_blocks,
                     inode_bitmap_blocks,
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    JOURNAL_BLOCKS as u32,
+                    version,
+                    checksum_blocks,
                 );
-            },
-        );
-        // write back immediately
+                *super_block
+            });
+        if backup_blocks > 0 {
+            get_block_cache(total_blocks as usize - 1, Arc::clone(&block_device))?
+                .lock()
+                .modify(0, |backup: &mut SuperBlock| *backup = super_block);
+        }
+        let mut efs = Self::new(block_device, super_block);
         // create a inode for root node "/"
-        assert_eq!(efs.alloc_inode(), 0);
+        assert_eq!(efs.alloc_inode()?, 0);
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
-        get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
+        let block_mapping = efs.block_mapping();
+        get_block_cache(root_inode_block_id as usize, Arc::clone(&efs.block_device))?
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-                disk_inode.initialize(DiskInodeType::Directory);
+                disk_inode.initialize(DiskInodeType::Directory, block_mapping);
             });
-        block_cache_sync_all();
-        Arc::new(Mutex::new(efs))
+        if let Some(checksums) = &efs.checksums {
+            for block_id in checksums.covered() {
+                checksums.write(&efs.block_device, block_id)?;
+            }
+        }
+        efs.commit()?;
+        Ok(efs)
+    }
+    /// The filesystem described by a super block
+    fn new(block_device: Arc<dyn BlockDevice>, super_block: SuperBlock) -> Self {
+        let journal_blocks = super_block.journal_blocks;
+        let inode_bitmap_start = 1 + journal_blocks;
+        let inode_area_start = inode_bitmap_start + super_block.inode_bitmap_blocks;
+        let data_bitmap_start = inode_area_start + super_block.inode_area_blocks;
+        let checksum_start = data_bitmap_start + super_block.data_bitmap_blocks;
+        let checksums = (super_block.version >= 3).then(|| {
+            ChecksumArea::new(
+                inode_bitmap_start as usize..checksum_start as usize,
+                checksum_start as usize,
+            )
+        });
+        Self {
+            block_device,
+            inode_bitmap: Bitmap::new(
+                inode_bitmap_start as usize,
+                super_block.inode_bitmap_blocks as usize,
+                super_block.inode_bitmap_blocks as usize * BLOCK_BITS,
+            ),
+            data_bitmap: Bitmap::new(
+                data_bitmap_start as usize,
+                super_block.data_bitmap_blocks as usize,
+                super_block.data_area_blocks as usize,
+            ),
+            journal: (journal_blocks > 0).then(|| Journal::new(1)),
+            version: super_block.version,
+            super_block,
+            checksums,
+            inode_area_start_block: inode_area_start,
+            data_area_start_block: checksum_start + super_block.checksum_blocks,
+        }
     }
     /// Open a block device as a filesystem
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
-        // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
-                assert!(super_block.is_valid(), "Error loading EFS!");
-                let inode_total_blocks =
-                    super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
-                    block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
-                    data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
-                        super_block.data_bitmap_blocks as usize,
-                    ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Result<Arc<Mutex<Self>>, OpenError> {
+        let super_block = Self::read_super_block(&block_device)?;
+        let efs = Self::new(block_device, super_block);
+        // finish the transaction interrupted by a crash
+        if let Some(journal) = &efs.journal {
+            journal.replay(&efs.block_device)?;
+        }
+        set_block_cache_checksums(&efs.block_device, efs.checksums.clone());
+        Ok(Arc::new(Mutex::new(efs)))
+    }
+    /// Read the super block of a device, or its backup if the super block is not valid
+    pub fn read_super_block(block_device: &Arc<dyn BlockDevice>) -> Result<SuperBlock, OpenError> {
+        let read = |block_id: usize| {
+            Ok::<_, Corrupted>(
+                get_block_cache(block_id, Arc::clone(block_device))?
+                    .lock()
+                    .read(0, |super_block: &SuperBlock| *super_block),
+            )
+        };
+        let mut super_block = read(0)?;
+        if !super_block.is_valid() {
+            let blocks = block_device.num_blocks().ok_or(OpenError::BadSuperBlock)?;
+            super_block = blocks
+                .checked_sub(1)
+                .map(read)
+                .transpose()?
+                .filter(|backup| {
+                    backup.is_valid()
+                        && backup.backup_blocks() > 0
+                        && backup.total_blocks as usize == blocks
+                })
+                .ok_or(OpenError::BadSuperBlock)?;
+        }
+        if super_block.version > EFS_VERSION {
+            return Err(OpenError::UnsupportedVersion(super_block.version));
+        }
+        Ok(super_block)
+    }
+    /// Commit the metadata changed by the running operation
+    pub fn commit(&self) -> Result<(), Corrupted> {
+        if let Some(checksums) = &self.checksums {
+            checksums.update(&self.block_device)?;
+        }
+        match &self.journal {
+            Some(journal) => journal.commit(&self.block_device),
+            None => block_cache_journaled(&self.block_device)
+                .iter()
+                .for_each(|cache| cache.lock().checkpoint()),
+        }
+        Ok(())
+    }
+    /// The max length of a name in a directory
+    pub fn name_length_limit(&self) -> usize {
+        if self.version == 0 {
+            NAME_LENGTH_LIMIT_V0
+        } else {
+            NAME_LENGTH_LIMIT
+        }
+    }
+    /// How new inodes map their blocks
+    pub fn block_mapping(&self) -> BlockMapping {
+        if self.version < 2 {
+            BlockMapping::Indirect
+        } else {
+            BlockMapping::Extents
+        }
     }
     /// Get the root inode of the filesystem
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
@@ -122,27 +344,106 @@ impl EasyFileSystem {
             (inode_id % inodes_per_block) as usize * inode_size,
         )
     }
+    /// Get inode id by the position of its disk inode
+    pub fn get_inode_id(&self, block_id: u32, block_offset: usize) -> u32 {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        (block_id - self.inode_area_start_block) * inodes_per_block
+            + (block_offset / inode_size) as u32
+    }
     /// Get data block by id
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
         self.data_area_start_block + data_block_id
     }
     /// Allocate a new inode
-    pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+    pub fn alloc_inode(&mut self) -> Result<u32, WriteError> {
+        let inode_id = self.inode_bitmap.alloc(&self.block_device)?;
+        Ok(inode_id.ok_or(WriteError::NoSpace)? as u32)
     }
-    /// Allocate a data block
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    /// Count the data blocks which are not allocated yet
+    pub fn free_data_blocks(&self) -> Result<u32, Corrupted> {
+        let mut free = 0;
+        for bit in 0..self.data_bitmap.maximum() {
+            if !self.data_bitmap.is_allocated(&self.block_device, bit)? {
+                free += 1;
+            }
+        }
+        Ok(free)
     }
-    /// Deallocate a data block
-    pub fn dealloc_data(&mut self, block_id: u32) {
-        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+    /// Deallocate an inode
+    pub fn dealloc_inode(&mut self, inode_id: u32) -> Result<(), Corrupted> {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
+    }
+    /// Allocate a data block, which is cleared to zero
+    pub fn alloc_data(&mut self) -> Result<u32, WriteError> {
+        let bit = self.data_bitmap.alloc(&self.block_device)?;
+        let block_id = bit.ok_or(WriteError::NoSpace)? as u32 + self.data_area_start_block;
+        // the block is free on disk until the transaction commits,
+        // so clearing it needs no journaling
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))?
             .lock()
-            .modify(0, |data_block: &mut DataBlock| {
-                data_block.iter_mut().for_each(|p| {
-                    *p = 0;
-                })
-            });
+            .modify_data(0, |data_block: &mut DataBlock| data_block.fill(0));
+        Ok(block_id)
+    }
+    /// Allocate up to `count` contiguous data blocks, preferring the ones from `goal` on.
+    /// Return the first block and how many were allocated, which are cleared to zero
+    pub fn alloc_data_run(&mut self, goal: u32, count: u32) -> Result<(u32, u32), WriteError> {
+        let goal = goal.saturating_sub(self.data_area_start_block) as usize;
+        let (bit, len) = self
+            .data_bitmap
+            .alloc_contiguous(&self.block_device, goal, count as usize)?
+            .ok_or(WriteError::NoSpace)?;
+        let start = bit as u32 + self.data_area_start_block;
+        for block_id in start..start + len as u32 {
+            get_block_cache(block_id as usize, Arc::clone(&self.block_device))?
+                .lock()
+                .modify_data(0, |data_block: &mut DataBlock| data_block.fill(0));
+        }
+        Ok((start, len as u32))
+    }
+    /// Grow a disk inode to `new_size` bytes, allocating the blocks it needs.
+    /// Fail with `NoSpace` before changing anything if they may not all be free
+    pub fn increase_size(
+        &mut self,
+        disk_inode: &mut DiskInode,
+        new_size: u32,
+    ) -> Result<(), WriteError> {
+        if new_size <= disk_inode.size {
+            return Ok(());
+        }
+        let block_device = Arc::clone(&self.block_device);
+        let mut needed = new_size.div_ceil(BLOCK_SZ as u32) - disk_inode.data_blocks();
+        let most_blocks = match disk_inode.block_mapping() {
+            BlockMapping::Indirect => disk_inode.blocks_num_needed(new_size),
+            // an extent per block at worst, filling the last extent block,
+            // then new ones, and indirect1 and indirect2 themselves
+            BlockMapping::Extents => needed + needed.div_ceil(EXTENT_BLOCK_COUNT as u32) + 2,
+        };
+        if !self
+            .data_bitmap
+            .has_free(&block_device, most_blocks as usize)?
+        {
+            return Err(WriteError::NoSpace);
+        }
+        if disk_inode.block_mapping() == BlockMapping::Indirect {
+            let blocks = (0..disk_inode.blocks_num_needed(new_size))
+                .map(|_| self.alloc_data())
+                .collect::<Result<_, _>>()?;
+            disk_inode.increase_size(new_size, blocks, &block_device)?;
+            return Ok(());
+        }
+        while needed > 0 {
+            let goal = disk_inode.end_block(&block_device)?;
+            let (start, len) = self.alloc_data_run(goal, needed)?;
+            disk_inode.push_extent(start, len, &mut || self.alloc_data(), &block_device)?;
+            needed -= len;
+        }
+        disk_inode.size = new_size;
+        Ok(())
+    }
+    /// Deallocate a data block
+    pub fn dealloc_data(&mut self, block_id: u32) -> Result<(), Corrupted> {
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
@@ -0,0 +1,593 @@
+//! Consistency check and repair of an easy-fs image
+
+use super::config::{
+    BLOCK_SZ, EXTENT_BLOCK_COUNT, EXTENT_INDIRECT1_BOUND, INODE_DIRECT_COUNT, INODE_EXTENT_COUNT,
+    INODE_INDIRECT_COUNT,
+};
+use super::directory::{self, Entry};
+use super::{
+    BlockDevice, BlockMapping, DIRENT_SZ, DirEntry, DiskInode, DiskInodeType, EasyFileSystem,
+    SuperBlock, WriteError, block_cache_sync_all, get_block_cache, set_block_cache_checksums,
+};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+use core::ops::Range;
+
+/// Passes of repair before giving up, as fixing a directory may free blocks
+const MAX_PASSES: usize = 4;
+
+/// A disk inode read without trusting its type
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawInode {
+    size: u32,
+    direct: [u32; INODE_DIRECT_COUNT],
+    indirect1: u32,
+    indirect2: u32,
+    r#type: u8,
+    block_mapping: u8,
+}
+
+/// A indirect block
+type IndirectBlock = [u32; INODE_INDIRECT_COUNT];
+/// A block of extents
+type ExtentBlock = [[u32; 2]; EXTENT_BLOCK_COUNT];
+
+/// An inconsistency found in an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The copy of the super block in `block` is not valid, the other one is used
+    BadSuperBlock { block: u32 },
+    /// A metadata block does not match its checksum
+    BadChecksum { block: u32 },
+    /// An inode points to a block out of the data area,
+    /// only its first `size` bytes are reachable
+    BadBlockPointer { inode: u32, block: u32, size: u32 },
+    /// The size of a directory is not a multiple of the entry size,
+    /// or of the block size from version 1
+    BadDirSize { inode: u32, size: u32 },
+    /// A record of a directory has a bad length, which hides the records after it in its block
+    BadRecord { dir: u32, offset: usize },
+    /// A block is referenced by two inodes
+    DoubleAllocated { block: u32, inodes: [u32; 2] },
+    /// A block in use is free in the data bitmap
+    UnallocatedBlock { block: u32 },
+    /// A block allocated in the data bitmap is not used
+    LeakedBlock { block: u32 },
+    /// A linked inode is free in the inode bitmap
+    UnallocatedInode { inode: u32 },
+    /// An allocated inode is not linked from any directory
+    OrphanInode { inode: u32 },
+    /// The directory entry at `offset` has an empty, unterminated or non UTF-8 name
+    InvalidName { dir: u32, offset: usize, inode: u32 },
+    /// The directory entry at `offset` links an inode out of range or linked already
+    BadEntry { dir: u32, offset: usize, inode: u32 },
+}
+
+impl Problem {
+    /// Whether the problem can be repaired safely
+    pub fn repairable(&self) -> bool {
+        !matches!(self, Self::DoubleAllocated { .. })
+    }
+    /// Order of repairs: inodes are truncated before the bitmaps are fixed,
+    /// bitmaps before anything is allocated, and entries are removed from the last one
+    fn repair_order(&self) -> (u8, Reverse<usize>) {
+        match *self {
+            Self::BadSuperBlock { .. }
+            | Self::BadChecksum { .. }
+            | Self::BadBlockPointer { .. }
+            | Self::BadDirSize { .. }
+            | Self::BadRecord { .. } => (0, Reverse(0)),
+            Self::UnallocatedBlock { .. } | Self::UnallocatedInode { .. } => (1, Reverse(0)),
+            Self::LeakedBlock { .. } => (2, Reverse(0)),
+            Self::InvalidName { .. } => (3, Reverse(0)),
+            Self::BadEntry { offset, .. } => (4, Reverse(offset)),
+            Self::OrphanInode { .. } => (5, Reverse(0)),
+            Self::DoubleAllocated { .. } => (6, Reverse(0)),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::BadSuperBlock { block } => write!(f, "super block in block {block} is not valid"),
+            Self::BadChecksum { block } => write!(f, "block {block} does not match its checksum"),
+            Self::BadBlockPointer { inode, block, size } => write!(
+                f,
+                "inode {inode} points to block {block} out of the data area, {size} bytes valid"
+            ),
+            Self::BadDirSize { inode, .. } => {
+                write!(f, "directory {inode} ends with a partial entry")
+            }
+            Self::BadRecord { dir, offset } => {
+                write!(f, "record at {offset} of directory {dir} has a bad length")
+            }
+            Self::DoubleAllocated { block, inodes } => {
+                write!(
+                    f,
+                    "block {block} is used by inodes {} and {}",
+                    inodes[0], inodes[1]
+                )
+            }
+            Self::UnallocatedBlock { block } => {
+                write!(f, "block {block} is used but free in the data bitmap")
+            }
+            Self::LeakedBlock { block } => write!(f, "block {block} is allocated but unused"),
+            Self::UnallocatedInode { inode } => {
+                write!(f, "inode {inode} is linked but free in the inode bitmap")
+            }
+            Self::OrphanInode { inode } => write!(f, "inode {inode} is allocated but unreachable"),
+            Self::InvalidName { dir, offset, .. } => {
+                write!(
+                    f,
+                    "entry at {offset} of directory {dir} has an invalid name"
+                )
+            }
+            Self::BadEntry { dir, offset, inode } => {
+                write!(
+                    f,
+                    "entry at {offset} of directory {dir} links bad inode {inode}"
+                )
+            }
+        }
+    }
+}
+
+/// Name given to an entry which is renamed or relinked under the root
+fn lost_name(inode: u32) -> String {
+    format!("#{inode}")
+}
+
+/// Check an image, and repair what can be repaired safely if `repair` is set.
+/// Return the problems found, or `None` if it is not a valid easy-fs image
+pub fn check(block_device: Arc<dyn BlockDevice>, repair: bool) -> Option<Vec<Problem>> {
+    // replay the journal before looking at anything
+    let efs = EasyFileSystem::open(Arc::clone(&block_device)).ok()?;
+    let mut fs = efs.lock();
+    // checksums are checked as problems rather than when blocks are loaded
+    set_block_cache_checksums(&block_device, None);
+    let inode_count = fs
+        .inode_bitmap
+        .maximum()
+        .min(fs.super_block.inode_area_blocks as usize * BLOCK_SZ / size_of::<DiskInode>())
+        as u32;
+    let data_start = fs.get_data_block_id(0);
+    let data_area = data_start..data_start + fs.super_block.data_area_blocks;
+    if read_inode(&fs, 0).r#type != DiskInodeType::Directory as u8 {
+        set_block_cache_checksums(&block_device, fs.checksums.clone());
+        return None;
+    }
+
+    let mut found: Vec<Problem> = Vec::new();
+    for _ in 0..MAX_PASSES {
+        let mut problems = Checker::new(&fs, inode_count, data_area.clone()).run();
+        for problem in problems.iter() {
+            if !found.contains(problem) {
+                found.push(problem.clone());
+            }
+        }
+        if !repair || !problems.iter().any(Problem::repairable) {
+            break;
+        }
+        problems.sort_by_key(Problem::repair_order);
+        for problem in problems.iter() {
+            fix(&mut fs, problem);
+            loaded(fs.commit());
+        }
+    }
+    if repair {
+        block_cache_sync_all();
+    }
+    set_block_cache_checksums(&block_device, fs.checksums.clone());
+    Some(found)
+}
+
+/// The result of loading blocks, which cannot fail while fsck runs
+/// as checksums are checked as problems rather than when blocks are loaded
+fn loaded<T, E: fmt::Debug>(result: Result<T, E>) -> T {
+    result.expect("blocks are not checked against checksums during fsck")
+}
+
+/// Read an inode by id
+fn read_inode(fs: &EasyFileSystem, inode: u32) -> RawInode {
+    let (block_id, offset) = fs.get_disk_inode_pos(inode);
+    loaded(get_block_cache(
+        block_id as usize,
+        Arc::clone(&fs.block_device),
+    ))
+    .lock()
+    .read(offset, |raw: &RawInode| *raw)
+}
+
+/// Walk of an image from the root directory
+struct Checker<'a> {
+    fs: &'a EasyFileSystem,
+    inode_count: u32,
+    data_area: Range<u32>,
+    /// The inode using each block
+    owners: BTreeMap<u32, u32>,
+    /// Inodes linked from a directory
+    linked: BTreeSet<u32>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(fs: &'a EasyFileSystem, inode_count: u32, data_area: Range<u32>) -> Self {
+        Self {
+            fs,
+            inode_count,
+            data_area,
+            owners: BTreeMap::new(),
+            linked: BTreeSet::new(),
+            problems: Vec::new(),
+        }
+    }
+    fn run(mut self) -> Vec<Problem> {
+        let block_device = &self.fs.block_device;
+        let super_block = &self.fs.super_block;
+        if super_block.backup_blocks() > 0 {
+            for block in [0, super_block.total_blocks - 1] {
+                let valid = loaded(get_block_cache(block as usize, Arc::clone(block_device)))
+                    .lock()
+                    .read(0, SuperBlock::is_valid);
+                if !valid {
+                    self.problems.push(Problem::BadSuperBlock { block });
+                }
+            }
+        }
+        if let Some(checksums) = &self.fs.checksums {
+            for block in checksums.covered() {
+                if !loaded(checksums.verify(block_device, block)) {
+                    let block = block as u32;
+                    self.problems.push(Problem::BadChecksum { block });
+                }
+            }
+        }
+        self.linked.insert(0);
+        if !loaded(self.fs.inode_bitmap.is_allocated(block_device, 0)) {
+            self.problems.push(Problem::UnallocatedInode { inode: 0 });
+        }
+        self.walk(0);
+        for inode in 1..self.inode_count {
+            if loaded(
+                self.fs
+                    .inode_bitmap
+                    .is_allocated(block_device, inode as usize),
+            ) && !self.linked.contains(&inode)
+            {
+                // its own subtree is not orphaned
+                self.problems.push(Problem::OrphanInode { inode });
+                self.linked.insert(inode);
+                self.walk(inode);
+            }
+        }
+        for bit in 0..self.fs.data_bitmap.maximum() {
+            let block = self.data_area.start + bit as u32;
+            if loaded(self.fs.data_bitmap.is_allocated(block_device, bit))
+                && !self.owners.contains_key(&block)
+            {
+                self.problems.push(Problem::LeakedBlock { block });
+            }
+        }
+        self.problems
+    }
+    /// Walk an inode and everything under it
+    fn walk(&mut self, inode: u32) {
+        let mut pending = vec![inode];
+        while let Some(inode) = pending.pop() {
+            let raw = read_inode(self.fs, inode);
+            let mut blocks = Vec::new();
+            let mut size = raw.size;
+            if let Err(block) = self.collect(inode, &raw, &mut blocks) {
+                size = (blocks.len() * BLOCK_SZ) as u32;
+                self.problems
+                    .push(Problem::BadBlockPointer { inode, block, size });
+            }
+            if raw.r#type != DiskInodeType::Directory as u8 {
+                continue;
+            }
+            for entry in self.read_entries(inode, size, &blocks) {
+                let (dir, offset, child) = (inode, entry.offset, entry.inode_number);
+                if entry.name.is_none() {
+                    let inode = child;
+                    self.problems
+                        .push(Problem::InvalidName { dir, offset, inode });
+                }
+                if child >= self.inode_count || !self.linked.insert(child) {
+                    let inode = child;
+                    self.problems.push(Problem::BadEntry { dir, offset, inode });
+                    continue;
+                }
+                if !loaded(
+                    self.fs
+                        .inode_bitmap
+                        .is_allocated(&self.fs.block_device, child as usize),
+                ) {
+                    self.problems
+                        .push(Problem::UnallocatedInode { inode: child });
+                }
+                pending.push(child);
+            }
+        }
+    }
+    /// Read the entries of directory `dir` of `size` bytes in `blocks`
+    fn read_entries(&mut self, dir: u32, mut size: u32, blocks: &[u32]) -> Vec<Entry> {
+        let read_block = |block: u32| {
+            loaded(get_block_cache(
+                block as usize,
+                Arc::clone(&self.fs.block_device),
+            ))
+            .lock()
+            .read(0, |data_block: &[u8; BLOCK_SZ]| *data_block)
+        };
+        let unit = if self.fs.version == 0 {
+            DIRENT_SZ
+        } else {
+            BLOCK_SZ
+        };
+        if !(size as usize).is_multiple_of(unit) {
+            size -= (size as usize % unit) as u32;
+            self.problems.push(Problem::BadDirSize { inode: dir, size });
+        }
+        let mut entries = Vec::new();
+        if self.fs.version == 0 {
+            for offset in (0..size as usize).step_by(DIRENT_SZ) {
+                let mut dirent = DirEntry::empty();
+                let data_block = read_block(blocks[offset / BLOCK_SZ]);
+                dirent
+                    .as_bytes_mut()
+                    .copy_from_slice(&data_block[offset % BLOCK_SZ..][..DIRENT_SZ]);
+                entries.push(Entry {
+                    offset,
+                    next: offset + DIRENT_SZ,
+                    inode_number: dirent.inode_number(),
+                    name: dirent.checked_name().map(String::from),
+                });
+            }
+            return entries;
+        }
+        for (i, &block) in blocks.iter().take(size as usize / BLOCK_SZ).enumerate() {
+            let (block_entries, bad) = directory::block_entries(&read_block(block), i * BLOCK_SZ);
+            entries.extend(block_entries);
+            if let Some(offset) = bad {
+                self.problems.push(Problem::BadRecord { dir, offset });
+            }
+        }
+        entries
+    }
+    /// Collect the data blocks of an inode in order, and claim them with its index blocks.
+    /// Stop at the first pointer out of the data area and return it
+    fn collect(&mut self, inode: u32, raw: &RawInode, blocks: &mut Vec<u32>) -> Result<(), u32> {
+        let total = (raw.size as usize).div_ceil(BLOCK_SZ);
+        if raw.block_mapping == BlockMapping::Extents as u8 {
+            return self.collect_extents(inode, raw, total, blocks);
+        }
+        for &block in raw.direct.iter().take(total) {
+            self.claim(self.valid(block)?, inode);
+            blocks.push(block);
+        }
+        if total <= INODE_DIRECT_COUNT {
+            return Ok(());
+        }
+        let rest = total - INODE_DIRECT_COUNT;
+        self.collect_indirect(
+            inode,
+            &[raw.indirect1],
+            rest.min(INODE_INDIRECT_COUNT),
+            blocks,
+        )?;
+        if rest <= INODE_INDIRECT_COUNT {
+            return Ok(());
+        }
+        let rest = rest - INODE_INDIRECT_COUNT;
+        let indirect2 = self.read_indirect(self.valid(raw.indirect2)?);
+        for (i, &indirect1) in indirect2
+            .iter()
+            .take(rest.div_ceil(INODE_INDIRECT_COUNT))
+            .enumerate()
+        {
+            let count = (rest - i * INODE_INDIRECT_COUNT).min(INODE_INDIRECT_COUNT);
+            if i == 0 {
+                self.collect_indirect(inode, &[raw.indirect2, indirect1], count, blocks)?;
+            } else {
+                self.collect_indirect(inode, &[indirect1], count, blocks)?;
+            }
+        }
+        Ok(())
+    }
+    /// Collect `count` data blocks listed in the last of `index_blocks`,
+    /// which are claimed once they lead to a valid data block
+    fn collect_indirect(
+        &mut self,
+        inode: u32,
+        index_blocks: &[u32],
+        count: usize,
+        blocks: &mut Vec<u32>,
+    ) -> Result<(), u32> {
+        let index = *index_blocks.last().unwrap();
+        let indirect = self.read_indirect(self.valid(index)?);
+        for (i, &block) in indirect.iter().take(count).enumerate() {
+            self.valid(block)?;
+            if i == 0 {
+                for &index_block in index_blocks {
+                    self.claim(index_block, inode);
+                }
+            }
+            self.claim(block, inode);
+            blocks.push(block);
+        }
+        Ok(())
+    }
+    /// `collect` for an inode of extents, an extent of no blocks counts as a bad pointer.
+    /// The extents past the `total` data blocks are ignored
+    fn collect_extents(
+        &mut self,
+        inode: u32,
+        raw: &RawInode,
+        total: usize,
+        blocks: &mut Vec<u32>,
+    ) -> Result<(), u32> {
+        // index blocks to claim along with the next valid extent
+        let mut index_blocks = Vec::new();
+        let mut extent_block: ExtentBlock = [[0; 2]; EXTENT_BLOCK_COUNT];
+        let mut indirect2: IndirectBlock = [0; INODE_INDIRECT_COUNT];
+        let mut e = 0;
+        while blocks.len() < total {
+            let [start, len] = if e < INODE_EXTENT_COUNT {
+                raw.direct.as_chunks::<2>().0[e]
+            } else {
+                let i = e - INODE_EXTENT_COUNT;
+                if i == 0 {
+                    index_blocks.push(self.valid(raw.indirect1)?);
+                    extent_block = self.read_extents(raw.indirect1);
+                } else if e >= EXTENT_INDIRECT1_BOUND {
+                    let j = e - EXTENT_INDIRECT1_BOUND;
+                    if j == 0 {
+                        index_blocks.push(self.valid(raw.indirect2)?);
+                        indirect2 = self.read_indirect(raw.indirect2);
+                    }
+                    if j.is_multiple_of(EXTENT_BLOCK_COUNT) {
+                        let block = *indirect2.get(j / EXTENT_BLOCK_COUNT).ok_or(0u32)?;
+                        index_blocks.push(self.valid(block)?);
+                        extent_block = self.read_extents(block);
+                    }
+                }
+                extent_block[i % EXTENT_BLOCK_COUNT]
+            };
+            let len = (len as usize).min(total - blocks.len()) as u32;
+            self.valid(start)?;
+            if len == 0 || start as u64 + len as u64 > self.data_area.end as u64 {
+                return Err(start);
+            }
+            for index_block in index_blocks.drain(..) {
+                self.claim(index_block, inode);
+            }
+            for block in start..start + len {
+                self.claim(block, inode);
+                blocks.push(block);
+            }
+            e += 1;
+        }
+        Ok(())
+    }
+    fn valid(&self, block: u32) -> Result<u32, u32> {
+        if self.data_area.contains(&block) {
+            Ok(block)
+        } else {
+            Err(block)
+        }
+    }
+    fn read_indirect(&self, block: u32) -> IndirectBlock {
+        loaded(get_block_cache(
+            block as usize,
+            Arc::clone(&self.fs.block_device),
+        ))
+        .lock()
+        .read(0, |indirect: &IndirectBlock| *indirect)
+    }
+    fn read_extents(&self, block: u32) -> ExtentBlock {
+        loaded(get_block_cache(
+            block as usize,
+            Arc::clone(&self.fs.block_device),
+        ))
+        .lock()
+        .read(0, |extent_block: &ExtentBlock| *extent_block)
+    }
+    /// Record the use of a block by an inode
+    fn claim(&mut self, block: u32, inode: u32) {
+        if let Some(&owner) = self.owners.get(&block) {
+            let inodes = [owner, inode];
+            self.problems
+                .push(Problem::DoubleAllocated { block, inodes });
+            return;
+        }
+        self.owners.insert(block, inode);
+        let bit = (block - self.data_area.start) as usize;
+        if !loaded(self.fs.data_bitmap.is_allocated(&self.fs.block_device, bit)) {
+            self.problems.push(Problem::UnallocatedBlock { block });
+        }
+    }
+}
+
+/// Repair a problem
+fn fix(fs: &mut EasyFileSystem, problem: &Problem) {
+    let block_device = Arc::clone(&fs.block_device);
+    let inode_cache = |fs: &EasyFileSystem, inode: u32| {
+        let (block_id, offset) = fs.get_disk_inode_pos(inode);
+        (
+            loaded(get_block_cache(
+                block_id as usize,
+                Arc::clone(&block_device),
+            )),
+            offset,
+        )
+    };
+    match *problem {
+        Problem::BadSuperBlock { block } => {
+            let super_block = fs.super_block;
+            let cache = loaded(get_block_cache(block as usize, Arc::clone(&block_device)));
+            let mut cache = cache.lock();
+            cache.modify(0, |data: &mut [u8; BLOCK_SZ]| data.fill(0));
+            cache.modify(0, |copy: &mut SuperBlock| *copy = super_block);
+        }
+        Problem::BadChecksum { block } => {
+            let checksums = fs.checksums.as_ref().unwrap();
+            loaded(checksums.write(&block_device, block as usize));
+        }
+        Problem::BadBlockPointer { inode, size, .. } | Problem::BadDirSize { inode, size } => {
+            let (cache, offset) = inode_cache(fs, inode);
+            cache
+                .lock()
+                .modify(offset, |raw: &mut RawInode| raw.size = size);
+        }
+        Problem::UnallocatedBlock { block } => {
+            let bit = block - fs.get_data_block_id(0);
+            loaded(fs.data_bitmap.set(&block_device, bit as usize));
+        }
+        Problem::LeakedBlock { block } => loaded(fs.dealloc_data(block)),
+        Problem::UnallocatedInode { inode } => {
+            loaded(fs.inode_bitmap.set(&block_device, inode as usize))
+        }
+        Problem::BadRecord { dir, offset } => {
+            let (cache, block_offset) = inode_cache(fs, dir);
+            cache.lock().modify(block_offset, |dir: &mut DiskInode| {
+                loaded(directory::clear_records(fs, dir, offset));
+            });
+        }
+        Problem::InvalidName { dir, offset, inode } => {
+            // or unlinked if the new name does not fit, to be relinked as an orphan
+            let (cache, block_offset) = inode_cache(fs, dir);
+            cache.lock().modify(block_offset, |dir: &mut DiskInode| {
+                if !loaded(directory::rename_entry(fs, dir, offset, &lost_name(inode))) {
+                    loaded(directory::remove_entry(fs, dir, offset));
+                }
+            });
+        }
+        Problem::BadEntry { dir, offset, .. } => {
+            let (cache, block_offset) = inode_cache(fs, dir);
+            cache.lock().modify(block_offset, |dir: &mut DiskInode| {
+                loaded(directory::remove_entry(fs, dir, offset));
+            });
+        }
+        Problem::OrphanInode { inode } => {
+            // link it under the root
+            let (cache, block_offset) = inode_cache(fs, 0);
+            cache.lock().modify(block_offset, |root: &mut DiskInode| {
+                // lost names are short enough for any version,
+                // and the inode stays an orphan if the root cannot grow
+                let inserted = directory::insert_entry(fs, root, &lost_name(inode), inode);
+                if inserted != Err(WriteError::NoSpace) {
+                    loaded(inserted);
+                }
+            });
+        }
+        Problem::DoubleAllocated { .. } => {}
+    }
+}
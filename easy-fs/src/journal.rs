@@ -0,0 +1,95 @@
+//! Write-ahead journal for metadata.
+//!
+//! Every high-level operation is a transaction. Blocks it changes through
+//! `BlockCache::modify` stay in the cache until `commit`. A commit copies
+//! them into the journal area, then writes the header listing their home
+//! block ids, which is the commit point. After that the blocks are written
+//! home and the header is cleared. Replaying a committed header after a
+//! crash finishes the transaction, and an uncommitted one is ignored, so
+//! the metadata on disk is always from before or after the operation.
+//!
+//! File data skips the journal, like ext4's `data=writeback` mode. A
+//! transaction changing more blocks than the journal holds is committed in
+//! several parts, and only each part is atomic then.
+
+use super::block_cache::BlockCache;
+use super::config::{BLOCK_SZ, JOURNAL_CAPACITY, JOURNAL_MAGIC};
+use super::{BlockDevice, Corrupted, block_cache_journaled, get_block_cache};
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// The header block: magic, number of blocks, then their home block ids
+type JournalHeader = [u32; BLOCK_SZ / 4];
+type DataBlock = [u8; BLOCK_SZ];
+
+/// The journal area of a filesystem
+pub struct Journal {
+    start_block: usize,
+}
+
+impl Journal {
+    /// The journal area starting at `start_block`
+    pub fn new(start_block: usize) -> Self {
+        Self { start_block }
+    }
+    fn write_header(&self, header: &JournalHeader, block_device: &Arc<dyn BlockDevice>) {
+        let mut block = [0u8; BLOCK_SZ];
+        for (bytes, word) in block.as_chunks_mut::<4>().0.iter_mut().zip(header.iter()) {
+            *bytes = word.to_le_bytes();
+        }
+        block_device.write_block(self.start_block, &block);
+    }
+    fn read_header(&self, block_device: &Arc<dyn BlockDevice>) -> JournalHeader {
+        let mut block = [0u8; BLOCK_SZ];
+        block_device.read_block(self.start_block, &mut block);
+        let mut header = [0u32; BLOCK_SZ / 4];
+        for (word, bytes) in header.iter_mut().zip(block.as_chunks::<4>().0) {
+            *word = u32::from_le_bytes(*bytes);
+        }
+        header
+    }
+    /// Commit the blocks changed by the running transaction and write them home
+    pub fn commit(&self, block_device: &Arc<dyn BlockDevice>) {
+        let caches = block_cache_journaled(block_device);
+        for part in caches.chunks(JOURNAL_CAPACITY) {
+            self.commit_part(part, block_device);
+        }
+    }
+    /// Commit at most `JOURNAL_CAPACITY` blocks as one transaction
+    fn commit_part(&self, caches: &[Arc<Mutex<BlockCache>>], block_device: &Arc<dyn BlockDevice>) {
+        let mut header: JournalHeader = [0; BLOCK_SZ / 4];
+        header[0] = JOURNAL_MAGIC;
+        header[1] = caches.len() as u32;
+        for (i, cache) in caches.iter().enumerate() {
+            let cache = cache.lock();
+            header[2 + i] = cache.block_id() as u32;
+            block_device.write_block(self.start_block + 1 + i, cache.data());
+        }
+        // commit point
+        self.write_header(&header, block_device);
+        for cache in caches.iter() {
+            cache.lock().checkpoint();
+        }
+        self.write_header(&[0; BLOCK_SZ / 4], block_device);
+    }
+    /// Finish the transaction committed before a crash, if any
+    pub fn replay(&self, block_device: &Arc<dyn BlockDevice>) -> Result<(), Corrupted> {
+        let header = self.read_header(block_device);
+        let count = header[1] as usize;
+        if header[0] != JOURNAL_MAGIC || count == 0 || count > JOURNAL_CAPACITY {
+            return Ok(());
+        }
+        let mut block = [0u8; BLOCK_SZ];
+        for (i, &block_id) in header[2..2 + count].iter().enumerate() {
+            block_device.read_block(self.start_block + 1 + i, &mut block);
+            let cache = get_block_cache(block_id as usize, Arc::clone(block_device))?;
+            let mut cache = cache.lock();
+            cache.modify_data(0, |data_block: &mut DataBlock| {
+                data_block.copy_from_slice(&block)
+            });
+            cache.sync();
+        }
+        self.write_header(&[0; BLOCK_SZ / 4], block_device);
+        Ok(())
+    }
+}
@@ -1,10 +1,13 @@
+use crate::checksum::crc32c;
 use crate::config::*;
-use crate::{BlockDevice, get_block_cache};
+use crate::{BlockDevice, Corrupted, WriteError, get_block_cache};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
+use core::ops::Range;
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SuperBlock {
     magic: u32,
     pub total_blocks: u32,
@@ -12,6 +15,14 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// Blocks of the journal area, which follows the super block
+    pub journal_blocks: u32,
+    /// Version of the on-disk format, 0 in images made before it was recorded
+    pub version: u32,
+    /// Blocks of the checksum area, which follows the data bitmap, from version 3
+    pub checksum_blocks: u32,
+    /// Checksum of the fields above, from version 3
+    checksum: u32,
 }
 impl fmt::Debug for SuperBlock {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -21,11 +32,15 @@ impl fmt::Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("journal_blocks", &self.journal_blocks)
+            .field("version", &self.version)
+            .field("checksum_blocks", &self.checksum_blocks)
             .finish()
     }
 }
 impl SuperBlock {
     /// Initialize a super block
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -33,6 +48,9 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        journal_blocks: u32,
+        version: u32,
+        checksum_blocks: u32,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -41,11 +59,41 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            journal_blocks,
+            version,
+            checksum_blocks,
+            checksum: 0,
+        };
+        if version >= 3 {
+            self.checksum = self.compute_checksum();
         }
     }
-    /// Check if a super block is valid using efs magic
+    fn compute_checksum(&self) -> u32 {
+        let len = core::mem::offset_of!(Self, checksum);
+        crc32c(unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, len) })
+    }
+    /// Blocks of the backup super block at the end of the image
+    pub fn backup_blocks(&self) -> u32 {
+        (self.version >= 3) as u32
+    }
+    /// Check if a super block is valid using efs magic, its checksum from version 3,
+    /// and that its areas add up to the whole image
     pub fn is_valid(&self) -> bool {
+        let blocks = [
+            1,
+            self.journal_blocks,
+            self.inode_bitmap_blocks,
+            self.inode_area_blocks,
+            self.data_bitmap_blocks,
+            self.checksum_blocks,
+            self.data_area_blocks,
+            self.backup_blocks(),
+        ]
+        .iter()
+        .try_fold(0u32, |sum, &blocks| sum.checked_add(blocks));
         self.magic == EFS_MAGIC
+            && (self.version < 3 || self.checksum == self.compute_checksum())
+            && blocks == Some(self.total_blocks)
     }
 }
 
@@ -56,11 +104,29 @@ pub enum DiskInodeType {
     Directory,
 }
 
+/// How a disk inode maps its data blocks
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BlockMapping {
+    /// A pointer per block in `direct`, `indirect1` and `indirect2`
+    Indirect,
+    /// Runs of contiguous blocks, the first ones in the place of `direct`,
+    /// then in the extent block of `indirect1` and the ones under `indirect2`
+    Extents,
+}
+
 /// A indirect block
 type IndirectBlock = [u32; INODE_INDIRECT_COUNT];
 /// A data block
 type DataBlock = [u8; BLOCK_SZ];
+/// A run of contiguous blocks, as its first block and length
+type Extent = [u32; 2];
+/// A block of extents
+type ExtentBlock = [Extent; EXTENT_BLOCK_COUNT];
 /// A disk inode
+///
+/// The extents of an inode cover exactly its data blocks from the first one,
+/// anything past them is left over by a shrink and never read
 #[repr(C)]
 pub struct DiskInode {
     pub size: u32,
@@ -68,16 +134,23 @@ pub struct DiskInode {
     pub indirect1: u32,
     pub indirect2: u32,
     r#type: DiskInodeType,
+    /// 0 in inodes made before version 2, which all use block pointers
+    block_mapping: BlockMapping,
 }
 impl DiskInode {
     /// Initialize a disk inode, as well as all direct inodes under it
     /// indirect1 and indirect2 block are allocated only when they are needed
-    pub fn initialize(&mut self, r#type: DiskInodeType) {
+    pub fn initialize(&mut self, r#type: DiskInodeType, block_mapping: BlockMapping) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
         self.r#type = r#type;
+        self.block_mapping = block_mapping;
+    }
+    /// How this inode maps its data blocks
+    pub fn block_mapping(&self) -> BlockMapping {
+        self.block_mapping
     }
     /// Whether this inode is a directory
     pub fn is_dir(&self) -> bool {
@@ -118,29 +191,232 @@ impl DiskInode {
         Self::total_blocks(new_size) - Self::total_blocks(self.size)
     }
     /// Get id of block given inner id
-    pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+    pub fn get_block_id(
+        &self,
+        inner_id: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<u32, Corrupted> {
+        if self.block_mapping == BlockMapping::Extents {
+            return Ok(self.block_ids(inner_id..inner_id + 1, block_device)?[0]);
+        }
         let inner_id = inner_id as usize;
-        if inner_id < INODE_DIRECT_COUNT {
+        Ok(if inner_id < INODE_DIRECT_COUNT {
             self.direct[inner_id]
         } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))?
                 .lock()
                 .read(0, |indirect_block: &IndirectBlock| {
                     indirect_block[inner_id - INODE_DIRECT_COUNT]
                 })
         } else {
             let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
                 .lock()
                 .read(0, |indirect2: &IndirectBlock| {
                     indirect2[last / INODE_INDIRECT1_COUNT]
                 });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
+            get_block_cache(indirect1 as usize, Arc::clone(block_device))?
                 .lock()
                 .read(0, |indirect1: &IndirectBlock| {
                     indirect1[last % INODE_INDIRECT1_COUNT]
                 })
+        })
+    }
+
+    /// Get ids of the blocks given a range of inner ids, walking the extents only once
+    pub fn block_ids(
+        &self,
+        range: Range<u32>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<u32>, Corrupted> {
+        if self.block_mapping == BlockMapping::Indirect {
+            return range
+                .map(|inner_id| self.get_block_id(inner_id, block_device))
+                .collect();
+        }
+        let mut v = Vec::with_capacity(range.len());
+        let mut first = 0;
+        for [start, len] in self.extents(block_device)? {
+            let ids = range.start.max(first)..range.end.min(first + len);
+            v.extend(ids.map(|inner_id| start + inner_id - first));
+            first += len;
+        }
+        Ok(v)
+    }
+    /// The extents covering the data blocks
+    fn extents(&self, block_device: &Arc<dyn BlockDevice>) -> Result<Vec<Extent>, Corrupted> {
+        let total = self.data_blocks();
+        let mut v = Vec::new();
+        let mut covered = 0;
+        // take extents until they cover all data blocks, return whether they do
+        let mut take = |extents: &[Extent]| {
+            for &[start, len] in extents {
+                if covered == total {
+                    break;
+                }
+                let len = len.min(total - covered);
+                v.push([start, len]);
+                covered += len;
+            }
+            covered == total
+        };
+        if take(self.direct.as_chunks::<2>().0) {
+            return Ok(v);
+        }
+        let read_extents = |block_id: u32| {
+            Ok(
+                get_block_cache(block_id as usize, Arc::clone(block_device))?
+                    .lock()
+                    .read(0, |extent_block: &ExtentBlock| *extent_block),
+            )
+        };
+        if take(&read_extents(self.indirect1)?) {
+            return Ok(v);
+        }
+        let indirect2 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
+            .lock()
+            .read(0, |indirect2: &IndirectBlock| *indirect2);
+        for block_id in indirect2 {
+            if take(&read_extents(block_id)?) {
+                break;
+            }
+        }
+        Ok(v)
+    }
+    /// Extent blocks holding the first `count` extents, and the indirect2 block above them
+    fn extent_index_blocks(
+        &self,
+        count: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<u32>, Corrupted> {
+        let mut v = Vec::new();
+        if count > INODE_EXTENT_COUNT {
+            v.push(self.indirect1);
+        }
+        if count > EXTENT_INDIRECT1_BOUND {
+            v.push(self.indirect2);
+            let extent_blocks = (count - EXTENT_INDIRECT1_BOUND).div_ceil(EXTENT_BLOCK_COUNT);
+            get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(&indirect2[..extent_blocks]);
+                });
+        }
+        Ok(v)
+    }
+    /// Overwrite the `e`-th extent, whose slot exists already
+    fn set_extent(
+        &mut self,
+        e: usize,
+        extent: Extent,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<(), Corrupted> {
+        if e < INODE_EXTENT_COUNT {
+            self.direct.as_chunks_mut::<2>().0[e] = extent;
+            return Ok(());
+        }
+        let (block_id, index) = if e < EXTENT_INDIRECT1_BOUND {
+            (self.indirect1, e - INODE_EXTENT_COUNT)
+        } else {
+            let e = e - EXTENT_INDIRECT1_BOUND;
+            let block_id = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    indirect2[e / EXTENT_BLOCK_COUNT]
+                });
+            (block_id, e % EXTENT_BLOCK_COUNT)
+        };
+        get_block_cache(block_id as usize, Arc::clone(block_device))?
+            .lock()
+            .modify(0, |extent_block: &mut ExtentBlock| {
+                extent_block[index] = extent;
+            });
+        Ok(())
+    }
+    /// The block right after the last data block, 0 if there is none
+    pub fn end_block(&self, block_device: &Arc<dyn BlockDevice>) -> Result<u32, Corrupted> {
+        Ok(self
+            .extents(block_device)?
+            .last()
+            .map_or(0, |&[start, len]| start + len))
+    }
+    /// Map `len` more blocks from `start` after the data blocks of an inode of extents,
+    /// and grow its size to cover them. The extent blocks it needs are allocated with `alloc`
+    pub fn push_extent(
+        &mut self,
+        start: u32,
+        len: u32,
+        alloc: &mut dyn FnMut() -> Result<u32, WriteError>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<(), WriteError> {
+        assert!(self.block_mapping == BlockMapping::Extents);
+        let extents = self.extents(block_device)?;
+        let count = extents.len();
+        let data_blocks = self.data_blocks();
+        self.size = (data_blocks + len) * BLOCK_SZ as u32;
+        if let Some(&[last_start, last_len]) = extents.last() {
+            // the last extent is rewritten in any case, as it may be longer than
+            // the data blocks after a shrink
+            if last_start + last_len == start {
+                self.set_extent(count - 1, [last_start, last_len + len], block_device)?;
+                return Ok(());
+            }
+            self.set_extent(count - 1, [last_start, last_len], block_device)?;
+        }
+        // a new slot, with the extent blocks it needs
+        if count == INODE_EXTENT_COUNT {
+            self.indirect1 = alloc()?;
+        } else if count >= EXTENT_INDIRECT1_BOUND {
+            let e = count - EXTENT_INDIRECT1_BOUND;
+            assert!(
+                e / EXTENT_BLOCK_COUNT < INODE_INDIRECT_COUNT,
+                "Too many extents!"
+            );
+            if e == 0 {
+                self.indirect2 = alloc()?;
+            }
+            if e.is_multiple_of(EXTENT_BLOCK_COUNT) {
+                let block_id = alloc()?;
+                get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
+                    .lock()
+                    .modify(0, |indirect2: &mut IndirectBlock| {
+                        indirect2[e / EXTENT_BLOCK_COUNT] = block_id;
+                    });
+            }
+        }
+        self.set_extent(count, [start, len], block_device)?;
+        Ok(())
+    }
+
+    /// Get ids of all blocks owned by current disk inode, including indirect blocks
+    pub fn all_block_ids(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<u32>, Corrupted> {
+        if self.block_mapping == BlockMapping::Extents {
+            let extents = self.extents(block_device)?;
+            let mut v = self.block_ids(0..self.data_blocks(), block_device)?;
+            v.extend(self.extent_index_blocks(extents.len(), block_device)?);
+            return Ok(v);
+        }
+        let data_blocks = self.data_blocks();
+        let mut v = (0..data_blocks)
+            .map(|inner_id| self.get_block_id(inner_id, block_device))
+            .collect::<Result<Vec<u32>, _>>()?;
+        if data_blocks as usize > INODE_DIRECT_COUNT {
+            v.push(self.indirect1);
+        }
+        if data_blocks as usize > INDIRECT1_BOUND {
+            v.push(self.indirect2);
+            let indirect1_count =
+                (data_blocks as usize - INDIRECT1_BOUND).div_ceil(INODE_INDIRECT1_COUNT);
+            get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(&indirect2[..indirect1_count]);
+                });
         }
+        Ok(v)
     }
 
     /// Inncrease the size of current disk inode
@@ -149,7 +425,7 @@ impl DiskInode {
         new_size: u32,
         new_blocks: Vec<u32>,
         block_device: &Arc<dyn BlockDevice>,
-    ) {
+    ) -> Result<(), Corrupted> {
         let mut current_blocks = self.data_blocks();
         self.size = new_size;
         let mut total_blocks = self.data_blocks();
@@ -167,10 +443,10 @@ impl DiskInode {
             current_blocks -= INODE_DIRECT_COUNT as u32;
             total_blocks -= INODE_DIRECT_COUNT as u32;
         } else {
-            return;
+            return Ok(());
         }
         // fill indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))?
             .lock()
             .modify(0, |indirect1: &mut IndirectBlock| {
                 while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
@@ -186,7 +462,7 @@ impl DiskInode {
             current_blocks -= INODE_INDIRECT1_COUNT as u32;
             total_blocks -= INODE_INDIRECT1_COUNT as u32;
         } else {
-            return;
+            return Ok(());
         }
         // fill indirect2 from (a0, b0) -> (a1, b1)
         let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
@@ -194,32 +470,118 @@ impl DiskInode {
         let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
         let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
         // alloc low-level indirect1
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        indirect2[a0] = new_blocks.next().unwrap();
-                    }
-                    // fill current
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            indirect1[b0] = new_blocks.next().unwrap();
-                        });
-                    // move to next
-                    b0 += 1;
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        b0 = 0;
-                        a0 += 1;
-                    }
-                }
-            });
+        let indirect2 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?;
+        let mut indirect2 = indirect2.lock();
+        while (a0 < a1) || (a0 == a1 && b0 < b1) {
+            if b0 == 0 {
+                let block_id = new_blocks.next().unwrap();
+                indirect2.modify(0, |indirect2: &mut IndirectBlock| indirect2[a0] = block_id);
+            }
+            // fill current
+            let indirect1 = indirect2.read(0, |indirect2: &IndirectBlock| indirect2[a0]);
+            get_block_cache(indirect1 as usize, Arc::clone(block_device))?
+                .lock()
+                .modify(0, |indirect1: &mut IndirectBlock| {
+                    indirect1[b0] = new_blocks.next().unwrap();
+                });
+            // move to next
+            b0 += 1;
+            if b0 == INODE_INDIRECT1_COUNT {
+                b0 = 0;
+                a0 += 1;
+            }
+        }
+        Ok(())
     }
 
+    /// Decrease the size of current disk inode and return blocks that should be deallocated
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<u32>, Corrupted> {
+        assert!(new_size <= self.size);
+        if self.block_mapping == BlockMapping::Extents {
+            return self.decrease_extents(new_size, block_device);
+        }
+        let data_blocks = self.data_blocks() as usize;
+        let new_data_blocks = Self::_data_blocks(new_size) as usize;
+        let mut v = (new_data_blocks..data_blocks)
+            .map(|inner_id| self.get_block_id(inner_id as u32, block_device))
+            .collect::<Result<Vec<u32>, _>>()?;
+        // indirect1 block
+        if data_blocks > INODE_DIRECT_COUNT && new_data_blocks <= INODE_DIRECT_COUNT {
+            v.push(self.indirect1);
+        }
+        // low-level indirect1 blocks, then indirect2 block
+        if data_blocks > INDIRECT1_BOUND {
+            let indirect1_count = |data_blocks: usize| {
+                data_blocks
+                    .saturating_sub(INDIRECT1_BOUND)
+                    .div_ceil(INODE_INDIRECT1_COUNT)
+            };
+            get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(
+                        &indirect2[indirect1_count(new_data_blocks)..indirect1_count(data_blocks)],
+                    );
+                });
+            if new_data_blocks <= INDIRECT1_BOUND {
+                v.push(self.indirect2);
+            }
+        }
+        self.size = new_size;
+        Ok(v)
+    }
+    /// `decrease_size` of an inode of extents
+    fn decrease_extents(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<u32>, Corrupted> {
+        let extents = self.extents(block_device)?;
+        let new_data_blocks = Self::_data_blocks(new_size);
+        let mut v = Vec::new();
+        let mut first = 0;
+        let mut new_count = 0;
+        for (e, &[start, len]) in extents.iter().enumerate() {
+            let keep = new_data_blocks.saturating_sub(first).min(len);
+            v.extend(start + keep..start + len);
+            if keep > 0 {
+                new_count = e + 1;
+                if keep < len {
+                    self.set_extent(e, [start, keep], block_device)?;
+                }
+            }
+            first += len;
+        }
+        let kept = self.extent_index_blocks(new_count, block_device)?;
+        v.extend(
+            self.extent_index_blocks(extents.len(), block_device)?
+                .into_iter()
+                .filter(|block_id| !kept.contains(block_id)),
+        );
+        if new_count <= INODE_EXTENT_COUNT {
+            self.indirect1 = 0;
+        }
+        if new_count <= EXTENT_INDIRECT1_BOUND {
+            self.indirect2 = 0;
+        }
+        self.size = new_size;
+        Ok(v)
+    }
     /// Clear size to zero and return blocks that should be deallocated.
     /// We will clear the block contents to zero later.
-    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+    pub fn clear_size(
+        &mut self,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<u32>, Corrupted> {
+        if self.block_mapping == BlockMapping::Extents {
+            let v = self.decrease_extents(0, block_device)?;
+            self.direct.fill(0);
+            return Ok(v);
+        }
         let mut v: Vec<u32> = Vec::new();
         let mut data_blocks = self.data_blocks() as usize;
         self.size = 0;
@@ -236,12 +598,12 @@ impl DiskInode {
             data_blocks -= INODE_DIRECT_COUNT;
             current_blocks = 0;
         } else {
-            return v;
+            return Ok(v);
         }
         // indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))?
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
+            .read(0, |indirect1: &IndirectBlock| {
                 while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
                     v.push(indirect1[current_blocks]);
                     //indirect1[current_blocks] = 0;
@@ -254,41 +616,39 @@ impl DiskInode {
             v.push(self.indirect2);
             data_blocks -= INODE_INDIRECT1_COUNT;
         } else {
-            return v;
+            return Ok(v);
         }
         // indirect2
         assert!(data_blocks <= INODE_INDIRECT2_COUNT);
         let a1 = data_blocks / INODE_INDIRECT1_COUNT;
         let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+        let indirect2 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))?
             .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry);
-                            }
-                        });
-                }
-                // last indirect1 block
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry);
-                            }
-                        });
-                    //indirect2[a1] = 0;
-                }
-            });
+            .read(0, |indirect2: &IndirectBlock| *indirect2);
+        // full indirect1 blocks
+        for entry in indirect2.iter().take(a1) {
+            v.push(*entry);
+            get_block_cache(*entry as usize, Arc::clone(block_device))?
+                .lock()
+                .read(0, |indirect1: &IndirectBlock| {
+                    for entry in indirect1.iter() {
+                        v.push(*entry);
+                    }
+                });
+        }
+        // last indirect1 block
+        if b1 > 0 {
+            v.push(indirect2[a1]);
+            get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))?
+                .lock()
+                .read(0, |indirect1: &IndirectBlock| {
+                    for entry in indirect1.iter().take(b1) {
+                        v.push(*entry);
+                    }
+                });
+        }
         self.indirect2 = 0;
-        v
+        Ok(v)
     }
     /// Read data from current disk inode
     pub fn read_at(
@@ -296,13 +656,17 @@ impl DiskInode {
         offset: usize,
         buf: &mut [u8],
         block_device: &Arc<dyn BlockDevice>,
-    ) -> usize {
+    ) -> Result<usize, Corrupted> {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         if start >= end {
-            return 0;
+            return Ok(0);
         }
         let mut start_block = start / BLOCK_SZ;
+        let block_ids = self.block_ids(
+            start_block as u32..end.div_ceil(BLOCK_SZ) as u32,
+            block_device,
+        )?;
         let mut read_size = 0usize;
         loop {
             // calculate end of current block
@@ -312,9 +676,9 @@ impl DiskInode {
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
             get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
+                block_ids[start_block - offset / BLOCK_SZ] as usize,
                 Arc::clone(block_device),
-            )
+            )?
             .lock()
             .read(0, |data_block: &DataBlock| {
                 let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
@@ -328,7 +692,7 @@ impl DiskInode {
             start_block += 1;
             start = end_current_block;
         }
-        read_size
+        Ok(read_size)
     }
     /// Write data into current disk inode
     /// size must be adjusted properly beforehand
@@ -337,11 +701,18 @@ impl DiskInode {
         offset: usize,
         buf: &[u8],
         block_device: &Arc<dyn BlockDevice>,
-    ) -> usize {
+    ) -> Result<usize, Corrupted> {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         assert!(start <= end);
+        if start == end {
+            return Ok(0);
+        }
         let mut start_block = start / BLOCK_SZ;
+        let block_ids = self.block_ids(
+            start_block as u32..end.div_ceil(BLOCK_SZ) as u32,
+            block_device,
+        )?;
         let mut write_size = 0usize;
         loop {
             // calculate end of current block
@@ -349,16 +720,22 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
+            let cache = get_block_cache(
+                block_ids[start_block - offset / BLOCK_SZ] as usize,
                 Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            )?;
+            let mut cache = cache.lock();
+            let write = |data_block: &mut DataBlock| {
                 let src = &buf[write_size..write_size + block_write_size];
                 let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
                 dst.copy_from_slice(src);
-            });
+            };
+            // entries of a directory are metadata
+            if self.is_dir() {
+                cache.modify(0, write);
+            } else {
+                cache.modify_data(0, write);
+            }
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -367,14 +744,14 @@ impl DiskInode {
             start_block += 1;
             start = end_current_block;
         }
-        write_size
+        Ok(write_size)
     }
 }
 
-/// A directory entry
+/// A directory entry of version 0
 #[repr(C)]
 pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
+    name: [u8; NAME_LENGTH_LIMIT_V0 + 1],
     inode_number: u32,
 }
 
@@ -382,13 +759,13 @@ impl DirEntry {
     /// Create an empty directory entry
     pub fn empty() -> Self {
         Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
+            name: [0u8; NAME_LENGTH_LIMIT_V0 + 1],
             inode_number: 0,
         }
     }
     /// Crate a directory entry from name and inode number
     pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
+        let mut bytes = [0u8; NAME_LENGTH_LIMIT_V0 + 1];
         bytes[..name.len()].copy_from_slice(name.as_bytes());
         Self {
             name: bytes,
@@ -403,10 +780,12 @@ impl DirEntry {
     pub fn as_bytes_mut(&mut self) -> &mut [u8; size_of::<Self>()] {
         unsafe { core::mem::transmute(self) }
     }
-    /// Get name of the entry
-    pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap()
+    /// Get name of the entry if it is a valid one:
+    /// NUL terminated, UTF-8, not empty and without '/'
+    pub fn checked_name(&self) -> Option<&str> {
+        let len = self.name.iter().position(|&b| b == 0)?;
+        let name = core::str::from_utf8(&self.name[..len]).ok()?;
+        (!name.is_empty() && !name.contains('/')).then_some(name)
     }
     /// Get inode number of the entry
     pub fn inode_number(&self) -> u32 {
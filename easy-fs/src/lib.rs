@@ -1,18 +1,31 @@
-#![no_std]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 extern crate alloc;
 mod bitmap;
 mod block_cache;
 mod block_dev;
+mod checksum;
+mod directory;
 mod efs;
+pub mod fsck;
+mod journal;
 mod layout;
+#[cfg(test)]
+mod model_test;
 mod vfs;
 use config::fs as config;
 
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+pub use block_cache::{Corrupted, block_cache_sync_all, set_block_cache_capacity};
+use block_cache::{
+    block_cache_journaled, block_cache_sync, get_block_cache, set_block_cache_checksums,
+};
 pub use block_dev::BlockDevice;
-pub use efs::EasyFileSystem;
+#[cfg(any(test, feature = "std"))]
+pub use block_dev::{RamBlockDevice, test_data};
+pub use directory::record_len;
+pub use efs::{EasyFileSystem, OpenError, WriteError};
+use journal::Journal;
 use layout::*;
 pub use vfs::Inode;
 const DIRENT_SZ: usize = core::mem::size_of::<DirEntry>();
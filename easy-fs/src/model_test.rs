@@ -0,0 +1,358 @@
+//! Run random operations against easy-fs and a `HashMap` model of it,
+//! checking they always agree
+
+use crate::config::{
+    BLOCK_BITS, BLOCK_SZ, EFS_MAGIC, EFS_VERSION, EXTENT_BLOCK_COUNT, EXTENT_INDIRECT1_BOUND,
+    INDIRECT1_BOUND, INODE_INDIRECT1_COUNT,
+};
+use crate::{
+    EasyFileSystem, Inode, RamBlockDevice, SuperBlock, WriteError, block_cache_sync_all, fsck,
+};
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const TOTAL_BLOCKS: usize = 16 * 1024;
+/// Names of files are picked from a few, so they are often reused
+const NAMES: usize = 12;
+/// Lengths of the names, the longer ones only fit from version 1
+const NAME_LENGTHS: [usize; 7] = [1, 5, 27, 28, 60, 200, 255];
+/// Files grow up to two blocks of indirect2
+const MAX_SIZE: usize = (INDIRECT1_BOUND + 2 * INODE_INDIRECT1_COUNT) * BLOCK_SZ;
+const STEPS: usize = 200;
+const SEEDS: u64 = 16;
+
+type Model = HashMap<String, Vec<u8>>;
+
+/// The `i`-th name
+fn name(i: usize) -> String {
+    let len = NAME_LENGTHS[i % NAME_LENGTHS.len()];
+    let first = char::from(b'a' + i as u8);
+    std::iter::once(first)
+        .chain(std::iter::repeat_n('_', len - 1))
+        .collect()
+}
+
+/// A copy of `device` whose super block claims format `version`,
+/// only meaningful between versions sharing a layout
+fn set_version(device: &RamBlockDevice, version: u32) -> Arc<RamBlockDevice> {
+    block_cache_sync_all();
+    let mut image = device.image();
+    let pos = std::mem::offset_of!(SuperBlock, version);
+    image[pos..pos + 4].copy_from_slice(&version.to_ne_bytes());
+    Arc::new(RamBlockDevice::from_image(image))
+}
+
+/// A fresh image of format `version`
+fn fresh(version: u32) -> Arc<RamBlockDevice> {
+    let device = Arc::new(RamBlockDevice::new(TOTAL_BLOCKS));
+    EasyFileSystem::create_version(device.clone(), TOTAL_BLOCKS as u32, 1, version);
+    device
+}
+
+/// Check every file of `root` against the model
+fn compare(root: &Inode, model: &Model) {
+    let mut names = root.ls().unwrap();
+    names.sort();
+    let mut expected: Vec<_> = model.keys().cloned().collect();
+    expected.sort();
+    assert_eq!(names, expected);
+    for (name, data) in model {
+        let inode = root.find(name).unwrap().unwrap();
+        let mut buf = vec![0; data.len() + BLOCK_SZ];
+        assert_eq!(inode.read_at(0, &mut buf).unwrap(), data.len());
+        assert!(buf[..data.len()] == data[..], "content of {name}");
+    }
+}
+
+/// Run a random sequence of operations on an image of format `versions[0]`,
+/// which is upgraded to `versions[1]` halfway. Return the largest size reached by a file
+fn run(seed: u64, versions: [u32; 2]) -> usize {
+    let mut device = fresh(versions[0]);
+    let mut efs = EasyFileSystem::open(device.clone()).unwrap();
+    let name_length_limit = efs.lock().name_length_limit();
+    let mut root = EasyFileSystem::root_inode(&efs);
+    let mut model = Model::new();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut max_size = 0;
+    for step in 0..STEPS {
+        let version = versions[step * 2 / STEPS];
+        if step == STEPS / 2 && versions[0] != versions[1] {
+            // the inodes made so far keep mapping their blocks the old way
+            device = set_version(&device, version);
+            efs = EasyFileSystem::open(device.clone()).unwrap();
+            root = EasyFileSystem::root_inode(&efs);
+        }
+        let name = name(rng.random_range(0..NAMES));
+        let context = format!("version {version} seed {seed} step {step} on {name}");
+        if name.len() > name_length_limit {
+            assert!(root.create(&name).unwrap().is_none(), "{context}");
+            assert!(root.find(&name).unwrap().is_none(), "{context}");
+            continue;
+        }
+        let inode = root.find(&name).unwrap();
+        assert_eq!(inode.is_some(), model.contains_key(&name), "{context}");
+        let (Some(inode), Some(data)) = (inode, model.get_mut(&name)) else {
+            assert!(root.create(&name).unwrap().is_some(), "{context}");
+            model.insert(name, Vec::new());
+            continue;
+        };
+        match rng.random_range(0..10) {
+            0 => assert!(root.create(&name).unwrap().is_none(), "{context}"),
+            1 => {
+                inode.clear().unwrap();
+                data.clear();
+            }
+            2 => {
+                assert!(root.unlink(&name).unwrap(), "{context}");
+                assert!(!root.unlink(&name).unwrap(), "{context}");
+                model.remove(&name);
+                continue;
+            }
+            3 => {
+                let size = rng.random_range(0..=MAX_SIZE);
+                inode.truncate(size).unwrap();
+                data.resize(size, 0);
+            }
+            4..=6 => {
+                // possibly past the end, leaving a hole of zeros
+                let len = rng.random_range(1..=64 * BLOCK_SZ);
+                let offset = rng
+                    .random_range(0..=data.len() + 4 * BLOCK_SZ)
+                    .min(MAX_SIZE - len);
+                let bytes: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+                assert_eq!(inode.write_at(offset, &bytes).unwrap(), len, "{context}");
+                if data.len() < offset + len {
+                    data.resize(offset + len, 0);
+                }
+                data[offset..offset + len].copy_from_slice(&bytes);
+            }
+            _ => {
+                let offset = rng.random_range(0..=data.len() + BLOCK_SZ);
+                let mut buf = vec![0; rng.random_range(0..8 * BLOCK_SZ)];
+                let expected = data.get(offset..).unwrap_or_default();
+                let len = expected.len().min(buf.len());
+                assert_eq!(inode.read_at(offset, &mut buf).unwrap(), len, "{context}");
+                assert!(buf[..len] == expected[..len], "{context}");
+            }
+        }
+        assert_eq!(inode.size().unwrap(), data.len(), "{context}");
+        max_size = max_size.max(data.len());
+    }
+    compare(&root, &model);
+
+    // the image is consistent, and holds the same files once mounted again
+    block_cache_sync_all();
+    assert_eq!(fsck::check(device.clone(), false), Some(vec![]));
+    let device = Arc::new(RamBlockDevice::from_image(device.image()));
+    let efs = EasyFileSystem::open(device).unwrap();
+    compare(&EasyFileSystem::root_inode(&efs), &model);
+    max_size
+}
+
+#[test]
+fn model_test() {
+    for versions in [[0, 0], [1, 1], [EFS_VERSION, EFS_VERSION], [1, 2]] {
+        let max_size = (0..SEEDS).map(|seed| run(seed, versions)).max().unwrap();
+        assert!(
+            max_size > INDIRECT1_BOUND * BLOCK_SZ,
+            "indirect2 never reached"
+        );
+    }
+}
+
+/// Files written a block at a time in turns take an extent per block,
+/// and reach the extent blocks under indirect2
+#[test]
+fn fragmented_test() {
+    const BLOCKS: usize = EXTENT_INDIRECT1_BOUND + 2 * EXTENT_BLOCK_COUNT;
+    let device = fresh(EFS_VERSION);
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let mut model = Model::new();
+    for name in ["a", "b"] {
+        root.create(name).unwrap().unwrap();
+        model.insert(name.into(), Vec::new());
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    for i in 0..BLOCKS {
+        for name in ["a", "b"] {
+            let bytes: Vec<u8> = (0..BLOCK_SZ).map(|_| rng.random()).collect();
+            root.find(name)
+                .unwrap()
+                .unwrap()
+                .write_at(i * BLOCK_SZ, &bytes)
+                .unwrap();
+            model.get_mut(name).unwrap().extend(bytes);
+        }
+    }
+    compare(&root, &model);
+
+    // shrinking frees the extent blocks, and growing again takes new extents
+    let a = root.find("a").unwrap().unwrap();
+    for size in [(EXTENT_INDIRECT1_BOUND - 1) * BLOCK_SZ, 3 * BLOCK_SZ / 2] {
+        a.truncate(size).unwrap();
+        model.get_mut("a").unwrap().truncate(size);
+        compare(&root, &model);
+    }
+    let bytes: Vec<u8> = (0..BLOCKS * BLOCK_SZ).map(|_| rng.random()).collect();
+    a.write_at(BLOCK_SZ, &bytes).unwrap();
+    model.get_mut("a").unwrap().splice(BLOCK_SZ.., bytes);
+    assert!(root.unlink("b").unwrap());
+    model.remove("b");
+    compare(&root, &model);
+
+    block_cache_sync_all();
+    assert_eq!(fsck::check(device.clone(), false), Some(vec![]));
+    let device = Arc::new(RamBlockDevice::from_image(device.image()));
+    let efs = EasyFileSystem::open(device).unwrap();
+    compare(&EasyFileSystem::root_inode(&efs), &model);
+}
+
+/// Formatting an image whose checksum area is larger than the journal
+/// commits the checksums in parts
+#[test]
+fn large_transaction_test() {
+    // an inode area of 16K blocks takes 129 checksum blocks
+    let device = Arc::new(RamBlockDevice::new(TOTAL_BLOCKS * 2));
+    EasyFileSystem::create(device.clone(), (TOTAL_BLOCKS * 2) as u32, 16);
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let mut model = Model::new();
+    for i in 0..NAMES {
+        let bytes = vec![i as u8; i * BLOCK_SZ];
+        root.create(&name(i))
+            .unwrap()
+            .unwrap()
+            .write_at(0, &bytes)
+            .unwrap();
+        model.insert(name(i), bytes);
+    }
+    compare(&root, &model);
+
+    block_cache_sync_all();
+    assert_eq!(fsck::check(device.clone(), false), Some(vec![]));
+    let device = Arc::new(RamBlockDevice::from_image(device.image()));
+    let efs = EasyFileSystem::open(device).unwrap();
+    compare(&EasyFileSystem::root_inode(&efs), &model);
+}
+
+/// An image laid out byte for byte like those made before the format had a
+/// version: no journal, the inode bitmap in block 1, fixed-size directory entries,
+/// and a root holding the file `old`
+fn baseline_image(blocks: usize) -> Vec<u8> {
+    let inode_bitmap_blocks = 1;
+    let inode_area_blocks = BLOCK_BITS * 128 / BLOCK_SZ;
+    let data_total_blocks = blocks - 1 - inode_bitmap_blocks - inode_area_blocks;
+    let data_bitmap_blocks = data_total_blocks.div_ceil(BLOCK_BITS + 1);
+    let data_area_blocks = data_total_blocks - data_bitmap_blocks;
+    let inode_area_start = 1 + inode_bitmap_blocks;
+    let data_bitmap_start = inode_area_start + inode_area_blocks;
+    let data_area_start = data_bitmap_start + data_bitmap_blocks;
+
+    let mut image = vec![0u8; blocks * BLOCK_SZ];
+    let mut put = |pos: usize, bytes: &[u8]| image[pos..pos + bytes.len()].copy_from_slice(bytes);
+    let super_block = [
+        EFS_MAGIC,
+        blocks as u32,
+        inode_bitmap_blocks as u32,
+        inode_area_blocks as u32,
+        data_bitmap_blocks as u32,
+        data_area_blocks as u32,
+    ];
+    for (i, field) in super_block.iter().enumerate() {
+        put(i * 4, &field.to_le_bytes());
+    }
+    // inodes 0 and 1, data blocks 0 and 1
+    put(BLOCK_SZ, &[0b11]);
+    put(data_bitmap_start * BLOCK_SZ, &[0b11]);
+    // size, direct[0], then the type at byte 124, 1 for a directory
+    let root = inode_area_start * BLOCK_SZ;
+    put(root, &32u32.to_le_bytes());
+    put(root + 4, &(data_area_start as u32).to_le_bytes());
+    put(root + 124, &[1]);
+    let file = root + 128;
+    put(file, &5u32.to_le_bytes());
+    put(file + 4, &(data_area_start as u32 + 1).to_le_bytes());
+    // a 28-byte name, then the inode number
+    put(data_area_start * BLOCK_SZ, b"old");
+    put(data_area_start * BLOCK_SZ + 28, &1u32.to_le_bytes());
+    put((data_area_start + 1) * BLOCK_SZ, b"hello");
+    image
+}
+
+/// Images from before the journal are written in place, around the old files
+#[test]
+fn baseline_image_test() {
+    let device = Arc::new(RamBlockDevice::from_image(baseline_image(4096)));
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    assert_eq!(efs.lock().super_block.journal_blocks, 0);
+    let root = EasyFileSystem::root_inode(&efs);
+    let mut model = Model::from([("old".into(), b"hello".to_vec())]);
+    compare(&root, &model);
+    let mut rng = StdRng::seed_from_u64(0);
+    for i in 0..NAMES {
+        let name = name(i);
+        if name.len() > efs.lock().name_length_limit() {
+            continue;
+        }
+        let bytes: Vec<u8> = (0..i * BLOCK_SZ + i).map(|_| rng.random()).collect();
+        let inode = root.create(&name).unwrap().unwrap();
+        inode.write_at(0, &bytes).unwrap();
+        model.insert(name, bytes);
+    }
+    assert!(root.unlink(&name(0)).unwrap());
+    model.remove(&name(0));
+    compare(&root, &model);
+
+    block_cache_sync_all();
+    assert_eq!(fsck::check(device.clone(), false), Some(vec![]));
+    let device = Arc::new(RamBlockDevice::from_image(device.image()));
+    let efs = EasyFileSystem::open(device).unwrap();
+    compare(&EasyFileSystem::root_inode(&efs), &model);
+}
+
+/// Looking up, listing or creating entries under a file finds nothing
+#[test]
+fn file_entries_test() {
+    let device = fresh(EFS_VERSION);
+    let efs = EasyFileSystem::open(device).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("a").unwrap().unwrap();
+    file.write_at(0, &[0xff; BLOCK_SZ]).unwrap();
+    assert!(file.find("a").unwrap().is_none());
+    assert!(file.read_dir(0).unwrap().is_none());
+    assert!(file.ls().unwrap().is_empty());
+    assert!(file.create("b").unwrap().is_none());
+    assert!(!file.unlink("a").unwrap());
+    assert_eq!(file.size().unwrap(), BLOCK_SZ);
+}
+
+#[test]
+fn disk_full_test() {
+    let device = Arc::new(RamBlockDevice::new(4096));
+    EasyFileSystem::create(device.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("a").unwrap().unwrap();
+    let bytes = vec![0xa5; 4096 * BLOCK_SZ];
+    let mut written = file.write_at(0, &bytes).unwrap();
+    assert!(0 < written && written < bytes.len());
+    // the space left over by a short write may still take smaller ones
+    loop {
+        assert_eq!(file.size().unwrap(), written);
+        match file.write_at(written, &bytes[..BLOCK_SZ]) {
+            Ok(len) => written += len,
+            Err(err) => break assert_eq!(err, WriteError::NoSpace),
+        }
+    }
+    assert_eq!(file.size().unwrap(), written);
+
+    block_cache_sync_all();
+    assert_eq!(fsck::check(device.clone(), false), Some(vec![]));
+    file.truncate(0).unwrap();
+    let other = root.create("b").unwrap().unwrap();
+    assert_eq!(other.write_at(0, &bytes[..BLOCK_SZ]), Ok(BLOCK_SZ));
+    block_cache_sync_all();
+    assert_eq!(fsck::check(device, false), Some(vec![]));
+}
@@ -1,13 +1,20 @@
+use super::config::BLOCK_SZ;
+use super::directory::{self, Entry};
 use super::{
-    BlockDevice, DIRENT_SZ, DirEntry, DiskInode, DiskInodeType, EasyFileSystem,
-    block_cache_sync_all, get_block_cache,
+    BlockDevice, Corrupted, DiskInode, DiskInodeType, EasyFileSystem, WriteError, block_cache_sync,
+    get_block_cache,
 };
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::{Mutex, MutexGuard};
+use spin::Mutex;
+
+/// Bytes written by one transaction in `Inode::write_at`
+const WRITE_CHUNK_SZ: usize = 64 * BLOCK_SZ;
 
 /// Virtual filesystem layer over easy-fs
+///
+/// Every operation fails with `Corrupted` if a block it loads does not match its checksum
 pub struct Inode {
     block_id: usize,
     block_offset: usize,
@@ -31,157 +38,278 @@ impl Inode {
         }
     }
     /// Call a function over a disk inode to read it
-    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
-        get_block_cache(self.block_id, Arc::clone(&self.block_device))
-            .lock()
-            .read(self.block_offset, f)
+    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> Result<V, Corrupted> {
+        Ok(
+            get_block_cache(self.block_id, Arc::clone(&self.block_device))?
+                .lock()
+                .read(self.block_offset, f),
+        )
     }
     /// Call a function over a disk inode to modify it
-    fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
-        get_block_cache(self.block_id, Arc::clone(&self.block_device))
-            .lock()
-            .modify(self.block_offset, f)
-    }
-    /// Find inode under a disk inode by name
-    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
-        // assert it is a directory
-        assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device,),
-                DIRENT_SZ,
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
-            }
-        }
-        None
+    fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> Result<V, Corrupted> {
+        Ok(
+            get_block_cache(self.block_id, Arc::clone(&self.block_device))?
+                .lock()
+                .modify(self.block_offset, f),
+        )
+    }
+    /// Find the entry of a name under a disk inode
+    fn find_entry(
+        &self,
+        fs: &EasyFileSystem,
+        name: &str,
+        disk_inode: &DiskInode,
+    ) -> Result<Option<Entry>, Corrupted> {
+        directory::entries(fs, disk_inode, 0)
+            .find(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |entry| entry.name.as_deref() == Some(name))
+            })
+            .transpose()
     }
     /// Find inode under current inode by name
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+    pub fn find(&self, name: &str) -> Result<Option<Arc<Inode>>, Corrupted> {
         let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode).map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
-        })
+        let entry = self.read_disk_inode(|disk_inode| self.find_entry(&fs, name, disk_inode))??;
+        Ok(entry.map(|entry| {
+            let (block_id, block_offset) = fs.get_disk_inode_pos(entry.inode_number);
+            Arc::new(Self::new(
+                block_id,
+                block_offset,
+                self.fs.clone(),
+                self.block_device.clone(),
+            ))
+        }))
     }
-    /// Increase the size of a disk inode
-    fn increase_size(
-        &self,
-        new_size: u32,
-        disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
-    ) {
-        if new_size < disk_inode.size {
-            return;
-        }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
-        let mut v: Vec<u32> = Vec::with_capacity(blocks_needed as usize);
-        for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
-        }
-        disk_inode.increase_size(new_size, v, &self.block_device);
+    /// Create a file under current inode by name.
+    /// Return `None` if the name exists or is longer than `name_length_limit`
+    pub fn create(&self, name: &str) -> Result<Option<Arc<Inode>>, WriteError> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+    /// Create a directory under current inode by name
+    pub fn create_dir(&self, name: &str) -> Result<Option<Arc<Inode>>, WriteError> {
+        self.create_inode(name, DiskInodeType::Directory)
     }
-    /// Create inode under current inode by name
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+    fn create_inode(
+        &self,
+        name: &str,
+        r#type: DiskInodeType,
+    ) -> Result<Option<Arc<Inode>>, WriteError> {
         let mut fs = self.fs.lock();
-        let op = |root_inode: &DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
+        // only a directory takes entries, and a name only once
+        let taken = |root_inode: &DiskInode| {
+            Ok::<_, Corrupted>(
+                !root_inode.is_dir() || self.find_entry(&fs, name, root_inode)?.is_some(),
+            )
         };
-        if self.read_disk_inode(op).is_some() {
-            return None;
+        if self.read_disk_inode(taken)?? {
+            return Ok(None);
         }
         // create a new file
         // alloc a inode with an indirect block
-        let new_inode_id = fs.alloc_inode();
+        let new_inode_id = fs.alloc_inode()?;
+        // a name too long for the directory, or no space for the entry,
+        // leaves the inode free
+        let inserted = self.modify_disk_inode(|root_inode| {
+            directory::insert_entry(&mut fs, root_inode, name, new_inode_id)
+        })?;
+        if inserted != Ok(true) {
+            fs.dealloc_inode(new_inode_id)?;
+            fs.commit()?;
+            return inserted.map(|_| None);
+        }
         // initialize inode
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
+        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))?
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
+                new_inode.initialize(r#type, fs.block_mapping());
             });
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
+        fs.commit()?;
         // return inode
-        Some(Arc::new(Self::new(
+        Ok(Some(Arc::new(Self::new(
             block_id,
             block_offset,
             self.fs.clone(),
             self.block_device.clone(),
-        )))
+        ))))
         // release efs lock automatically by compiler
     }
     /// List inodes under current inode
-    pub fn ls(&self) -> Vec<String> {
-        let _fs = self.fs.lock();
+    pub fn ls(&self) -> Result<Vec<String>, Corrupted> {
+        let fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
-                    DIRENT_SZ,
-                );
-                v.push(String::from(dirent.name()));
-            }
-            v
-        })
+            directory::entries(&fs, disk_inode, 0)
+                .filter_map(|entry| entry.map(|entry| entry.name).transpose())
+                .collect()
+        })?
+    }
+    /// Read the first entry under current inode at or after `offset`, which starts from 0.
+    /// Return its name, its inode and the offset to read the entry after it
+    pub fn read_dir(
+        &self,
+        offset: usize,
+    ) -> Result<Option<(String, Arc<Inode>, usize)>, Corrupted> {
+        let fs = self.fs.lock();
+        let entry = self.read_disk_inode(|disk_inode| {
+            // entries with a damaged name are left to fsck
+            directory::entries(&fs, disk_inode, offset)
+                .find(|entry| entry.as_ref().map_or(true, |entry| entry.name.is_some()))
+                .transpose()
+        })??;
+        Ok(entry.map(|entry| {
+            let (block_id, block_offset) = fs.get_disk_inode_pos(entry.inode_number);
+            (
+                entry.name.unwrap(),
+                Arc::new(Self::new(
+                    block_id,
+                    block_offset,
+                    self.fs.clone(),
+                    self.block_device.clone(),
+                )),
+                entry.next,
+            )
+        }))
+    }
+    /// The max length of a name under current inode
+    pub fn name_length_limit(&self) -> usize {
+        self.fs.lock().name_length_limit()
+    }
+    /// Inode number of current inode
+    pub fn inode_id(&self) -> u32 {
+        self.fs
+            .lock()
+            .get_inode_id(self.block_id as u32, self.block_offset)
+    }
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> Result<bool, Corrupted> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+    /// Size of current inode in bytes
+    pub fn size(&self) -> Result<usize, Corrupted> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
     }
     /// Read data from current inode
-    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Corrupted> {
         let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))?
     }
-    /// Write data to current inode
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+    /// Write data to current inode. Running out of space after some chunks
+    /// is a short write, and before any is `NoSpace`
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, WriteError> {
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
-        size
+        let mut size = 0;
+        // one transaction per chunk, so the changed metadata fits in the journal
+        for chunk in buf.chunks(WRITE_CHUNK_SZ) {
+            let written = self.modify_disk_inode(|disk_inode| {
+                fs.increase_size(disk_inode, (offset + size + chunk.len()) as u32)?;
+                Ok(disk_inode.write_at(offset + size, chunk, &self.block_device)?)
+            })?;
+            match written {
+                Ok(len) => size += len,
+                Err(WriteError::NoSpace) if size > 0 => break,
+                Err(err) => return Err(err),
+            }
+            fs.commit()?;
+        }
+        Ok(size)
+    }
+    /// Write the cached blocks of current inode back to disk,
+    /// along with the bitmaps that record their allocation
+    pub fn sync(&self) -> Result<(), Corrupted> {
+        let fs = self.fs.lock();
+        let block_ids =
+            self.read_disk_inode(|disk_inode| disk_inode.all_block_ids(&self.block_device))??;
+        block_cache_sync(
+            &self.block_device,
+            core::iter::once(self.block_id)
+                .chain(block_ids.into_iter().map(|block_id| block_id as usize))
+                .chain(fs.inode_bitmap.block_ids())
+                .chain(fs.data_bitmap.block_ids()),
+        );
+        Ok(())
+    }
+    /// Set the size of current inode, bytes past the old size read as zero
+    pub fn truncate(&self, new_size: usize) -> Result<(), WriteError> {
+        let mut fs = self.fs.lock();
+        let size = self.read_disk_inode(|disk_inode| disk_inode.size as usize)?;
+        if new_size < size {
+            self.modify_disk_inode(|disk_inode| {
+                // keep the tail of the last block zeroed for a later growth
+                let end = new_size.next_multiple_of(BLOCK_SZ).min(size);
+                disk_inode.write_at(
+                    new_size,
+                    &[0; BLOCK_SZ][..end - new_size],
+                    &self.block_device,
+                )?;
+                for data_block in disk_inode.decrease_size(new_size as u32, &self.block_device)? {
+                    fs.dealloc_data(data_block)?;
+                }
+                Ok::<_, Corrupted>(())
+            })??;
+            fs.commit()?;
+            return Ok(());
+        }
+        // one transaction per chunk like `write_at`
+        for chunk_end in (size..new_size)
+            .step_by(WRITE_CHUNK_SZ)
+            .map(|chunk_start| (chunk_start + WRITE_CHUNK_SZ).min(new_size))
+        {
+            self.modify_disk_inode(|disk_inode| fs.increase_size(disk_inode, chunk_end as u32))??;
+            fs.commit()?;
+        }
+        Ok(())
+    }
+    /// Remove a file under current inode by name, and free its inode and blocks.
+    /// Return false if there is no such file
+    pub fn unlink(&self, name: &str) -> Result<bool, Corrupted> {
+        let mut fs = self.fs.lock();
+        let entry = self.read_disk_inode(|disk_inode| self.find_entry(&fs, name, disk_inode))??;
+        let Some(Entry {
+            offset,
+            inode_number: inode_id,
+            ..
+        }) = entry
+        else {
+            return Ok(false);
+        };
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let removed = get_block_cache(block_id as usize, Arc::clone(&self.block_device))?
+            .lock()
+            .modify(block_offset, |disk_inode: &mut DiskInode| {
+                if disk_inode.is_dir() {
+                    return Ok(false);
+                }
+                for data_block in disk_inode.clear_size(&self.block_device)? {
+                    fs.dealloc_data(data_block)?;
+                }
+                Ok(true)
+            })?;
+        if !removed {
+            return Ok(false);
+        }
+        fs.dealloc_inode(inode_id)?;
+        self.modify_disk_inode(|dir_inode| directory::remove_entry(&mut fs, dir_inode, offset))??;
+        fs.commit()?;
+        Ok(true)
     }
     /// Clear the data in current inode
-    pub fn clear(&self) {
+    pub fn clear(&self) -> Result<(), Corrupted> {
         let mut fs = self.fs.lock();
         self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
+            let total_blocks = disk_inode.all_block_ids(&self.block_device)?.len();
+            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device)?;
+            assert!(data_blocks_dealloc.len() == total_blocks);
             for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
+                fs.dealloc_data(data_block)?;
             }
-        });
-        block_cache_sync_all();
+            Ok(())
+        })??;
+        fs.commit()
     }
 }
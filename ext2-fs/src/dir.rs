@@ -0,0 +1,292 @@
+//! Directory records: the inode, the length of the record, the length of
+//! the name, the type of the inode with the filetype feature, and the name.
+//! The records of a block cover it, a free one has inode 0 or is the room
+//! left after the name of the one before it
+
+use super::fs::Ext2FileSystem;
+use super::layout::{
+    DiskInode, INDEX_FL, S_IFDIR, S_IFMT, S_IFREG, set_u16, set_u32, u16_at, u32_at,
+};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of a record before the name
+const HEADER_SZ: usize = 8;
+/// Types of the inode in a record
+const FT_UNKNOWN: u8 = 0;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+
+/// A used record found in a directory
+pub struct Entry {
+    pub name: String,
+    pub inode: u32,
+    /// Byte offset of the record in the directory
+    pub pos: usize,
+    /// Byte offset of the record after it
+    pub next: usize,
+}
+
+/// The room a record with a name of `name_len` bytes takes, aligned to 4 bytes
+fn record_len(name_len: usize) -> usize {
+    (HEADER_SZ + name_len).next_multiple_of(4)
+}
+
+/// A record as laid out at the start of `raw`
+struct Record {
+    inode: u32,
+    rec_len: usize,
+    name_len: usize,
+}
+
+impl Record {
+    /// The record at `pos` in a block, `None` if it is broken
+    fn parse(block: &[u8], pos: usize, has_filetype: bool) -> Option<Self> {
+        if pos + HEADER_SZ > block.len() {
+            return None;
+        }
+        let rec_len = u16_at(block, pos + 4) as usize;
+        // without the filetype feature the length of the name has 16 bits
+        let name_len = match has_filetype {
+            true => block[pos + 6] as usize,
+            false => u16_at(block, pos + 6) as usize,
+        };
+        let record = Self {
+            inode: u32_at(block, pos),
+            rec_len,
+            name_len,
+        };
+        let valid = rec_len >= HEADER_SZ
+            && rec_len.is_multiple_of(4)
+            && pos + rec_len <= block.len()
+            && (record.inode == 0 || HEADER_SZ + name_len <= rec_len);
+        valid.then_some(record)
+    }
+    /// The room taken by the name, the rest can hold other records
+    fn used_len(&self) -> usize {
+        match self.inode {
+            0 => 0,
+            _ => record_len(self.name_len),
+        }
+    }
+}
+
+/// Write a record at `pos` in a block
+fn write_record(
+    block: &mut [u8],
+    pos: usize,
+    inode: u32,
+    rec_len: usize,
+    name: &[u8],
+    file_type: u8,
+    has_filetype: bool,
+) {
+    set_u32(block, pos, inode);
+    set_u16(block, pos + 4, rec_len as u16);
+    match has_filetype {
+        true => {
+            block[pos + 6] = name.len() as u8;
+            block[pos + 7] = file_type;
+        }
+        false => set_u16(block, pos + 6, name.len() as u16),
+    }
+    block[pos + HEADER_SZ..][..name.len()].copy_from_slice(name);
+}
+
+/// The type of an inode in a record
+pub fn file_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        _ => FT_UNKNOWN,
+    }
+}
+
+/// Read a block of a directory, a hole reads as one free record
+fn read_dir_block(fs: &Ext2FileSystem, dir: &DiskInode, index: u32, block: &mut [u8]) {
+    match fs.block_of(dir, index) {
+        0 => {
+            block.fill(0);
+            set_u16(block, 4, block.len() as u16);
+        }
+        id => fs.read_block(id, 0, block),
+    }
+}
+
+/// Used records of a directory, from a byte offset
+pub struct Entries<'a> {
+    fs: &'a Ext2FileSystem,
+    dir: &'a DiskInode,
+    pos: usize,
+    block: Vec<u8>,
+    /// The block of the directory read into `block`
+    read: Option<usize>,
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Entry;
+    fn next(&mut self) -> Option<Entry> {
+        let block_size = self.block.len();
+        let has_filetype = self.fs.has_filetype();
+        while self.pos < self.dir.size as usize {
+            let index = self.pos / block_size;
+            if self.read != Some(index) {
+                read_dir_block(self.fs, self.dir, index as u32, &mut self.block);
+                self.read = Some(index);
+            }
+            // walk from the start of the block, `pos` may be in a record
+            // merged with the one before it since it was returned
+            let start = index * block_size;
+            let mut offset = 0;
+            let mut found = None;
+            while let Some(record) = Record::parse(&self.block, offset, has_filetype) {
+                let next = offset + record.rec_len;
+                if start + offset >= self.pos && record.inode != 0 {
+                    found = Some((offset, record, next));
+                    break;
+                }
+                offset = next;
+            }
+            let Some((offset, record, next)) = found else {
+                // the rest of a broken block is skipped
+                self.pos = start + block_size;
+                continue;
+            };
+            self.pos = start + next;
+            let name = &self.block[offset + HEADER_SZ..][..record.name_len];
+            return Some(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                inode: record.inode,
+                pos: start + offset,
+                next: start + next,
+            });
+        }
+        None
+    }
+}
+
+/// Used records of a directory, from the byte offset `pos`
+pub fn entries<'a>(fs: &'a Ext2FileSystem, dir: &'a DiskInode, pos: usize) -> Entries<'a> {
+    Entries {
+        fs,
+        dir,
+        pos,
+        block: vec![0; fs.block_size()],
+        read: None,
+    }
+}
+
+/// Add a record for `inode` to a directory, in the first room large enough
+/// or in a new block. Return false if the volume is full
+pub fn insert_entry(
+    fs: &mut Ext2FileSystem,
+    dir: &mut DiskInode,
+    goal: u32,
+    name: &str,
+    inode: u32,
+    file_type: u8,
+) -> bool {
+    let has_filetype = fs.has_filetype();
+    let block_size = fs.block_size();
+    let needed = record_len(name.len());
+    // the hash tree of an indexed directory would miss the new name
+    dir.flags &= !INDEX_FL;
+    let mut block = vec![0u8; block_size];
+    for index in 0..(dir.size as usize / block_size) as u32 {
+        let id = fs.block_of(dir, index);
+        if id == 0 {
+            continue;
+        }
+        fs.read_block(id, 0, &mut block);
+        let mut pos = 0;
+        while let Some(record) = Record::parse(&block, pos, has_filetype) {
+            let used = record.used_len();
+            if record.rec_len - used >= needed {
+                if used > 0 {
+                    set_u16(&mut block, pos + 4, used as u16);
+                }
+                let rec_len = record.rec_len - used;
+                let name = name.as_bytes();
+                write_record(
+                    &mut block,
+                    pos + used,
+                    inode,
+                    rec_len,
+                    name,
+                    file_type,
+                    has_filetype,
+                );
+                fs.write_block(id, pos, &block[pos..pos + record.rec_len]);
+                return true;
+            }
+            pos += record.rec_len;
+        }
+    }
+    // a new block with one record covering it
+    block.fill(0);
+    write_record(
+        &mut block,
+        0,
+        inode,
+        block_size,
+        name.as_bytes(),
+        file_type,
+        has_filetype,
+    );
+    let index = (dir.size as usize / block_size) as u32;
+    let Some((id, _)) = fs.map_block(dir, index, goal) else {
+        return false;
+    };
+    fs.write_block(id, 0, &block);
+    dir.size += block_size as u64;
+    true
+}
+
+/// Remove the record at byte offset `pos` of a directory, the one before
+/// it in its block takes its room
+pub fn remove_entry(fs: &mut Ext2FileSystem, dir: &mut DiskInode, pos: usize) {
+    let has_filetype = fs.has_filetype();
+    let block_size = fs.block_size();
+    dir.flags &= !INDEX_FL;
+    let id = fs.block_of(dir, (pos / block_size) as u32);
+    let mut block = vec![0u8; block_size];
+    fs.read_block(id, 0, &mut block);
+    let target = pos % block_size;
+    let mut offset = 0;
+    let mut prev = None;
+    while let Some(record) = Record::parse(&block, offset, has_filetype) {
+        if offset == target {
+            match prev {
+                Some(prev) => {
+                    let prev_len = u16_at(&block, prev + 4) as usize;
+                    set_u16(&mut block, prev + 4, (prev_len + record.rec_len) as u16);
+                }
+                None => set_u32(&mut block, offset, 0),
+            }
+            fs.write_block(id, 0, &block);
+            return;
+        }
+        prev = Some(offset);
+        offset += record.rec_len;
+    }
+}
+
+/// The first block of a new directory, with `.` and `..`
+pub fn new_dir_block(fs: &Ext2FileSystem, inode: u32, parent: u32) -> Vec<u8> {
+    let has_filetype = fs.has_filetype();
+    let mut block = vec![0u8; fs.block_size()];
+    let dot_len = record_len(1);
+    write_record(&mut block, 0, inode, dot_len, b".", FT_DIR, has_filetype);
+    let rest = block.len() - dot_len;
+    write_record(
+        &mut block,
+        dot_len,
+        parent,
+        rest,
+        b"..",
+        FT_DIR,
+        has_filetype,
+    );
+    block
+}
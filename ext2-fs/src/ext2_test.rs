@@ -0,0 +1,293 @@
+//! Read and change the images made by `fixtures/make-fixtures` with mke2fs
+//! and debugfs, checking the results with e2fsck and debugfs when they are
+//! installed
+
+use crate::{BLOCK_SZ, Ext2FileSystem, Inode, OpenError};
+use easy_fs::{RamBlockDevice, test_data};
+use spin::Mutex;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+const IMAGES: &[&str] = &["ext2-1k.img", "ext2-4k.img"];
+
+fn load(image: &str) -> Arc<RamBlockDevice> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(image);
+    Arc::new(RamBlockDevice::from_image(std::fs::read(path).unwrap()))
+}
+
+fn open(device: &Arc<RamBlockDevice>) -> Arc<Mutex<Ext2FileSystem>> {
+    match Ext2FileSystem::open(device.clone()) {
+        Ok(fs) => fs,
+        Err(err) => panic!("cannot open the image: {err}"),
+    }
+}
+
+fn root(device: &Arc<RamBlockDevice>) -> Inode {
+    Ext2FileSystem::root_inode(&open(device))
+}
+
+/// The content of numbers.txt
+fn numbers() -> Vec<u8> {
+    (1..=60000)
+        .map(|i| format!("{i}\n"))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Read a whole file in pieces of `chunk` bytes
+fn read_all(inode: &Inode, chunk: usize) -> Vec<u8> {
+    let mut content = Vec::new();
+    let mut buf = vec![0u8; chunk];
+    loop {
+        let len = inode.read_at(content.len(), &mut buf);
+        if len == 0 {
+            return content;
+        }
+        content.extend_from_slice(&buf[..len]);
+    }
+}
+
+/// Check the image on `device` with `e2fsck -fn` and run the debugfs
+/// `request` on it. `None` if e2fsprogs is not installed
+fn e2fsprogs(device: &RamBlockDevice, tag: &str, request: &str) -> Option<Vec<u8>> {
+    let path = std::env::temp_dir().join(format!("ext2-test-{}-{tag}.img", std::process::id()));
+    std::fs::write(&path, device.image()).unwrap();
+    let fsck = match Command::new("e2fsck").arg("-fn").arg(&path).output() {
+        Ok(output) => output,
+        Err(_) => {
+            std::fs::remove_file(&path).unwrap();
+            return None;
+        }
+    };
+    let output = Command::new("debugfs")
+        .arg("-R")
+        .arg(request)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        fsck.status.success(),
+        "e2fsck found errors:\n{}",
+        String::from_utf8_lossy(&fsck.stdout)
+    );
+    Some(output.stdout)
+}
+
+#[test]
+fn open_rejects_unsupported_super_blocks() {
+    let patched = |pos: usize, value: u32| {
+        let mut image = load(IMAGES[0]).image();
+        image[1024 + pos..][..4].copy_from_slice(&value.to_le_bytes());
+        Ext2FileSystem::open(Arc::new(RamBlockDevice::from_image(image))).err()
+    };
+    assert_eq!(patched(56, 0), Some(OpenError::BadMagic));
+    // extents, metadata checksums and 16 KiB blocks
+    assert_eq!(
+        patched(96, 0x42),
+        Some(OpenError::UnsupportedFeatures(0x40))
+    );
+    assert_eq!(
+        patched(100, 0x403),
+        Some(OpenError::ReadOnlyFeatures(0x400))
+    );
+    assert_eq!(patched(24, 4), Some(OpenError::UnsupportedBlockSize(16384)));
+    assert_eq!(patched(76, 2), Some(OpenError::UnsupportedRevision(2)));
+    assert_eq!(patched(32, 0), Some(OpenError::Corrupted));
+}
+
+#[test]
+fn read_fixtures() {
+    for image in IMAGES {
+        let root = root(&load(image));
+        let mut names = root.ls();
+        names.sort();
+        assert_eq!(
+            names,
+            ["docs", "hello.txt", "link", "lost+found", "numbers.txt"]
+        );
+        let hello = root.find("hello.txt").unwrap();
+        assert!(!hello.is_dir());
+        assert_eq!(read_all(&hello, 64), b"hello from ext2\n");
+        let docs = root.find("docs").unwrap();
+        assert!(docs.is_dir());
+        let notes = docs.find("notes.txt").unwrap();
+        assert_eq!(read_all(&notes, 7), b"written by debugfs\n");
+        // over the double indirect block with 1 KiB blocks
+        let file = root.find("numbers.txt").unwrap();
+        assert_eq!(file.size(), numbers().len());
+        assert_eq!(read_all(&file, 1000), numbers());
+        // a fast symlink keeps its target in the inode, not in blocks
+        let link = root.find("link").unwrap();
+        assert_eq!(link.size(), "hello.txt".len());
+        assert_eq!(link.read_at(0, &mut [0; 16]), 0);
+        assert!(root.find("missing").is_none());
+        assert!(root.find("..").is_none());
+        assert!(hello.find("x").is_none());
+        // entries in order, each read once
+        let mut offset = 0;
+        let mut read = Vec::new();
+        while let Some((name, inode, next)) = root.read_dir(offset) {
+            assert_eq!(inode.inode_id(), root.find(&name).unwrap().inode_id());
+            read.push(name);
+            offset = next;
+        }
+        assert_eq!(read, root.ls());
+    }
+}
+
+#[test]
+fn write_round_trip() {
+    for (i, image) in IMAGES.iter().enumerate() {
+        let device = load(image);
+        let fs = open(&device);
+        let free = fs.lock().free_counts();
+        let root = Ext2FileSystem::root_inode(&fs);
+        let big = test_data(300 * 1024 + 7, i);
+        let file = root.create("new.txt").unwrap();
+        for (n, chunk) in big.chunks(3000).enumerate() {
+            assert_eq!(file.write_at(n * 3000, chunk), chunk.len());
+        }
+        assert!(root.create("new.txt").is_none());
+        let dir = root.create_dir("made").unwrap();
+        let inner = dir.create("inner.txt").unwrap();
+        assert_eq!(inner.write_at(0, b"inside"), 6);
+        // overwrite in place
+        assert_eq!(file.write_at(1000, b"patched"), 7);
+        let mut expected = big.clone();
+        expected[1000..1007].copy_from_slice(b"patched");
+
+        let root = self::root(&device);
+        let file = root.find("new.txt").unwrap();
+        assert_eq!(read_all(&file, 4096), expected);
+        let inner = root.find("made").unwrap().find("inner.txt").unwrap();
+        assert_eq!(read_all(&inner, 64), b"inside");
+        assert_eq!(
+            read_all(&root.find("numbers.txt").unwrap(), 4096),
+            numbers()
+        );
+        let tag = format!("round-trip-{i}");
+        if let Some(out) = e2fsprogs(&device, &tag, "cat /new.txt") {
+            assert_eq!(out, expected);
+            let out = e2fsprogs(&device, &tag, "cat /made/inner.txt").unwrap();
+            assert_eq!(out, b"inside");
+        }
+
+        assert!(root.unlink("new.txt"));
+        assert!(!root.unlink("new.txt"));
+        assert!(!root.unlink("made"));
+        assert!(root.find("made").unwrap().unlink("inner.txt"));
+        assert!(root.find("new.txt").is_none());
+        // only the directory is left
+        let (blocks, inodes) = open(&device).lock().free_counts();
+        assert_eq!((blocks + 1, inodes + 1), free);
+        e2fsprogs(&device, &tag, "ls");
+    }
+}
+
+#[test]
+fn truncate_and_holes() {
+    for (i, image) in IMAGES.iter().enumerate() {
+        let device = load(image);
+        let fs = open(&device);
+        let (free_blocks, _) = fs.lock().free_counts();
+        let block_size = fs.lock().block_size();
+        let root = Ext2FileSystem::root_inode(&fs);
+        let file = root.create("sparse").unwrap();
+        // past the direct blocks, leaving them as holes
+        let offset = 20 * block_size + 3;
+        assert_eq!(file.write_at(offset, b"end"), 3);
+        assert_eq!(file.size(), offset + 3);
+        let content = read_all(&file, BLOCK_SZ);
+        assert!(content[..offset].iter().all(|&b| b == 0));
+        assert_eq!(&content[offset..], b"end");
+        // a data block and an indirect block
+        assert_eq!(fs.lock().free_counts().0, free_blocks - 2);
+
+        let full = test_data(30 * block_size, i);
+        assert_eq!(file.write_at(0, &full), full.len());
+        file.truncate(block_size + 5);
+        assert_eq!(fs.lock().free_counts().0, free_blocks - 2);
+        // the truncated tail reads as zeros after growing again
+        file.truncate(3 * block_size);
+        let content = read_all(&file, 1000);
+        assert_eq!(&content[..block_size + 5], &full[..block_size + 5]);
+        assert!(content[block_size + 5..].iter().all(|&b| b == 0));
+        e2fsprogs(&device, &format!("truncate-{i}"), "ls");
+
+        file.truncate(0);
+        assert_eq!(file.size(), 0);
+        assert_eq!(fs.lock().free_counts().0, free_blocks);
+        assert!(root.unlink("sparse"));
+        e2fsprogs(&device, &format!("truncate-{i}"), "ls");
+    }
+}
+
+#[test]
+fn directory_grows_over_blocks() {
+    for (i, image) in IMAGES.iter().enumerate() {
+        let device = load(image);
+        let root = root(&device);
+        let dir = root.create_dir("many").unwrap();
+        let name = |n: usize| format!("a file with a rather long name {n:03}");
+        // fewer than the inodes left
+        for n in 0..40 {
+            let file = dir.create(&name(n)).unwrap();
+            file.write_at(0, name(n).as_bytes());
+        }
+        let size = dir.size();
+        assert!(size > 1024);
+        assert_eq!(dir.ls().len(), 40);
+        for n in (0..40).step_by(2) {
+            assert!(dir.unlink(&name(n)));
+        }
+        let left: Vec<_> = (1..40).step_by(2).map(name).collect();
+        assert_eq!(dir.ls(), left);
+        // the room of the removed names is taken again
+        for n in (0..40).step_by(2) {
+            dir.create(&name(n)).unwrap();
+        }
+        assert_eq!(dir.size(), size);
+        let file = dir.find(&name(39)).unwrap();
+        assert_eq!(read_all(&file, 100), name(39).as_bytes());
+        if let Some(out) = e2fsprogs(&device, &format!("dir-{i}"), "ls -p /many") {
+            let listed = String::from_utf8(out).unwrap();
+            // with `.` and `..`
+            assert_eq!(listed.lines().filter(|line| !line.is_empty()).count(), 42);
+        }
+    }
+}
+
+#[test]
+fn full_volume() {
+    for (i, image) in IMAGES.iter().enumerate() {
+        let device = load(image);
+        let fs = open(&device);
+        let free = fs.lock().free_counts();
+        let root = Ext2FileSystem::root_inode(&fs);
+        let file = root.create("filler").unwrap();
+        let chunk = test_data(64 * 1024, i);
+        let mut written = 0;
+        loop {
+            let len = file.write_at(written, &chunk);
+            written += len;
+            if len < chunk.len() {
+                break;
+            }
+        }
+        assert_eq!(fs.lock().free_counts().0, 0);
+        assert_eq!(file.size(), written);
+        assert_eq!(
+            read_all(&file, 4096),
+            chunk.repeat(written.div_ceil(chunk.len()))[..written]
+        );
+        assert!(root.create_dir("no room").is_none());
+        e2fsprogs(&device, &format!("full-{i}"), "ls");
+        assert!(root.unlink("filler"));
+        assert_eq!(fs.lock().free_counts(), free);
+        e2fsprogs(&device, &format!("full-{i}"), "ls");
+    }
+}
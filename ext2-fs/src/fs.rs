@@ -0,0 +1,499 @@
+use super::BLOCK_SZ;
+use super::layout::{
+    DIRECT_BLOCKS, DiskInode, GROUP_DESC_COUNTS, GROUP_DESC_SZ, GroupDesc, INCOMPAT_FILETYPE,
+    OpenError, ROOT_INO, SUPER_BLOCK_COUNTS, SUPER_BLOCK_POS, SUPER_BLOCK_SZ, SuperBlock, u32_at,
+};
+use super::vfs::Inode;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use easy_fs::BlockDevice;
+use spin::Mutex;
+
+/// An ext2 volume on a block device
+pub struct Ext2FileSystem {
+    device: Arc<dyn BlockDevice>,
+    super_block: SuperBlock,
+    groups: Vec<GroupDesc>,
+}
+
+impl Ext2FileSystem {
+    /// Open the volume on `device`
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Arc<Mutex<Self>>, OpenError> {
+        let mut raw = [0u8; SUPER_BLOCK_SZ];
+        read_bytes(&device, SUPER_BLOCK_POS, &mut raw);
+        let super_block = SuperBlock::parse(&raw)?;
+        let group_count = super_block.group_count() as usize;
+        if super_block.inodes_count / super_block.inodes_per_group < group_count as u32 {
+            return Err(OpenError::Corrupted);
+        }
+        // the descriptors are in the blocks after the super block
+        let mut table = vec![0u8; group_count * GROUP_DESC_SZ];
+        let table_pos = (super_block.first_data_block as u64 + 1) * super_block.block_size as u64;
+        read_bytes(&device, table_pos, &mut table);
+        let groups = table.chunks(GROUP_DESC_SZ).map(GroupDesc::parse).collect();
+        Ok(Arc::new(Mutex::new(Self {
+            device,
+            super_block,
+            groups,
+        })))
+    }
+    /// The root directory
+    pub fn root_inode(fs: &Arc<Mutex<Self>>) -> Inode {
+        Inode::new(ROOT_INO, Arc::clone(fs))
+    }
+    pub(crate) fn block_size(&self) -> usize {
+        self.super_block.block_size
+    }
+    /// Whether directory records hold the type of their inode
+    pub(crate) fn has_filetype(&self) -> bool {
+        self.super_block.feature_incompat & INCOMPAT_FILETYPE != 0
+    }
+    /// Number of free blocks and free inodes
+    pub fn free_counts(&self) -> (u32, u32) {
+        (
+            self.super_block.free_blocks_count,
+            self.super_block.free_inodes_count,
+        )
+    }
+    /// Read a block at `offset` in it
+    pub(crate) fn read_block(&self, block: u32, offset: usize, buf: &mut [u8]) {
+        let pos = block as u64 * self.block_size() as u64 + offset as u64;
+        read_bytes(&self.device, pos, buf);
+    }
+    /// Write a block at `offset` in it
+    pub(crate) fn write_block(&self, block: u32, offset: usize, buf: &[u8]) {
+        let pos = block as u64 * self.block_size() as u64 + offset as u64;
+        write_bytes(&self.device, pos, buf);
+    }
+    /// The group of an inode
+    pub(crate) fn inode_group(&self, ino: u32) -> u32 {
+        (ino - 1) / self.super_block.inodes_per_group
+    }
+    /// Byte offset of an inode on the device
+    fn inode_pos(&self, ino: u32) -> u64 {
+        let group = &self.groups[self.inode_group(ino) as usize];
+        let index = (ino - 1) % self.super_block.inodes_per_group;
+        group.inode_table as u64 * self.block_size() as u64
+            + index as u64 * self.super_block.inode_size as u64
+    }
+    pub(crate) fn read_inode(&self, ino: u32) -> DiskInode {
+        let mut raw = vec![0u8; self.super_block.inode_size];
+        read_bytes(&self.device, self.inode_pos(ino), &mut raw);
+        DiskInode::parse(raw)
+    }
+    pub(crate) fn write_inode(&self, ino: u32, inode: &DiskInode) {
+        write_bytes(&self.device, self.inode_pos(ino), &inode.to_bytes());
+    }
+    /// A new inode with the type and permissions `mode`
+    pub(crate) fn new_inode(&self, mode: u16) -> DiskInode {
+        DiskInode::new(mode, self.super_block.inode_size)
+    }
+    /// Write the free counts of the volume and of `group` back
+    fn write_counts(&self, group: usize) {
+        write_bytes(
+            &self.device,
+            SUPER_BLOCK_POS + SUPER_BLOCK_COUNTS,
+            &self.super_block.counts(),
+        );
+        let table_pos = (self.super_block.first_data_block as u64 + 1) * self.block_size() as u64;
+        write_bytes(
+            &self.device,
+            table_pos + (group * GROUP_DESC_SZ) as u64 + GROUP_DESC_COUNTS,
+            &self.groups[group].counts(),
+        );
+    }
+    /// Blocks in `group`, the last one may be shorter
+    fn group_blocks(&self, group: usize) -> u32 {
+        let start = group as u32 * self.super_block.blocks_per_group;
+        (self.super_block.blocks_count - self.super_block.first_data_block - start)
+            .min(self.super_block.blocks_per_group)
+    }
+    /// Set the first clear bit below `len` in the bitmap at `bitmap`, return its index
+    fn take_bit(&self, bitmap: u32, len: u32) -> Option<u32> {
+        let mut bits = vec![0u8; self.block_size()];
+        self.read_block(bitmap, 0, &mut bits);
+        let (byte, bit) = bits
+            .iter()
+            .enumerate()
+            .find(|(_, byte)| **byte != 0xff)
+            .map(|(i, byte)| (i, byte.trailing_ones()))?;
+        let index = byte as u32 * 8 + bit;
+        if index >= len {
+            return None;
+        }
+        self.write_block(bitmap, byte, &[bits[byte] | 1 << bit]);
+        Some(index)
+    }
+    /// Clear a bit of the bitmap at `bitmap`
+    fn clear_bit(&self, bitmap: u32, index: u32) {
+        let mut byte = [0u8];
+        self.read_block(bitmap, index as usize / 8, &mut byte);
+        assert!(
+            byte[0] & 1 << (index % 8) != 0,
+            "freeing a free ext2 block or inode"
+        );
+        self.write_block(bitmap, index as usize / 8, &[byte[0] & !(1 << (index % 8))]);
+    }
+    /// Groups from `goal`, wrapping around
+    fn groups_from(&self, goal: u32) -> impl Iterator<Item = usize> + use<> {
+        let count = self.groups.len();
+        (0..count).map(move |i| (goal as usize + i) % count)
+    }
+    /// Allocate a block, in group `goal` if there is room, its data is left as it was
+    fn alloc_block(&mut self, goal: u32) -> Option<u32> {
+        for group in self.groups_from(goal) {
+            if self.groups[group].free_blocks_count == 0 {
+                continue;
+            }
+            let bitmap = self.groups[group].block_bitmap;
+            let Some(index) = self.take_bit(bitmap, self.group_blocks(group)) else {
+                continue;
+            };
+            self.groups[group].free_blocks_count -= 1;
+            self.super_block.free_blocks_count -= 1;
+            self.write_counts(group);
+            return Some(
+                self.super_block.first_data_block
+                    + group as u32 * self.super_block.blocks_per_group
+                    + index,
+            );
+        }
+        None
+    }
+    /// Allocate a block filled with zeros
+    fn alloc_zeroed_block(&mut self, goal: u32) -> Option<u32> {
+        let block = self.alloc_block(goal)?;
+        self.write_block(block, 0, &vec![0; self.block_size()]);
+        Some(block)
+    }
+    fn free_block(&mut self, block: u32) {
+        let index = block - self.super_block.first_data_block;
+        let group = (index / self.super_block.blocks_per_group) as usize;
+        self.clear_bit(
+            self.groups[group].block_bitmap,
+            index % self.super_block.blocks_per_group,
+        );
+        self.groups[group].free_blocks_count += 1;
+        self.super_block.free_blocks_count += 1;
+        self.write_counts(group);
+    }
+    /// Allocate an inode, in group `goal` if there is room. The inode is not written
+    pub(crate) fn alloc_inode(&mut self, goal: u32, is_dir: bool) -> Option<u32> {
+        for group in self.groups_from(goal) {
+            if self.groups[group].free_inodes_count == 0 {
+                continue;
+            }
+            let bitmap = self.groups[group].inode_bitmap;
+            let Some(index) = self.take_bit(bitmap, self.super_block.inodes_per_group) else {
+                continue;
+            };
+            let ino = group as u32 * self.super_block.inodes_per_group + index + 1;
+            if ino < self.super_block.first_ino {
+                // the reserved inodes are marked used, unless the bitmap is broken
+                continue;
+            }
+            self.groups[group].free_inodes_count -= 1;
+            if is_dir {
+                self.groups[group].used_dirs_count += 1;
+            }
+            self.super_block.free_inodes_count -= 1;
+            self.write_counts(group);
+            return Some(ino);
+        }
+        None
+    }
+    /// Free an inode and clear it on the device
+    pub(crate) fn free_inode(&mut self, ino: u32, is_dir: bool) {
+        let group = self.inode_group(ino) as usize;
+        let index = (ino - 1) % self.super_block.inodes_per_group;
+        self.clear_bit(self.groups[group].inode_bitmap, index);
+        self.groups[group].free_inodes_count += 1;
+        if is_dir {
+            self.groups[group].used_dirs_count -= 1;
+        }
+        self.super_block.free_inodes_count += 1;
+        self.write_counts(group);
+        write_bytes(
+            &self.device,
+            self.inode_pos(ino),
+            &vec![0; self.super_block.inode_size],
+        );
+    }
+    /// Block addresses in an indirect block
+    fn per_block(&self) -> u32 {
+        (self.block_size() / 4) as u32
+    }
+    /// Sectors counted in `i_blocks` for a block
+    fn block_sectors(&self) -> u32 {
+        (self.block_size() / 512) as u32
+    }
+    /// Where block `index` of a file is mapped: the slot in `i_block`
+    /// and the index in each level of indirect blocks under it
+    fn block_path(&self, index: u32) -> Option<(usize, Vec<u32>)> {
+        let per = self.per_block() as u64;
+        let mut index = index as u64;
+        if index < DIRECT_BLOCKS as u64 {
+            return Some((index as usize, Vec::new()));
+        }
+        index -= DIRECT_BLOCKS as u64;
+        let mut span = per;
+        for depth in 1..=3 {
+            if index < span {
+                let mut path = vec![0; depth];
+                for level in (0..depth).rev() {
+                    path[level] = (index % per) as u32;
+                    index /= per;
+                }
+                return Some((DIRECT_BLOCKS + depth - 1, path));
+            }
+            index -= span;
+            span *= per;
+        }
+        None
+    }
+    /// Read the block address at `index` in the indirect block `block`
+    fn read_entry(&self, block: u32, index: u32) -> u32 {
+        let mut raw = [0u8; 4];
+        self.read_block(block, index as usize * 4, &mut raw);
+        u32_at(&raw, 0)
+    }
+    /// The block holding block `index` of a file, 0 for a hole
+    pub(crate) fn block_of(&self, inode: &DiskInode, index: u32) -> u32 {
+        let Some((slot, path)) = self.block_path(index) else {
+            return 0;
+        };
+        let mut block = inode.block[slot];
+        for &entry in &path {
+            if block == 0 {
+                break;
+            }
+            block = self.read_entry(block, entry);
+        }
+        block
+    }
+    /// The block holding block `index` of a file, allocated with the indirect
+    /// blocks leading to it if it is a hole. Also return whether it is new
+    pub(crate) fn map_block(
+        &mut self,
+        inode: &mut DiskInode,
+        index: u32,
+        goal: u32,
+    ) -> Option<(u32, bool)> {
+        let (slot, path) = self.block_path(index)?;
+        let mut is_new = false;
+        if inode.block[slot] == 0 {
+            // an indirect block is read before it is written
+            inode.block[slot] = match path.is_empty() {
+                true => self.alloc_block(goal)?,
+                false => self.alloc_zeroed_block(goal)?,
+            };
+            inode.sectors += self.block_sectors();
+            is_new = true;
+        }
+        let mut block = inode.block[slot];
+        for (level, &entry) in path.iter().enumerate() {
+            let mut next = self.read_entry(block, entry);
+            if next == 0 {
+                next = match level + 1 == path.len() {
+                    true => self.alloc_block(goal)?,
+                    false => self.alloc_zeroed_block(goal)?,
+                };
+                self.write_block(block, entry as usize * 4, &next.to_le_bytes());
+                inode.sectors += self.block_sectors();
+                is_new = true;
+            }
+            block = next;
+        }
+        Some((block, is_new))
+    }
+    /// Free `block` and, if it is an indirect block of `depth` levels,
+    /// the blocks under it. Return the number freed
+    fn free_tree(&mut self, block: u32, depth: u32) -> u32 {
+        let mut freed = 1;
+        if depth > 0 {
+            for entry in 0..self.per_block() {
+                let child = self.read_entry(block, entry);
+                if child != 0 {
+                    freed += self.free_tree(child, depth - 1);
+                }
+            }
+        }
+        self.free_block(block);
+        freed
+    }
+    /// Free the blocks at and after `start` under the indirect block `block`
+    /// of `depth` levels. Return the number freed and whether it is empty now
+    fn free_tree_from(&mut self, block: u32, depth: u32, start: u64) -> (u32, bool) {
+        let span = (self.per_block() as u64).pow(depth - 1);
+        let mut freed = 0;
+        let mut empty = true;
+        for entry in 0..self.per_block() {
+            let child = self.read_entry(block, entry);
+            if child == 0 {
+                continue;
+            }
+            let child_start = entry as u64 * span;
+            let child_empty = if start <= child_start {
+                freed += self.free_tree(child, depth - 1);
+                true
+            } else if depth > 1 && start < child_start + span {
+                let (child_freed, child_empty) =
+                    self.free_tree_from(child, depth - 1, start - child_start);
+                freed += child_freed;
+                if child_empty {
+                    self.free_block(child);
+                    freed += 1;
+                }
+                child_empty
+            } else {
+                false
+            };
+            if child_empty {
+                self.write_block(block, entry as usize * 4, &0u32.to_le_bytes());
+            } else {
+                empty = false;
+            }
+        }
+        (freed, empty)
+    }
+    /// Free the blocks of a file at and after block `start`
+    pub(crate) fn free_blocks_from(&mut self, inode: &mut DiskInode, start: u32) {
+        let mut freed = 0;
+        for block in inode.block[..DIRECT_BLOCKS].iter_mut().skip(start as usize) {
+            if *block != 0 {
+                freed += self.free_tree(*block, 0);
+                *block = 0;
+            }
+        }
+        let per = self.per_block() as u64;
+        let mut first = DIRECT_BLOCKS as u64;
+        let mut span = per;
+        for depth in 1..=3 {
+            let slot = DIRECT_BLOCKS + depth as usize - 1;
+            let block = inode.block[slot];
+            if block != 0 && (start as u64) < first + span {
+                if start as u64 <= first {
+                    freed += self.free_tree(block, depth);
+                    inode.block[slot] = 0;
+                } else {
+                    let (tree_freed, empty) =
+                        self.free_tree_from(block, depth, start as u64 - first);
+                    freed += tree_freed;
+                    if empty {
+                        self.free_block(block);
+                        freed += 1;
+                        inode.block[slot] = 0;
+                    }
+                }
+            }
+            first += span;
+            span *= per;
+        }
+        inode.sectors -= freed * self.block_sectors();
+    }
+    /// Read the data of a file at `offset`, return the bytes read
+    pub(crate) fn read_data(&self, inode: &DiskInode, offset: usize, buf: &mut [u8]) -> usize {
+        if !inode.has_blocks() {
+            return 0;
+        }
+        let block_size = self.block_size();
+        let end = (offset + buf.len()).min(inode.size as usize);
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % block_size;
+            let len = (block_size - block_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..][..len];
+            match self.block_of(inode, (pos / block_size) as u32) {
+                0 => dst.fill(0),
+                block => self.read_block(block, block_offset, dst),
+            }
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    /// Write the data of a file at `offset`, growing it, with new blocks
+    /// in group `goal` if there is room. Return the bytes written, which
+    /// are fewer than asked when the volume is full
+    pub(crate) fn write_data(
+        &mut self,
+        inode: &mut DiskInode,
+        goal: u32,
+        offset: usize,
+        buf: &[u8],
+    ) -> usize {
+        if !inode.has_blocks() {
+            return 0;
+        }
+        let block_size = self.block_size();
+        let end = offset + buf.len();
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % block_size;
+            let len = (block_size - block_offset).min(end - pos);
+            let Some((block, is_new)) = self.map_block(inode, (pos / block_size) as u32, goal)
+            else {
+                break;
+            };
+            if is_new && len < block_size {
+                self.write_block(block, 0, &vec![0; block_size]);
+            }
+            self.write_block(block, block_offset, &buf[pos - offset..][..len]);
+            pos += len;
+        }
+        inode.size = inode.size.max(pos as u64);
+        pos.saturating_sub(offset)
+    }
+    /// Set the size of a file, bytes past the old size read as zero
+    pub(crate) fn truncate(&mut self, inode: &mut DiskInode, size: usize) {
+        if !inode.has_blocks() {
+            return;
+        }
+        let block_size = self.block_size();
+        if size < inode.size as usize {
+            self.free_blocks_from(inode, size.div_ceil(block_size) as u32);
+            // keep the tail of the last block zeroed for a later growth
+            if !size.is_multiple_of(block_size) {
+                let block = self.block_of(inode, (size / block_size) as u32);
+                if block != 0 {
+                    let tail = block_size - size % block_size;
+                    self.write_block(block, size % block_size, &vec![0; tail]);
+                }
+            }
+        }
+        // growing leaves a hole
+        inode.size = size as u64;
+    }
+}
+
+/// Read the device at a byte offset
+fn read_bytes(device: &Arc<dyn BlockDevice>, offset: u64, buf: &mut [u8]) {
+    let mut sector = [0u8; BLOCK_SZ];
+    let mut done = 0;
+    while done < buf.len() {
+        let pos = offset + done as u64;
+        let sector_offset = (pos % BLOCK_SZ as u64) as usize;
+        let len = (BLOCK_SZ - sector_offset).min(buf.len() - done);
+        device.read_block((pos / BLOCK_SZ as u64) as usize, &mut sector);
+        buf[done..][..len].copy_from_slice(&sector[sector_offset..][..len]);
+        done += len;
+    }
+}
+
+/// Write the device at a byte offset, keeping the rest of sectors written in part
+fn write_bytes(device: &Arc<dyn BlockDevice>, offset: u64, buf: &[u8]) {
+    let mut sector = [0u8; BLOCK_SZ];
+    let mut done = 0;
+    while done < buf.len() {
+        let pos = offset + done as u64;
+        let sector_id = (pos / BLOCK_SZ as u64) as usize;
+        let sector_offset = (pos % BLOCK_SZ as u64) as usize;
+        let len = (BLOCK_SZ - sector_offset).min(buf.len() - done);
+        if len < BLOCK_SZ {
+            device.read_block(sector_id, &mut sector);
+        }
+        sector[sector_offset..][..len].copy_from_slice(&buf[done..][..len]);
+        device.write_block(sector_id, &sector);
+        done += len;
+    }
+}
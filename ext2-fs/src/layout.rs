@@ -0,0 +1,281 @@
+//! The super block, group descriptors and inodes as laid out on the device,
+//! little endian
+
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const EXT2_MAGIC: u16 = 0xef53;
+/// Byte offset of the super block on the device, after the boot block
+pub const SUPER_BLOCK_POS: u64 = 1024;
+pub const SUPER_BLOCK_SZ: usize = 1024;
+pub const GROUP_DESC_SZ: usize = 32;
+pub const ROOT_INO: u32 = 2;
+/// Direct blocks in `i_block`, the single, double and triple indirect ones follow
+pub const DIRECT_BLOCKS: usize = 12;
+/// Blocks are 1 KiB shifted left by up to this
+const MAX_LOG_BLOCK_SIZE: u32 = 2;
+
+/// Directory entries hold the type of their inode
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Only some groups hold a backup of the super block
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Sizes of regular files have 64 bits
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+/// The type in the upper bits of `i_mode`
+pub const S_IFMT: u16 = 0xf000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFDIR: u16 = 0x4000;
+const S_IFLNK: u16 = 0xa000;
+/// The flag of a directory indexed by a hash tree, which is not kept up to date here
+pub const INDEX_FL: u32 = 0x1000;
+
+pub fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+pub fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+pub fn set_u16(buf: &mut [u8], pos: usize, value: u16) {
+    buf[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn set_u32(buf: &mut [u8], pos: usize, value: u32) {
+    buf[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Why a volume cannot be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    /// The super block has no ext2 magic number
+    BadMagic,
+    /// A revision after 1
+    UnsupportedRevision(u32),
+    /// Blocks larger than 4 KiB
+    UnsupportedBlockSize(u32),
+    /// Incompatible features which are not implemented, like extents
+    UnsupportedFeatures(u32),
+    /// Features which allow reading but not writing without knowing them
+    ReadOnlyFeatures(u32),
+    /// Counts in the super block which make no sense
+    Corrupted,
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::BadMagic => write!(f, "no ext2 magic number"),
+            Self::UnsupportedRevision(rev) => write!(f, "unsupported ext2 revision {rev}"),
+            Self::UnsupportedBlockSize(size) => write!(f, "unsupported ext2 block size {size}"),
+            Self::UnsupportedFeatures(features) => {
+                write!(f, "unsupported incompatible features {features:#x}")
+            }
+            Self::ReadOnlyFeatures(features) => {
+                write!(f, "unsupported read-only compatible features {features:#x}")
+            }
+            Self::Corrupted => write!(f, "corrupted super block"),
+        }
+    }
+}
+
+/// What is needed from the super block
+pub struct SuperBlock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    /// The block holding the super block, 1 for 1 KiB blocks and 0 otherwise
+    pub first_data_block: u32,
+    pub block_size: usize,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    /// The first inode which is not reserved
+    pub first_ino: u32,
+    pub inode_size: usize,
+    pub feature_incompat: u32,
+}
+
+/// Offsets of the free counts in the super block
+const FREE_BLOCKS_COUNT: usize = 12;
+const FREE_INODES_COUNT: usize = 16;
+/// Offset of the free counts in the super block, as written by `SuperBlock::counts`
+pub const SUPER_BLOCK_COUNTS: u64 = FREE_BLOCKS_COUNT as u64;
+/// Offset of the counts in a group descriptor, as written by `GroupDesc::counts`
+pub const GROUP_DESC_COUNTS: u64 = 12;
+
+impl SuperBlock {
+    pub fn parse(raw: &[u8; SUPER_BLOCK_SZ]) -> Result<Self, OpenError> {
+        if u16_at(raw, 56) != EXT2_MAGIC {
+            return Err(OpenError::BadMagic);
+        }
+        let rev_level = u32_at(raw, 76);
+        let log_block_size = u32_at(raw, 24);
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            return Err(OpenError::UnsupportedBlockSize(
+                1024u32.checked_shl(log_block_size).unwrap_or(0),
+            ));
+        }
+        let mut super_block = Self {
+            inodes_count: u32_at(raw, 0),
+            blocks_count: u32_at(raw, 4),
+            free_blocks_count: u32_at(raw, FREE_BLOCKS_COUNT),
+            free_inodes_count: u32_at(raw, FREE_INODES_COUNT),
+            first_data_block: u32_at(raw, 20),
+            block_size: 1024 << log_block_size,
+            blocks_per_group: u32_at(raw, 32),
+            inodes_per_group: u32_at(raw, 40),
+            // revision 0 has fixed inodes and no features
+            first_ino: 11,
+            inode_size: 128,
+            feature_incompat: 0,
+        };
+        match rev_level {
+            0 => {}
+            1 => {
+                super_block.first_ino = u32_at(raw, 84);
+                super_block.inode_size = u16_at(raw, 88) as usize;
+                super_block.feature_incompat = u32_at(raw, 96);
+                let unsupported = super_block.feature_incompat & !INCOMPAT_SUPPORTED;
+                if unsupported != 0 {
+                    return Err(OpenError::UnsupportedFeatures(unsupported));
+                }
+                let read_only = u32_at(raw, 100) & !RO_COMPAT_SUPPORTED;
+                if read_only != 0 {
+                    return Err(OpenError::ReadOnlyFeatures(read_only));
+                }
+            }
+            rev => return Err(OpenError::UnsupportedRevision(rev)),
+        }
+        let inode_size = super_block.inode_size;
+        if super_block.blocks_per_group == 0
+            || super_block.inodes_per_group == 0
+            || super_block.first_data_block >= super_block.blocks_count
+            || inode_size < 128
+            || !inode_size.is_power_of_two()
+            || inode_size > super_block.block_size
+        {
+            return Err(OpenError::Corrupted);
+        }
+        Ok(super_block)
+    }
+    /// Number of block groups
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+    /// The free counts as laid out at `SUPER_BLOCK_COUNTS`
+    pub fn counts(&self) -> [u8; 8] {
+        let mut raw = [0; 8];
+        set_u32(&mut raw, 0, self.free_blocks_count);
+        set_u32(&mut raw, 4, self.free_inodes_count);
+        raw
+    }
+}
+
+/// A block group descriptor
+#[derive(Clone, Copy)]
+pub struct GroupDesc {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+}
+
+impl GroupDesc {
+    pub fn parse(raw: &[u8]) -> Self {
+        Self {
+            block_bitmap: u32_at(raw, 0),
+            inode_bitmap: u32_at(raw, 4),
+            inode_table: u32_at(raw, 8),
+            free_blocks_count: u16_at(raw, 12),
+            free_inodes_count: u16_at(raw, 14),
+            used_dirs_count: u16_at(raw, 16),
+        }
+    }
+    /// The counts as laid out at `GROUP_DESC_COUNTS`
+    pub fn counts(&self) -> [u8; 6] {
+        let mut raw = [0; 6];
+        set_u16(&mut raw, 0, self.free_blocks_count);
+        set_u16(&mut raw, 2, self.free_inodes_count);
+        set_u16(&mut raw, 4, self.used_dirs_count);
+        raw
+    }
+}
+
+/// An inode, with the raw bytes it was read from so the fields
+/// which are not known here are written back as they were
+#[derive(Clone)]
+pub struct DiskInode {
+    pub mode: u16,
+    pub size: u64,
+    pub links_count: u16,
+    /// Blocks held, counted in 512-byte sectors
+    pub sectors: u32,
+    pub flags: u32,
+    pub block: [u32; 15],
+    raw: Vec<u8>,
+}
+
+impl DiskInode {
+    /// A cleared inode of `inode_size` bytes with the type and permissions `mode`
+    pub fn new(mode: u16, inode_size: usize) -> Self {
+        let mut inode = Self::parse(alloc::vec![0; inode_size]);
+        inode.mode = mode;
+        inode
+    }
+    pub fn parse(raw: Vec<u8>) -> Self {
+        let mode = u16_at(&raw, 0);
+        let mut size = u32_at(&raw, 4) as u64;
+        if mode & S_IFMT == S_IFREG {
+            size |= (u32_at(&raw, 108) as u64) << 32;
+        }
+        let mut block = [0; 15];
+        for (i, block) in block.iter_mut().enumerate() {
+            *block = u32_at(&raw, 40 + i * 4);
+        }
+        Self {
+            mode,
+            size,
+            links_count: u16_at(&raw, 26),
+            sectors: u32_at(&raw, 28),
+            flags: u32_at(&raw, 32),
+            block,
+            raw,
+        }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = self.raw.clone();
+        set_u16(&mut raw, 0, self.mode);
+        set_u32(&mut raw, 4, self.size as u32);
+        if self.is_file() {
+            set_u32(&mut raw, 108, (self.size >> 32) as u32);
+        }
+        set_u16(&mut raw, 26, self.links_count);
+        set_u32(&mut raw, 28, self.sectors);
+        set_u32(&mut raw, 32, self.flags);
+        for (i, &block) in self.block.iter().enumerate() {
+            set_u32(&mut raw, 40 + i * 4, block);
+        }
+        raw
+    }
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+    /// Whether `block` maps data blocks. Devices keep their number there
+    /// and fast symlinks, which hold no blocks, their target
+    pub fn has_blocks(&self) -> bool {
+        match self.mode & S_IFMT {
+            S_IFREG | S_IFDIR => true,
+            S_IFLNK => self.sectors != 0,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! An ext2 driver over the `BlockDevice` of easy-fs, for revision 0 and 1
+//! volumes with blocks of 1 KiB to 4 KiB, as made by `mke2fs -t ext2`
+//!
+//! Files are mapped by the 12 direct and 3 indirect blocks of their inode
+//! and directories are lists of records, read and changed in place. The
+//! device is written through, nothing is cached
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+mod dir;
+#[cfg(test)]
+mod ext2_test;
+mod fs;
+mod layout;
+mod vfs;
+use config::fs::BLOCK_SZ;
+
+pub use easy_fs::BlockDevice;
+pub use fs::Ext2FileSystem;
+pub use layout::OpenError;
+pub use vfs::Inode;
@@ -0,0 +1,188 @@
+use super::dir::{self, Entry, file_type};
+use super::fs::Ext2FileSystem;
+use super::layout::{DiskInode, S_IFDIR, S_IFREG};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// The longest name of a directory record
+const NAME_LENGTH_LIMIT: usize = 255;
+/// Permissions of new files and directories
+const FILE_PERMISSIONS: u16 = 0o644;
+const DIR_PERMISSIONS: u16 = 0o755;
+
+/// A file or directory of an ext2 volume, by its inode number
+pub struct Inode {
+    ino: u32,
+    fs: Arc<Mutex<Ext2FileSystem>>,
+}
+
+impl Inode {
+    pub(crate) fn new(ino: u32, fs: Arc<Mutex<Ext2FileSystem>>) -> Self {
+        Self { ino, fs }
+    }
+    fn child(&self, ino: u32) -> Arc<Inode> {
+        Arc::new(Self::new(ino, Arc::clone(&self.fs)))
+    }
+    /// Find the record of a name under a directory, not `.` or `..`
+    fn find_entry(fs: &Ext2FileSystem, dir: &DiskInode, name: &str) -> Option<Entry> {
+        if !dir.is_dir() || name == "." || name == ".." {
+            return None;
+        }
+        dir::entries(fs, dir, 0).find(|entry| entry.name == name)
+    }
+    /// Find inode under current inode by name
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let fs = self.fs.lock();
+        let dir = fs.read_inode(self.ino);
+        Self::find_entry(&fs, &dir, name).map(|entry| self.child(entry.inode))
+    }
+    /// Create a file under current inode by name.
+    /// Return `None` if the name exists or the volume is full
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, S_IFREG | FILE_PERMISSIONS)
+    }
+    /// Create a directory under current inode by name
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, S_IFDIR | DIR_PERMISSIONS)
+    }
+    fn create_inode(&self, name: &str, mode: u16) -> Option<Arc<Inode>> {
+        let mut fs = self.fs.lock();
+        let mut dir = fs.read_inode(self.ino);
+        if name.is_empty()
+            || name.len() > NAME_LENGTH_LIMIT
+            || name.contains('/')
+            || !dir.is_dir()
+            || name == "."
+            || name == ".."
+            || Self::find_entry(&fs, &dir, name).is_some()
+        {
+            return None;
+        }
+        let goal = fs.inode_group(self.ino);
+        let mut inode = fs.new_inode(mode);
+        let is_dir = inode.is_dir();
+        let ino = fs.alloc_inode(goal, is_dir)?;
+        inode.links_count = 1;
+        if is_dir {
+            // its `.` and the `..` of the parent
+            inode.links_count = 2;
+            let block = dir::new_dir_block(&fs, ino, self.ino);
+            let written = fs.write_data(&mut inode, goal, 0, &block);
+            if written < block.len() {
+                fs.free_blocks_from(&mut inode, 0);
+                fs.free_inode(ino, is_dir);
+                return None;
+            }
+        }
+        fs.write_inode(ino, &inode);
+        if !dir::insert_entry(&mut fs, &mut dir, goal, name, ino, file_type(mode)) {
+            fs.free_blocks_from(&mut inode, 0);
+            fs.free_inode(ino, is_dir);
+            fs.write_inode(self.ino, &dir);
+            return None;
+        }
+        if is_dir {
+            dir.links_count += 1;
+        }
+        fs.write_inode(self.ino, &dir);
+        Some(self.child(ino))
+    }
+    /// List inodes under current inode
+    pub fn ls(&self) -> Vec<String> {
+        let fs = self.fs.lock();
+        let dir = fs.read_inode(self.ino);
+        if !dir.is_dir() {
+            return Vec::new();
+        }
+        dir::entries(&fs, &dir, 0)
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .map(|entry| entry.name)
+            .collect()
+    }
+    /// Read the first entry under current inode at or after `offset`, which starts from 0,
+    /// skipping `.` and `..`. Return its name, its inode and the offset to read the entry after it
+    pub fn read_dir(&self, offset: usize) -> Option<(String, Arc<Inode>, usize)> {
+        let fs = self.fs.lock();
+        let dir = fs.read_inode(self.ino);
+        if !dir.is_dir() {
+            return None;
+        }
+        let entry = dir::entries(&fs, &dir, offset)
+            .find(|entry| entry.name != "." && entry.name != "..")?;
+        Some((entry.name, self.child(entry.inode), entry.next))
+    }
+    /// The max length of a name under current inode
+    pub fn name_length_limit(&self) -> usize {
+        NAME_LENGTH_LIMIT
+    }
+    /// Inode number of current inode
+    pub fn inode_id(&self) -> u32 {
+        self.ino
+    }
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        self.fs.lock().read_inode(self.ino).is_dir()
+    }
+    /// Size of current inode in bytes
+    pub fn size(&self) -> usize {
+        self.fs.lock().read_inode(self.ino).size as usize
+    }
+    /// Read data from current inode
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let fs = self.fs.lock();
+        let inode = fs.read_inode(self.ino);
+        if inode.is_dir() {
+            return 0;
+        }
+        fs.read_data(&inode, offset, buf)
+    }
+    /// Write data to current inode, return the bytes written
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut fs = self.fs.lock();
+        let mut inode = fs.read_inode(self.ino);
+        if inode.is_dir() {
+            return 0;
+        }
+        let goal = fs.inode_group(self.ino);
+        let written = fs.write_data(&mut inode, goal, offset, buf);
+        fs.write_inode(self.ino, &inode);
+        written
+    }
+    /// Set the size of current inode, bytes past the old size read as zero
+    pub fn truncate(&self, new_size: usize) {
+        let mut fs = self.fs.lock();
+        let mut inode = fs.read_inode(self.ino);
+        if inode.is_dir() {
+            return;
+        }
+        fs.truncate(&mut inode, new_size);
+        fs.write_inode(self.ino, &inode);
+    }
+    /// Remove a file under current inode by name, and free its inode and blocks
+    /// once no other name links to it. Return false if there is no such file
+    pub fn unlink(&self, name: &str) -> bool {
+        let mut fs = self.fs.lock();
+        let mut dir = fs.read_inode(self.ino);
+        let Some(entry) = Self::find_entry(&fs, &dir, name) else {
+            return false;
+        };
+        let mut inode = fs.read_inode(entry.inode);
+        if inode.is_dir() {
+            return false;
+        }
+        dir::remove_entry(&mut fs, &mut dir, entry.pos);
+        fs.write_inode(self.ino, &dir);
+        inode.links_count = inode.links_count.saturating_sub(1);
+        if inode.links_count > 0 {
+            fs.write_inode(entry.inode, &inode);
+            return true;
+        }
+        if inode.has_blocks() {
+            fs.free_blocks_from(&mut inode, 0);
+        }
+        fs.free_inode(entry.inode, false);
+        true
+    }
+}
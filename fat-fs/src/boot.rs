@@ -0,0 +1,87 @@
+//! The boot sector of a FAT32 volume and the BIOS parameter block in it
+
+use super::BLOCK_SZ;
+use core::fmt;
+
+/// Why a volume cannot be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    /// The boot sector does not end with the 0x55 0xAA signature
+    BadSignature,
+    /// A FAT12 or FAT16 volume, or one whose parameters make no sense
+    NotFat32,
+    /// Sectors are not made of whole blocks of the device
+    UnsupportedSectorSize(u16),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::BadSignature => write!(f, "no FAT boot sector signature"),
+            Self::NotFat32 => write!(f, "not a FAT32 volume"),
+            Self::UnsupportedSectorSize(size) => {
+                write!(f, "unsupported FAT sector size {size}")
+            }
+        }
+    }
+}
+
+/// What is needed from the BIOS parameter block, counted in sectors
+pub struct BootSector {
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    /// Sectors before the first FAT
+    pub reserved_sectors: u32,
+    pub fat_count: u32,
+    /// Sectors of each FAT
+    pub fat_sectors: u32,
+    pub total_sectors: u32,
+    /// First cluster of the root directory
+    pub root_cluster: u32,
+}
+
+impl BootSector {
+    pub fn parse(sector: &[u8; BLOCK_SZ]) -> Result<Self, OpenError> {
+        let u16_at = |pos: usize| u16::from_le_bytes([sector[pos], sector[pos + 1]]);
+        let u32_at = |pos: usize| u32::from_le_bytes(sector[pos..pos + 4].try_into().unwrap());
+        if sector[510..] != [0x55, 0xaa] {
+            return Err(OpenError::BadSignature);
+        }
+        // FAT32 has no fixed root directory and keeps the FAT size in 32 bits
+        if u16_at(17) != 0 || u16_at(22) != 0 {
+            return Err(OpenError::NotFat32);
+        }
+        let bytes_per_sector = u16_at(11);
+        if bytes_per_sector == 0 || !(bytes_per_sector as usize).is_multiple_of(BLOCK_SZ) {
+            return Err(OpenError::UnsupportedSectorSize(bytes_per_sector));
+        }
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            small => small as u32,
+        };
+        let boot = Self {
+            bytes_per_sector: bytes_per_sector as u32,
+            sectors_per_cluster: sector[13] as u32,
+            reserved_sectors: u16_at(14) as u32,
+            fat_count: sector[16] as u32,
+            fat_sectors: u32_at(36),
+            total_sectors,
+            root_cluster: u32_at(44),
+        };
+        if !boot.sectors_per_cluster.is_power_of_two()
+            || boot.fat_count == 0
+            || boot.fat_sectors == 0
+            || boot.root_cluster < 2
+            || boot.data_sector() >= boot.total_sectors
+        {
+            return Err(OpenError::NotFat32);
+        }
+        Ok(boot)
+    }
+    /// The first sector of the data area, holding cluster 2
+    pub fn data_sector(&self) -> u32 {
+        // saturating, so parameters of a broken volume are rejected
+        self.reserved_sectors
+            .saturating_add(self.fat_count.saturating_mul(self.fat_sectors))
+    }
+}
@@ -0,0 +1,140 @@
+//! Directory entries: a 32-byte short entry with an 8.3 name for each
+//! file, after the long name entries holding its name in UTF-16
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Size of a directory entry
+pub const ENTRY_SZ: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// The attributes of a long name entry, read-only, hidden, system and volume id
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// The first byte of an entry after the last one of a directory
+const END: u8 = 0x00;
+/// The first byte of a deleted entry
+const FREE: u8 = 0xe5;
+/// The first byte of a short name starting with 0xe5
+const KANJI_E5: u8 = 0x05;
+/// Flags of a short name stored in upper case but shown in lower case
+const LOWER_CASE_BASE: u8 = 0x08;
+const LOWER_CASE_EXT: u8 = 0x10;
+/// Set in the order of the last long name entry, which comes first
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Where the 13 UTF-16 characters of a long name entry are
+const LONG_NAME_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// A file or directory found in a directory
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub first_cluster: u32,
+    pub size: u32,
+    /// Index of the short entry in the directory
+    pub slot: usize,
+}
+
+/// Long name entries read so far, from the last one
+struct LongName {
+    /// Order of the entry read last, the next one has the order before it
+    order: u8,
+    checksum: u8,
+    chars: Vec<u16>,
+}
+
+/// Checksum of a short name, kept in its long name entries
+fn checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// The name of a short entry, as `BASE.EXT`
+fn short_name(raw: &[u8]) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let bytes = bytes.trim_ascii_end();
+        bytes
+            .iter()
+            .map(|&byte| {
+                let c = char::from(byte);
+                if lower { c.to_ascii_lowercase() } else { c }
+            })
+            .collect()
+    };
+    let mut base = raw[..8].to_vec();
+    if base[0] == KANJI_E5 {
+        base[0] = FREE;
+    }
+    let mut name = part(&base, raw[12] & LOWER_CASE_BASE != 0);
+    let ext = part(&raw[8..11], raw[12] & LOWER_CASE_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// The first entry of directory data at or after the slot `pos`,
+/// leaving out `.`, `..` and the volume label
+pub fn entry_from(data: &[u8], pos: usize) -> Option<Entry> {
+    let mut long_name: Option<LongName> = None;
+    for (slot, raw) in data.as_chunks::<ENTRY_SZ>().0.iter().enumerate().skip(pos) {
+        match raw[0] {
+            END => return None,
+            FREE => {
+                long_name = None;
+                continue;
+            }
+            _ => {}
+        }
+        if raw[11] & 0x3f == ATTR_LONG_NAME {
+            let order = raw[0] & !LAST_LONG_ENTRY;
+            let chars = LONG_NAME_OFFSETS.map(|i| u16::from_le_bytes([raw[i], raw[i + 1]]));
+            if raw[0] & LAST_LONG_ENTRY != 0 {
+                long_name = Some(LongName {
+                    order,
+                    checksum: raw[13],
+                    chars: chars.to_vec(),
+                });
+            } else {
+                // the parts come from the end of the name
+                long_name = long_name
+                    .take()
+                    .filter(|long| order + 1 == long.order && raw[13] == long.checksum)
+                    .map(|mut long| {
+                        long.order = order;
+                        long.chars.splice(0..0, chars);
+                        long
+                    });
+            }
+            continue;
+        }
+        let long_name = long_name.take();
+        if raw[11] & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+            continue;
+        }
+        let name = match long_name {
+            // a long name left by a system not knowing them is stale
+            Some(long) if long.order == 1 && long.checksum == checksum(&raw[..11]) => {
+                let len = long.chars.iter().position(|&c| c == 0);
+                char::decode_utf16(
+                    long.chars[..len.unwrap_or(long.chars.len())]
+                        .iter()
+                        .copied(),
+                )
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+            }
+            _ => short_name(raw),
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([raw[i], raw[i + 1]]) as u32;
+        return Some(Entry {
+            name,
+            is_dir: raw[11] & ATTR_DIRECTORY != 0,
+            first_cluster: u16_at(20) << 16 | u16_at(26),
+            size: u32::from_le_bytes(raw[28..32].try_into().unwrap()),
+            slot,
+        });
+    }
+    None
+}
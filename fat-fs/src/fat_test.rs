@@ -0,0 +1,383 @@
+//! Read FAT32 images built here the way `mkfs.vfat` and Linux lay them out
+
+use crate::{BLOCK_SZ, FatFileSystem, OpenError};
+use easy_fs::{BlockDevice, RamBlockDevice, test_data};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const TOTAL_SECTORS: usize = 4096;
+const RESERVED_SECTORS: usize = 32;
+const FAT_SECTORS: usize = 32;
+/// A cluster is a sector
+const CLUSTER_SZ: usize = BLOCK_SZ;
+const DATA_START: usize = (RESERVED_SECTORS + 2 * FAT_SECTORS) * BLOCK_SZ;
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+
+enum Node {
+    File(Vec<u8>),
+    Dir(Vec<(&'static str, Node)>),
+    /// A deleted entry
+    Deleted,
+    /// A file whose long name does not match its short entry any more,
+    /// named by the short entry
+    Stale(&'static str),
+}
+
+/// A FAT32 image being built
+struct Image {
+    bytes: Vec<u8>,
+    next_cluster: u32,
+}
+
+impl Image {
+    fn new() -> Self {
+        let mut image = Self {
+            bytes: vec![0; TOTAL_SECTORS * BLOCK_SZ],
+            next_cluster: 2,
+        };
+        image.set_fat(0, 0x0fff_fff8);
+        image.set_fat(1, END_OF_CHAIN);
+        image
+    }
+    fn set_fat(&mut self, cluster: u32, value: u32) {
+        for fat in 0..2 {
+            let pos = (RESERVED_SECTORS + fat * FAT_SECTORS) * BLOCK_SZ + cluster as usize * 4;
+            self.bytes[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    /// Take `count` new clusters, chained backwards so chains are followed
+    /// through the FAT and not by position
+    fn alloc(&mut self, count: usize) -> Vec<u32> {
+        let first = self.next_cluster;
+        self.next_cluster += count as u32;
+        let clusters: Vec<u32> = (first..self.next_cluster).rev().collect();
+        for pair in clusters.windows(2) {
+            self.set_fat(pair[0], pair[1]);
+        }
+        if let Some(&last) = clusters.last() {
+            self.set_fat(last, END_OF_CHAIN);
+        }
+        clusters
+    }
+    fn write(&mut self, clusters: &[u32], data: &[u8]) {
+        for (&cluster, chunk) in clusters.iter().zip(data.chunks(CLUSTER_SZ)) {
+            let pos = DATA_START + (cluster as usize - 2) * CLUSTER_SZ;
+            self.bytes[pos..pos + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+    /// Store a node, return its first cluster
+    fn store(&mut self, node: &Node) -> u32 {
+        match node {
+            Node::File(data) => {
+                let clusters = self.alloc(data.len().div_ceil(CLUSTER_SZ));
+                self.write(&clusters, data);
+                clusters.first().copied().unwrap_or(0)
+            }
+            Node::Dir(children) => {
+                let mut entries = Vec::new();
+                for (i, (name, child)) in children.iter().enumerate() {
+                    let cluster = self.store(child);
+                    entries.extend(dir_entries(i, name, child, cluster));
+                }
+                let clusters = self.alloc((entries.len() + 2 * 32).div_ceil(CLUSTER_SZ));
+                // the `..` of a nested directory is not read, leave it at the root
+                let mut data = short_entry(b".          ", 0x10, 0, clusters[0], 0).to_vec();
+                data.extend(short_entry(b"..         ", 0x10, 0, 0, 0));
+                data.extend(entries);
+                self.write(&clusters, &data);
+                clusters[0]
+            }
+            Node::Deleted | Node::Stale(_) => 0,
+        }
+    }
+    /// The image with `root` as its root directory
+    fn build(root: &Node) -> Vec<u8> {
+        let mut image = Self::new();
+        let Node::Dir(children) = root else {
+            panic!("The root is a directory");
+        };
+        let mut entries = short_entry(b"TESTVOL    ", 0x08, 0, 0, 0).to_vec();
+        for (i, (name, child)) in children.iter().enumerate() {
+            let cluster = image.store(child);
+            entries.extend(dir_entries(i, name, child, cluster));
+        }
+        let clusters = image.alloc(entries.len().div_ceil(CLUSTER_SZ));
+        image.write(&clusters, &entries);
+        image.boot_sector(clusters[0]);
+        image.bytes
+    }
+    fn boot_sector(&mut self, root_cluster: u32) {
+        let sector = &mut self.bytes[..BLOCK_SZ];
+        sector[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        sector[3..11].copy_from_slice(b"mkfs.fat");
+        sector[11..13].copy_from_slice(&(BLOCK_SZ as u16).to_le_bytes());
+        sector[13] = (CLUSTER_SZ / BLOCK_SZ) as u8;
+        sector[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        sector[16] = 2;
+        sector[21] = 0xf8;
+        sector[32..36].copy_from_slice(&(TOTAL_SECTORS as u32).to_le_bytes());
+        sector[36..40].copy_from_slice(&(FAT_SECTORS as u32).to_le_bytes());
+        sector[44..48].copy_from_slice(&root_cluster.to_le_bytes());
+        sector[510..].copy_from_slice(&[0x55, 0xaa]);
+    }
+}
+
+fn short_entry(name: &[u8; 11], attr: u8, case: u8, cluster: u32, size: u32) -> [u8; 32] {
+    let mut entry = [0u8; 32];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[12] = case;
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+/// Long name entries of `name` for the short name `short`, from the last part
+fn long_entries(name: &str, short: &[u8; 11], checksum_delta: u8) -> Vec<u8> {
+    let checksum = short
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+        .wrapping_add(checksum_delta);
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    if chars.len() % 13 != 0 {
+        chars.push(0);
+    }
+    chars.resize(chars.len().div_ceil(13) * 13, 0xffff);
+    let parts: Vec<_> = chars.chunks(13).collect();
+    let mut entries = Vec::new();
+    for (i, part) in parts.iter().enumerate().rev() {
+        let mut entry = [0u8; 32];
+        entry[0] = (i + 1) as u8 | if i + 1 == parts.len() { 0x40 } else { 0 };
+        entry[11] = 0x0f;
+        entry[13] = checksum;
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (&offset, c) in offsets.iter().zip(part.iter()) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.extend(entry);
+    }
+    entries
+}
+
+/// Entries for the `i`-th node of a directory: a plain short entry for an
+/// upper case 8.3 name, a short entry marked lower case for a lower case one,
+/// or long name entries before a made up short one
+fn dir_entries(i: usize, name: &str, node: &Node, cluster: u32) -> Vec<u8> {
+    let (attr, size) = match node {
+        Node::File(data) => (0x20, data.len() as u32),
+        Node::Dir(_) => (0x10, 0),
+        Node::Deleted => {
+            let mut entry = short_entry(b"GONE    TXT", 0x20, 0, 0, 0);
+            entry[0] = 0xe5;
+            return entry.to_vec();
+        }
+        Node::Stale(short) => {
+            let mut short_name = [b' '; 11];
+            short_name[..short.len()].copy_from_slice(short.as_bytes());
+            let mut entries = long_entries(name, &short_name, 1);
+            entries.extend(short_entry(&short_name, 0x20, 0, 0, 0));
+            return entries;
+        }
+    };
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let fits = base.len() <= 8 && ext.len() <= 3 && !name.contains(' ');
+    let mut short = [b' '; 11];
+    if fits && (name == name.to_uppercase() || name == name.to_lowercase()) {
+        short[..base.len()].copy_from_slice(base.to_uppercase().as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(ext.to_uppercase().as_bytes());
+        let case = if name == name.to_uppercase() { 0 } else { 0x18 };
+        return short_entry(&short, attr, case, cluster, size).to_vec();
+    }
+    short[..8].copy_from_slice(format!("LONG{i:04}").as_bytes());
+    let mut entries = long_entries(name, &short, 0);
+    entries.extend(short_entry(&short, attr, 0, cluster, size));
+    entries
+}
+
+fn open(image: Vec<u8>) -> Result<Arc<FatFileSystem>, OpenError> {
+    let device: Arc<dyn BlockDevice> = Arc::new(RamBlockDevice::from_image(image));
+    FatFileSystem::open(device)
+}
+
+const LONG_NAME: &str = "A file with a long name, over 26 characters.data";
+
+fn tree() -> Node {
+    Node::Dir(vec![
+        ("README.TXT", Node::File(b"read me\n".to_vec())),
+        ("notes.txt", Node::File(test_data(100, 1))),
+        ("", Node::Deleted),
+        (LONG_NAME, Node::File(test_data(3 * CLUSTER_SZ + 7, 2))),
+        (
+            "sub",
+            Node::Dir(vec![
+                ("nested file.bin", Node::File(test_data(6 * CLUSTER_SZ, 3))),
+                ("Ünïcode ✓", Node::File(b"utf-16".to_vec())),
+                ("inner", Node::Dir(vec![])),
+            ]),
+        ),
+        ("empty", Node::File(vec![])),
+        ("stale long name", Node::Stale("STALE   TXT")),
+    ])
+}
+
+#[test]
+fn open_rejects_unsupported_boot_sectors() {
+    let mut image = Image::build(&tree());
+    image[510] = 0;
+    assert_eq!(open(image).err(), Some(OpenError::BadSignature));
+    // a FAT16 boot sector has the size of the FAT in 16 bits
+    let mut image = Image::build(&tree());
+    image[22] = 16;
+    assert_eq!(open(image).err(), Some(OpenError::NotFat32));
+    let mut image = Image::build(&tree());
+    image[11..13].copy_from_slice(&256u16.to_le_bytes());
+    assert_eq!(
+        open(image).err(),
+        Some(OpenError::UnsupportedSectorSize(256))
+    );
+}
+
+#[test]
+fn read_tree() {
+    let fs = open(Image::build(&tree())).unwrap();
+    let root = FatFileSystem::root_inode(&fs);
+    assert!(root.is_dir());
+    assert_eq!(
+        root.ls(),
+        [
+            "README.TXT",
+            "notes.txt",
+            LONG_NAME,
+            "sub",
+            "empty",
+            "STALE.TXT"
+        ]
+    );
+    let sub = root.find("SUB").unwrap();
+    assert!(sub.is_dir());
+    assert_eq!(sub.ls(), ["nested file.bin", "Ünïcode ✓", "inner"]);
+    assert!(sub.find("inner").unwrap().ls().is_empty());
+    assert!(root.find("gone.txt").is_none());
+    assert!(root.find("stale long name").is_none());
+
+    let read_all = |inode: &crate::Inode| {
+        let mut buf = vec![0; inode.size() + CLUSTER_SZ];
+        let len = inode.read_at(0, &mut buf);
+        buf.truncate(len);
+        buf
+    };
+    assert_eq!(read_all(&root.find("readme.txt").unwrap()), b"read me\n");
+    assert_eq!(
+        read_all(&root.find("NOTES.TXT").unwrap()),
+        test_data(100, 1)
+    );
+    assert_eq!(
+        read_all(&root.find(LONG_NAME).unwrap()),
+        test_data(3 * CLUSTER_SZ + 7, 2)
+    );
+    assert_eq!(read_all(&sub.find("ÜNïcode ✓").unwrap()), b"utf-16");
+    assert!(read_all(&root.find("empty").unwrap()).is_empty());
+
+    // reads across clusters and past the end
+    let nested = sub.find("nested file.bin").unwrap();
+    let expected = test_data(6 * CLUSTER_SZ, 3);
+    let mut buf = [0u8; 100];
+    assert_eq!(nested.read_at(CLUSTER_SZ - 50, &mut buf), 100);
+    assert_eq!(buf, expected[CLUSTER_SZ - 50..CLUSTER_SZ + 50]);
+    assert_eq!(nested.read_at(6 * CLUSTER_SZ - 30, &mut buf), 30);
+    assert_eq!(buf[..30], expected[6 * CLUSTER_SZ - 30..]);
+    assert_eq!(nested.read_at(6 * CLUSTER_SZ, &mut buf), 0);
+}
+
+#[test]
+fn read_dir_over_clusters() {
+    const NAMES: [&str; 8] = [
+        "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth",
+    ];
+    let files = (0..40)
+        .map(|i| {
+            let name: &'static str = format!("{} file of many", NAMES[i % 8]).leak();
+            (name, Node::File(test_data(i, i)))
+        })
+        .collect();
+    let fs = open(Image::build(&Node::Dir(vec![("many", Node::Dir(files))]))).unwrap();
+    let dir = FatFileSystem::root_inode(&fs).find("many").unwrap();
+    let mut names = Vec::new();
+    let mut ids = Vec::new();
+    let mut offset = 0;
+    while let Some((name, inode, next)) = dir.read_dir(offset) {
+        assert_eq!(inode.size(), names.len());
+        names.push(name);
+        ids.push(inode.inode_id());
+        offset = next;
+    }
+    assert_eq!(names.len(), 40);
+    assert_eq!(names[39], "eighth file of many");
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 40);
+}
+
+#[test]
+fn chain_with_a_loop_ends() {
+    let mut image = Image::build(&tree());
+    // point the first cluster of a file back at itself
+    let first = test_data(6 * CLUSTER_SZ, 3);
+    let cluster = (2..TOTAL_SECTORS as u32)
+        .find(|&cluster| {
+            let pos = DATA_START + (cluster as usize - 2) * CLUSTER_SZ;
+            image[pos..pos + CLUSTER_SZ] == first[..CLUSTER_SZ]
+        })
+        .unwrap();
+    let pos = RESERVED_SECTORS * BLOCK_SZ + cluster as usize * 4;
+    image[pos..pos + 4].copy_from_slice(&cluster.to_le_bytes());
+    let fs = open(image).unwrap();
+    let nested = FatFileSystem::root_inode(&fs)
+        .find("sub")
+        .unwrap()
+        .find("nested file.bin")
+        .unwrap();
+    let mut buf = vec![0; 6 * CLUSTER_SZ];
+    assert_eq!(nested.read_at(0, &mut buf), 6 * CLUSTER_SZ);
+    assert_eq!(buf[..CLUSTER_SZ], buf[CLUSTER_SZ..2 * CLUSTER_SZ]);
+}
+
+/// A device counting the blocks read from it
+struct CountingDevice {
+    inner: RamBlockDevice,
+    reads: AtomicUsize,
+}
+
+impl BlockDevice for CountingDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.inner.read_block(block_id, buf);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.inner.write_block(block_id, buf);
+    }
+}
+
+#[test]
+fn chain_is_followed_once() {
+    const CLUSTERS: usize = 64;
+    let image = Image::build(&Node::Dir(vec![(
+        "big",
+        Node::File(test_data(CLUSTERS * CLUSTER_SZ, 4)),
+    )]));
+    let device = Arc::new(CountingDevice {
+        inner: RamBlockDevice::from_image(image),
+        reads: AtomicUsize::new(0),
+    });
+    let fs = FatFileSystem::open(device.clone()).unwrap();
+    let big = FatFileSystem::root_inode(&fs).find("big").unwrap();
+    let before = device.reads.load(Ordering::Relaxed);
+    let mut buf = [0u8; CLUSTER_SZ];
+    for i in 0..CLUSTERS {
+        assert_eq!(big.read_at(i * CLUSTER_SZ, &mut buf), CLUSTER_SZ);
+    }
+    // a block per cluster of data, and the FAT entries of the chain
+    // fit in a block or two
+    assert!(device.reads.load(Ordering::Relaxed) - before <= CLUSTERS + 2);
+}
@@ -0,0 +1,116 @@
+use super::BLOCK_SZ;
+use super::boot::{BootSector, OpenError};
+use super::vfs::Inode;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::BlockDevice;
+
+/// The FAT entry of a cluster holds the next one in its low 28 bits
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+
+/// A FAT32 volume on a block device
+pub struct FatFileSystem {
+    device: Arc<dyn BlockDevice>,
+    /// Byte offset of the first FAT
+    fat_start: u64,
+    /// Byte offset of cluster 2, the first one
+    data_start: u64,
+    cluster_size: usize,
+    /// Clusters of the data area
+    cluster_count: u32,
+    root_cluster: u32,
+}
+
+impl FatFileSystem {
+    /// Open the volume on `device`
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, OpenError> {
+        let mut sector = [0u8; BLOCK_SZ];
+        device.read_block(0, &mut sector);
+        let boot = BootSector::parse(&sector)?;
+        let bytes_per_sector = boot.bytes_per_sector as u64;
+        Ok(Arc::new(Self {
+            device,
+            fat_start: boot.reserved_sectors as u64 * bytes_per_sector,
+            data_start: boot.data_sector() as u64 * bytes_per_sector,
+            cluster_size: (boot.sectors_per_cluster * boot.bytes_per_sector) as usize,
+            cluster_count: (boot.total_sectors - boot.data_sector()) / boot.sectors_per_cluster,
+            root_cluster: boot.root_cluster,
+        }))
+    }
+    /// The root directory
+    pub fn root_inode(fs: &Arc<Self>) -> Inode {
+        Inode::root(Arc::clone(fs), fs.root_cluster)
+    }
+    pub(crate) fn cluster_size(&self) -> usize {
+        self.cluster_size
+    }
+    /// Byte offset of a cluster on the device
+    pub(crate) fn cluster_pos(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - 2) as u64 * self.cluster_size as u64
+    }
+    /// Read the device at a byte offset
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) {
+        let mut block = [0u8; BLOCK_SZ];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let block_offset = (pos % BLOCK_SZ as u64) as usize;
+            let len = (BLOCK_SZ - block_offset).min(buf.len() - done);
+            self.device
+                .read_block((pos / BLOCK_SZ as u64) as usize, &mut block);
+            buf[done..][..len].copy_from_slice(&block[block_offset..][..len]);
+            done += len;
+        }
+    }
+    /// Whether `cluster` is in the data area, and not a free,
+    /// bad or end of chain mark
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+    /// The cluster after `cluster` in its chain. `fat_block` is the id and data
+    /// of the block of the FAT read last, only read again for another block.
+    /// The FAT starts on a sector, so no entry spans two blocks
+    fn next_cluster(&self, cluster: u32, fat_block: &mut (usize, [u8; BLOCK_SZ])) -> Option<u32> {
+        let pos = self.fat_start + cluster as u64 * 4;
+        let block_id = (pos / BLOCK_SZ as u64) as usize;
+        if fat_block.0 != block_id {
+            self.device.read_block(block_id, &mut fat_block.1);
+            fat_block.0 = block_id;
+        }
+        let offset = (pos % BLOCK_SZ as u64) as usize;
+        let entry = fat_block.1[offset..][..4].try_into().unwrap();
+        let next = u32::from_le_bytes(entry) & FAT_ENTRY_MASK;
+        self.is_data_cluster(next).then_some(next)
+    }
+    /// The clusters of the chain starting at `first`
+    pub(crate) fn chain(&self, first: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut fat_block = (usize::MAX, [0u8; BLOCK_SZ]);
+        let mut cluster = Some(first).filter(|&cluster| self.is_data_cluster(cluster));
+        while let Some(current) = cluster {
+            // only a chain with a loop can be longer than the data area
+            if chain.len() >= self.cluster_count as usize {
+                break;
+            }
+            chain.push(current);
+            cluster = self.next_cluster(current, &mut fat_block);
+        }
+        chain
+    }
+    /// Read the data of the clusters of `chain` at `offset`, return the bytes read
+    pub(crate) fn read_chain(&self, chain: &[u32], offset: usize, buf: &mut [u8]) -> usize {
+        let end = (offset + buf.len()).min(chain.len() * self.cluster_size);
+        let mut pos = offset;
+        while pos < end {
+            let cluster_offset = pos % self.cluster_size;
+            let len = (self.cluster_size - cluster_offset).min(end - pos);
+            let cluster = chain[pos / self.cluster_size];
+            self.read_bytes(
+                self.cluster_pos(cluster) + cluster_offset as u64,
+                &mut buf[pos - offset..][..len],
+            );
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+}
@@ -0,0 +1,20 @@
+//! A read-only FAT32 driver over the `BlockDevice` of easy-fs
+//!
+//! It follows cluster chains through the FAT and reads directories with
+//! their long file names. Names are looked up ignoring ASCII case, as
+//! FAT itself does
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+mod boot;
+mod dir;
+#[cfg(test)]
+mod fat_test;
+mod fs;
+mod vfs;
+use config::fs::BLOCK_SZ;
+
+pub use boot::OpenError;
+pub use easy_fs::BlockDevice;
+pub use fs::FatFileSystem;
+pub use vfs::Inode;
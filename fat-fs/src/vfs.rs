@@ -0,0 +1,112 @@
+use super::dir::{ENTRY_SZ, Entry, entry_from};
+use super::fs::FatFileSystem;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Once;
+
+/// A file or directory of a FAT32 volume
+pub struct Inode {
+    fs: Arc<FatFileSystem>,
+    first_cluster: u32,
+    size: u32,
+    is_dir: bool,
+    /// Position of the entry on the device in entries, 1 for the root
+    /// which has none, as no entry is in the boot sector
+    id: u64,
+    /// The clusters of the data, followed through the FAT on first use.
+    /// The volume is read-only, so they never change
+    chain: Once<Vec<u32>>,
+}
+
+impl Inode {
+    pub(crate) fn root(fs: Arc<FatFileSystem>, root_cluster: u32) -> Self {
+        Self {
+            fs,
+            first_cluster: root_cluster,
+            size: 0,
+            is_dir: true,
+            id: 1,
+            chain: Once::new(),
+        }
+    }
+    /// The clusters of the data
+    fn chain(&self) -> &[u32] {
+        self.chain.call_once(|| self.fs.chain(self.first_cluster))
+    }
+    /// The clusters of this directory and the data in them
+    fn dir_data(&self) -> (&[u32], Vec<u8>) {
+        if !self.is_dir {
+            return (&[], Vec::new());
+        }
+        let chain = self.chain();
+        let mut data = vec![0u8; chain.len() * self.fs.cluster_size()];
+        self.fs.read_chain(chain, 0, &mut data);
+        (chain, data)
+    }
+    /// The inode of an entry of this directory, made of the clusters `chain`
+    fn child(&self, entry: &Entry, chain: &[u32]) -> Arc<Inode> {
+        let offset = entry.slot * ENTRY_SZ;
+        let cluster = chain[offset / self.fs.cluster_size()];
+        let pos = self.fs.cluster_pos(cluster) + (offset % self.fs.cluster_size()) as u64;
+        Arc::new(Self {
+            fs: Arc::clone(&self.fs),
+            first_cluster: entry.first_cluster,
+            size: entry.size,
+            is_dir: entry.is_dir,
+            id: pos / ENTRY_SZ as u64,
+            chain: Once::new(),
+        })
+    }
+    /// Find inode under current inode by name, ignoring ASCII case
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let (chain, data) = self.dir_data();
+        let mut pos = 0;
+        while let Some(entry) = entry_from(&data, pos) {
+            if entry.name.eq_ignore_ascii_case(name) {
+                return Some(self.child(&entry, chain));
+            }
+            pos = entry.slot + 1;
+        }
+        None
+    }
+    /// List the names under current inode
+    pub fn ls(&self) -> Vec<String> {
+        let (_, data) = self.dir_data();
+        let mut names = Vec::new();
+        let mut pos = 0;
+        while let Some(entry) = entry_from(&data, pos) {
+            names.push(entry.name);
+            pos = entry.slot + 1;
+        }
+        names
+    }
+    /// The first entry at or after the entry slot `offset`,
+    /// with the offset to read the entry after it
+    pub fn read_dir(&self, offset: usize) -> Option<(String, Arc<Inode>, usize)> {
+        let (chain, data) = self.dir_data();
+        let entry = entry_from(&data, offset)?;
+        let inode = self.child(&entry, chain);
+        Some((entry.name, inode, entry.slot + 1))
+    }
+    /// Unique in the volume
+    pub fn inode_id(&self) -> u64 {
+        self.id
+    }
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+    /// Size of a file in bytes, 0 for a directory
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+    /// Read data of a file at `offset`, return the bytes read
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        if self.is_dir || offset >= self.size() {
+            return 0;
+        }
+        let len = buf.len().min(self.size() - offset);
+        self.fs.read_chain(self.chain(), offset, &mut buf[..len])
+    }
+}
@@ -8,7 +8,9 @@ mod lang_items;
 mod syscall;
 
 pub use config::{
-    fs::OpenFlag,
+    errno,
+    fs::{FD_LIMIT, OpenFlag, dirent, fcntl, seek},
+    ipc::{IPC_PRIVATE, IPC_RMID, ShmFlag},
     signal::{SignalAction, SignalID},
     syscall::SyscallID,
 };
@@ -44,15 +46,42 @@ pub extern "C" fn _start(args: *const &[&[u8]]) -> ! {
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+/// Duplicate `old_fd` onto `new_fd`, only `CLOEXEC` is allowed in `flags`
+pub fn dup3(old_fd: usize, new_fd: usize, flags: OpenFlag) -> isize {
+    sys_dup3(old_fd, new_fd, flags)
+}
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
 pub fn open(name: &str, flags: OpenFlag) -> isize {
     sys_open(&name, flags)
 }
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(&path)
+}
+/// Remove a file
+pub fn unlink(path: &str) -> isize {
+    sys_unlink(&path)
+}
+/// Mount a filesystem of type `fs_type` from `source`, like the block device `vda`,
+/// on the directory `target`
+pub fn mount(source: &str, target: &str, fs_type: &str) -> isize {
+    sys_mount(&source, &target, &fs_type)
+}
+/// Unmount the filesystem mounted on the directory `target`
+pub fn umount(target: &str) -> isize {
+    sys_umount(&target)
+}
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
 pub fn pipe() -> Option<(usize, usize)> {
+    pipe2(OpenFlag::empty())
+}
+/// Create a pipe whose ends take `NONBLOCK` and `CLOEXEC` from `flags`
+pub fn pipe2(flags: OpenFlag) -> Option<(usize, usize)> {
     let mut pipefd = (0, 0);
-    match sys_pipe(&mut pipefd.0, &mut pipefd.1) {
+    match sys_pipe(&mut pipefd.0, &mut pipefd.1, flags) {
         0 => Some(pipefd),
         -1 => None,
         _ => panic!("unexpected return value: {}", -1),
@@ -61,6 +90,27 @@ pub fn pipe() -> Option<(usize, usize)> {
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
 }
+/// Read entries of the directory `fd` into `buf`, decode them with [`dirent::Dirent::decode`]
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    sys_getdents(fd, buf)
+}
+/// Move the offset of `fd` by `offset` from where `whence` of [`seek`] says,
+/// return the new offset
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+/// Make the data written to `fd` reach the disk
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
+/// Make all written data reach the disk
+pub fn sync() -> isize {
+    sys_sync()
+}
+/// Make the data of the filesystem holding `fd` reach the disk
+pub fn syncfs(fd: usize) -> isize {
+    sys_syncfs(fd)
+}
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
@@ -107,6 +157,24 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
         }
     }
 }
+/// The id of the shared memory segment of `key` holding at least `size` bytes,
+/// created with `ShmFlag::CREAT` if there is none
+pub fn shmget(key: usize, size: usize, flags: ShmFlag) -> isize {
+    sys_shmget(key, size, flags)
+}
+/// Attach the segment `id` at `addr`, or where the kernel chooses if it is 0,
+/// return where
+pub fn shmat(id: usize, addr: usize, flags: ShmFlag) -> isize {
+    sys_shmat(id, addr, flags)
+}
+/// Detach the segment attached at `addr`
+pub fn shmdt(addr: usize) -> isize {
+    sys_shmdt(addr)
+}
+/// Control the segment `id`: `IPC_RMID` removes it, its memory goes once nobody has it attached
+pub fn shmctl(id: usize, cmd: usize) -> isize {
+    sys_shmctl(id, cmd)
+}
 pub fn sleep(period_ms: usize) {
     let start = get_time();
     while get_time() < start + period_ms {
@@ -1,4 +1,4 @@
-use super::{OpenFlag, SignalAction, SignalID, SyscallID};
+use super::{OpenFlag, ShmFlag, SignalAction, SignalID, SyscallID};
 use core::arch::asm;
 
 fn syscall(id: SyscallID, args: [usize; 3]) -> isize {
@@ -17,16 +17,45 @@ fn syscall(id: SyscallID, args: [usize; 3]) -> isize {
 pub(super) fn sys_dup(fd: usize) -> isize {
     syscall(SyscallID::Dup, [fd, 0, 0])
 }
+pub(super) fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SyscallID::Fcntl, [fd, cmd, arg])
+}
+pub(super) fn sys_dup3(old_fd: usize, new_fd: usize, flags: OpenFlag) -> isize {
+    syscall(SyscallID::Dup3, [old_fd, new_fd, flags.bits()])
+}
 pub(super) fn sys_open(path: &&str, flag: OpenFlag) -> isize {
     syscall(SyscallID::Open, [path as *const _ as _, flag.bits(), 0])
 }
+pub(super) fn sys_mkdir(path: &&str) -> isize {
+    syscall(SyscallID::Mkdir, [path as *const _ as _, 0, 0])
+}
+pub(super) fn sys_unlink(path: &&str) -> isize {
+    syscall(SyscallID::Unlink, [path as *const _ as _, 0, 0])
+}
+pub(super) fn sys_mount(source: &&str, target: &&str, fs_type: &&str) -> isize {
+    syscall(
+        SyscallID::Mount,
+        [
+            source as *const _ as _,
+            target as *const _ as _,
+            fs_type as *const _ as _,
+        ],
+    )
+}
+pub(super) fn sys_umount(target: &&str) -> isize {
+    syscall(SyscallID::Umount, [target as *const _ as _, 0, 0])
+}
 pub(super) fn sys_close(fd: usize) -> isize {
     syscall(SyscallID::Close, [fd, 0, 0])
 }
-pub(super) fn sys_pipe(pipe_read: &mut usize, pipe_write: &mut usize) -> isize {
+pub(super) fn sys_pipe(pipe_read: &mut usize, pipe_write: &mut usize, flags: OpenFlag) -> isize {
     syscall(
         SyscallID::Pipe,
-        [pipe_read as *const _ as _, pipe_write as *const _ as _, 0],
+        [
+            pipe_read as *const _ as _,
+            pipe_write as *const _ as _,
+            flags.bits(),
+        ],
     )
 }
 pub(super) fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
@@ -35,6 +64,24 @@ pub(super) fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
         [fd, buffer.as_mut_ptr() as _, buffer.len()],
     )
 }
+pub(super) fn sys_getdents(fd: usize, buffer: &mut [u8]) -> isize {
+    syscall(
+        SyscallID::GetDents,
+        [fd, buffer.as_mut_ptr() as _, buffer.len()],
+    )
+}
+pub(super) fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    syscall(SyscallID::Lseek, [fd, offset as _, whence])
+}
+pub(super) fn sys_fsync(fd: usize) -> isize {
+    syscall(SyscallID::Fsync, [fd, 0, 0])
+}
+pub(super) fn sys_sync() -> isize {
+    syscall(SyscallID::Sync, [0, 0, 0])
+}
+pub(super) fn sys_syncfs(fd: usize) -> isize {
+    syscall(SyscallID::SyncFs, [fd, 0, 0])
+}
 pub(super) fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SyscallID::Write, [fd, buffer.as_ptr() as _, buffer.len()])
 }
@@ -92,3 +139,15 @@ pub(super) fn sys_sigreturn() -> isize {
 pub(super) fn sys_poweroff() -> isize {
     syscall(SyscallID::PowerOff, [0, 0, 0])
 }
+pub(super) fn sys_shmget(key: usize, size: usize, flags: ShmFlag) -> isize {
+    syscall(SyscallID::ShmGet, [key, size, flags.bits()])
+}
+pub(super) fn sys_shmctl(id: usize, cmd: usize) -> isize {
+    syscall(SyscallID::ShmCtl, [id, cmd, 0])
+}
+pub(super) fn sys_shmat(id: usize, addr: usize, flags: ShmFlag) -> isize {
+    syscall(SyscallID::ShmAt, [id, addr, flags.bits()])
+}
+pub(super) fn sys_shmdt(addr: usize) -> isize {
+    syscall(SyscallID::ShmDt, [addr, 0, 0])
+}
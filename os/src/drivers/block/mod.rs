@@ -4,20 +4,47 @@ pub use virtio_blk::VirtIOBlock;
 
 use crate::sync::UpSafeLazyCell;
 
-use super::BlockDeviceImpl;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use easy_fs::BlockDevice;
 
-pub static BLOCK_DEVICE: UpSafeLazyCell<Arc<dyn BlockDevice>> = unsafe {
+/// Names of the virtio block devices, by the base of their MMIO registers
+const VIRTIO_BLOCK_DEVICES: &[(&str, usize)] = &[
+    ("vda", 0x1000_1000),
+    ("vdb", 0x1000_2000),
+    ("vdc", 0x1000_3000),
+];
+
+/// The block devices found, by name
+static BLOCK_DEVICES: UpSafeLazyCell<Vec<(&str, Arc<dyn BlockDevice>)>> = unsafe {
     UpSafeLazyCell::new(|| {
         virtio_blk::init();
-        Arc::new(BlockDeviceImpl::new())
+        VIRTIO_BLOCK_DEVICES
+            .iter()
+            .filter_map(|&(name, base)| {
+                let device: Arc<dyn BlockDevice> = Arc::new(VirtIOBlock::new(base)?);
+                Some((name, device))
+            })
+            .collect()
     })
 };
 
+/// The block device called `name`, virtio disks are `vda`, `vdb` and `vdc`
+pub fn block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .iter()
+        .find(|(other, _)| *other == name)
+        .map(|(_, device)| Arc::clone(device))
+}
+
+/// Names of the block devices found
+pub fn block_devices() -> Vec<&'static str> {
+    BLOCK_DEVICES.iter().map(|(name, _)| *name).collect()
+}
+
 #[allow(unused)]
 pub fn block_device_test() {
-    let block_device = BLOCK_DEVICE.clone();
+    let block_device = block_device("vda").unwrap();
     let mut write_buffer = [0u8; 512];
     let mut read_buffer = [0u8; 512];
     for i in 0..512 {
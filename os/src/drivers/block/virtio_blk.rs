@@ -1,18 +1,26 @@
 use super::BlockDevice;
 use crate::memory::{
-    FrameTracker, PageTable, PhysAddr, PhysPageNum, VirtAddr, frame_alloc, frame_dealloc,
+    FrameRangeTracker, PageTable, PhysAddr, PhysPageNum, VirtAddr, frame_alloc_contiguous,
     kernel_token,
 };
 use crate::sync::UpSafeCell;
 use alloc::vec::Vec;
 use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
+/// Offset of the device type in the MMIO registers
+const VIRTIO_DEVICE_ID: usize = 0x008;
+/// Device type of a block device
+const VIRTIO_ID_BLOCK: u32 = 2;
+/// Offset of the capacity in 512-byte sectors in the config space of a block device
+const VIRTIO_BLK_CAPACITY: usize = 0x100;
 
-pub struct VirtIOBlock(UpSafeCell<VirtIOBlk<'static, VirtioHal>>);
+pub struct VirtIOBlock {
+    blk: UpSafeCell<VirtIOBlk<'static, VirtioHal>>,
+    /// Base of the MMIO registers
+    base: usize,
+}
 
-static mut QUEUE_FRAMES: Option<UpSafeCell<Vec<FrameTracker>>> = None;
+static mut QUEUE_FRAMES: Option<UpSafeCell<Vec<FrameRangeTracker>>> = None;
 
 #[deny(dead_code)]
 pub fn init() {
@@ -22,27 +30,38 @@ pub fn init() {
 }
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0
+        self.blk
             .borrow_mut()
             .read_block(block_id, buf)
             .expect("Error when reading VirtIOBlk");
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0
+        self.blk
             .borrow_mut()
             .write_block(block_id, buf)
             .expect("Error when writing VirtIOBlk");
     }
+    fn num_blocks(&self) -> Option<usize> {
+        let capacity =
+            unsafe { core::ptr::read_volatile((self.base + VIRTIO_BLK_CAPACITY) as *const u64) };
+        Some(capacity as usize)
+    }
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        unsafe {
-            Self(UpSafeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            ))
+    /// The block device with its MMIO registers at `base`, `None` if there is none
+    pub fn new(base: usize) -> Option<Self> {
+        // a slot without a device has type 0
+        let device_id =
+            unsafe { core::ptr::read_volatile((base + VIRTIO_DEVICE_ID) as *const u32) };
+        if device_id != VIRTIO_ID_BLOCK {
+            return None;
         }
+        let blk = unsafe { VirtIOBlk::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).ok()? };
+        Some(Self {
+            blk: unsafe { UpSafeCell::new(blk) },
+            base,
+        })
     }
 }
 
@@ -50,30 +69,27 @@ pub struct VirtioHal;
 
 impl Hal for VirtioHal {
     fn dma_alloc(pages: usize) -> usize {
-        let mut ppn_base = PhysPageNum(0);
-        for i in 0..pages {
-            let frame = frame_alloc().unwrap();
-            if i == 0 {
-                ppn_base = frame.ppn;
-            }
-            assert_eq!(frame.ppn.0, ppn_base.0 + i);
-            unsafe { QUEUE_FRAMES.as_ref() }
-                .unwrap()
-                .borrow_mut()
-                .push(frame);
-        }
-        let pa: PhysAddr = ppn_base.into();
+        let order = pages.next_power_of_two().trailing_zeros() as usize;
+        let frames = frame_alloc_contiguous(order).unwrap();
+        let pa: PhysAddr = frames.ppn.into();
+        unsafe { QUEUE_FRAMES.as_ref() }
+            .unwrap()
+            .borrow_mut()
+            .push(frames);
         pa.0
     }
 
-    fn dma_dealloc(pa: usize, pages: usize) -> i32 {
-        let pa = PhysAddr::from(pa);
-        let mut ppn_base: PhysPageNum = pa.into();
-        for _ in 0..pages {
-            frame_dealloc(ppn_base);
-            ppn_base = ppn_base + 1;
+    fn dma_dealloc(pa: usize, _pages: usize) -> i32 {
+        let ppn: PhysPageNum = PhysAddr::from(pa).into();
+        let mut frames = unsafe { QUEUE_FRAMES.as_ref() }.unwrap().borrow_mut();
+        // the frames are freed when their tracker is dropped
+        match frames.iter().position(|frames| frames.ppn == ppn) {
+            Some(i) => {
+                frames.swap_remove(i);
+                0
+            }
+            None => -1,
         }
-        0
     }
 
     fn phys_to_virt(addr: usize) -> usize {
@@ -1,6 +1,3 @@
 pub mod block;
 
-pub use block::BLOCK_DEVICE;
-
-type BlockDeviceImpl = block::VirtIOBlock;
-
+pub use block::{block_device, block_devices};
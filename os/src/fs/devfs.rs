@@ -0,0 +1,249 @@
+//! devfs: a node for each device, opened and used like a file
+//!
+//! `null` takes any write and reads as empty, `zero` reads as zeros,
+//! `urandom` reads from a PRNG, and `console` and `tty` are the SBI
+//! console. Each block device has a node reading and writing its blocks,
+//! straight to the device and not through the block cache of easy-fs. The
+//! swap device has none, and a mounted device cannot be opened for writing
+
+use super::cfg::BLOCK_SZ;
+use super::stdio::{console_ready, getchar};
+use super::vfs::{self, DirEntry, FileSystem, Inode, InodeType};
+use crate::drivers::{block_device, block_devices};
+use crate::sbi::console_putchar;
+use crate::sync::UpSafeCell;
+use crate::timer::get_time;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use config::errno::{EBUSY, ENOENT};
+use config::memory::SWAP_DEVICE;
+use easy_fs::BlockDevice;
+
+/// The devices as a filesystem
+pub struct DevFs;
+
+/// Open devfs, there is nothing to read from `source`
+pub fn open(_source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    Ok(Arc::new(DevFs))
+}
+
+impl FileSystem for DevFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DevInode::Root)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CharDevice {
+    Null,
+    Zero,
+    Urandom,
+    Console,
+}
+
+/// Character devices by name, listed before the block devices
+const CHAR_DEVICES: &[(&str, CharDevice)] = &[
+    ("null", CharDevice::Null),
+    ("zero", CharDevice::Zero),
+    ("urandom", CharDevice::Urandom),
+    ("console", CharDevice::Console),
+    ("tty", CharDevice::Console),
+];
+
+enum DevInode {
+    Root,
+    Char(CharDevice),
+    Block(&'static str, Arc<dyn BlockDevice>),
+}
+
+/// Names of the block devices with a node, all but the swap device,
+/// whose slots are no file to read or write
+fn block_device_names() -> impl Iterator<Item = &'static str> {
+    block_devices()
+        .into_iter()
+        .filter(|&name| name != SWAP_DEVICE)
+}
+
+impl DevInode {
+    /// The node called `name`
+    fn child(name: &str) -> Option<Self> {
+        if let Some(&(_, device)) = CHAR_DEVICES.iter().find(|(other, _)| *other == name) {
+            return Some(Self::Char(device));
+        }
+        let name = block_device_names().find(|&other| other == name)?;
+        Some(Self::Block(name, block_device(name)?))
+    }
+    /// Size in bytes of a block device
+    fn block_device_size(device: &Arc<dyn BlockDevice>) -> usize {
+        device.num_blocks().unwrap_or(0) * BLOCK_SZ
+    }
+}
+
+/// State of the xorshift generator behind `urandom`, zero until seeded
+static RANDOM_STATE: UpSafeCell<u64> = unsafe { UpSafeCell::new(0) };
+
+/// Mix `seed` into the state, which is seeded from the time on first use
+fn random_mix(state: &mut u64, seed: u64) {
+    if *state == 0 {
+        *state = get_time() as u64;
+    }
+    // splitmix64, so similar seeds give unrelated states
+    let mut z = (*state ^ seed).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    *state = (z ^ (z >> 31)).max(1);
+}
+
+/// Fill `buf` with pseudorandom bytes
+fn random_fill(buf: &mut [u8]) {
+    let mut state = RANDOM_STATE.borrow_mut();
+    if *state == 0 {
+        random_mix(&mut state, 0);
+    }
+    for chunk in buf.chunks_mut(8) {
+        // xorshift64*
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+impl CharDevice {
+    fn read(self, buf: &mut [u8]) -> usize {
+        match self {
+            Self::Null => 0,
+            Self::Zero => {
+                buf.fill(0);
+                buf.len()
+            }
+            Self::Urandom => {
+                random_fill(buf);
+                buf.len()
+            }
+            Self::Console => {
+                // wait for a character, then take those already typed
+                let mut len = 0;
+                for byte in buf.iter_mut() {
+                    if len > 0 && !console_ready() {
+                        break;
+                    }
+                    *byte = getchar();
+                    len += 1;
+                }
+                len
+            }
+        }
+    }
+    fn write(self, buf: &[u8]) -> usize {
+        match self {
+            Self::Null | Self::Zero => {}
+            Self::Urandom => {
+                let mut state = RANDOM_STATE.borrow_mut();
+                for chunk in buf.chunks(8) {
+                    let mut seed = [0u8; 8];
+                    seed[..chunk.len()].copy_from_slice(chunk);
+                    random_mix(&mut state, u64::from_le_bytes(seed));
+                }
+            }
+            Self::Console => buf.iter().for_each(|&c| console_putchar(c as usize)),
+        }
+        buf.len()
+    }
+}
+
+impl Inode for DevInode {
+    fn r#type(&self) -> InodeType {
+        match self {
+            Self::Root => InodeType::Dir,
+            Self::Char(_) => InodeType::CharDevice,
+            Self::Block(..) => InodeType::BlockDevice,
+        }
+    }
+    fn size(&self) -> usize {
+        match self {
+            Self::Block(_, device) => Self::block_device_size(device),
+            _ => 0,
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let device = match self {
+            Self::Root => return Ok(0),
+            // character devices have no offset
+            Self::Char(device) => return Ok(device.read(buf)),
+            Self::Block(_, device) => device,
+        };
+        let end = (offset + buf.len()).min(Self::block_device_size(device));
+        let mut block = [0u8; BLOCK_SZ];
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % BLOCK_SZ;
+            let len = (BLOCK_SZ - block_offset).min(end - pos);
+            device.read_block(pos / BLOCK_SZ, &mut block);
+            buf[pos - offset..][..len].copy_from_slice(&block[block_offset..][..len]);
+            pos += len;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        let device = match self {
+            Self::Root => return Ok(0),
+            Self::Char(device) => return Ok(device.write(buf)),
+            Self::Block(_, device) => device,
+        };
+        let end = (offset + buf.len()).min(Self::block_device_size(device));
+        let mut block = [0u8; BLOCK_SZ];
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % BLOCK_SZ;
+            let len = (BLOCK_SZ - block_offset).min(end - pos);
+            // keep the rest of a block written in part
+            if len < BLOCK_SZ {
+                device.read_block(pos / BLOCK_SZ, &mut block);
+            }
+            block[block_offset..][..len].copy_from_slice(&buf[pos - offset..][..len]);
+            device.write_block(pos / BLOCK_SZ, &block);
+            pos += len;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Ok(())
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        match self {
+            Self::Root => Ok(Arc::new(Self::child(name).ok_or(ENOENT)?)),
+            _ => Err(ENOENT),
+        }
+    }
+    fn open(&self, writable: bool) -> Result<(), isize> {
+        match self {
+            // writing the blocks of a mounted filesystem behind its back corrupts it
+            Self::Block(name, _) if writable && vfs::mounted(name) => Err(EBUSY),
+            _ => Ok(()),
+        }
+    }
+    fn read_ready(&self) -> bool {
+        match self {
+            Self::Char(CharDevice::Console) => console_ready(),
+            _ => true,
+        }
+    }
+    fn read_dir(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        if !matches!(self, Self::Root) {
+            return None;
+        }
+        let name = match CHAR_DEVICES.get(pos) {
+            Some((name, _)) => name,
+            None => block_device_names().nth(pos - CHAR_DEVICES.len())?,
+        };
+        let entry = DirEntry {
+            name: name.to_string(),
+            // the root is 1
+            ino: pos + 2,
+            r#type: Self::child(name)?.r#type(),
+        };
+        Some((entry, pos + 1))
+    }
+}
@@ -0,0 +1,126 @@
+//! easy-fs on a block device as a filesystem of the VFS
+
+use super::vfs::{DirEntry, FileSystem, Inode, InodeType};
+use crate::drivers::block_device;
+use crate::sync::UpSafeCell;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use config::errno::{EINVAL, EIO, ENODEV, ENOENT, ENOSPC};
+use easy_fs::{Corrupted, EasyFileSystem, WriteError};
+
+/// An easy-fs image, by its root directory
+pub struct EasyFs(Arc<easy_fs::Inode>);
+
+/// Images opened by `open` by the name of their device,
+/// so a device mounted twice shares one filesystem
+static OPENED: UpSafeCell<Vec<(String, Weak<EasyFs>)>> = unsafe { UpSafeCell::new(Vec::new()) };
+
+/// Open the easy-fs image on the block device named `source`
+pub fn open(source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    let mut opened = OPENED.borrow_mut();
+    opened.retain(|(_, fs)| fs.strong_count() > 0);
+    if let Some(fs) = opened
+        .iter()
+        .find(|(name, _)| name == source)
+        .and_then(|(_, fs)| fs.upgrade())
+    {
+        return Ok(fs);
+    }
+    let device = block_device(source).ok_or(ENODEV)?;
+    let efs = EasyFileSystem::open(device).map_err(|err| {
+        log::warn!("cannot open easy-fs on {source}: {err}");
+        EINVAL
+    })?;
+    let fs = Arc::new(EasyFs(Arc::new(EasyFileSystem::root_inode(&efs))));
+    opened.push((source.to_string(), Arc::downgrade(&fs)));
+    Ok(fs)
+}
+
+impl FileSystem for EasyFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(EasyInode(Arc::clone(&self.0)))
+    }
+    fn sync(&self) {
+        easy_fs::block_cache_sync_all();
+    }
+}
+
+/// A block of the image does not match its checksum
+fn eio(err: Corrupted) -> isize {
+    log::warn!("easy-fs: {err}");
+    EIO
+}
+
+/// An allocation failed for want of space or on a corrupted block
+fn write_error(err: WriteError) -> isize {
+    match err {
+        WriteError::NoSpace => ENOSPC,
+        WriteError::Corrupted(block_id) => eio(Corrupted { block_id }),
+    }
+}
+
+/// An inode of an easy-fs image. Operations which cannot fail
+/// go on as if a corrupted block was empty
+struct EasyInode(Arc<easy_fs::Inode>);
+
+impl Inode for EasyInode {
+    fn r#type(&self) -> InodeType {
+        if self.0.is_dir().map_err(eio).unwrap_or(false) {
+            InodeType::Dir
+        } else {
+            InodeType::File
+        }
+    }
+    fn size(&self) -> usize {
+        self.0.size().map_err(eio).unwrap_or(0)
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        self.0.read_at(offset, buf).map_err(eio)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        self.0.write_at(offset, buf).map_err(write_error)
+    }
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        self.0.truncate(size).map_err(write_error)
+    }
+    fn sync(&self) {
+        if let Err(err) = self.0.sync() {
+            eio(err);
+        }
+    }
+    fn name_length_limit(&self) -> usize {
+        self.0.name_length_limit()
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        let inode = self.0.find(name).map_err(eio)?.ok_or(ENOENT)?;
+        Ok(Arc::new(Self(inode)))
+    }
+    fn create(&self, name: &str, r#type: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        let inode = match r#type {
+            InodeType::File => self.0.create(name),
+            InodeType::Dir => self.0.create_dir(name),
+            // easy-fs has no device nodes
+            InodeType::CharDevice | InodeType::BlockDevice => return Err(ENOSPC),
+        };
+        Ok(Arc::new(Self(inode.map_err(write_error)?.ok_or(ENOSPC)?)))
+    }
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        match self.0.unlink(name).map_err(eio)? {
+            true => Ok(()),
+            false => Err(ENOENT),
+        }
+    }
+    fn read_dir(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        let (name, inode, next) = self.0.read_dir(pos).map_err(eio).ok()??;
+        let entry = DirEntry {
+            name,
+            ino: inode.inode_id() as usize,
+            r#type: Self(inode).r#type(),
+        };
+        Some((entry, next))
+    }
+    fn page_cached(&self) -> bool {
+        true
+    }
+}
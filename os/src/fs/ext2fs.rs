@@ -0,0 +1,103 @@
+//! ext2 on a block device as a filesystem of the VFS
+
+use super::vfs::{DirEntry, FileSystem, Inode, InodeType};
+use crate::drivers::block_device;
+use crate::sync::UpSafeCell;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use config::errno::{EINVAL, ENODEV, ENOENT, ENOSPC};
+use ext2_fs::Ext2FileSystem;
+
+/// An ext2 volume, by its root directory
+pub struct Ext2Fs(Arc<ext2_fs::Inode>);
+
+/// Volumes opened by `open` by the name of their device,
+/// so a device mounted twice shares one filesystem
+static OPENED: UpSafeCell<Vec<(String, Weak<Ext2Fs>)>> = unsafe { UpSafeCell::new(Vec::new()) };
+
+/// Open the ext2 volume on the block device named `source`
+pub fn open(source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    let mut opened = OPENED.borrow_mut();
+    opened.retain(|(_, fs)| fs.strong_count() > 0);
+    if let Some(fs) = opened
+        .iter()
+        .find(|(name, _)| name == source)
+        .and_then(|(_, fs)| fs.upgrade())
+    {
+        return Ok(fs);
+    }
+    let device = block_device(source).ok_or(ENODEV)?;
+    let fs = Ext2FileSystem::open(device).map_err(|err| {
+        log::warn!("cannot open ext2 on {source}: {err}");
+        EINVAL
+    })?;
+    let fs = Arc::new(Ext2Fs(Arc::new(Ext2FileSystem::root_inode(&fs))));
+    opened.push((source.to_string(), Arc::downgrade(&fs)));
+    Ok(fs)
+}
+
+impl FileSystem for Ext2Fs {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(Ext2Inode(Arc::clone(&self.0)))
+    }
+}
+
+/// An inode of an ext2 volume, written through to the device
+struct Ext2Inode(Arc<ext2_fs::Inode>);
+
+impl Inode for Ext2Inode {
+    fn r#type(&self) -> InodeType {
+        if self.0.is_dir() {
+            InodeType::Dir
+        } else {
+            InodeType::File
+        }
+    }
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        Ok(self.0.read_at(offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        Ok(self.0.write_at(offset, buf))
+    }
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        self.0.truncate(size);
+        Ok(())
+    }
+    fn name_length_limit(&self) -> usize {
+        self.0.name_length_limit()
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        Ok(Arc::new(Self(self.0.find(name).ok_or(ENOENT)?)))
+    }
+    fn create(&self, name: &str, r#type: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        let inode = match r#type {
+            InodeType::File => self.0.create(name),
+            InodeType::Dir => self.0.create_dir(name),
+            // device nodes need a device number, which is not passed here
+            InodeType::CharDevice | InodeType::BlockDevice => None,
+        };
+        Ok(Arc::new(Self(inode.ok_or(ENOSPC)?)))
+    }
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        match self.0.unlink(name) {
+            true => Ok(()),
+            false => Err(ENOENT),
+        }
+    }
+    fn read_dir(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        let (name, inode, next) = self.0.read_dir(pos)?;
+        let entry = DirEntry {
+            name,
+            ino: inode.inode_id() as usize,
+            r#type: Self(inode).r#type(),
+        };
+        Some((entry, next))
+    }
+    fn page_cached(&self) -> bool {
+        true
+    }
+}
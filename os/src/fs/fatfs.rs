@@ -0,0 +1,70 @@
+//! A FAT32 volume on a block device as a filesystem of the VFS, read only
+
+use super::vfs::{DirEntry, FileSystem, Inode, InodeType};
+use crate::drivers::block_device;
+use alloc::sync::Arc;
+use config::errno::{EINVAL, ENODEV, ENOENT};
+use fat_fs::FatFileSystem;
+
+/// A FAT32 volume, by its root directory
+pub struct FatFs(Arc<fat_fs::Inode>);
+
+/// Open the FAT32 volume on the block device named `source`
+pub fn open(source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    let device = block_device(source).ok_or(ENODEV)?;
+    let fs = FatFileSystem::open(device).map_err(|err| {
+        log::warn!("cannot open FAT32 on {source}: {err}");
+        EINVAL
+    })?;
+    Ok(Arc::new(FatFs(Arc::new(FatFileSystem::root_inode(&fs)))))
+}
+
+impl FileSystem for FatFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(FatInode(Arc::clone(&self.0)))
+    }
+}
+
+/// An inode of a FAT32 volume, writes change nothing
+struct FatInode(Arc<fat_fs::Inode>);
+
+impl Inode for FatInode {
+    fn r#type(&self) -> InodeType {
+        if self.0.is_dir() {
+            InodeType::Dir
+        } else {
+            InodeType::File
+        }
+    }
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        Ok(self.0.read_at(offset, buf))
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, isize> {
+        Ok(0)
+    }
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Ok(())
+    }
+    /// The longest long file name
+    fn name_length_limit(&self) -> usize {
+        255
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        Ok(Arc::new(Self(self.0.find(name).ok_or(ENOENT)?)))
+    }
+    fn read_dir(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        let (name, inode, next) = self.0.read_dir(pos)?;
+        let entry = DirEntry {
+            name,
+            ino: inode.inode_id() as usize,
+            r#type: Self(inode).r#type(),
+        };
+        Some((entry, next))
+    }
+    fn page_cached(&self) -> bool {
+        true
+    }
+}
@@ -1,95 +1,140 @@
-//! `Arc<Inode>` -> `OSInodeInner`: In order to open files concurrently
-//! we need to wrap `Inode` into `Arc`,but `Mutex` in `Inode` prevents
-//! file systems from being accessed simultaneously
+//! `Arc<dyn Inode>` -> `OSInodeInner`: an inode of any filesystem,
+//! shared by the files opened on it and the dentry cache
 //!
-//! `UPSafeCell<OSInodeInner>` -> `OSInode`: for static `ROOT_INODE`,we
-//! need to wrap `OSInodeInner` into `UPSafeCell`
+//! `UPSafeCell<OSInodeInner>` -> `OSInode`: the offset and status
+//! of an opened file change through a shared reference
+//!
+//! Files with a page cache are read through it, and programs are
+//! loaded from its pages
 
 use super::File;
 use super::cfg::OpenFlag;
-use crate::memory::UserBuffer;
+use super::cfg::dirent::Dirent;
+use super::cfg::seek::{SEEK_CUR, SEEK_END, SEEK_SET};
+use super::page_cache::PageCache;
+use super::vfs::{self, Dentry, Inode, InodeType};
+use crate::memory::{FilePages, FrameTracker, UserBuffer, frame_alloc};
 use crate::sync::UpSafeCell;
-use crate::{drivers::BLOCK_DEVICE, sync::UpSafeLazyCell};
+use alloc::string::String;
 use alloc::sync::Arc;
-use alloc::vec::Vec;
-use easy_fs::{EasyFileSystem, Inode};
+use alloc::vec;
+use config::errno::{EEXIST, EINVAL, EISDIR, ENOENT, ENOTDIR};
+use config::memory::PAGE_SIZE;
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
 pub struct OSInode {
     readable: bool,
     writable: bool,
+    dir: bool,
+    /// The path the file was opened by
+    path: String,
+    pages: Option<Arc<PageCache>>,
     inner: UpSafeCell<OSInodeInner>,
 }
 /// The OS inode inner in 'UPSafeCell'
 pub struct OSInodeInner {
+    /// Offset in bytes, or position of the next entry for a directory
     offset: usize,
-    inode: Arc<Inode>,
+    inode: Arc<dyn Inode>,
+    status: OpenFlag,
 }
 
 impl OSInode {
-    /// Construct an OS inode from a inode
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    /// Construct an OS inode from the dentry of an inode, keeping the status part of `flags`
+    pub fn new(readable: bool, writable: bool, dentry: &Arc<Dentry>, flags: OpenFlag) -> Self {
+        let inode = Arc::clone(dentry.inode());
         Self {
             readable,
             writable,
-            inner: unsafe { UpSafeCell::new(OSInodeInner { offset: 0, inode }) },
+            dir: inode.is_dir(),
+            path: dentry.path(),
+            pages: dentry.pages().cloned(),
+            inner: unsafe {
+                UpSafeCell::new(OSInodeInner {
+                    offset: 0,
+                    inode,
+                    status: flags.status(),
+                })
+            },
         }
     }
-    /// Read all data inside a inode into vector
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.borrow_mut();
-        let mut buffer = [0u8; 512];
-        let mut v: Vec<u8> = Vec::new();
-        loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
-            if len == 0 {
-                break;
-            }
-            inner.offset += len;
-            v.extend_from_slice(&buffer[..len]);
+}
+
+impl FilePages for OSInode {
+    fn page(&self, index: usize) -> Option<Arc<FrameTracker>> {
+        let inner = self.inner.borrow();
+        if let Some(pages) = &self.pages {
+            return pages.page(&*inner.inode, index);
         }
-        v
+        // a frame of its own for a file without a page cache
+        let frame = frame_alloc()?;
+        let len = inner
+            .inode
+            .read_at(index * PAGE_SIZE, frame.ppn.as_bytes())
+            .ok()?;
+        (len > 0).then(|| Arc::new(frame))
     }
 }
 
-pub static ROOT_INODE: UpSafeLazyCell<Arc<Inode>> = unsafe {
-    UpSafeLazyCell::new(|| {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    })
-};
-
-/// List all files in the filesystems
+/// List all files in the root directory
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
-        println!("{}", app);
+    let root = vfs::lookup("/").unwrap();
+    let mut pos = 0;
+    while let Some((entry, next)) = root.inode().read_dir(pos) {
+        println!("{}", entry.name);
+        pos = next;
     }
     println!("**************/");
 }
 
-///Open file with flags
-pub fn open_file(name: &str, flags: OpenFlag) -> Option<Arc<OSInode>> {
+/// Open a file with flags, return the error number if it fails
+pub fn open_file(path: &str, flags: OpenFlag) -> Result<Arc<OSInode>, isize> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlag::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
-            // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
-        }
-    } else {
-        ROOT_INODE.find(name).map(|inode| {
+    let (dir, name) = vfs::lookup_parent(path)?;
+    let dentry = match dir.child(name) {
+        Ok(dentry) => {
+            let inode = dentry.inode();
+            // directories can only be opened for reading
+            if inode.is_dir() && writable {
+                return Err(EISDIR);
+            }
+            if !inode.is_dir() && flags.contains(OpenFlag::DIRECTORY) {
+                return Err(ENOTDIR);
+            }
+            // `EXCL` only makes sense together with `CREATE`
+            if flags.contains(OpenFlag::CREATE | OpenFlag::EXCL) {
+                return Err(EEXIST);
+            }
+            inode.open(writable)?;
             if flags.contains(OpenFlag::TRUNC) {
-                inode.clear();
+                inode.truncate(0)?;
+                if let Some(pages) = dentry.pages() {
+                    pages.invalidate(0, usize::MAX);
+                }
             }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
-    }
+            dentry
+        }
+        Err(ENOENT) if flags.contains(OpenFlag::CREATE | OpenFlag::DIRECTORY) => {
+            return Err(EINVAL);
+        }
+        Err(ENOENT) if flags.contains(OpenFlag::CREATE) => dir.create(name, InodeType::File)?,
+        Err(err) => return Err(err),
+    };
+    Ok(Arc::new(OSInode::new(readable, writable, &dentry, flags)))
+}
+
+/// Create a directory
+pub fn mkdir(path: &str) -> Result<(), isize> {
+    let (dir, name) = vfs::lookup_parent(path)?;
+    dir.create(name, InodeType::Dir)?;
+    Ok(())
+}
+
+/// Remove a file
+pub fn unlink(path: &str) -> Result<(), isize> {
+    let (dir, name) = vfs::lookup_parent(path)?;
+    dir.unlink(name)
 }
 
 impl File for OSInode {
@@ -99,28 +144,109 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, isize> {
+        if self.dir {
+            // entries are read by `getdents`
+            return Ok(0);
+        }
         let mut inner = self.inner.borrow_mut();
         let mut total_read_size = 0usize;
         for slice in buf.0.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
-                break;
-            }
+            let read_size = match &self.pages {
+                Some(pages) => pages.read(&*inner.inode, inner.offset, slice),
+                None => inner.inode.read_at(inner.offset, slice),
+            };
+            // an error after some bytes comes with the next read
+            let read_size = match read_size {
+                Ok(read_size) => read_size,
+                Err(_) if total_read_size > 0 => break,
+                Err(err) => return Err(err),
+            };
             inner.offset += read_size;
             total_read_size += read_size;
+            // the end of the file, or all a device has for now
+            if read_size < slice.len() {
+                break;
+            }
         }
-        total_read_size
+        Ok(total_read_size)
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
         let mut inner = self.inner.borrow_mut();
+        if inner.status.contains(OpenFlag::APPEND) {
+            // nothing can run between seeking and writing, so appending is atomic
+            inner.offset = inner.inode.size();
+        }
         let mut total_write_size = 0usize;
         for slice in buf.0.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
+            let write_size = match inner.inode.write_at(inner.offset, slice) {
+                Ok(write_size) => write_size,
+                Err(_) if total_write_size > 0 => break,
+                Err(err) => return Err(err),
+            };
+            if let Some(pages) = &self.pages {
+                pages.invalidate(inner.offset, inner.offset + write_size);
+            }
             inner.offset += write_size;
             total_write_size += write_size;
+            // the filesystem is full
+            if write_size < slice.len() {
+                break;
+            }
         }
-        total_write_size
+        Ok(total_write_size)
+    }
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+    /// The offset of a directory is the position of its next entry
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, isize> {
+        let mut inner = self.inner.borrow_mut();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => inner.offset,
+            SEEK_END => inner.inode.size(),
+            _ => return Err(EINVAL),
+        };
+        inner.offset = base.checked_add_signed(offset).ok_or(EINVAL)?;
+        Ok(inner.offset)
+    }
+    fn getdents(&self, buf: UserBuffer) -> Option<usize> {
+        if !self.dir {
+            return None;
+        }
+        let mut inner = self.inner.borrow_mut();
+        let mut records = vec![0u8; buf.len()];
+        let mut len = 0;
+        while let Some((entry, next)) = inner.inode.read_dir(inner.offset) {
+            let dirent = Dirent {
+                ino: entry.ino as u64,
+                off: next as i64,
+                r#type: entry.r#type.dirent_type(),
+                name: &entry.name,
+            };
+            let Some(reclen) = dirent.encode(&mut records[len..]) else {
+                break;
+            };
+            len += reclen;
+            inner.offset = next;
+        }
+        buf.into_bytes()
+            .zip(&records[..len])
+            .for_each(|(dst, src)| *dst = *src);
+        Some(len)
+    }
+    fn sync(&self) -> bool {
+        self.inner.borrow().inode.sync();
+        true
+    }
+    fn status(&self) -> OpenFlag {
+        self.inner.borrow().status
+    }
+    fn set_status(&self, flags: OpenFlag) {
+        self.inner.borrow_mut().status = flags.status();
+    }
+    fn read_ready(&self) -> bool {
+        self.inner.borrow().inode.read_ready()
     }
 }
@@ -1,22 +1,162 @@
 //! File system in os
+mod devfs;
+mod easyfs;
+mod ext2fs;
+mod fatfs;
 mod inode;
+mod page_cache;
 mod pipe;
+mod procfs;
 mod stdio;
+mod tmpfs;
+mod vfs;
 use crate::memory::UserBuffer;
+use crate::sync::UpSafeCell;
+use alloc::string::String;
+use alloc::sync::Arc;
+use config::errno::{EEXIST, ENODEV, ESPIPE};
 pub use config::fs as cfg;
+use vfs::FileSystem;
 /// File trait
 pub trait File: Send + Sync {
     /// If readable
     fn readable(&self) -> bool;
     /// If writable
     fn writable(&self) -> bool;
-    /// Read file to `UserBuffer`
-    fn read(&self, buf: UserBuffer) -> usize;
-    /// Write `UserBuffer` to file
-    fn write(&self, buf: UserBuffer) -> usize;
+    /// Read file to `UserBuffer`, return the bytes read or the error number
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize>;
+    /// Write `UserBuffer` to file, return the bytes written or the error number
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize>;
+    /// What the file is, like the path it was opened by
+    fn name(&self) -> String;
+    /// Move the offset by `offset` from where `whence` says, return the new
+    /// offset or the error number. Files without an offset fail with `ESPIPE`
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// Fill `buf` with directory entries following the last call,
+    /// return `None` if the file is not a directory
+    fn getdents(&self, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Write cached data of the file back to disk,
+    /// return false if the file does not support syncing
+    fn sync(&self) -> bool {
+        false
+    }
+    /// Status flags of the opened file, like `APPEND` and `NONBLOCK`
+    fn status(&self) -> OpenFlag {
+        OpenFlag::empty()
+    }
+    /// Replace the status flags, files without any status ignore it
+    fn set_status(&self, _flags: OpenFlag) {}
+    /// If a read would return without blocking
+    fn read_ready(&self) -> bool {
+        true
+    }
+    /// If a write would return without blocking
+    fn write_ready(&self) -> bool {
+        true
+    }
+}
+
+/// An entry of the fd table: an opened file and flags of the descriptor itself
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub file: Arc<dyn File + Send + Sync>,
+    /// Close the descriptor on `exec`
+    pub cloexec: bool,
+}
+
+impl FileDescriptor {
+    pub fn new(file: Arc<dyn File + Send + Sync>, flags: OpenFlag) -> Self {
+        Self {
+            file,
+            cloexec: flags.contains(OpenFlag::CLOEXEC),
+        }
+    }
+}
+
+/// Time of the last write back of the block cache
+static LAST_FLUSH_MS: UpSafeCell<usize> = unsafe { UpSafeCell::new(0) };
+
+/// Write dirty blocks back to disk if they have been cached for long enough
+pub fn flush_periodically(now_ms: usize) {
+    let mut last_flush_ms = LAST_FLUSH_MS.borrow_mut();
+    if now_ms - *last_flush_ms >= cfg::BLOCK_CACHE_FLUSH_INTERVAL_MS {
+        *last_flush_ms = now_ms;
+        sync_all();
+    }
+}
+
+/// Write all dirty blocks back to disk
+pub fn sync_all() {
+    vfs::sync_all();
+}
+
+/// A filesystem type `mount` knows, opening a filesystem from its source
+type OpenFs = fn(&str) -> Result<Arc<dyn FileSystem>, isize>;
+
+/// Filesystem types by name
+const FS_TYPES: &[(&str, OpenFs)] = &[
+    ("easy-fs", easyfs::open),
+    ("ext2", ext2fs::open),
+    ("tmpfs", tmpfs::open),
+    ("procfs", procfs::open),
+    ("devfs", devfs::open),
+    ("vfat", fatfs::open),
+];
+
+/// Types tried in order for the root filesystem
+const ROOT_FS_TYPES: &[&str] = &["easy-fs", "ext2"];
+/// The device holding the root filesystem
+const ROOT_DEVICE: &str = "vda";
+
+/// Open a filesystem of type `fs_type` from `source`
+fn open_fs(fs_type: &str, source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    let (_, open) = FS_TYPES
+        .iter()
+        .find(|(name, _)| *name == fs_type)
+        .ok_or(ENODEV)?;
+    open(source)
+}
+
+/// Open the root filesystem with the first type its device holds,
+/// there is nothing to run without it. Return it with its type
+fn open_root() -> (Arc<dyn FileSystem>, &'static str) {
+    ROOT_FS_TYPES
+        .iter()
+        .find_map(|&fs_type| Some((open_fs(fs_type, ROOT_DEVICE).ok()?, fs_type)))
+        .unwrap_or_else(|| panic!("Cannot mount the root file system on {ROOT_DEVICE}"))
+}
+
+/// Mount a filesystem of type `fs_type` from `source` on the directory `target`
+pub fn mount(source: &str, target: &str, fs_type: &str) -> Result<(), isize> {
+    vfs::mount(open_fs(fs_type, source)?, source, fs_type, target)
+}
+
+/// Filesystems mounted at boot by their type, source and directory
+const BOOT_MOUNTS: &[(&str, &str, &str)] = &[
+    ("tmpfs", "tmpfs", "/tmp"),
+    ("procfs", "proc", "/proc"),
+    ("devfs", "dev", "/dev"),
+];
+
+/// Mount the filesystems of `BOOT_MOUNTS`, making their directories if needed
+pub fn init() {
+    for &(fs_type, source, target) in BOOT_MOUNTS {
+        let mounted = match mkdir(target) {
+            Ok(()) | Err(EEXIST) => mount(source, target, fs_type),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = mounted {
+            log::warn!("cannot mount {fs_type} on {target}: error {err}");
+        }
+    }
 }
 
 pub use cfg::OpenFlag;
-pub use inode::{list_apps, open_file};
+pub use inode::{list_apps, mkdir, open_file, unlink};
 pub use pipe::make_pipe;
 pub use stdio::{Stderr, Stdin, Stdout};
+pub use vfs::umount;
@@ -0,0 +1,147 @@
+//! The page cache: pages of files kept in frames after they are read, so
+//! reading a file again, or loading a program again, skips the disk
+//!
+//! Each file has a `PageCache`, shared by the files opened on it through
+//! its dentry. Programs map the frames of read-only segments directly, so
+//! the frames live as long as a program uses them. Writes drop the pages
+//! they change, and the least recently used pages are dropped once there
+//! are `PAGE_CACHE_PAGES` of them
+
+use super::cfg::PAGE_CACHE_PAGES;
+use super::vfs::Inode;
+use crate::memory::{FrameTracker, frame_alloc};
+use crate::sync::UpSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use config::errno::ENOMEM;
+use config::memory::PAGE_SIZE;
+
+struct CachedPage {
+    frame: Arc<FrameTracker>,
+    /// Value of the clock when the page was last used
+    last_use: usize,
+}
+
+/// Pages of all files, by the id of their cache and their index
+struct Pages {
+    pages: BTreeMap<(usize, usize), CachedPage>,
+    clock: usize,
+    next_id: usize,
+}
+
+impl Pages {
+    /// Drop the least recently used page, false if there is none
+    fn evict(&mut self) -> bool {
+        let Some(key) = self
+            .pages
+            .iter()
+            .min_by_key(|(_, page)| page.last_use)
+            .map(|(key, _)| *key)
+        else {
+            return false;
+        };
+        self.pages.remove(&key);
+        true
+    }
+}
+
+static PAGES: UpSafeCell<Pages> = unsafe {
+    UpSafeCell::new(Pages {
+        pages: BTreeMap::new(),
+        clock: 0,
+        next_id: 0,
+    })
+};
+
+/// Number of pages in the cache
+pub fn cached_pages() -> usize {
+    PAGES.borrow().pages.len()
+}
+
+/// The cached pages of a file, dropped with it
+pub struct PageCache {
+    id: usize,
+}
+
+impl PageCache {
+    pub fn new() -> Self {
+        let mut pages = PAGES.borrow_mut();
+        pages.next_id += 1;
+        Self { id: pages.next_id }
+    }
+    /// The frame holding page `index` of `inode`, with zeros past the end of the file,
+    /// `None` if the page is past the end or cannot be read into a frame
+    pub fn page(&self, inode: &dyn Inode, index: usize) -> Option<Arc<FrameTracker>> {
+        if index * PAGE_SIZE >= inode.size() {
+            return None;
+        }
+        self.cached(inode, index).ok()
+    }
+    /// The frame holding page `index` of `inode`, read from it if it is not cached
+    fn cached(&self, inode: &dyn Inode, index: usize) -> Result<Arc<FrameTracker>, isize> {
+        let mut pages = PAGES.borrow_mut();
+        pages.clock += 1;
+        let clock = pages.clock;
+        if let Some(page) = pages.pages.get_mut(&(self.id, index)) {
+            page.last_use = clock;
+            return Ok(Arc::clone(&page.frame));
+        }
+        // dropping pages may free frames, unless programs still map them
+        let frame = loop {
+            if let Some(frame) = frame_alloc() {
+                break Arc::new(frame);
+            }
+            if !pages.evict() {
+                return Err(ENOMEM);
+            }
+        };
+        if pages.pages.len() >= PAGE_CACHE_PAGES {
+            pages.evict();
+        }
+        drop(pages);
+        // frames are zeroed, so a page read in part ends with zeros
+        inode.read_at(index * PAGE_SIZE, frame.ppn.as_bytes())?;
+        let page = CachedPage {
+            frame: Arc::clone(&frame),
+            last_use: clock,
+        };
+        PAGES.borrow_mut().pages.insert((self.id, index), page);
+        Ok(frame)
+    }
+    /// Read `inode` at `offset` through the cache, return the bytes read,
+    /// or the error number if a page fails before any byte is read
+    pub fn read(&self, inode: &dyn Inode, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let end = (offset + buf.len()).min(inode.size());
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let frame = match self.cached(inode, pos / PAGE_SIZE) {
+                Ok(frame) => frame,
+                Err(_) if pos > offset => break,
+                Err(err) => return Err(err),
+            };
+            buf[pos - offset..][..len].copy_from_slice(&frame.ppn.as_bytes()[page_offset..][..len]);
+            pos += len;
+        }
+        Ok(pos.saturating_sub(offset))
+    }
+    /// Drop the cached pages holding any byte in `start..end`
+    pub fn invalidate(&self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let range = (self.id, start / PAGE_SIZE)..=(self.id, (end - 1) / PAGE_SIZE);
+        let mut pages = PAGES.borrow_mut();
+        let keys: alloc::vec::Vec<_> = pages.pages.range(range).map(|(key, _)| *key).collect();
+        for key in keys {
+            pages.pages.remove(&key);
+        }
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        self.invalidate(0, usize::MAX);
+    }
+}
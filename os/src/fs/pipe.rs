@@ -1,30 +1,37 @@
-use super::File;
 use super::cfg;
+use super::{File, OpenFlag};
 use crate::memory::UserBuffer;
 use crate::sync::UpSafeCell;
+use alloc::string::{String, ToString};
 use alloc::sync::{Arc, Weak};
 
 use crate::task;
 pub struct Pipe {
     readable: bool,
     writable: bool,
+    status: UpSafeCell<OpenFlag>,
     buffer: Arc<UpSafeCell<PipeRingBuffer>>,
 }
 impl Pipe {
-    fn read_end_with_buffer(buffer: Arc<UpSafeCell<PipeRingBuffer>>) -> Self {
+    fn read_end_with_buffer(buffer: Arc<UpSafeCell<PipeRingBuffer>>, status: OpenFlag) -> Self {
         Self {
             readable: true,
             writable: false,
+            status: unsafe { UpSafeCell::new(status) },
             buffer,
         }
     }
-    fn write_end_with_buffer(buffer: Arc<UpSafeCell<PipeRingBuffer>>) -> Self {
+    fn write_end_with_buffer(buffer: Arc<UpSafeCell<PipeRingBuffer>>, status: OpenFlag) -> Self {
         Self {
             readable: false,
             writable: true,
+            status: unsafe { UpSafeCell::new(status) },
             buffer,
         }
     }
+    fn nonblock(&self) -> bool {
+        self.status.borrow().contains(OpenFlag::NONBLOCK)
+    }
 }
 struct PipeRingBuffer {
     arr: [u8; cfg::PIPE_BUFFER_SIZE],
@@ -74,23 +81,26 @@ impl PipeRingBuffer {
     }
 }
 
-/// Return (read_end, write_end)
-pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
+/// Return (read_end, write_end), both ends keep the status part of `flags`
+pub fn make_pipe(flags: OpenFlag) -> (Arc<Pipe>, Arc<Pipe>) {
     let buffer = Arc::new(unsafe { UpSafeCell::new(PipeRingBuffer::new()) });
-    let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
-    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
+    let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone(), flags.status()));
+    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone(), flags.status()));
     buffer.borrow_mut().set_write_end(&write_end);
     (read_end, write_end)
 }
 
 impl File for Pipe {
+    fn name(&self) -> String {
+        "pipe".to_string()
+    }
     fn readable(&self) -> bool {
         self.readable
     }
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
         assert!(self.readable());
         let want_to_read = buf.len();
         let mut buf_iter = buf.into_bytes();
@@ -99,8 +109,8 @@ impl File for Pipe {
             let mut ring_buffer = self.buffer.borrow_mut();
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
-                if ring_buffer.all_write_ends_closed() {
-                    return already_read;
+                if ring_buffer.all_write_ends_closed() || self.nonblock() {
+                    return Ok(already_read);
                 }
                 drop(ring_buffer);
                 task::suspend_current_and_run_next();
@@ -111,15 +121,15 @@ impl File for Pipe {
                     *byte_ref = ring_buffer.read_byte();
                     already_read += 1;
                     if already_read == want_to_read {
-                        return already_read;
+                        return Ok(already_read);
                     }
                 } else {
-                    return already_read;
+                    return Ok(already_read);
                 }
             }
         }
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
         assert!(self.writable());
         let want_to_write = buf.len();
         let mut buf_iter = buf.into_bytes();
@@ -128,6 +138,9 @@ impl File for Pipe {
             let mut ring_buffer = self.buffer.borrow_mut();
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
+                if self.nonblock() {
+                    return Ok(already_write);
+                }
                 drop(ring_buffer);
                 task::suspend_current_and_run_next();
                 continue;
@@ -138,12 +151,25 @@ impl File for Pipe {
                     ring_buffer.write_byte(*byte_ref);
                     already_write += 1;
                     if already_write == want_to_write {
-                        return already_write;
+                        return Ok(already_write);
                     }
                 } else {
-                    return already_write;
+                    return Ok(already_write);
                 }
             }
         }
     }
+    fn status(&self) -> OpenFlag {
+        *self.status.borrow()
+    }
+    fn set_status(&self, flags: OpenFlag) {
+        *self.status.borrow_mut() = flags.status();
+    }
+    fn read_ready(&self) -> bool {
+        let ring_buffer = self.buffer.borrow();
+        ring_buffer.available_read() > 0 || ring_buffer.all_write_ends_closed()
+    }
+    fn write_ready(&self) -> bool {
+        self.buffer.borrow().available_write() > 0
+    }
 }
@@ -0,0 +1,290 @@
+//! procfs: files made up from the state of the kernel when they are read
+//!
+//! `meminfo`, `buddyinfo`, `slabinfo` and `mounts` describe the whole system. The directory of
+//! each task, named by its pid, holds its `status`, its `maps` and a file
+//! under `fd` for each opened descriptor. `self` is the directory of the
+//! task looking it up
+
+use super::page_cache::cached_pages;
+use super::vfs::{self, DirEntry, FileSystem, Inode, InodeType};
+use crate::memory::{
+    MapPermission, frame_count, frame_free_blocks, heap_usage, slab_usage, swap_count,
+};
+use crate::task::{self, TaskControlBlock, TaskStatus};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use config::errno::ENOENT;
+use config::memory::PAGE_SIZE;
+
+/// The kernel state as a filesystem
+pub struct ProcFs;
+
+/// Open procfs, there is nothing to read from `source`
+pub fn open(_source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    Ok(Arc::new(ProcFs))
+}
+
+impl FileSystem for ProcFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(ProcInode::Root)
+    }
+}
+
+/// Files of the root directory, before the directories of the tasks
+const ROOT_FILES: &[&str] = &["meminfo", "buddyinfo", "slabinfo", "mounts"];
+/// Entries of the directory of a task
+const TASK_FILES: &[&str] = &["status", "maps", "fd"];
+
+/// A file or directory of procfs. Those of a task do not keep it alive,
+/// they are empty once it has gone
+enum ProcInode {
+    Root,
+    MemInfo,
+    BuddyInfo,
+    SlabInfo,
+    Mounts,
+    Task(Weak<TaskControlBlock>),
+    Status(Weak<TaskControlBlock>),
+    Maps(Weak<TaskControlBlock>),
+    Fds(Weak<TaskControlBlock>),
+    Fd(Weak<TaskControlBlock>, usize),
+}
+
+impl ProcInode {
+    /// Inode number, each task has a range of them after its pid
+    fn ino(&self) -> usize {
+        let task_ino = |task: &Weak<TaskControlBlock>, i: usize| {
+            task.upgrade().map_or(0, |task| task.getpid() << 16) + i
+        };
+        match self {
+            Self::Root => 1,
+            Self::MemInfo => 2,
+            Self::Mounts => 3,
+            Self::BuddyInfo => 4,
+            Self::SlabInfo => 5,
+            Self::Task(task) => task_ino(task, 0),
+            Self::Status(task) => task_ino(task, 1),
+            Self::Maps(task) => task_ino(task, 2),
+            Self::Fds(task) => task_ino(task, 3),
+            Self::Fd(task, fd) => task_ino(task, 4 + fd),
+        }
+    }
+    /// Look up an entry of this directory
+    fn child(&self, name: &str) -> Option<Self> {
+        match self {
+            Self::Root => match name {
+                "meminfo" => Some(Self::MemInfo),
+                "buddyinfo" => Some(Self::BuddyInfo),
+                "slabinfo" => Some(Self::SlabInfo),
+                "mounts" => Some(Self::Mounts),
+                "self" => Some(Self::Task(Arc::downgrade(&task::current_task()?))),
+                _ => {
+                    let task = task::pid2task(name.parse().ok()?)?;
+                    Some(Self::Task(Arc::downgrade(&task)))
+                }
+            },
+            Self::Task(task) => {
+                task.upgrade()?;
+                match name {
+                    "status" => Some(Self::Status(task.clone())),
+                    "maps" => Some(Self::Maps(task.clone())),
+                    "fd" => Some(Self::Fds(task.clone())),
+                    _ => None,
+                }
+            }
+            Self::Fds(task) => {
+                let fd: usize = name.parse().ok()?;
+                let opened = task
+                    .upgrade()?
+                    .inner_exclusive_access()
+                    .fd_table
+                    .get(fd)?
+                    .is_some();
+                opened.then(|| Self::Fd(task.clone(), fd))
+            }
+            _ => None,
+        }
+    }
+    /// The entry of this directory at or after `pos`, its name and the position after it
+    fn entry(&self, pos: usize) -> Option<(String, Self, usize)> {
+        match self {
+            Self::Root => match ROOT_FILES.get(pos) {
+                Some(name) => Some((name.to_string(), self.child(name)?, pos + 1)),
+                None => {
+                    // positions after the files are pids
+                    let task = task::task_from_pid(pos - ROOT_FILES.len())?;
+                    let pid = task.getpid();
+                    let next = pid + ROOT_FILES.len() + 1;
+                    Some((pid.to_string(), Self::Task(Arc::downgrade(&task)), next))
+                }
+            },
+            Self::Task(_) => {
+                let name = TASK_FILES.get(pos)?;
+                Some((name.to_string(), self.child(name)?, pos + 1))
+            }
+            Self::Fds(task) => {
+                let fd = task
+                    .upgrade()?
+                    .inner_exclusive_access()
+                    .fd_table
+                    .iter()
+                    .enumerate()
+                    .skip(pos)
+                    .find(|(_, fd)| fd.is_some())?
+                    .0;
+                Some((fd.to_string(), Self::Fd(task.clone(), fd), fd + 1))
+            }
+            _ => None,
+        }
+    }
+    /// Content of this file, made up now
+    fn text(&self) -> String {
+        match self {
+            Self::MemInfo => meminfo(),
+            Self::BuddyInfo => buddyinfo(),
+            Self::SlabInfo => slabinfo(),
+            Self::Mounts => vfs::mount_table(),
+            Self::Status(task) => task.upgrade().map(|task| status(&task)).unwrap_or_default(),
+            Self::Maps(task) => task.upgrade().map(|task| maps(&task)).unwrap_or_default(),
+            Self::Fd(task, fd) => {
+                let Some(task) = task.upgrade() else {
+                    return String::new();
+                };
+                let file = match task.inner_exclusive_access().fd_table.get(*fd) {
+                    Some(Some(fd)) => Arc::clone(&fd.file),
+                    _ => return String::new(),
+                };
+                format!("{}\n", file.name())
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+fn meminfo() -> String {
+    let (frames, free_frames) = frame_count();
+    let (heap, heap_used) = heap_usage();
+    let (swap, free_swap) = swap_count();
+    let slab_pages: usize = slab_usage().iter().map(|(_, _, pages)| pages).sum();
+    [
+        ("MemTotal:", frames * PAGE_SIZE),
+        ("MemFree:", free_frames * PAGE_SIZE),
+        ("Cached:", cached_pages() * PAGE_SIZE),
+        ("Slab:", slab_pages * PAGE_SIZE),
+        ("HeapTotal:", heap),
+        ("HeapFree:", heap - heap_used),
+        ("SwapTotal:", swap * PAGE_SIZE),
+        ("SwapFree:", free_swap * PAGE_SIZE),
+    ]
+    .iter()
+    .map(|(name, bytes)| format!("{name:<12}{:>8} kB\n", bytes / 1024))
+    .collect()
+}
+
+/// Free blocks of each order of the frame allocator, like Linux
+fn buddyinfo() -> String {
+    let blocks: String = frame_free_blocks()
+        .iter()
+        .map(|count| format!(" {count:>6}"))
+        .collect();
+    format!("Node 0, zone   Normal{blocks}\n")
+}
+
+/// Objects in use and in all, and their size, for each slab cache
+fn slabinfo() -> String {
+    let mut text = String::from("# name        active_objs num_objs objsize\n");
+    for (size, used, pages) in slab_usage() {
+        let name = format!("size-{size}");
+        let total = pages * PAGE_SIZE / size;
+        text += &format!("{name:<14}{used:>11} {total:>8} {size:>7}\n");
+    }
+    text
+}
+
+fn status(task: &TaskControlBlock) -> String {
+    let inner = task.inner_exclusive_access();
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(Weak::upgrade)
+        .map_or(0, |parent| parent.getpid());
+    let state = match inner.task_status {
+        TaskStatus::Zombie => "Z (zombie)",
+        _ if inner.frozen => "T (stopped)",
+        TaskStatus::Running => "R (running)",
+        TaskStatus::Ready => "R (ready)",
+    };
+    format!(
+        "Pid:\t{}\nPPid:\t{}\nState:\t{}\nVmRSS:\t{} kB\nSigPnd:\t{:08x}\nSigBlk:\t{:08x}\n",
+        task.getpid(),
+        ppid,
+        state,
+        inner.memory_set.resident_frames() * PAGE_SIZE / 1024,
+        inner.signals.bits(),
+        inner.signal_mask.bits()
+    )
+}
+
+fn maps(task: &TaskControlBlock) -> String {
+    let inner = task.inner_exclusive_access();
+    inner
+        .memory_set
+        .areas()
+        .map(|(start, end, permission)| {
+            let flag = |bit, c| if permission.contains(bit) { c } else { '-' };
+            format!(
+                "{:016x}-{:016x} {}{}{}{}\n",
+                usize::from(start),
+                usize::from(end),
+                flag(MapPermission::R, 'r'),
+                flag(MapPermission::W, 'w'),
+                flag(MapPermission::X, 'x'),
+                flag(MapPermission::U, 'u'),
+            )
+        })
+        .collect()
+}
+
+impl Inode for ProcInode {
+    fn r#type(&self) -> InodeType {
+        match self {
+            Self::Root | Self::Task(_) | Self::Fds(_) => InodeType::Dir,
+            _ => InodeType::File,
+        }
+    }
+    fn size(&self) -> usize {
+        self.text().len()
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let text = self.text();
+        let Some(rest) = text.as_bytes().get(offset..) else {
+            return Ok(0);
+        };
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        Ok(len)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, isize> {
+        Ok(0)
+    }
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Ok(())
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        Ok(Arc::new(self.child(name).ok_or(ENOENT)?))
+    }
+    fn read_dir(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        let (name, inode, next) = self.entry(pos)?;
+        let entry = DirEntry {
+            name,
+            ino: inode.ino(),
+            r#type: inode.r#type(),
+        };
+        Some((entry, next))
+    }
+    /// Tasks and descriptors come and go by themselves
+    fn cache_lookups(&self) -> bool {
+        false
+    }
+}
@@ -1,78 +1,131 @@
 //!Stdin & Stdout
-use super::File;
+use super::{File, OpenFlag};
 use crate::memory::UserBuffer;
 use crate::sbi::console_getchar;
+use crate::sync::UpSafeCell;
 use crate::task::suspend_current_and_run_next;
-///Standard input
-pub struct Stdin;
+use alloc::string::{String, ToString};
+///Standard input, with the status flags set by `fcntl`
+pub struct Stdin {
+    status: UpSafeCell<OpenFlag>,
+}
 ///Standard output
 pub struct Stdout;
 ///Standard error
 pub struct Stderr;
 
+/// A character taken from the console by `read_ready` but not read yet
+static PENDING: UpSafeCell<Option<u8>> = unsafe { UpSafeCell::new(None) };
+
+/// Take a character from the console, waiting for one
+pub fn getchar() -> u8 {
+    if let Some(ch) = PENDING.borrow_mut().take() {
+        return ch;
+    }
+    // busy loop
+    let mut c: usize;
+    loop {
+        c = console_getchar();
+        if c == 0 {
+            suspend_current_and_run_next();
+            continue;
+        } else {
+            break;
+        }
+    }
+    c as u8
+}
+
+/// If a character can be taken from the console without waiting
+pub fn console_ready() -> bool {
+    let mut pending = PENDING.borrow_mut();
+    if pending.is_none() {
+        match console_getchar() {
+            0 => {}
+            c => *pending = Some(c as u8),
+        }
+    }
+    pending.is_some()
+}
+
+impl Stdin {
+    pub fn new() -> Self {
+        Self {
+            status: unsafe { UpSafeCell::new(OpenFlag::empty()) },
+        }
+    }
+}
+
 impl File for Stdin {
+    fn name(&self) -> String {
+        "stdin".to_string()
+    }
     fn readable(&self) -> bool {
         true
     }
     fn writable(&self) -> bool {
         false
     }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
+    fn read(&self, mut user_buf: UserBuffer) -> Result<usize, isize> {
         assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
-        }
-        let ch = c as u8;
+        let ch = getchar();
         unsafe {
             user_buf.0[0].as_mut_ptr().write_volatile(ch);
         }
-        1
+        Ok(1)
     }
-    fn write(&self, _user_buf: UserBuffer) -> usize {
+    fn write(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot write to stdin!");
     }
+    fn status(&self) -> OpenFlag {
+        *self.status.borrow()
+    }
+    fn set_status(&self, flags: OpenFlag) {
+        *self.status.borrow_mut() = flags.status();
+    }
+    fn read_ready(&self) -> bool {
+        console_ready()
+    }
 }
 
 impl File for Stdout {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
     fn readable(&self) -> bool {
         false
     }
     fn writable(&self) -> bool {
         true
     }
-    fn read(&self, _user_buf: UserBuffer) -> usize {
+    fn read(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot read from stdout!");
     }
-    fn write(&self, user_buf: UserBuffer) -> usize {
+    fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
         for buffer in user_buf.0.iter() {
             print!("{}", core::str::from_utf8(*buffer).unwrap());
         }
-        user_buf.len()
+        Ok(user_buf.len())
     }
 }
 
 impl File for Stderr {
+    fn name(&self) -> String {
+        "stderr".to_string()
+    }
     fn readable(&self) -> bool {
         false
     }
     fn writable(&self) -> bool {
         true
     }
-    fn read(&self, _user_buf: UserBuffer) -> usize {
+    fn read(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot read from stdout!");
     }
-    fn write(&self, user_buf: UserBuffer) -> usize {
+    fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
         for buffer in user_buf.0.iter() {
             print!("{}", core::str::from_utf8(*buffer).unwrap());
         }
-        user_buf.len()
+        Ok(user_buf.len())
     }
 }
@@ -0,0 +1,248 @@
+//! tmpfs: a filesystem in memory, whose file data lives in frames
+//!
+//! Pages of a file are allocated on the first write to them, so holes
+//! left by `truncate` or by writing past the end take no memory. Every
+//! tmpfs holds at most `TMPFS_PAGE_LIMIT` pages of file data, or as many
+//! as a `size=<bytes>` option of its source allows; names and inodes on
+//! the kernel heap are not counted
+
+use super::cfg::TMPFS_PAGE_LIMIT;
+use super::vfs::{DirEntry, FileSystem, Inode, InodeType};
+use crate::memory::{FrameTracker, frame_alloc};
+use crate::sync::UpSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map::Entry;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use config::errno::{EINVAL, ENOENT, ENOSPC};
+use config::memory::PAGE_SIZE;
+
+/// A filesystem in memory
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+/// Open a new empty tmpfs. `source` is a comma separated list of options,
+/// of which only `size=<bytes>` means something: the limit of file data,
+/// rounded up to whole pages
+pub fn open(source: &str) -> Result<Arc<dyn FileSystem>, isize> {
+    let mut limit = TMPFS_PAGE_LIMIT;
+    for option in source.split(',') {
+        if let Some(size) = option.strip_prefix("size=") {
+            let size: usize = size.parse().map_err(|_| EINVAL)?;
+            limit = size.div_ceil(PAGE_SIZE);
+        }
+    }
+    let usage = Arc::new(Usage {
+        pages: unsafe { UpSafeCell::new(0) },
+        limit,
+        next_ino: unsafe { UpSafeCell::new(0) },
+    });
+    let root = TmpInode::new(&usage, InodeType::Dir);
+    Ok(Arc::new(TmpFs { root }))
+}
+
+impl FileSystem for TmpFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// What a tmpfs uses, shared by its inodes
+struct Usage {
+    /// Pages of file data allocated
+    pages: UpSafeCell<usize>,
+    limit: usize,
+    next_ino: UpSafeCell<usize>,
+}
+
+impl Usage {
+    /// Allocate a page of file data, if the limit and the frames allow it
+    fn alloc(&self) -> Option<FrameTracker> {
+        let mut pages = self.pages.borrow_mut();
+        if *pages >= self.limit {
+            return None;
+        }
+        let frame = frame_alloc()?;
+        *pages += 1;
+        Some(frame)
+    }
+    /// Count `count` pages of file data as freed
+    fn release(&self, count: usize) {
+        *self.pages.borrow_mut() -= count;
+    }
+}
+
+enum Content {
+    /// Pages of data by their index, missing ones read as zero
+    File {
+        size: usize,
+        pages: BTreeMap<usize, FrameTracker>,
+    },
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+}
+
+/// A file or directory of a tmpfs
+struct TmpInode {
+    ino: usize,
+    usage: Arc<Usage>,
+    content: UpSafeCell<Content>,
+}
+
+impl TmpInode {
+    fn new(usage: &Arc<Usage>, r#type: InodeType) -> Arc<Self> {
+        let ino = {
+            let mut next_ino = usage.next_ino.borrow_mut();
+            *next_ino += 1;
+            *next_ino - 1
+        };
+        let content = match r#type {
+            InodeType::Dir => Content::Dir(BTreeMap::new()),
+            _ => Content::File {
+                size: 0,
+                pages: BTreeMap::new(),
+            },
+        };
+        Arc::new(Self {
+            ino,
+            usage: Arc::clone(usage),
+            content: unsafe { UpSafeCell::new(content) },
+        })
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        // the frames go back with the trackers
+        if let Content::File { pages, .. } = &*self.content.borrow() {
+            self.usage.release(pages.len());
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn r#type(&self) -> InodeType {
+        match &*self.content.borrow() {
+            Content::File { .. } => InodeType::File,
+            Content::Dir(_) => InodeType::Dir,
+        }
+    }
+    fn size(&self) -> usize {
+        match &*self.content.borrow() {
+            Content::File { size, .. } => *size,
+            Content::Dir(entries) => entries.len(),
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let content = self.content.borrow();
+        let Content::File { size, pages } = &*content else {
+            return Ok(0);
+        };
+        let end = (offset + buf.len()).min(*size);
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..][..len];
+            match pages.get(&(pos / PAGE_SIZE)) {
+                Some(frame) => dst.copy_from_slice(&frame.ppn.as_bytes()[page_offset..][..len]),
+                None => dst.fill(0),
+            }
+            pos += len;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+    /// Write as much as there is room for, failing with `ENOSPC` if there is none
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        let mut content = self.content.borrow_mut();
+        let Content::File { size, pages } = &mut *content else {
+            return Ok(0);
+        };
+        let mut written = 0;
+        while written < buf.len() {
+            let pos = offset + written;
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(buf.len() - written);
+            let frame = match pages.entry(pos / PAGE_SIZE) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match self.usage.alloc() {
+                    Some(frame) => entry.insert(frame),
+                    None => break,
+                },
+            };
+            frame.ppn.as_bytes()[page_offset..][..len].copy_from_slice(&buf[written..][..len]);
+            written += len;
+        }
+        if written > 0 {
+            *size = (*size).max(offset + written);
+        } else if !buf.is_empty() {
+            return Err(ENOSPC);
+        }
+        Ok(written)
+    }
+    fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        let mut content = self.content.borrow_mut();
+        let Content::File { size, pages } = &mut *content else {
+            return Ok(());
+        };
+        if new_size < *size {
+            let freed = pages.split_off(&new_size.div_ceil(PAGE_SIZE));
+            self.usage.release(freed.len());
+            // keep the tail of the last page zeroed for a later growth
+            if let Some(frame) = pages.get(&(new_size / PAGE_SIZE)) {
+                frame.ppn.as_bytes()[new_size % PAGE_SIZE..].fill(0);
+            }
+        }
+        *size = new_size;
+        Ok(())
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        match &*self.content.borrow() {
+            Content::Dir(entries) => Ok(entries.get(name).ok_or(ENOENT)?.clone()),
+            Content::File { .. } => Err(ENOENT),
+        }
+    }
+    fn create(&self, name: &str, r#type: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        let mut content = self.content.borrow_mut();
+        let Content::Dir(entries) = &mut *content else {
+            return Err(ENOSPC);
+        };
+        // there are no device nodes in a tmpfs
+        if entries.contains_key(name) || !matches!(r#type, InodeType::File | InodeType::Dir) {
+            return Err(ENOSPC);
+        }
+        let inode = TmpInode::new(&self.usage, r#type);
+        entries.insert(name.to_string(), Arc::clone(&inode));
+        Ok(inode)
+    }
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        let mut content = self.content.borrow_mut();
+        let Content::Dir(entries) = &mut *content else {
+            return Err(ENOENT);
+        };
+        match entries.get(name) {
+            Some(inode) if !inode.is_dir() => {
+                // the data stays until the files opened on it are closed
+                entries.remove(name);
+                Ok(())
+            }
+            _ => Err(ENOENT),
+        }
+    }
+    fn unlink_frees(&self) -> bool {
+        false
+    }
+    fn read_dir(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        let content = self.content.borrow();
+        let Content::Dir(entries) = &*content else {
+            return None;
+        };
+        let (name, inode) = entries.iter().nth(pos)?;
+        let entry = DirEntry {
+            name: name.clone(),
+            ino: inode.ino,
+            r#type: inode.r#type(),
+        };
+        Some((entry, pos + 1))
+    }
+}
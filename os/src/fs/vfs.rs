@@ -0,0 +1,373 @@
+//! The virtual filesystem: filesystems and their inodes behind traits,
+//! a cache of the names looked up in directories, and the mount table
+//!
+//! Mounting a filesystem on a directory hides what the directory holds
+//! until it is unmounted. Paths are resolved from the root directory,
+//! going into the filesystems mounted on the directories they pass
+
+use super::cfg::NAME_LENGTH_LIMIT;
+use super::cfg::dirent::{DT_BLK, DT_CHR, DT_DIR, DT_REG};
+use super::page_cache::PageCache;
+use crate::sync::{UpSafeCell, UpSafeLazyCell};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use config::errno::{EBUSY, EEXIST, EINVAL, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR};
+
+/// Type of an inode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeType {
+    File,
+    Dir,
+    CharDevice,
+    BlockDevice,
+}
+
+impl InodeType {
+    /// Type of the inode in a `dirent` record
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            Self::File => DT_REG,
+            Self::Dir => DT_DIR,
+            Self::CharDevice => DT_CHR,
+            Self::BlockDevice => DT_BLK,
+        }
+    }
+}
+
+/// An entry read from a directory
+pub struct DirEntry {
+    pub name: String,
+    /// Inode number, unique in its filesystem
+    pub ino: usize,
+    pub r#type: InodeType,
+}
+
+/// A filesystem which can be mounted
+pub trait FileSystem: Send + Sync {
+    /// The root directory
+    fn root(&self) -> Arc<dyn Inode>;
+    /// Write cached data back to the device
+    fn sync(&self) {}
+}
+
+/// A file or directory of a filesystem, directory operations fail on files
+pub trait Inode: Send + Sync {
+    fn r#type(&self) -> InodeType;
+    /// Size in bytes
+    fn size(&self) -> usize;
+    /// Read data at `offset`, return the bytes read or the error number
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize>;
+    /// Write data at `offset`, return the bytes written or the error number
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize>;
+    /// Set the size, bytes past the old size read as zero
+    fn truncate(&self, size: usize) -> Result<(), isize>;
+    /// Write cached data of the inode back to the device
+    fn sync(&self) {}
+    /// The max length of a name in this directory
+    fn name_length_limit(&self) -> usize {
+        NAME_LENGTH_LIMIT
+    }
+    /// Find an inode under this directory by name, `ENOENT` if there is none
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, isize> {
+        Err(ENOENT)
+    }
+    /// Create an inode under this directory, `ENOSPC` if there is no room for it
+    fn create(&self, _name: &str, _type: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        Err(ENOSPC)
+    }
+    /// Remove a file under this directory, `ENOENT` if there is no such file
+    fn unlink(&self, _name: &str) -> Result<(), isize> {
+        Err(ENOENT)
+    }
+    /// Whether `unlink` frees the data of the file at once, rather than once
+    /// the inode is dropped, so a file still opened cannot be unlinked
+    fn unlink_frees(&self) -> bool {
+        true
+    }
+    /// The first entry of this directory at or after `pos`, which starts from 0,
+    /// and the position to read the entry after it
+    fn read_dir(&self, _pos: usize) -> Option<(DirEntry, usize)> {
+        None
+    }
+    /// Check this file may be opened, for writing if `writable`
+    fn open(&self, _writable: bool) -> Result<(), isize> {
+        Ok(())
+    }
+    /// Whether names looked up in this directory may be cached,
+    /// false if they come and go without `create` and `unlink`
+    fn cache_lookups(&self) -> bool {
+        true
+    }
+    /// Whether reads of this file may be served from the page cache,
+    /// false if its data changes by itself or is in memory already
+    fn page_cached(&self) -> bool {
+        false
+    }
+    /// If a read would return without waiting, false for a device with no input yet
+    fn read_ready(&self) -> bool {
+        true
+    }
+    fn is_dir(&self) -> bool {
+        self.r#type() == InodeType::Dir
+    }
+}
+
+/// A name looked up in a directory, cached with its inode
+pub struct Dentry {
+    name: String,
+    /// The directory holding the name, none for the root
+    parent: Option<Weak<Dentry>>,
+    inode: Arc<dyn Inode>,
+    /// Names looked up under this directory
+    children: UpSafeCell<BTreeMap<String, Arc<Dentry>>>,
+    /// Root of the filesystem mounted on this directory
+    mounted: UpSafeCell<Option<Arc<Dentry>>>,
+    /// Pages of the file, if it is read through the page cache
+    pages: Option<Arc<PageCache>>,
+}
+
+impl Dentry {
+    fn new(name: &str, parent: Option<Weak<Dentry>>, inode: Arc<dyn Inode>) -> Arc<Self> {
+        let pages = (inode.page_cached() && !inode.is_dir()).then(|| Arc::new(PageCache::new()));
+        Arc::new(Self {
+            name: name.to_string(),
+            parent,
+            inode,
+            children: unsafe { UpSafeCell::new(BTreeMap::new()) },
+            mounted: unsafe { UpSafeCell::new(None) },
+            pages,
+        })
+    }
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+    pub fn pages(&self) -> Option<&Arc<PageCache>> {
+        self.pages.as_ref()
+    }
+    /// The absolute path to this name
+    pub fn path(self: &Arc<Self>) -> String {
+        let mut names = Vec::new();
+        let mut dentry = Arc::clone(self);
+        while let Some(parent) = dentry.parent.as_ref().and_then(Weak::upgrade) {
+            names.push(dentry.name.clone());
+            dentry = parent;
+        }
+        if names.is_empty() {
+            return "/".to_string();
+        }
+        names.iter().rev().map(|name| format!("/{name}")).collect()
+    }
+    /// What is seen at this place: the root of the filesystem mounted last on it, if any
+    fn covered(self: &Arc<Self>) -> Arc<Self> {
+        let mut dentry = Arc::clone(self);
+        loop {
+            let mounted = dentry.mounted.borrow().clone();
+            match mounted {
+                Some(root) => dentry = root,
+                None => return dentry,
+            }
+        }
+    }
+    fn parent(self: &Arc<Self>) -> Arc<Self> {
+        // the root of a mounted filesystem has the parent of its mountpoint
+        self.parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .unwrap_or_else(|| Arc::clone(self))
+    }
+    /// Whether this is `ancestor` or under it
+    fn is_under(self: &Arc<Self>, ancestor: &Arc<Self>) -> bool {
+        let mut dentry = Arc::clone(self);
+        loop {
+            if Arc::ptr_eq(&dentry, ancestor) {
+                return true;
+            }
+            match dentry.parent.as_ref().and_then(Weak::upgrade) {
+                Some(parent) => dentry = parent,
+                None => return false,
+            }
+        }
+    }
+    /// Look up a name under this directory, going into what is mounted on it
+    pub fn child(self: &Arc<Self>, name: &str) -> Result<Arc<Self>, isize> {
+        if !self.inode.is_dir() {
+            return Err(ENOTDIR);
+        }
+        match name {
+            "" | "." => return Ok(Arc::clone(self)),
+            ".." => return Ok(self.parent()),
+            _ => {}
+        }
+        if name.len() > self.inode.name_length_limit() {
+            return Err(ENAMETOOLONG);
+        }
+        if let Some(child) = self.children.borrow().get(name) {
+            return Ok(child.covered());
+        }
+        let inode = self.inode.lookup(name)?;
+        let child = Self::new(name, Some(Arc::downgrade(self)), inode);
+        if self.inode.cache_lookups() {
+            self.children
+                .borrow_mut()
+                .insert(name.to_string(), Arc::clone(&child));
+        }
+        Ok(child)
+    }
+    /// Create an inode under this directory
+    pub fn create(self: &Arc<Self>, name: &str, r#type: InodeType) -> Result<Arc<Self>, isize> {
+        match self.child(name) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(err) => return Err(err),
+        }
+        let inode = self.inode.create(name, r#type)?;
+        let child = Self::new(name, Some(Arc::downgrade(self)), inode);
+        self.children
+            .borrow_mut()
+            .insert(name.to_string(), Arc::clone(&child));
+        Ok(child)
+    }
+    /// Remove a file under this directory
+    pub fn unlink(self: &Arc<Self>, name: &str) -> Result<(), isize> {
+        let child = self.child(name)?;
+        if child.inode.is_dir() {
+            return Err(EISDIR);
+        }
+        // the inode is held by the dentry alone when the file is not opened
+        if self.inode.unlink_frees() && Arc::strong_count(&child.inode) > 1 {
+            return Err(EBUSY);
+        }
+        drop(child);
+        self.inode.unlink(name)?;
+        self.children.borrow_mut().remove(name);
+        Ok(())
+    }
+}
+
+/// A filesystem mounted on a directory
+struct Mount {
+    fs: Arc<dyn FileSystem>,
+    source: String,
+    fs_type: String,
+    /// The directory hidden by the filesystem, none for the root filesystem
+    mountpoint: Option<Arc<Dentry>>,
+    root: Arc<Dentry>,
+}
+
+/// Mounted filesystems, in the order they were mounted
+static MOUNTS: UpSafeCell<Vec<Mount>> = unsafe { UpSafeCell::new(Vec::new()) };
+
+/// The root directory, with the root filesystem
+static ROOT: UpSafeLazyCell<Arc<Dentry>> = unsafe {
+    UpSafeLazyCell::new(|| {
+        let (fs, fs_type) = super::open_root();
+        let root = Dentry::new("", None, fs.root());
+        MOUNTS.borrow_mut().push(Mount {
+            fs,
+            source: super::ROOT_DEVICE.to_string(),
+            fs_type: fs_type.to_string(),
+            mountpoint: None,
+            root: Arc::clone(&root),
+        });
+        root
+    })
+};
+
+/// Resolve a path. There is no working directory, so relative paths start from the root too
+pub fn lookup(path: &str) -> Result<Arc<Dentry>, isize> {
+    path.split('/')
+        .try_fold(ROOT.covered(), |dir, name| dir.child(name))
+}
+
+/// Resolve all but the last name of a path, return the directory and that name
+pub fn lookup_parent(path: &str) -> Result<(Arc<Dentry>, &str), isize> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    Ok((lookup(dir)?, name))
+}
+
+/// Mount `fs` of type `fs_type` from `source` on the directory at `path`,
+/// on top of anything mounted there
+pub fn mount(
+    fs: Arc<dyn FileSystem>,
+    source: &str,
+    fs_type: &str,
+    path: &str,
+) -> Result<(), isize> {
+    let mountpoint = lookup(path)?;
+    if !mountpoint.inode.is_dir() {
+        return Err(ENOTDIR);
+    }
+    let root = Dentry::new(&mountpoint.name, mountpoint.parent.clone(), fs.root());
+    *mountpoint.mounted.borrow_mut() = Some(Arc::clone(&root));
+    MOUNTS.borrow_mut().push(Mount {
+        fs,
+        source: source.to_string(),
+        fs_type: fs_type.to_string(),
+        mountpoint: Some(mountpoint),
+        root,
+    });
+    Ok(())
+}
+
+/// Unmount the filesystem whose root is at `path`. Files opened in it stay usable
+pub fn umount(path: &str) -> Result<(), isize> {
+    let root = lookup(path)?;
+    let mut mounts = MOUNTS.borrow_mut();
+    let i = mounts
+        .iter()
+        .position(|mount| Arc::ptr_eq(&mount.root, &root))
+        .ok_or(EINVAL)?;
+    // the root filesystem, or one with others mounted inside it
+    let Some(mountpoint) = mounts[i].mountpoint.clone() else {
+        return Err(EBUSY);
+    };
+    if mounts
+        .iter()
+        .filter_map(|mount| mount.mountpoint.as_ref())
+        .any(|other| other.is_under(&root))
+    {
+        return Err(EBUSY);
+    }
+    let mount = mounts.remove(i);
+    drop(mounts);
+    mount.fs.sync();
+    *mountpoint.mounted.borrow_mut() = None;
+    Ok(())
+}
+
+/// A line for each mounted filesystem with its source, directory and type,
+/// in the order they were mounted
+pub fn mount_table() -> String {
+    MOUNTS
+        .borrow()
+        .iter()
+        .map(|mount| {
+            let path = match &mount.mountpoint {
+                Some(mountpoint) => mountpoint.path(),
+                None => "/".to_string(),
+            };
+            format!("{} {} {}\n", mount.source, path, mount.fs_type)
+        })
+        .collect()
+}
+
+/// Whether a filesystem is mounted from `source`
+pub fn mounted(source: &str) -> bool {
+    MOUNTS.borrow().iter().any(|mount| mount.source == source)
+}
+
+/// Write the cached data of every mounted filesystem back
+pub fn sync_all() {
+    let filesystems: Vec<_> = MOUNTS
+        .borrow()
+        .iter()
+        .map(|mount| Arc::clone(&mount.fs))
+        .collect();
+    for fs in filesystems {
+        fs.sync();
+    }
+}
@@ -39,6 +39,8 @@ pub fn main() -> ! {
     memory::remap_test();
     trap::init();
 
+    fs::init();
+    memory::swap_on(config::memory::SWAP_DEVICE);
     fs::list_apps();
     task::add_init();
     trap::enable_timer_interrupt();
@@ -1,8 +1,10 @@
 //! Implementation of [`FrameAllocator`] which
 //! controls all the frames in the operating system.
 
-use super::{PhysAddr, PhysPageNum};
+use super::cfg::KERNEL_RESERVED_FRAMES;
+use super::{PhysAddr, PhysPageNum, swap};
 use crate::sync::{UpSafeCell, UpSafeLazyCell};
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 
@@ -35,59 +37,187 @@ impl Drop for FrameTracker {
     }
 }
 
+/// manage `2^order` contiguous frames which have the same lifecycle as the tracker
+pub struct FrameRangeTracker {
+    pub ppn: PhysPageNum,
+    order: usize,
+}
+
+impl FrameRangeTracker {
+    fn new(ppn: PhysPageNum, order: usize) -> Self {
+        for i in 0..1 << order {
+            (ppn + i).as_bytes().fill(0);
+        }
+        Self { ppn, order }
+    }
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        1 << self.order
+    }
+}
+
+impl Debug for FrameRangeTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "FrameRangeTracker:PPN={:#x},order={}",
+            self.ppn.0, self.order
+        ))
+    }
+}
+
+impl Drop for FrameRangeTracker {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR.borrow_mut().dealloc(self.ppn, self.order);
+    }
+}
+
+/// The largest blocks hold `2^MAX_ORDER` frames
+pub const MAX_ORDER: usize = 10;
+
 trait FrameAllocator {
     fn new() -> Self;
-    fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn dealloc(&mut self, ppn: PhysPageNum);
+    /// Allocate `2^order` contiguous frames aligned to their size
+    fn alloc(&mut self, order: usize) -> Option<PhysPageNum>;
+    fn dealloc(&mut self, ppn: PhysPageNum, order: usize);
 }
 
-/// an implementation for frame allocator
-pub struct StackFrameAllocator {
-    current: usize,
+/// No frame, the end of a free list
+const NONE: usize = usize::MAX;
+
+/// A buddy allocator: free blocks of `2^k` frames, aligned to their size, are
+/// kept in a list for each order `k`. A block is split in two buddies to
+/// allocate a smaller one, and merged back with its buddy when both are free
+///
+/// The lists are linked through the free frames themselves, whose first
+/// words hold the previous and the next block of the list
+pub struct BuddyFrameAllocator {
+    start: usize,
     end: usize,
-    recycled: Vec<usize>,
+    /// Head of the free list of each order
+    free: [usize; MAX_ORDER + 1],
+    /// Number of free blocks of each order
+    free_blocks: [usize; MAX_ORDER + 1],
+    /// For each frame, one more than the order of the free block it starts, or 0
+    orders: Vec<u8>,
 }
 
-impl StackFrameAllocator {
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
+        self.start = l.0;
         self.end = r.0;
+        self.orders = vec![0; r.0 - l.0];
+        // the largest aligned blocks which fit
+        let mut ppn = l.0;
+        while ppn < r.0 {
+            let mut order = MAX_ORDER.min(ppn.trailing_zeros() as usize);
+            while ppn + (1 << order) > r.0 {
+                order -= 1;
+            }
+            self.push(ppn, order);
+            ppn += 1 << order;
+        }
+    }
+    /// Number of frames in all, and of the free ones
+    pub fn count(&self) -> (usize, usize) {
+        let free = (0..=MAX_ORDER)
+            .map(|order| self.free_blocks[order] << order)
+            .sum();
+        (self.end - self.start, free)
+    }
+    /// Number of free blocks of each order
+    pub fn free_blocks(&self) -> [usize; MAX_ORDER + 1] {
+        self.free_blocks
+    }
+    /// The previous and the next block in the list of the free block `ppn`
+    fn links(ppn: usize) -> &'static mut [usize; 2] {
+        PhysPageNum(ppn).as_mut()
+    }
+    /// The order of the free block starting at `ppn`, if there is one
+    fn free_order(&self, ppn: usize) -> Option<usize> {
+        match self.orders[ppn - self.start] {
+            0 => None,
+            order => Some(order as usize - 1),
+        }
+    }
+    fn push(&mut self, ppn: usize, order: usize) {
+        let next = self.free[order];
+        *Self::links(ppn) = [NONE, next];
+        if next != NONE {
+            Self::links(next)[0] = ppn;
+        }
+        self.free[order] = ppn;
+        self.free_blocks[order] += 1;
+        self.orders[ppn - self.start] = order as u8 + 1;
+    }
+    fn remove(&mut self, ppn: usize, order: usize) {
+        let [prev, next] = *Self::links(ppn);
+        if prev == NONE {
+            self.free[order] = next;
+        } else {
+            Self::links(prev)[1] = next;
+        }
+        if next != NONE {
+            Self::links(next)[0] = prev;
+        }
+        self.free_blocks[order] -= 1;
+        self.orders[ppn - self.start] = 0;
     }
 }
-impl FrameAllocator for StackFrameAllocator {
+
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
+            start: 0,
             end: 0,
-            recycled: Vec::new(),
+            free: [NONE; MAX_ORDER + 1],
+            free_blocks: [0; MAX_ORDER + 1],
+            orders: Vec::new(),
         }
     }
-    fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
+    fn alloc(&mut self, order: usize) -> Option<PhysPageNum> {
+        let from = (order..=MAX_ORDER).find(|&k| self.free[k] != NONE)?;
+        let ppn = self.free[from];
+        self.remove(ppn, from);
+        // give back the upper halves of the block until it has the order asked
+        for k in (order..from).rev() {
+            self.push(ppn + (1 << k), k);
         }
+        Some(ppn.into())
     }
-    fn dealloc(&mut self, ppn: PhysPageNum) {
-        let ppn = ppn.0;
-        // validity check
-        if ppn >= self.current || self.recycled.contains(&ppn) {
+    fn dealloc(&mut self, ppn: PhysPageNum, order: usize) {
+        let mut ppn = ppn.0;
+        // validity check: in range and not in a free block
+        let free = ppn < self.start
+            || ppn + (1 << order) > self.end
+            || (0..=MAX_ORDER).any(|k| {
+                let block = ppn & !((1 << k) - 1);
+                block >= self.start && self.free_order(block) == Some(k)
+            });
+        if free {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
-        // recycle
-        self.recycled.push(ppn);
+        // merge with the buddy while it is free
+        let mut order = order;
+        while order < MAX_ORDER {
+            let buddy = ppn ^ (1 << order);
+            if buddy < self.start
+                || buddy + (1 << order) > self.end
+                || self.free_order(buddy) != Some(order)
+            {
+                break;
+            }
+            self.remove(buddy, order);
+            ppn = ppn.min(buddy);
+            order += 1;
+        }
+        self.push(ppn, order);
     }
 }
 
-// type FrameAllocatorImpl = StackFrameAllocator;
-static FRAME_ALLOCATOR: UpSafeLazyCell<UpSafeCell<StackFrameAllocator>> = unsafe {
+static FRAME_ALLOCATOR: UpSafeLazyCell<UpSafeCell<BuddyFrameAllocator>> = unsafe {
     UpSafeLazyCell::new(|| {
-        let frame_allocator: UpSafeCell<StackFrameAllocator> =
-            UpSafeCell::new(StackFrameAllocator::new());
+        let frame_allocator: UpSafeCell<BuddyFrameAllocator> =
+            UpSafeCell::new(BuddyFrameAllocator::new());
         use super::cfg::MEMORY_END;
         use crate::label::ekernel;
         frame_allocator.borrow_mut().init(
@@ -98,14 +228,68 @@ static FRAME_ALLOCATOR: UpSafeLazyCell<UpSafeCell<StackFrameAllocator>> = unsafe
     })
 };
 
-/// allocate a frame
+/// Allocate `2^order` contiguous frames, swapping pages out if there are none.
+/// The last `KERNEL_RESERVED_FRAMES` free frames are left to the kernel heap,
+/// which cannot swap pages out
+fn alloc(order: usize) -> Option<PhysPageNum> {
+    loop {
+        let mut allocator = FRAME_ALLOCATOR.borrow_mut();
+        let ppn = if allocator.count().1 >= KERNEL_RESERVED_FRAMES + (1 << order) {
+            allocator.alloc(order)
+        } else {
+            None
+        };
+        drop(allocator);
+        match ppn {
+            Some(ppn) => return Some(ppn),
+            // a frame swapped out may complete a free block
+            None if swap::swap_out() => {}
+            None => return None,
+        }
+    }
+}
+
+/// allocate a frame, swapping a page out if there is no free one
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR.borrow_mut().alloc().map(FrameTracker::new)
+    alloc(0).map(FrameTracker::new)
+}
+
+/// Allocate `2^order` contiguous frames, aligned to their size, like for DMA
+pub fn frame_alloc_contiguous(order: usize) -> Option<FrameRangeTracker> {
+    assert!(
+        order <= MAX_ORDER,
+        "cannot allocate 2^{order} contiguous frames"
+    );
+    alloc(order).map(|ppn| FrameRangeTracker::new(ppn, order))
 }
 
 /// deallocate a frame
-pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.borrow_mut().dealloc(ppn);
+fn frame_dealloc(ppn: PhysPageNum) {
+    FRAME_ALLOCATOR.borrow_mut().dealloc(ppn, 0);
+}
+
+/// Allocate `2^order` contiguous frames, left as they are, for the kernel heap.
+/// Pages are not swapped out for them: swapping allocates from the heap too
+pub(super) fn frame_alloc_raw(order: usize) -> Option<PhysPageNum> {
+    if order > MAX_ORDER {
+        return None;
+    }
+    FRAME_ALLOCATOR.borrow_mut().alloc(order)
+}
+
+/// Free frames from [`frame_alloc_raw`]
+pub(super) fn frame_dealloc_raw(ppn: PhysPageNum, order: usize) {
+    FRAME_ALLOCATOR.borrow_mut().dealloc(ppn, order);
+}
+
+/// Number of frames in all, and of the free ones
+pub fn frame_count() -> (usize, usize) {
+    FRAME_ALLOCATOR.borrow().count()
+}
+
+/// Number of free blocks of each order
+pub fn frame_free_blocks() -> [usize; MAX_ORDER + 1] {
+    FRAME_ALLOCATOR.borrow().free_blocks()
 }
 
 #[allow(unused)]
@@ -124,5 +308,17 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v);
+    // blocks are aligned to their size, and merged back once freed
+    let before = frame_count();
+    let blocks: Vec<FrameRangeTracker> = (0..4)
+        .map(|order| frame_alloc_contiguous(order).unwrap())
+        .collect();
+    for block in &blocks {
+        println!("{:?}", block);
+        assert_eq!(block.ppn.0 % block.len(), 0);
+    }
+    assert_eq!(frame_count().1, before.1 - 15);
+    drop(blocks);
+    assert_eq!(frame_count(), before);
     println!("frame_allocator_test passed!");
 }
@@ -1,10 +1,124 @@
 //! The global allocator
+//!
+//! Small objects come from the slab caches. Larger ones, or small ones when
+//! there is no frame for a slab, come from the heap: the static heap space
+//! first, then regions of frames taken when it is full, which are given
+//! back once they are empty
 
-use linked_list_allocator::LockedHeap;
+use super::PhysPageNum;
+use super::cfg::{KERNEL_HEAP_GROW_ORDER, PAGE_SIZE};
+use super::frame_allocator::{frame_alloc_raw, frame_dealloc_raw};
+use super::slab::{slab_alloc, slab_dealloc};
+use crate::sync::UpSafeCell;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+
+/// Number of regions the heap may have, the static one included
+const MAX_REGIONS: usize = 32;
+
+/// A region of the heap, and the frames it is in
+struct Region {
+    heap: Heap,
+    /// The first frame and the order of the block, none for the static region
+    frames: Option<(PhysPageNum, usize)>,
+}
+
+impl Region {
+    fn contains(&self, ptr: *mut u8) -> bool {
+        (self.heap.bottom()..self.heap.top()).contains(&ptr)
+    }
+}
+
+/// The heap, a few regions each with its own free list
+struct KernelHeap {
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+impl KernelHeap {
+    fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let allocated = self.regions[..self.len]
+            .iter_mut()
+            .find_map(|region| region.heap.allocate_first_fit(layout).ok());
+        allocated.or_else(|| {
+            self.grow(layout)?;
+            self.regions[self.len - 1]
+                .heap
+                .allocate_first_fit(layout)
+                .ok()
+        })
+    }
+    /// Add a region with room for `layout`, none if there is no frame or no room for a region
+    fn grow(&mut self, layout: Layout) -> Option<()> {
+        if self.len == MAX_REGIONS {
+            return None;
+        }
+        // blocks of frames are aligned to their size
+        let pages = layout.size().max(layout.align()).div_ceil(PAGE_SIZE);
+        let order =
+            (pages.next_power_of_two().trailing_zeros() as usize).max(KERNEL_HEAP_GROW_ORDER);
+        let ppn = frame_alloc_raw(order)?;
+        let bytes = PAGE_SIZE << order;
+        let region = &mut self.regions[self.len];
+        unsafe { region.heap.init(ppn.as_bytes().as_mut_ptr(), bytes) };
+        region.frames = Some((ppn, order));
+        self.len += 1;
+        Some(())
+    }
+    /// Free `ptr`, false if it is not in the heap
+    fn dealloc(&mut self, ptr: *mut u8, layout: Layout) -> bool {
+        let Some(i) = self.regions[..self.len]
+            .iter()
+            .position(|region| region.contains(ptr))
+        else {
+            return false;
+        };
+        let region = &mut self.regions[i];
+        unsafe { region.heap.deallocate(NonNull::new_unchecked(ptr), layout) };
+        if region.heap.used() == 0
+            && let Some((ppn, order)) = region.frames.take()
+        {
+            self.len -= 1;
+            self.regions.swap(i, self.len);
+            self.regions[self.len].heap = Heap::empty();
+            frame_dealloc_raw(ppn, order);
+        }
+        true
+    }
+}
+
+static HEAP: UpSafeCell<KernelHeap> = unsafe {
+    UpSafeCell::new(KernelHeap {
+        regions: [const {
+            Region {
+                heap: Heap::empty(),
+                frames: None,
+            }
+        }; MAX_REGIONS],
+        len: 0,
+    })
+};
+
+/// Slab caches first, then the heap
+struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        slab_alloc(&layout)
+            .or_else(|| HEAP.borrow_mut().alloc(layout))
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !HEAP.borrow_mut().dealloc(ptr, layout) {
+            slab_dealloc(ptr, &layout);
+        }
+    }
+}
 
 #[global_allocator]
 /// heap allocator instance
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: KernelAllocator = KernelAllocator;
 
 #[alloc_error_handler]
 /// panic when heap allocation error occurs
@@ -21,7 +135,47 @@ static mut HEAP_SPACE: [MaybeUninit<u8>; KERNEL_HEAP_SIZE] =
 #[deny(dead_code)]
 /// initiate heap allocator
 pub fn init() {
-    HEAP_ALLOCATOR
-        .lock()
+    let mut heap = HEAP.borrow_mut();
+    heap.regions[0]
+        .heap
         .init_from_slice(unsafe { HEAP_SPACE.as_mut_slice() });
+    heap.len = 1;
+}
+
+/// Size of the kernel heap and the bytes used in it
+pub fn heap_usage() -> (usize, usize) {
+    let heap = HEAP.borrow();
+    heap.regions[..heap.len]
+        .iter()
+        .fold((0, 0), |(size, used), region| {
+            (size + region.heap.size(), used + region.heap.used())
+        })
+}
+
+#[allow(unused)]
+/// a simple test for the slab caches and the growth of the heap
+pub fn heap_test() {
+    use super::slab::{SLAB_CLASSES, slab_usage};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    let objects = |usage: [(usize, usize, usize); SLAB_CLASSES]| usage.map(|(_, used, _)| used);
+    // a small object is in a slab, aligned to its class
+    let before = objects(slab_usage());
+    let a = Box::new([5u8; 100]);
+    assert_eq!(a.as_ptr() as usize % 128, 0);
+    let after = objects(slab_usage());
+    assert_eq!(
+        after.iter().sum::<usize>(),
+        before.iter().sum::<usize>() + 1
+    );
+    drop(a);
+    assert_eq!(objects(slab_usage()), before);
+    // a vector larger than the heap space takes a region of frames, given back once dropped
+    let (size, _) = heap_usage();
+    let v: Vec<u8> = alloc::vec![1; KERNEL_HEAP_SIZE];
+    assert!(heap_usage().0 > size);
+    assert!(v.iter().all(|&b| b == 1));
+    drop(v);
+    assert_eq!(heap_usage().0, size);
+    println!("heap_test passed!");
 }
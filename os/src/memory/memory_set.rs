@@ -1,6 +1,8 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::cfg::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use super::cfg::{MEMORY_END, MMIO, PAGE_SIZE, SHM_BASE, SHM_END};
+use super::cfg::{TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use super::swap::{PinnedPages, SwapPage};
 use super::{FrameTracker, frame_alloc};
 use super::{PageTable, PageTableDirect, PageTableEntry, PageTableEntryFlags};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
@@ -10,15 +12,47 @@ use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::bitflags;
+use config::errno::{EINVAL, ENOEXEC, ENOMEM};
 use core::arch::asm;
 
-pub static KERNEL_SPACE: UpSafeLazyCell<Arc<UpSafeCell<MemorySet>>> =
-    unsafe { UpSafeLazyCell::new(|| Arc::new(UpSafeCell::new(MemorySet::new_kernel()))) };
+pub static KERNEL_SPACE: UpSafeLazyCell<Arc<UpSafeCell<MemorySet>>> = unsafe {
+    UpSafeLazyCell::new(|| {
+        let memory_set = MemorySet::new_kernel().expect("no frame for the kernel space");
+        Arc::new(UpSafeCell::new(memory_set))
+    })
+};
+
+/// The kernel page table never moves, so its token is kept and
+/// read without borrowing `KERNEL_SPACE`, which may be borrowed already
+/// when frames run out and pages are swapped out through a device
+static KERNEL_TOKEN: UpSafeLazyCell<PageTableDirect> =
+    unsafe { UpSafeLazyCell::new(|| KERNEL_SPACE.borrow().token()) };
 
 ///Get kernelspace root ppn
 pub fn kernel_token() -> PageTableDirect {
-    KERNEL_SPACE.borrow_mut().token()
+    **KERNEL_TOKEN
+}
+/// The pages of a file which programs are loaded from
+pub trait FilePages {
+    /// The frame holding page `index` of the file, with zeros past the end
+    /// of the file, `None` if the page is past the end or cannot be read.
+    /// The frame may be shared, it is only ever mapped read-only
+    fn page(&self, index: usize) -> Option<Arc<FrameTracker>>;
+}
+
+/// Read the first `len` bytes of `file`, less if it is shorter
+fn read_pages(file: &dyn FilePages, len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let Some(frame) = file.page(data.len() / PAGE_SIZE) else {
+            break;
+        };
+        let part = (len - data.len()).min(PAGE_SIZE);
+        data.extend_from_slice(&frame.ppn.as_bytes()[..part]);
+    }
+    data
 }
+
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     page_table: PageTable,
@@ -26,28 +60,28 @@ pub struct MemorySet {
 }
 
 impl MemorySet {
-    ///Create an empty `MemorySet`
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    ///Create an empty `MemorySet`, `None` if there is no frame for its page table
+    pub fn new_bare() -> Option<Self> {
+        Some(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+        })
     }
     ///Get pagetable `root_ppn`
     pub fn token(&self) -> PageTableDirect {
         self.page_table.token()
     }
-    /// Assume that no conflicts.
+    /// Assume that no conflicts. `None` if there are not enough frames
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
+    ) -> Option<()> {
         self.push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
-        );
+        )
     }
     ///Remove `MapArea` that starts with `start_vpn`
     pub fn pop_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) -> Option<MapArea> {
@@ -63,26 +97,28 @@ impl MemorySet {
             None
         }
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// Map `map_area` and add it, `None` and nothing mapped if there are not enough frames
+    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Option<()> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&self.page_table, data);
         }
         self.areas.push(map_area);
+        Some(())
     }
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> Option<()> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PageTableEntryFlags::R | PageTableEntryFlags::X,
-        );
+        )
     }
-    /// Without kernel stacks.
-    pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+    /// Without kernel stacks. `None` if there are not enough frames for the page table
+    pub fn new_kernel() -> Option<Self> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // map kernel sections
         log::debug!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         log::debug!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
@@ -101,7 +137,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::X,
             ),
             None,
-        );
+        )?;
         log::debug!("mapping .rodata section");
         memory_set.push(
             MapArea::new(
@@ -111,7 +147,7 @@ impl MemorySet {
                 MapPermission::R,
             ),
             None,
-        );
+        )?;
         log::debug!("mapping .data section");
         memory_set.push(
             MapArea::new(
@@ -121,7 +157,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         log::debug!("mapping .bss section");
         memory_set.push(
             MapArea::new(
@@ -131,7 +167,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         log::debug!("mapping physical memory");
         memory_set.push(
             MapArea::new(
@@ -141,7 +177,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         log::debug!("mapping memory-mapped registers");
         for pair in MMIO {
             memory_set.push(
@@ -152,26 +188,41 @@ impl MemorySet {
                     MapPermission::R | MapPermission::W,
                 ),
                 None,
-            );
+            )?;
         }
-        memory_set
+        Some(memory_set)
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
-    /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
+    /// also returns user_sp and entry point. `ENOEXEC` if `file` is not an elf
+    /// or its pages cannot be read, `ENOMEM` if there are not enough frames
+    pub fn from_elf(file: &dyn FilePages) -> Result<(Self, usize, usize), isize> {
+        let mut memory_set = Self::new_bare().ok_or(ENOMEM)?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline().ok_or(ENOMEM)?;
+        // the headers, which are most often all in the first page
+        let mut headers = read_pages(file, PAGE_SIZE);
+        let headers_end = {
+            let pt2 = &xmas_elf::ElfFile::new(&headers)
+                .or(Err(ENOEXEC))?
+                .header
+                .pt2;
+            pt2.ph_offset() as usize + pt2.ph_count() as usize * pt2.ph_entry_size() as usize
+        };
+        if headers_end > headers.len() {
+            headers = read_pages(file, headers_end);
+        }
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf = xmas_elf::ElfFile::new(&headers).or(Err(ENOEXEC))?;
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        if magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err(ENOEXEC);
+        }
         let ph_count = elf_header.pt2.ph_count();
         let mut max_end = VirtPageNum(0);
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            let ph = elf.program_header(i).or(Err(ENOEXEC))?;
+            if ph.get_type().or(Err(ENOEXEC))? == xmas_elf::program::Type::Load {
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
                 let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
                 let mut map_perm = MapPermission::U;
@@ -185,12 +236,30 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+                let mut map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end = max_end.max(map_area.vpn_range.end);
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                let offset = ph.offset() as usize;
+                let va = ph.virtual_addr() as usize;
+                let file_size = ph.file_size() as usize;
+                if !map_perm.contains(MapPermission::W)
+                    && file_size == ph.mem_size() as usize
+                    && offset % PAGE_SIZE == va % PAGE_SIZE
+                {
+                    // map the pages of the file itself, shared by the programs running it
+                    let first_page = offset / PAGE_SIZE;
+                    for (i, vpn) in map_area.vpn_range.into_iter().enumerate() {
+                        let frame = file.page(first_page + i).ok_or(ENOEXEC)?;
+                        map_area
+                            .map_frame(&mut memory_set.page_table, vpn, frame)
+                            .ok_or(ENOMEM)?;
+                    }
+                    memory_set.areas.push(map_area);
+                } else {
+                    memory_set.push(map_area, None).ok_or(ENOMEM)?;
+                    memory_set
+                        .copy_file(file, offset, va, file_size)
+                        .ok_or(ENOEXEC)?;
+                }
             }
         }
         // map user stack with U flags
@@ -199,48 +268,134 @@ impl MemorySet {
         // guard page
         user_stack_bottom += PAGE_SIZE;
         let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(
-            MapArea::new(
-                user_stack_bottom.into(),
-                user_stack_top.into(),
-                MapType::Framed,
-                MapPermission::R | MapPermission::W | MapPermission::U,
-            ),
-            None,
-        );
+        memory_set
+            .push(
+                MapArea::new(
+                    user_stack_bottom.into(),
+                    user_stack_top.into(),
+                    MapType::Framed,
+                    MapPermission::R | MapPermission::W | MapPermission::U,
+                ),
+                None,
+            )
+            .ok_or(ENOMEM)?;
         // map TrapContext
-        memory_set.push(
-            MapArea::new(
-                TRAP_CONTEXT.into(),
-                TRAMPOLINE.into(),
-                MapType::Framed,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
-        (
+        memory_set
+            .push(
+                MapArea::new(
+                    TRAP_CONTEXT.into(),
+                    TRAMPOLINE.into(),
+                    MapType::Framed,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            )
+            .ok_or(ENOMEM)?;
+        Ok((
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
+    }
+    /// Copy `len` bytes of `file` at `offset` to `va`, which is mapped already
+    fn copy_file(&self, file: &dyn FilePages, offset: usize, va: usize, len: usize) -> Option<()> {
+        let mut pos = 0;
+        while pos < len {
+            let (src, dst) = (offset + pos, va + pos);
+            let part = (PAGE_SIZE - src % PAGE_SIZE)
+                .min(PAGE_SIZE - dst % PAGE_SIZE)
+                .min(len - pos);
+            let frame = file.page(src / PAGE_SIZE)?;
+            let dst_ppn = self.translate(VirtAddr::from(dst).floor())?.ppn();
+            dst_ppn.as_bytes()[dst % PAGE_SIZE..][..part]
+                .copy_from_slice(&frame.ppn.as_bytes()[src % PAGE_SIZE..][..part]);
+            pos += part;
+        }
+        Some(())
     }
-    ///Clone a same `MemorySet`
-    pub fn from_existed_user(user_space: &Self) -> Self {
-        let mut memory_set = Self::new_bare();
+    ///Clone a same `MemorySet`, `None` if there are not enough frames
+    pub fn from_existed_user(user_space: &Self) -> Option<Self> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
+            let mut new_area = MapArea::from_another(area);
+            if !area.map_perm.contains(MapPermission::W) || area.map_type == MapType::Shared {
+                // nobody writes to read-only pages, so they are shared like shared memory
+                for (vpn, frame) in area.data_frames.iter() {
+                    new_area.map_frame(&mut memory_set.page_table, *vpn, Arc::clone(frame))?;
+                }
+                memory_set.areas.push(new_area);
+                continue;
+            }
+            memory_set.push(new_area, None)?;
+            // copy data from another space, swapping its pages in, and keeping
+            // both pages in their frames until the copy is done
             for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                let _src_pin = PinnedPages::new(user_space.token(), vpn..vpn + 1);
+                let _dst_pin = PinnedPages::new(memory_set.token(), vpn..vpn + 1);
+                let src_ppn = user_space.translate(vpn)?.ppn();
+                let dst_ppn = memory_set.translate(vpn)?.ppn();
                 dst_ppn.as_bytes().copy_from_slice(src_ppn.as_bytes());
             }
         }
-        memory_set
+        Some(memory_set)
+    }
+    /// Map the shared `frames` at `start`, or where there is room from `SHM_BASE`
+    /// on, return where. `EINVAL` if `start` is not aligned or not free, `ENOMEM`
+    /// if there is no room or no frame for the page table
+    pub fn attach_shared(
+        &mut self,
+        frames: &[Arc<FrameTracker>],
+        start: Option<VirtAddr>,
+        permission: MapPermission,
+    ) -> Result<VirtAddr, isize> {
+        let start = match start {
+            Some(start) if start.aligned() => start.floor(),
+            Some(_) => return Err(EINVAL),
+            None => self.free_range(frames.len()).ok_or(ENOMEM)?,
+        };
+        let end = VirtPageNum(start.0 + frames.len());
+        let overlaps = self
+            .areas
+            .iter()
+            .any(|area| area.vpn_range.start < end && start < area.vpn_range.end);
+        if end > VirtAddr::from(SHM_END).floor() || overlaps {
+            return Err(EINVAL);
+        }
+        let mut area = MapArea::new(start.into(), end.into(), MapType::Shared, permission);
+        for (vpn, frame) in (start..end).zip(frames) {
+            if area
+                .map_frame(&mut self.page_table, vpn, Arc::clone(frame))
+                .is_none()
+            {
+                area.unmap_to(&mut self.page_table, vpn);
+                return Err(ENOMEM);
+            }
+        }
+        self.areas.push(area);
+        Ok(start.into())
+    }
+    /// The first `len` pages free from `SHM_BASE` on
+    fn free_range(&self, len: usize) -> Option<VirtPageNum> {
+        let mut start = VirtAddr::from(SHM_BASE).floor();
+        let mut ranges: Vec<_> = self.areas.iter().map(|area| area.vpn_range).collect();
+        ranges.sort_by_key(|range| range.start);
+        for range in ranges {
+            if range.start.0 >= start.0 + len {
+                break;
+            }
+            start = start.max(range.end);
+        }
+        (start.0 + len <= VirtAddr::from(SHM_END).floor().0).then_some(start)
+    }
+    /// Unmap the shared memory attached at `start`, false if there is none
+    pub fn detach_shared(&mut self, start: VirtAddr) -> bool {
+        let shared = self.areas.iter().any(|area| {
+            area.map_type == MapType::Shared && VirtAddr::from(area.vpn_range.start) == start
+        });
+        shared && self.pop_area_with_start_vpn(start.floor()).is_some()
     }
     ///Refresh TLB with `sfence.vma`
     pub fn activate(&self) {
@@ -254,6 +409,29 @@ impl MemorySet {
         self.page_table.translate_vp(vpn)
     }
 
+    /// Start, end and permission of the mapped areas
+    pub fn areas(&self) -> impl Iterator<Item = (VirtAddr, VirtAddr, MapPermission)> + '_ {
+        self.areas.iter().map(|area| {
+            (
+                area.vpn_range.start.into(),
+                area.vpn_range.end.into(),
+                area.map_perm,
+            )
+        })
+    }
+    /// Number of frames used by the memory set and in memory: those of its
+    /// page table, and of its pages not swapped out, shared ones included
+    pub fn resident_frames(&self) -> usize {
+        let pages: usize = self
+            .areas
+            .iter()
+            .map(|area| {
+                let resident = area.swap_pages.values().filter(|page| page.resident());
+                area.data_frames.len() + resident.count()
+            })
+            .sum();
+        self.page_table.frames() + pages
+    }
     ///Remove all `MapArea`
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
@@ -264,7 +442,10 @@ impl MemorySet {
 /// map area structure, controls a contiguous piece of virtual memory
 pub struct MapArea {
     vpn_range: core::range::Range<VirtPageNum>,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// Frames of read-only and kernel areas, which may be shared
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    /// Pages of writable user areas, which may be swapped out
+    swap_pages: BTreeMap<VirtPageNum, Arc<SwapPage>>,
     map_type: MapType,
     map_perm: MapPermission,
 }
@@ -281,6 +462,7 @@ impl MapArea {
         Self {
             vpn_range: (start_vpn..end_vpn).into(),
             data_frames: BTreeMap::new(),
+            swap_pages: BTreeMap::new(),
             map_type,
             map_perm,
         }
@@ -289,34 +471,65 @@ impl MapArea {
         Self {
             vpn_range: another.vpn_range.clone(),
             data_frames: BTreeMap::new(),
+            swap_pages: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        let ppn: PhysPageNum;
+    /// Map `vpn`, `None` if there is no frame for it or for the page table
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Option<()> {
+        let pte_flags = PageTableEntryFlags::from_bits(self.map_perm.bits()).unwrap();
         match self.map_type {
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
-            }
+            MapType::Identical => page_table.map(vpn, PhysPageNum(vpn.0), pte_flags)?,
+            MapType::Shared => unreachable!("shared memory is mapped with its frames"),
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let frame = frame_alloc()?;
+                if self.map_perm.contains(MapPermission::U | MapPermission::W) {
+                    // private to the area, so it may be swapped out
+                    page_table.map(vpn, frame.ppn, pte_flags | PageTableEntryFlags::A)?;
+                    let page = SwapPage::new(frame, page_table.token(), vpn);
+                    self.swap_pages.insert(vpn, page);
+                } else {
+                    page_table.map(vpn, frame.ppn, pte_flags)?;
+                    self.data_frames.insert(vpn, Arc::new(frame));
+                }
             }
         }
+        Some(())
+    }
+    /// Map `vpn` to a frame which may be shared with other areas
+    pub fn map_frame(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        frame: Arc<FrameTracker>,
+    ) -> Option<()> {
         let pte_flags = PageTableEntryFlags::from_bits(self.map_perm.bits()).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        page_table.map(vpn, frame.ppn, pte_flags)?;
+        self.data_frames.insert(vpn, frame);
+        Some(())
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
+        if self.map_type != MapType::Identical {
             self.data_frames.remove(&vpn);
+            self.swap_pages.remove(&vpn);
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Map all pages, or none of them if there are not enough frames
+    pub fn map(&mut self, page_table: &mut PageTable) -> Option<()> {
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if self.map_one(page_table, vpn).is_none() {
+                self.unmap_to(page_table, vpn);
+                return None;
+            }
+        }
+        Some(())
+    }
+    /// Unmap the pages before `end`, the ones mapped when mapping failed at `end`
+    fn unmap_to(&mut self, page_table: &mut PageTable, end: VirtPageNum) {
+        for vpn in self.vpn_range.start..end {
+            self.unmap_one(page_table, vpn);
         }
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
@@ -341,10 +554,12 @@ impl MapArea {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// map type for memory set: identical or framed
+/// map type for memory set: identical, framed, or framed with frames shared
+/// by the memory sets attaching the same shared memory
 pub enum MapType {
     Identical,
     Framed,
+    Shared,
 }
 
 bitflags! {
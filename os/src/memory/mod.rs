@@ -11,16 +11,26 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod shm;
+mod slab;
+mod swap;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-pub use frame_allocator::{FrameTracker, frame_alloc, frame_dealloc};
+pub use frame_allocator::{
+    FrameRangeTracker, FrameTracker, frame_alloc, frame_alloc_contiguous, frame_count,
+    frame_free_blocks,
+};
+pub use heap_allocator::heap_usage;
 pub use memory_set::remap_test;
-pub use memory_set::{KERNEL_SPACE, MapPermission, MemorySet, kernel_token};
+pub use memory_set::{FilePages, KERNEL_SPACE, MapPermission, MemorySet, kernel_token};
 pub use page_table::{PageTable, PageTableDirect, PageTableEntryFlags};
 pub use page_table::{
     PageTableEntry, UserBuffer, translate_bytes, translate_bytes_slice, translate_necked_slice,
-    translate_ref, translate_ref_mut, translate_sized, translate_slice, translate_to,
+    translate_ref, translate_ref_mut, translate_sized, translate_to, user_writable,
 };
+pub use shm::{shm_frames, shm_get, shm_remove};
+pub use slab::slab_usage;
+pub use swap::{swap_count, swap_in_at, swap_on, swapped_out};
 
 use config::memory as cfg;
 
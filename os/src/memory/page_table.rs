@@ -1,5 +1,6 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
+use super::swap::{self, PinnedPages};
 use super::{FrameTracker, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, frame_alloc};
 use alloc::vec;
 use alloc::vec::Vec;
@@ -20,6 +21,9 @@ bitflags::bitflags! {
     }
 }
 
+/// A software bit, set with `V` clear in the entry of a page swapped out
+const SWAPPED: usize = 1 << 8;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 /// page table entry structure
@@ -36,6 +40,16 @@ impl PageTableEntry {
     pub fn empty() -> Self {
         PageTableEntry { bits: 0 }
     }
+    /// The entry of a page swapped out to `slot`, which faults when used
+    pub fn swapped(slot: usize) -> Self {
+        PageTableEntry {
+            bits: slot << 10 | SWAPPED,
+        }
+    }
+    /// The slot of a page swapped out
+    pub fn swap_slot(&self) -> Option<usize> {
+        (!self.valid() && self.bits & SWAPPED != 0).then_some(self.bits >> 10)
+    }
     pub fn ppn(&self) -> PhysPageNum {
         (self.bits >> 10 & ((1usize << 44) - 1)).into()
     }
@@ -61,14 +75,14 @@ pub struct PageTable {
     root_ppn: PageTableDirect,
     frames: Vec<FrameTracker>,
 }
-/// Assume that it won't oom when creating/mapping.
+/// Creating and mapping give `None` when there is no frame for a table
 impl PageTable {
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
+    pub fn new() -> Option<Self> {
+        let frame = frame_alloc()?;
+        Some(PageTable {
             root_ppn: frame.ppn.into(),
             frames: vec![frame],
-        }
+        })
     }
     /// Temporarily used to get arguments from user space.
     pub fn from(root: PageTableDirect) -> Self {
@@ -77,16 +91,16 @@ impl PageTable {
             frames: Vec::new(),
         }
     }
-    fn find_or_insert(&mut self, vpn: VirtPageNum) -> &mut PageTableEntry {
+    fn find_or_insert(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn: PhysPageNum = self.root_ppn.into();
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.as_page_table()[*idx];
             if i == 2 {
-                return pte;
+                return Some(pte);
             }
             if !pte.valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PageTableEntryFlags::V);
                 self.frames.push(frame);
             }
@@ -94,10 +108,10 @@ impl PageTable {
         }
         unreachable!()
     }
-    fn find(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    pub(super) fn find(&self, vpn: VirtPageNum) -> Option<&'static mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn: PhysPageNum = self.root_ppn.into();
-        let mut result: Option<&mut PageTableEntry> = None;
+        let mut result: Option<&'static mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.as_page_table()[*idx];
             if i == 2 {
@@ -111,170 +125,161 @@ impl PageTable {
         }
         result
     }
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PageTableEntryFlags) {
-        let pte = self.find_or_insert(vpn);
+    pub fn map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PageTableEntryFlags,
+    ) -> Option<()> {
+        let pte = self.find_or_insert(vpn)?;
         assert!(!pte.valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PageTableEntryFlags::V);
+        Some(())
     }
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         let pte = self.find(vpn).unwrap();
-        assert!(pte.valid(), "vpn {:?} is invalid before unmapping", vpn);
+        assert!(
+            pte.valid() || pte.swap_slot().is_some(),
+            "vpn {:?} is invalid before unmapping",
+            vpn
+        );
         *pte = PageTableEntry::empty();
     }
+    /// Find the entry of `vpn`, bringing the page back if it was swapped out.
+    /// The kernel using a page counts as an access, so it is not swapped out soon
+    /// `None` if it is not mapped, or cannot be swapped in for lack of frames
+    fn find_resident(&self, vpn: VirtPageNum) -> Option<&'static mut PageTableEntry> {
+        if let Some(slot) = self.find(vpn)?.swap_slot() {
+            swap::swap_in(slot);
+        }
+        let pte = self.find(vpn).filter(|pte| pte.valid())?;
+        pte.bits |= PageTableEntryFlags::A.bits() as usize;
+        Some(pte)
+    }
     pub fn translate_vp(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find(vpn).map(|pte| *pte)
+        self.find_resident(vpn).map(|pte| *pte)
     }
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find(va.floor()).map(|page_table_entry| {
+        self.find_resident(va.floor()).map(|page_table_entry| {
             let aligned_pa: PhysAddr = page_table_entry.ppn().into();
             (aligned_pa.0 | va.page_offset()).into()
         })
     }
+    /// Number of frames holding the tables
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
     pub fn token(&self) -> PageTableDirect {
         self.root_ppn
     }
 }
 
-/// translate a pointer to a mutable u8 Vec through page table
-pub fn translate_sized(
-    token: PageTableDirect,
-    mut ptr: *const u8,
-    mut len: usize,
-) -> Vec<&'static mut [u8]> {
+/// translate `len` bytes at `ptr` through page table, pinning their pages for as
+/// long as the buffer lives. `None` if some page is not mapped or cannot be swapped in
+pub fn translate_sized(token: PageTableDirect, ptr: *const u8, len: usize) -> Option<UserBuffer> {
+    let start = VirtAddr::from(ptr as usize);
+    let end = VirtAddr::from(ptr as usize + len);
+    // pinned first, so swapping a page in does not swap out one translated before
+    let pins = PinnedPages::new(token, start.floor()..end.ceil());
     let page_table = PageTable::from(token);
-    let mut resutl = Vec::new();
-    loop {
-        let part = page_table
-            .translate_va((ptr as usize).into())
-            .unwrap()
-            .to_end();
-        let part_len = part.len();
-        if len <= part_len {
-            resutl.push(&mut part[..len]);
-            break;
-        } else {
-            resutl.push(part);
-            len -= part_len;
-            ptr = (ptr as usize + part_len) as _;
-        }
+    let mut buffers = Vec::new();
+    let mut va = start;
+    while va < end {
+        let part = page_table.translate_va(va)?.to_end();
+        let part_len = part.len().min(end.0 - va.0);
+        buffers.push(&mut part[..part_len]);
+        va = VirtAddr::from(va.0 + part_len);
     }
-    resutl
+    Some(UserBuffer(buffers, pins))
 }
 
-pub fn translate_slice<T>(
-    token: PageTableDirect,
-    ptr: *const *const [T],
-) -> (Vec<&'static mut [T]>, usize) {
-    // assert
-    let mut raw_ptr = *translate_ref(token, ptr as *const *const T);
-    let mut len = *translate_ref(token, unsafe { (ptr as *const usize).add(1) });
-
+/// Whether the user may write all of `len` bytes at `ptr`, so the kernel
+/// does not write through pages mapped read-only, like shared file pages
+pub fn user_writable(token: PageTableDirect, ptr: *const u8, len: usize) -> bool {
     let page_table = PageTable::from(token);
-    let mut result = Vec::new();
-    loop {
-        let part: &'static mut [u8] = page_table
-            .translate_va((raw_ptr as usize).into())
-            .unwrap()
-            .to_end();
-        assert_eq!(part.len() % size_of::<T>(), 0);
-
-        let part: &'static mut [T] = unsafe {
-            core::slice::from_raw_parts_mut(part.as_ptr() as _, part.len() / size_of::<T>())
-        };
-        let part_len = part.len();
-        if len <= part_len {
-            result.push(&mut part[..len]);
-            break;
-        } else {
-            result.push(part);
-            len -= part_len;
-            raw_ptr = (raw_ptr as usize + part_len) as _;
-        }
-    }
-    (result, len)
+    let start = VirtAddr::from(ptr as usize).floor();
+    let end = VirtAddr::from(ptr as usize + len).ceil();
+    (start..end).all(|vpn| {
+        page_table.translate_vp(vpn).is_some_and(|pte| {
+            pte.valid()
+                && pte
+                    .flags()
+                    .contains(PageTableEntryFlags::W | PageTableEntryFlags::U)
+        })
+    })
 }
 
 pub fn translate_necked_slice<T: 'static>(
     token: PageTableDirect,
     ptr: *const *const [*const [T]],
-) -> impl DoubleEndedIterator<Item = *const *const [T]> {
-    let raw_ptr = *translate_ref(token, ptr as *const *const *const [T]);
-    let len = *translate_ref(token, unsafe { (ptr as *const usize).add(1) });
+) -> Option<impl DoubleEndedIterator<Item = *const *const [T]>> {
+    let raw_ptr = *translate_ref(token, ptr as *const *const *const [T])?;
+    let len = *translate_ref(token, unsafe { (ptr as *const usize).add(1) })?;
 
-    (raw_ptr as usize..raw_ptr as usize + len * size_of::<*const [*const [T]]>())
-        .step_by(size_of::<*const [*const [T]]>())
-        .map(|ptr| ptr as _)
+    Some(
+        (raw_ptr as usize..raw_ptr as usize + len * size_of::<*const [*const [T]]>())
+            .step_by(size_of::<*const [*const [T]]>())
+            .map(|ptr| ptr as _),
+    )
 }
 
 pub fn translate_bytes_slice(
     token: PageTableDirect,
     ptr: *const *const [*const str],
-) -> Vec<Vec<u8>> {
-    translate_necked_slice(token, ptr as *const *const [*const [u8]])
-        .into_iter()
+) -> Option<Vec<Vec<u8>>> {
+    translate_necked_slice(token, ptr as *const *const [*const [u8]])?
         .map(|ptr| translate_bytes(token, ptr as _))
         .collect()
 }
 
-/// translate a pointer to a mutable u8 Vec end with `\0` through page table to a `String`
-pub fn translate_bytes(token: PageTableDirect, ptr: *const *const str) -> Vec<u8> {
-    let (strs, len) = translate_slice(token, ptr as *const *const [u8]);
-    strs.iter().fold(Vec::with_capacity(len), |mut acc, str| {
-        acc.extend_from_slice(*str);
-        acc
-    })
+/// translate a `&str` in user space through page table and copy its bytes
+pub fn translate_bytes(token: PageTableDirect, ptr: *const *const str) -> Option<Vec<u8>> {
+    let raw_ptr = *translate_ref(token, ptr as *const *const u8)?;
+    let len = *translate_ref(token, unsafe { (ptr as *const usize).add(1) })?;
+    let page_table = PageTable::from(token);
+    let mut bytes = Vec::with_capacity(len);
+    // each part is copied before the next page is swapped in
+    while bytes.len() < len {
+        let va = VirtAddr::from(raw_ptr as usize + bytes.len());
+        let part = page_table.translate_va(va)?.to_end();
+        let part_len = part.len().min(len - bytes.len());
+        bytes.extend_from_slice(&part[..part_len]);
+    }
+    Some(bytes)
 }
 
 /// translate a generic through page table and return a mutable reference
-pub fn translate_ref_mut<T>(token: PageTableDirect, ptr: *mut T) -> &'static mut T {
+pub fn translate_ref_mut<T>(token: PageTableDirect, ptr: *mut T) -> Option<&'static mut T> {
     PageTable::from(token)
         .translate_va((ptr as usize).into())
-        .unwrap()
-        .as_mut()
+        .map(|pa| pa.as_mut())
 }
 
 /// translate a generic through page table and return a reference
-pub fn translate_ref<T>(token: PageTableDirect, ptr: *const T) -> &'static T {
+pub fn translate_ref<T>(token: PageTableDirect, ptr: *const T) -> Option<&'static T> {
     PageTable::from(token)
         .translate_va((ptr as usize).into())
-        .unwrap()
-        .as_mut()
+        .map(|pa| &*pa.as_mut())
 }
 
-/// translate a generic through page table and return a reference
-pub fn translate_to<T>(token: PageTableDirect, src: *const T, dst: &mut T) {
+/// copy a generic from user space through page table
+pub fn translate_to<T>(token: PageTableDirect, src: *const T, dst: &mut T) -> Option<()> {
     let page_table = PageTable::from(token);
-    let mut len = size_of::<T>();
-    let mut src = src as *const u8;
-    let mut dst = dst as *const _ as *mut u8;
-    loop {
-        let part = page_table
-            .translate_va((src as usize).into())
-            .unwrap()
-            .to_end();
-        if len <= part.len() {
-            unsafe {
-                core::ptr::copy(src, dst, len);
-            }
-            break;
-        } else {
-            unsafe {
-                core::ptr::copy(src, dst, part.len());
-                len -= part.len();
-                src = src.add(part.len());
-                dst = dst.add(part.len());
-            }
-        }
+    let dst = unsafe { core::slice::from_raw_parts_mut(dst as *mut T as *mut u8, size_of::<T>()) };
+    let mut copied = 0;
+    while copied < dst.len() {
+        let va = VirtAddr::from(src as usize + copied);
+        let part = page_table.translate_va(va)?.to_end();
+        let part_len = part.len().min(dst.len() - copied);
+        dst[copied..][..part_len].copy_from_slice(&part[..part_len]);
+        copied += part_len;
     }
+    Some(())
 }
-/// Array of u8 slice that user communicate with os
-pub struct UserBuffer(pub Vec<&'static mut [u8]>);
+/// Array of u8 slice that user communicate with os, with their pages pinned
+pub struct UserBuffer(pub Vec<&'static mut [u8]>, PinnedPages);
 
 impl UserBuffer {
-    /// Create a `UserBuffer` by parameter
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self(buffers)
-    }
     /// Length of `UserBuffer`
     pub fn len(&self) -> usize {
         self.0.iter().map(|buf| buf.len()).sum()
@@ -285,6 +290,11 @@ impl UserBuffer {
     }
     /// Iterator over the buffer
     pub fn into_bytes(self) -> impl Iterator<Item = &'static mut u8> {
-        self.0.into_iter().flat_map(|buf| buf.into_iter())
+        let Self(buffers, pins) = self;
+        // the pages stay pinned as long as the iterator lives
+        buffers.into_iter().flat_map(move |buf| {
+            let _ = &pins;
+            buf.into_iter()
+        })
     }
 }
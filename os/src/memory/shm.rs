@@ -0,0 +1,77 @@
+//! Shared memory segments, System V style: frames several memory sets map
+//!
+//! A segment is created or found by its key with `shm_get`, which gives its
+//! id. Attaching it maps its frames into a memory set, which holds them like
+//! any shared frame, so they stay with the memory sets mapping them after
+//! fork and go with them on exec and exit. Removing a segment only forgets
+//! its key and id: its frames are freed once the last memory set has let
+//! them go
+
+use super::cfg::{PAGE_SIZE, SHM_MAX_SIZE};
+use super::{FrameTracker, frame_alloc};
+use crate::sync::UpSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use config::errno::{EEXIST, EINVAL, ENOENT, ENOMEM};
+use config::ipc::{IPC_PRIVATE, ShmFlag};
+
+struct Segment {
+    key: usize,
+    frames: Vec<Arc<FrameTracker>>,
+}
+
+/// Segments not removed, by id
+struct Segments {
+    segments: BTreeMap<usize, Segment>,
+    next_id: usize,
+}
+
+static SEGMENTS: UpSafeCell<Segments> = unsafe {
+    UpSafeCell::new(Segments {
+        segments: BTreeMap::new(),
+        next_id: 0,
+    })
+};
+
+/// The id of the segment of `key` holding at least `size` bytes, created with
+/// `CREAT` if there is none. A segment of `IPC_PRIVATE` is always created
+pub fn shm_get(key: usize, size: usize, flags: ShmFlag) -> Result<usize, isize> {
+    let mut segments = SEGMENTS.borrow_mut();
+    let found = segments
+        .segments
+        .iter()
+        .find(|(_, segment)| key != IPC_PRIVATE && segment.key == key);
+    match found {
+        Some(_) if flags.contains(ShmFlag::CREAT | ShmFlag::EXCL) => return Err(EEXIST),
+        Some((_, segment)) if size > segment.frames.len() * PAGE_SIZE => return Err(EINVAL),
+        Some((&id, _)) => return Ok(id),
+        None if key != IPC_PRIVATE && !flags.contains(ShmFlag::CREAT) => return Err(ENOENT),
+        None => {}
+    }
+    if size == 0 || size > SHM_MAX_SIZE {
+        return Err(EINVAL);
+    }
+    let frames = (0..size.div_ceil(PAGE_SIZE))
+        .map(|_| frame_alloc().map(Arc::new))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(ENOMEM)?;
+    let id = segments.next_id;
+    segments.next_id += 1;
+    segments.segments.insert(id, Segment { key, frames });
+    Ok(id)
+}
+
+/// The frames of the segment `id`
+pub fn shm_frames(id: usize) -> Option<Vec<Arc<FrameTracker>>> {
+    let segments = SEGMENTS.borrow();
+    segments
+        .segments
+        .get(&id)
+        .map(|segment| segment.frames.clone())
+}
+
+/// Forget the segment `id`, false if there is none
+pub fn shm_remove(id: usize) -> bool {
+    SEGMENTS.borrow_mut().segments.remove(&id).is_some()
+}
@@ -0,0 +1,101 @@
+//! Slab caches: frames cut into objects of one size class, for the small
+//! objects the kernel allocates and frees all the time, like tasks, block
+//! caches and opened files
+//!
+//! Each class, a power of two from 16 to 2048 bytes, keeps its free objects
+//! in a list linked through the objects themselves, so allocating and
+//! freeing take O(1). A class takes one more frame when its list is empty,
+//! and keeps the frames it has taken
+
+use super::frame_allocator::frame_alloc_raw;
+use crate::sync::UpSafeCell;
+use config::memory::PAGE_SIZE;
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+/// The smallest class holds `2^MIN_CLASS_BITS` bytes, enough for a link
+const MIN_CLASS_BITS: usize = 4;
+/// The largest class holds `2^MAX_CLASS_BITS` bytes, two objects a frame
+const MAX_CLASS_BITS: usize = 11;
+/// Number of size classes
+pub const SLAB_CLASSES: usize = MAX_CLASS_BITS - MIN_CLASS_BITS + 1;
+
+/// The objects of a size class
+struct SlabCache {
+    /// The first free object, linked to the next by its first word
+    free: *mut u8,
+    /// Objects allocated
+    used: usize,
+    /// Frames cut into objects
+    pages: usize,
+}
+
+impl SlabCache {
+    const fn new() -> Self {
+        Self {
+            free: ptr::null_mut(),
+            used: 0,
+            pages: 0,
+        }
+    }
+    fn push(&mut self, obj: *mut u8) {
+        unsafe { obj.cast::<*mut u8>().write(self.free) };
+        self.free = obj;
+    }
+    /// Cut one more frame into objects of `size` bytes, false if there is no frame
+    fn grow(&mut self, size: usize) -> bool {
+        let Some(ppn) = frame_alloc_raw(0) else {
+            return false;
+        };
+        let base = ppn.as_bytes().as_mut_ptr();
+        for offset in (0..PAGE_SIZE).step_by(size).rev() {
+            self.push(unsafe { base.add(offset) });
+        }
+        self.pages += 1;
+        true
+    }
+    fn alloc(&mut self, size: usize) -> Option<NonNull<u8>> {
+        if self.free.is_null() && !self.grow(size) {
+            return None;
+        }
+        let obj = self.free;
+        self.free = unsafe { obj.cast::<*mut u8>().read() };
+        self.used += 1;
+        NonNull::new(obj)
+    }
+    fn dealloc(&mut self, obj: *mut u8) {
+        self.push(obj);
+        self.used -= 1;
+    }
+}
+
+static SLABS: UpSafeCell<[SlabCache; SLAB_CLASSES]> =
+    unsafe { UpSafeCell::new([const { SlabCache::new() }; SLAB_CLASSES]) };
+
+/// The class of objects of `layout`, none if they are too large for a slab
+fn class(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).next_power_of_two();
+    let bits = (size.trailing_zeros() as usize).max(MIN_CLASS_BITS);
+    (bits <= MAX_CLASS_BITS).then_some(bits - MIN_CLASS_BITS)
+}
+
+/// Allocate an object of `layout` from its slab, none if it has no slab or there is no frame
+pub(super) fn slab_alloc(layout: &Layout) -> Option<NonNull<u8>> {
+    let class = class(layout)?;
+    SLABS.borrow_mut()[class].alloc(1 << (class + MIN_CLASS_BITS))
+}
+
+/// Free an object from [`slab_alloc`]
+pub(super) fn slab_dealloc(ptr: *mut u8, layout: &Layout) {
+    let class = class(layout).expect("the object is too large for a slab");
+    SLABS.borrow_mut()[class].dealloc(ptr);
+}
+
+/// Object size, objects allocated and frames of each size class
+pub fn slab_usage() -> [(usize, usize, usize); SLAB_CLASSES] {
+    let slabs = SLABS.borrow();
+    core::array::from_fn(|class| {
+        let cache = &slabs[class];
+        (1 << (class + MIN_CLASS_BITS), cache.used, cache.pages)
+    })
+}
@@ -0,0 +1,283 @@
+//! Swapping: when frames run out, private pages of user programs are
+//! written to a block device and their frames are used again
+//!
+//! Pages which may be swapped out wait in a queue, in the order they were
+//! mapped or swapped in. The page at the front is swapped out unless it
+//! was accessed since it was last looked at, by the program or by the
+//! kernel translating its address, then it goes to the back with its `A`
+//! bit cleared (second chance). The invalid PTE of a page swapped out holds
+//! its slot, and the page is read back on a page fault or a translation.
+//! Pages the kernel reads or writes for a while, like the buffer of a
+//! syscall, are pinned so they stay in their frames meanwhile
+
+use super::{FrameTracker, PageTable, PageTableDirect, PageTableEntry, PageTableEntryFlags};
+use super::{PhysPageNum, VirtAddr, VirtPageNum, frame_alloc};
+use crate::drivers::block_device;
+use crate::sync::UpSafeCell;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use config::fs::BLOCK_SZ;
+use config::memory::PAGE_SIZE;
+use core::arch::asm;
+use core::ops::Range;
+use easy_fs::BlockDevice;
+
+/// Blocks of the device in a slot, which holds a page
+const SLOT_BLOCKS: usize = PAGE_SIZE / BLOCK_SZ;
+
+/// The device pages are swapped to, split in slots
+struct SwapArea {
+    device: Arc<dyn BlockDevice>,
+    slots: usize,
+    /// Slots from here on have never been used
+    current: usize,
+    recycled: Vec<usize>,
+    /// The page in each slot in use
+    pages: BTreeMap<usize, Weak<SwapPage>>,
+}
+
+impl SwapArea {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == self.slots {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    fn dealloc(&mut self, slot: usize) {
+        self.pages.remove(&slot);
+        self.recycled.push(slot);
+    }
+    fn write(&self, slot: usize, ppn: PhysPageNum) {
+        for (i, block) in ppn.as_bytes().chunks(BLOCK_SZ).enumerate() {
+            self.device.write_block(slot * SLOT_BLOCKS + i, block);
+        }
+    }
+    fn read(&self, slot: usize, ppn: PhysPageNum) {
+        for (i, block) in ppn.as_bytes().chunks_mut(BLOCK_SZ).enumerate() {
+            self.device.read_block(slot * SLOT_BLOCKS + i, block);
+        }
+    }
+}
+
+/// The swap area, none until `swap_on`
+static SWAP: UpSafeCell<Option<SwapArea>> = unsafe { UpSafeCell::new(None) };
+
+/// Resident pages which may be swapped out, the next to look at first
+static QUEUE: UpSafeCell<VecDeque<Weak<SwapPage>>> = unsafe { UpSafeCell::new(VecDeque::new()) };
+
+/// Pinned pages, by the root of their page table and their number, with their number of pins
+static PINNED: UpSafeCell<BTreeMap<(usize, VirtPageNum), usize>> =
+    unsafe { UpSafeCell::new(BTreeMap::new()) };
+
+/// Pages of a page table kept in their frames, not swapped out, until dropped
+pub struct PinnedPages {
+    root: usize,
+    vpns: Range<VirtPageNum>,
+}
+
+impl PinnedPages {
+    /// Pin the pages in `vpns` mapped by the page table of `token`, whether they are
+    /// mapped or swapped out yet or not
+    pub fn new(token: PageTableDirect, vpns: Range<VirtPageNum>) -> Self {
+        let root = PhysPageNum::from(token).0;
+        let mut pinned = PINNED.borrow_mut();
+        for vpn in vpns.clone() {
+            *pinned.entry((root, vpn)).or_insert(0) += 1;
+        }
+        Self { root, vpns }
+    }
+}
+
+impl Drop for PinnedPages {
+    fn drop(&mut self) {
+        let mut pinned = PINNED.borrow_mut();
+        for vpn in self.vpns.clone() {
+            let pins = pinned.get_mut(&(self.root, vpn)).unwrap();
+            *pins -= 1;
+            if *pins == 0 {
+                pinned.remove(&(self.root, vpn));
+            }
+        }
+    }
+}
+
+fn pinned(token: PageTableDirect, vpn: VirtPageNum) -> bool {
+    let root = PhysPageNum::from(token).0;
+    PINNED.borrow().contains_key(&(root, vpn))
+}
+
+/// Swap to the block device called `name`, if there is one
+pub fn swap_on(name: &str) {
+    let Some(device) = block_device(name) else {
+        return;
+    };
+    let slots = device.num_blocks().unwrap_or(0) / SLOT_BLOCKS;
+    log::info!("swapping to {name}, {} kB", slots * PAGE_SIZE / 1024);
+    *SWAP.borrow_mut() = Some(SwapArea {
+        device,
+        slots,
+        current: 0,
+        recycled: Vec::new(),
+        pages: BTreeMap::new(),
+    });
+}
+
+/// Number of slots in the swap area, and of the free ones
+pub fn swap_count() -> (usize, usize) {
+    SWAP.borrow().as_ref().map_or((0, 0), |swap| {
+        (swap.slots, swap.slots - swap.current + swap.recycled.len())
+    })
+}
+
+enum PageState {
+    Resident(FrameTracker),
+    /// In a slot of the swap area, with the flags of its PTE
+    Swapped(usize, u8),
+}
+
+/// A private page of a user area, in its frame or in the swap area
+pub struct SwapPage {
+    /// The page table mapping the page
+    token: PageTableDirect,
+    vpn: VirtPageNum,
+    state: UpSafeCell<PageState>,
+}
+
+impl SwapPage {
+    /// A page in `frame`, mapped at `vpn` by the page table of `token` already
+    pub fn new(frame: FrameTracker, token: PageTableDirect, vpn: VirtPageNum) -> Arc<Self> {
+        let page = Arc::new(Self {
+            token,
+            vpn,
+            state: unsafe { UpSafeCell::new(PageState::Resident(frame)) },
+        });
+        if SWAP.borrow().is_some() {
+            QUEUE.borrow_mut().push_back(Arc::downgrade(&page));
+        }
+        page
+    }
+    /// Whether the page is in its frame
+    pub fn resident(&self) -> bool {
+        matches!(*self.state.borrow(), PageState::Resident(_))
+    }
+    fn pte(&self) -> Option<&'static mut PageTableEntry> {
+        PageTable::from(self.token).find(self.vpn)
+    }
+}
+
+impl Drop for SwapPage {
+    fn drop(&mut self) {
+        if let PageState::Swapped(slot, _) = *self.state.borrow()
+            && let Some(swap) = SWAP.borrow_mut().as_mut()
+        {
+            swap.dealloc(slot);
+        }
+    }
+}
+
+fn flush_tlb() {
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
+
+/// Swap a page out to free its frame, false if there is no page to swap out or no room
+pub(super) fn swap_out() -> bool {
+    let mut swap = SWAP.borrow_mut();
+    let Some(swap) = swap.as_mut() else {
+        return false;
+    };
+    let mut queue = QUEUE.borrow_mut();
+    // each page gets at most one second chance, and is looked at at most twice
+    let mut chances = queue.len();
+    let mut steps = 2 * queue.len();
+    let freed = loop {
+        if steps == 0 {
+            break false;
+        }
+        steps -= 1;
+        let Some(page) = queue.pop_front() else {
+            break false;
+        };
+        let Some(page) = page.upgrade() else {
+            continue;
+        };
+        let Some(pte) = page.pte().filter(|pte| pte.valid()) else {
+            continue;
+        };
+        if pinned(page.token, page.vpn) {
+            queue.push_back(Arc::downgrade(&page));
+            continue;
+        }
+        if pte.flags().contains(PageTableEntryFlags::A) && chances > 0 {
+            chances -= 1;
+            pte.bits &= !(PageTableEntryFlags::A.bits() as usize);
+            queue.push_back(Arc::downgrade(&page));
+            continue;
+        }
+        let Some(slot) = swap.alloc() else {
+            queue.push_front(Arc::downgrade(&page));
+            break false;
+        };
+        let flags = pte.flags().bits();
+        if let PageState::Resident(frame) = &*page.state.borrow() {
+            swap.write(slot, frame.ppn);
+        }
+        *pte = PageTableEntry::swapped(slot);
+        swap.pages.insert(slot, Arc::downgrade(&page));
+        // the frame is freed here
+        *page.state.borrow_mut() = PageState::Swapped(slot, flags);
+        break true;
+    };
+    flush_tlb();
+    freed
+}
+
+/// Read back the page swapped out to `slot`, false if there is no frame for it
+pub(super) fn swap_in(slot: usize) -> bool {
+    let Some(page) = SWAP
+        .borrow()
+        .as_ref()
+        .and_then(|swap| swap.pages.get(&slot))
+        .and_then(Weak::upgrade)
+    else {
+        return false;
+    };
+    let PageState::Swapped(slot, flags) = *page.state.borrow() else {
+        return true;
+    };
+    // may swap other pages out, not this one which is not in the queue
+    let Some(frame) = frame_alloc() else {
+        return false;
+    };
+    let mut swap = SWAP.borrow_mut();
+    let swap = swap.as_mut().unwrap();
+    swap.read(slot, frame.ppn);
+    swap.dealloc(slot);
+    let flags = PageTableEntryFlags::from_bits_truncate(flags) | PageTableEntryFlags::A;
+    *page.pte().unwrap() = PageTableEntry::new(frame.ppn, flags);
+    *page.state.borrow_mut() = PageState::Resident(frame);
+    QUEUE.borrow_mut().push_back(Arc::downgrade(&page));
+    flush_tlb();
+    true
+}
+
+/// Whether the page at `va` is swapped out
+pub fn swapped_out(token: PageTableDirect, va: VirtAddr) -> bool {
+    PageTable::from(token)
+        .find(va.floor())
+        .is_some_and(|pte| pte.swap_slot().is_some())
+}
+
+/// Read back the page at `va` if it was swapped out, false if it was not or cannot be
+pub fn swap_in_at(token: PageTableDirect, va: VirtAddr) -> bool {
+    let slot = PageTable::from(token)
+        .find(va.floor())
+        .and_then(|pte| pte.swap_slot());
+    slot.is_some_and(swap_in)
+}
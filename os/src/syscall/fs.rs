@@ -2,24 +2,107 @@
 
 use alloc::string::String;
 
-use crate::fs;
+use super::cfg::{EAGAIN, EBADF, EBUSY, EFAULT, EINVAL, EMFILE, ENAMETOOLONG, ENOTDIR};
+use crate::fs::{self, FileDescriptor, OpenFlag};
 use crate::memory;
 use crate::task;
+use config::fs::fcntl::*;
+use config::fs::{FD_LIMIT, NAME_LENGTH_LIMIT, dirent};
 
 pub fn sys_dup(fd: usize) -> isize {
     let task = task::current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
     let src;
-    if let Some(fd) = inner.fd_table.get(fd).map_or(None, |fd| fd.as_ref()) {
-        src = fd.clone();
+    if let Some(fd) = inner.fd_table.get(fd).and_then(Option::as_ref) {
+        // close-on-exec belongs to the descriptor, the copy does not inherit it
+        src = FileDescriptor::new(fd.file.clone(), OpenFlag::empty());
     } else {
-        return -1;
+        return -EBADF;
+    }
+    let Some(new_fd) = inner.alloc_fd() else {
+        return -EMFILE;
+    };
+    inner.fd_table[new_fd] = Some(src);
+    new_fd as isize
+}
+
+/// Make `new_fd` refer to the file of `old_fd`, closing what `new_fd` referred to.
+/// Only `CLOEXEC` is allowed in `flags`
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: usize) -> isize {
+    let Some(flags) = OpenFlag::from_bits(flags) else {
+        return -EINVAL;
+    };
+    if old_fd == new_fd || !(flags - OpenFlag::CLOEXEC).is_empty() {
+        return -EINVAL;
+    }
+    if new_fd >= FD_LIMIT {
+        return -EBADF;
+    }
+    let task = task::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let src;
+    if let Some(fd) = inner.fd_table.get(old_fd).and_then(Option::as_ref) {
+        src = FileDescriptor::new(fd.file.clone(), flags);
+    } else {
+        return -EBADF;
+    }
+    if inner.fd_table.len() <= new_fd {
+        inner.fd_table.resize(new_fd + 1, None);
     }
-    let new_fd = inner.alloc_fd();
     inner.fd_table[new_fd] = Some(src);
     new_fd as isize
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = task::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let Some(Some(desc)) = inner.fd_table.get(fd) else {
+        return -EBADF;
+    };
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let flags = if cmd == F_DUPFD_CLOEXEC {
+                OpenFlag::CLOEXEC
+            } else {
+                OpenFlag::empty()
+            };
+            if arg >= FD_LIMIT {
+                return -EINVAL;
+            }
+            let src = FileDescriptor::new(desc.file.clone(), flags);
+            let Some(new_fd) = inner.alloc_fd_from(arg) else {
+                return -EMFILE;
+            };
+            inner.fd_table[new_fd] = Some(src);
+            new_fd as isize
+        }
+        F_GETFD => {
+            if desc.cloexec {
+                FD_CLOEXEC as isize
+            } else {
+                0
+            }
+        }
+        F_SETFD => {
+            inner.fd_table[fd].as_mut().unwrap().cloexec = arg & FD_CLOEXEC != 0;
+            0
+        }
+        F_GETFL => {
+            let file = &desc.file;
+            (OpenFlag::from_read_write(file.readable(), file.writable()) | file.status()).bits()
+                as isize
+        }
+        F_SETFL => {
+            let file = desc.file.clone();
+            // release current task TCB manually to avoid multi-borrow
+            drop(inner);
+            file.set_status(OpenFlag::from_bits_truncate(arg));
+            0
+        }
+        _ => -EINVAL,
+    }
+}
+
 /// write buf of length `len`  to a file with `fd`
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = task::current_user_token();
@@ -28,18 +111,23 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     if fd >= inner.fd_table.len() {
         return -1;
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        if !file.writable() {
+    if let Some(fd) = &inner.fd_table[fd] {
+        if !fd.file.writable() {
             return -1;
         }
-        let file = file.clone();
+        let file = fd.file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(memory::UserBuffer::new(memory::translate_sized(
-            token.into(),
-            buf,
-            len,
-        ))) as isize
+        if file.status().contains(OpenFlag::NONBLOCK) && !file.write_ready() {
+            return -EAGAIN;
+        }
+        let Some(buf) = memory::translate_sized(token, buf, len) else {
+            return -EFAULT;
+        };
+        match file.write(buf) {
+            Ok(len) => len as isize,
+            Err(err) => -err,
+        }
     } else {
         -1
     }
@@ -52,41 +140,188 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     if fd >= inner.fd_table.len() {
         return -1;
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
+    if let Some(fd) = &inner.fd_table[fd] {
+        let file = fd.file.clone();
         if !file.readable() {
             return -1;
         }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.read(memory::UserBuffer::new(memory::translate_sized(
-            token.into(),
-            buf,
-            len,
-        ))) as isize
+        if !memory::user_writable(token, buf, len) {
+            return -EFAULT;
+        }
+        if file.status().contains(OpenFlag::NONBLOCK) && !file.read_ready() {
+            return -EAGAIN;
+        }
+        let Some(buf) = memory::translate_sized(token, buf, len) else {
+            return -EFAULT;
+        };
+        match file.read(buf) {
+            Ok(len) => len as isize,
+            Err(err) => -err,
+        }
     } else {
         -1
     }
 }
 
+/// Read directory entries into `buf` as `dirent` records, return 0 at the end
+pub fn sys_getdents(fd: usize, buf: *const u8, len: usize) -> isize {
+    let token = task::current_user_token();
+    let task = task::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let Some(Some(fd)) = inner.fd_table.get(fd) else {
+        return -1;
+    };
+    let file = fd.file.clone();
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    // make sure any entry fits, or an empty result would look like the end
+    if len < dirent::reclen(NAME_LENGTH_LIMIT) {
+        return -EINVAL;
+    }
+    if !memory::user_writable(token, buf, len) {
+        return -EFAULT;
+    }
+    let Some(buf) = memory::translate_sized(token, buf, len) else {
+        return -EFAULT;
+    };
+    match file.getdents(buf) {
+        Some(len) => len as isize,
+        None => -ENOTDIR,
+    }
+}
+
+/// Move the offset of `fd` as `whence` says, return the new offset
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let task = task::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let Some(Some(fd)) = inner.fd_table.get(fd) else {
+        return -EBADF;
+    };
+    let file = fd.file.clone();
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    match file.seek(offset, whence) {
+        Ok(offset) => offset as isize,
+        Err(err) => -err,
+    }
+}
+
 pub fn sys_open(path: *const *const str, flags: usize) -> isize {
     let task = task::current_task().unwrap();
     let token = task::current_user_token();
-    let path = if let Ok(path) = String::from_utf8(memory::translate_bytes(token, path)) {
-        path
-    } else {
+    let Some(bytes) = memory::translate_bytes(token, path) else {
+        return -EFAULT;
+    };
+    let Ok(path) = String::from_utf8(bytes) else {
         return -1;
     };
-    if let Some(inode) = fs::open_file(path.as_str(), fs::OpenFlag::from_bits(flags).unwrap()) {
-        let mut inner = task.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
-    } else {
-        -1
+    let Some(flags) = OpenFlag::from_bits(flags) else {
+        return -1;
+    };
+    match fs::open_file(path.as_str(), flags) {
+        Ok(inode) => {
+            let mut inner = task.inner_exclusive_access();
+            let Some(fd) = inner.alloc_fd() else {
+                return -EMFILE;
+            };
+            inner.fd_table[fd] = Some(FileDescriptor::new(inode, flags));
+            fd as isize
+        }
+        // a name too long, or a device in use, is told apart from the other failures
+        Err(err @ (ENAMETOOLONG | EBUSY)) => -err,
+        Err(_) => -1,
+    }
+}
+
+/// Translate a string passed by pointer from user space
+fn translate_str(ptr: *const *const str) -> Option<String> {
+    String::from_utf8(memory::translate_bytes(task::current_user_token(), ptr)?).ok()
+}
+
+pub fn sys_mkdir(path: *const *const str) -> isize {
+    let Some(path) = translate_str(path) else {
+        return -EINVAL;
+    };
+    match fs::mkdir(&path) {
+        Ok(()) => 0,
+        Err(err) => -err,
+    }
+}
+
+pub fn sys_unlink(path: *const *const str) -> isize {
+    let Some(path) = translate_str(path) else {
+        return -EINVAL;
+    };
+    match fs::unlink(&path) {
+        Ok(()) => 0,
+        Err(err) => -err,
     }
 }
 
+/// Mount a filesystem of type `fs_type` from `source` on the directory `target`
+pub fn sys_mount(
+    source: *const *const str,
+    target: *const *const str,
+    fs_type: *const *const str,
+) -> isize {
+    let (Some(source), Some(target), Some(fs_type)) = (
+        translate_str(source),
+        translate_str(target),
+        translate_str(fs_type),
+    ) else {
+        return -EINVAL;
+    };
+    match fs::mount(&source, &target, &fs_type) {
+        Ok(()) => 0,
+        Err(err) => -err,
+    }
+}
+
+/// Unmount the filesystem mounted on the directory `target`
+pub fn sys_umount(target: *const *const str) -> isize {
+    let Some(target) = translate_str(target) else {
+        return -EINVAL;
+    };
+    match fs::umount(&target) {
+        Ok(()) => 0,
+        Err(err) => -err,
+    }
+}
+
+/// Write the cached data of one file back to disk
+pub fn sys_fsync(fd: usize) -> isize {
+    let task = task::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let Some(Some(fd)) = inner.fd_table.get(fd) else {
+        return -1;
+    };
+    let file = fd.file.clone();
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    if file.sync() { 0 } else { -EINVAL }
+}
+
+/// Write all cached data back to disk
+pub fn sys_sync() -> isize {
+    fs::sync_all();
+    0
+}
+
+/// Write the cached data of the filesystem holding `fd` back to disk
+pub fn sys_syncfs(fd: usize) -> isize {
+    let task = task::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    if !matches!(inner.fd_table.get(fd), Some(Some(_))) {
+        return -1;
+    }
+    drop(inner);
+    // which filesystem holds the file is not known here, sync them all
+    fs::sync_all();
+    0
+}
+
 pub fn sys_close(fd: usize) -> isize {
     let task = task::current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
@@ -100,16 +335,32 @@ pub fn sys_close(fd: usize) -> isize {
     0
 }
 
-pub fn sys_pipe(pipe_read: *mut usize, pipe_write: *mut usize) -> isize {
+/// Only `NONBLOCK` and `CLOEXEC` are meaningful in `flags`
+pub fn sys_pipe(pipe_read: *mut usize, pipe_write: *mut usize, flags: usize) -> isize {
+    let Some(flags) = OpenFlag::from_bits(flags) else {
+        return -1;
+    };
     let task = task::current_task().unwrap();
     let token = task::current_user_token();
     let mut inner = task.inner_exclusive_access();
-    let pipes = fs::make_pipe();
-    let read_fd = inner.alloc_fd();
-    inner.fd_table[read_fd] = Some(pipes.0);
-    let write_fd = inner.alloc_fd();
-    inner.fd_table[write_fd] = Some(pipes.1);
-    *memory::translate_ref_mut(token, pipe_read) = read_fd;
-    *memory::translate_ref_mut(token, pipe_write) = write_fd;
+    let pipes = fs::make_pipe(flags);
+    let Some(read_fd) = inner.alloc_fd() else {
+        return -EMFILE;
+    };
+    inner.fd_table[read_fd] = Some(FileDescriptor::new(pipes.0, flags));
+    let Some(write_fd) = inner.alloc_fd() else {
+        inner.fd_table[read_fd] = None;
+        return -EMFILE;
+    };
+    inner.fd_table[write_fd] = Some(FileDescriptor::new(pipes.1, flags));
+    // each written right after it is translated, as translating may swap out another page
+    for (ptr, fd) in [(pipe_read, read_fd), (pipe_write, write_fd)] {
+        let Some(dst) = memory::translate_ref_mut(token, ptr) else {
+            inner.fd_table[read_fd] = None;
+            inner.fd_table[write_fd] = None;
+            return -EFAULT;
+        };
+        *dst = fd;
+    }
     0
 }
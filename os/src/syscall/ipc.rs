@@ -0,0 +1,51 @@
+//! Shared memory syscalls
+use super::cfg::{EINVAL, IPC_RMID, ShmFlag};
+use crate::memory::{self, MapPermission, VirtAddr};
+use crate::task;
+
+/// The id of the shared memory segment of `key`, see [`memory::shm_get`]
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize {
+    match memory::shm_get(key, size, ShmFlag::from_bits_truncate(flags)) {
+        Ok(id) => id as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// Attach the segment `id` at `addr`, or where the kernel chooses if it is 0,
+/// return where
+pub fn sys_shmat(id: usize, addr: usize, flags: usize) -> isize {
+    let Some(frames) = memory::shm_frames(id) else {
+        return -EINVAL;
+    };
+    let mut permission = MapPermission::R | MapPermission::U;
+    if !ShmFlag::from_bits_truncate(flags).contains(ShmFlag::RDONLY) {
+        permission |= MapPermission::W;
+    }
+    let start = (addr != 0).then_some(VirtAddr::from(addr));
+    let task = task::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    match inner.memory_set.attach_shared(&frames, start, permission) {
+        Ok(start) => start.0 as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// Detach the segment attached at `addr`
+pub fn sys_shmdt(addr: usize) -> isize {
+    let task = task::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    if inner.memory_set.detach_shared(addr.into()) {
+        0
+    } else {
+        -EINVAL
+    }
+}
+
+/// Only `IPC_RMID`, which removes the segment `id`
+pub fn sys_shmctl(id: usize, cmd: usize) -> isize {
+    if cmd == IPC_RMID && memory::shm_remove(id) {
+        0
+    } else {
+        -EINVAL
+    }
+}
@@ -10,14 +10,18 @@
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
 mod cfg {
+    pub use config::errno::*;
+    pub use config::ipc::*;
     pub use config::signal::*;
     pub use config::syscall::*;
 }
 
 mod fs;
+mod ipc;
 mod process;
 
 use fs::*;
+use ipc::*;
 use process::*;
 
 /// handle syscall exception with `syscall_id` and other arguments
@@ -25,6 +29,8 @@ pub fn syscall(syscall_id: cfg::SyscallID, args: [usize; 3]) -> isize {
     use cfg::SyscallID;
     match syscall_id {
         SyscallID::Dup => sys_dup(args[0]),
+        SyscallID::Fcntl => sys_fcntl(args[0], args[1], args[2]),
+        SyscallID::Dup3 => sys_dup3(args[0], args[1], args[2]),
         SyscallID::Write => sys_write(args[0], args[1] as _, args[2]),
         SyscallID::Exit => sys_exit(args[0] as _),
         SyscallID::Yield => sys_yield(),
@@ -34,14 +40,27 @@ pub fn syscall(syscall_id: cfg::SyscallID, args: [usize; 3]) -> isize {
         SyscallID::SigReturn => sys_sigreturn(),
         SyscallID::GetTime => sys_get_time(),
         SyscallID::GetPid => sys_get_pid(),
+        SyscallID::ShmGet => sys_shmget(args[0], args[1], args[2]),
+        SyscallID::ShmCtl => sys_shmctl(args[0], args[1]),
+        SyscallID::ShmAt => sys_shmat(args[0], args[1], args[2]),
+        SyscallID::ShmDt => sys_shmdt(args[0]),
         SyscallID::Sbrk => sys_sbrk(args[0] as _),
         SyscallID::Fork => sys_fork(),
         SyscallID::Exec => sys_exec(args[0] as _, args[1] as _),
         SyscallID::WaitPid => sys_waitpid(args[0] as _, args[1] as _),
         SyscallID::Read => sys_read(args[0], args[1] as _, args[2]),
         SyscallID::Open => sys_open(args[0] as _, args[1]),
+        SyscallID::Mkdir => sys_mkdir(args[0] as _),
+        SyscallID::Unlink => sys_unlink(args[0] as _),
+        SyscallID::Mount => sys_mount(args[0] as _, args[1] as _, args[2] as _),
+        SyscallID::Umount => sys_umount(args[0] as _),
         SyscallID::Close => sys_close(args[0]),
-        SyscallID::Pipe => sys_pipe(args[0] as _, args[1] as _),
-        SyscallID::PowerOff => crate::sbi::shutdown(false), // _ => unreachable!("Unsupported syscall_id: {:?}", syscall_id),
+        SyscallID::GetDents => sys_getdents(args[0], args[1] as _, args[2]),
+        SyscallID::Lseek => sys_lseek(args[0], args[1] as _, args[2]),
+        SyscallID::Fsync => sys_fsync(args[0]),
+        SyscallID::Sync => sys_sync(),
+        SyscallID::SyncFs => sys_syncfs(args[0]),
+        SyscallID::Pipe => sys_pipe(args[0] as _, args[1] as _, args[2]),
+        SyscallID::PowerOff => sys_poweroff(), // _ => unreachable!("Unsupported syscall_id: {:?}", syscall_id),
     }
 }
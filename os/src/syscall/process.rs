@@ -1,5 +1,5 @@
 //! App management syscalls
-use super::cfg::{SignalAction, SignalFlags};
+use super::cfg::{EFAULT, SignalAction, SignalFlags};
 use crate::{
     fs, memory,
     task::{self, current_task},
@@ -30,6 +30,12 @@ pub fn sys_get_pid() -> isize {
     current_task().unwrap().pid.0 as _
 }
 
+/// write cached data back to disk and power off the machine
+pub fn sys_poweroff() -> ! {
+    fs::sync_all();
+    crate::sbi::shutdown(false)
+}
+
 /// change data segment size
 pub fn sys_sbrk(_size: isize) -> isize {
     unimplemented!()
@@ -37,7 +43,10 @@ pub fn sys_sbrk(_size: isize) -> isize {
 
 pub fn sys_fork() -> isize {
     let current_task = task::current_task().unwrap();
-    let new_task = current_task.fork();
+    let new_task = match current_task.fork() {
+        Ok(task) => task,
+        Err(errno) => return -errno,
+    };
     let new_pid = new_task.pid.0;
     // modify trap context of new_task, because it returns immediately after switching
     let trap_cx = new_task.inner_exclusive_access().get_trap_cx();
@@ -51,17 +60,21 @@ pub fn sys_fork() -> isize {
 
 pub fn sys_exec(path: *const *const str, args: *const *const [*const str]) -> isize {
     let token = task::current_user_token();
-    let path = if let Ok(path) = String::from_utf8(memory::translate_bytes(token, path)) {
-        path
-    } else {
+    let Some(path) = memory::translate_bytes(token, path) else {
+        return -EFAULT;
+    };
+    let Ok(path) = String::from_utf8(path) else {
         return -1;
     };
-    let args = memory::translate_bytes_slice(token, args);
-    if let Some(app_inode) = fs::open_file(path.as_str(), crate::fs::OpenFlag::RDONLY) {
-        let all_data = app_inode.read_all();
+    let Some(args) = memory::translate_bytes_slice(token, args) else {
+        return -EFAULT;
+    };
+    if let Ok(app_inode) = fs::open_file(path.as_str(), crate::fs::OpenFlag::RDONLY) {
         let task = current_task().unwrap();
-        task.exec(all_data.as_slice(), args);
-        0
+        match task.exec(app_inode.as_ref(), args) {
+            Ok(()) => 0,
+            Err(errno) => -errno,
+        }
     } else {
         -1
     }
@@ -89,6 +102,11 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ release child PCB
     });
     if let Some((idx, _)) = pair {
+        let Some(exit_code_ref) =
+            memory::translate_ref_mut(inner.memory_set.token(), exit_code_ptr)
+        else {
+            return -EFAULT;
+        };
         let child = inner.children.remove(idx);
         // confirm that child will be deallocated after removing from children list
         assert_eq!(Arc::strong_count(&child), 1);
@@ -96,7 +114,7 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ temporarily access child TCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
-        *memory::translate_ref_mut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+        *exit_code_ref = exit_code;
         found_pid as isize
     } else {
         -2
@@ -137,9 +155,15 @@ pub fn sys_sigaction(
         if check_sigaction_error(flag, action as usize, old_action as usize) {
             return -1;
         }
-        let prev_action = inner.signal_actions.table[signum as usize].clone();
-        *memory::translate_ref_mut(token, old_action) = prev_action;
-        inner.signal_actions.table[signum as usize] = memory::translate_ref(token, action).clone();
+        // the old action is written before the new one is translated, which may swap it out
+        let Some(old_action) = memory::translate_ref_mut(token, old_action) else {
+            return -EFAULT;
+        };
+        *old_action = inner.signal_actions.table[signum as usize].clone();
+        let Some(action) = memory::translate_ref(token, action) else {
+            return -EFAULT;
+        };
+        inner.signal_actions.table[signum as usize] = action.clone();
         0
     } else {
         -1
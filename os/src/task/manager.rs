@@ -49,6 +49,12 @@ pub fn pid2task(pid: usize) -> Option<Arc<TaskControlBlock>> {
     map.get(&pid).map(Arc::clone)
 }
 
+/// The task of the lowest pid not less than `pid`
+pub fn task_from_pid(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    let map = PID2TCB.borrow();
+    map.range(pid..).next().map(|(_, task)| Arc::clone(task))
+}
+
 pub fn remove_from_pid2task(pid: usize) {
     let mut map = PID2TCB.borrow_mut();
     if map.remove(&pid).is_none() {
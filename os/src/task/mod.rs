@@ -22,13 +22,13 @@ use crate::sbi::shutdown;
 use crate::{fs, sync::UpSafeLazyCell};
 use alloc::sync::Arc;
 use context::TaskContext;
-pub use manager::{add_task, fetch_task, pid2task, remove_from_pid2task};
+pub use manager::{add_task, fetch_task, pid2task, remove_from_pid2task, task_from_pid};
 pub use pid::{KernelStack, PidHandle, pid_alloc};
 pub use processor::{
     current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
 };
 use switch::switch;
-use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskStatus};
 
 mod cfg {
     pub use config::INIT_PROC_NAME;
@@ -69,6 +69,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
             "[kernel] Idle process exit with exit_code {} ...",
             exit_code
         );
+        fs::sync_all();
         if exit_code != 0 {
             shutdown(true)
         } else {
@@ -113,8 +114,7 @@ static INITPROC: UpSafeLazyCell<Arc<TaskControlBlock>> = unsafe {
     UpSafeLazyCell::new(|| {
         Arc::new({
             let inode = fs::open_file(cfg::INIT_PROC_NAME, fs::OpenFlag::RDONLY).unwrap();
-            let v = inode.read_all();
-            TaskControlBlock::new(v.as_slice())
+            TaskControlBlock::new(inode.as_ref())
         })
     })
 };
@@ -123,6 +123,38 @@ pub fn add_init() {
     add_task(INITPROC.clone());
 }
 
+/// Out of memory: send `SIGKILL` to the task using the most frames, but
+/// not to the idle one. False if there is no task to kill
+///
+/// Its frames are freed once it has run and exited, so while a task killed
+/// this way is still there, no other one is killed
+pub fn oom_kill() -> bool {
+    let mut victim: Option<(usize, Arc<TaskControlBlock>)> = None;
+    let mut pid = IDLE_PID + 1;
+    while let Some(task) = task_from_pid(pid) {
+        pid = task.getpid() + 1;
+        let inner = task.inner_exclusive_access();
+        if inner.signals.contains(cfg::SignalFlags::KILL) {
+            return true;
+        }
+        let frames = inner.memory_set.resident_frames();
+        drop(inner);
+        if victim.as_ref().is_none_or(|(most, _)| frames > *most) {
+            victim = Some((frames, task));
+        }
+    }
+    let Some((frames, task)) = victim else {
+        return false;
+    };
+    log::warn!(
+        "[kernel] out of memory, killing pid {} with {} frames",
+        task.getpid(),
+        frames
+    );
+    task.inner_exclusive_access().signals |= cfg::SignalFlags::KILL;
+    true
+}
+
 pub fn check_signals_error_of_current() -> Option<(i32, &'static str)> {
     let task = current_task().unwrap();
     task.inner_exclusive_access().signals.check_error()
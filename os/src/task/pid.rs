@@ -61,16 +61,16 @@ pub struct KernelStack {
 }
 
 impl KernelStack {
-    ///Create a kernelstack from pid
-    pub fn new(pid_handle: &PidHandle) -> Self {
+    ///Create a kernelstack from pid, `None` if there are not enough frames
+    pub fn new(pid_handle: &PidHandle) -> Option<Self> {
         let pid = pid_handle.0;
         let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
         KERNEL_SPACE.borrow_mut().insert_framed_area(
             kernel_stack_bottom.into(),
             kernel_stack_top.into(),
             MapPermission::R | MapPermission::W,
-        );
-        KernelStack { pid: pid_handle.0 }
+        )?;
+        Some(KernelStack { pid: pid_handle.0 })
     }
     #[allow(unused)]
     ///Push a value on top of kernelstack
@@ -3,12 +3,16 @@
 use super::TaskContext;
 use super::cfg::{SignalActions, SignalFlags, SignalID, TRAP_CONTEXT};
 use super::{KernelStack, PidHandle, pid_alloc};
-use crate::fs::{Stderr, Stdin, Stdout};
-use crate::memory::{self, KERNEL_SPACE, MemorySet, PageTableDirect, PhysPageNum, VirtAddr};
+use crate::fs::{FileDescriptor, OpenFlag, Stderr, Stdin, Stdout};
+use crate::memory::{
+    self, FilePages, KERNEL_SPACE, MemorySet, PageTableDirect, PhysPageNum, VirtAddr,
+};
 use crate::sync::UpSafeCell;
 use crate::trap::{TrapContext, trap_handler};
 use alloc::sync::{Arc, Weak};
 use alloc::{vec, vec::Vec};
+use config::errno::ENOMEM;
+use config::fs::FD_LIMIT;
 use core::cell::RefMut;
 
 pub struct TaskControlBlock {
@@ -30,7 +34,7 @@ pub struct TaskControlBlockInner {
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
     pub exit_code: i32,
-    pub fd_table: Vec<Option<Arc<dyn crate::fs::File + Send + Sync>>>,
+    pub fd_table: Vec<Option<FileDescriptor>>,
     pub signals: SignalFlags,
     pub signal_mask: SignalFlags,
     // the signal which is being handling
@@ -64,12 +68,25 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = self.fd_table.iter().position(|fd| fd.is_none()) {
-            fd
-        } else {
+    pub fn alloc_fd(&mut self) -> Option<usize> {
+        self.alloc_fd_from(0)
+    }
+    /// Allocate the lowest free fd not less than `min`, `None` if all fds below
+    /// `FD_LIMIT` are taken
+    pub fn alloc_fd_from(&mut self, min: usize) -> Option<usize> {
+        if min >= FD_LIMIT {
+            return None;
+        }
+        if self.fd_table.len() < min {
+            self.fd_table.resize(min, None);
+        }
+        if let Some(fd) = self.fd_table[min..].iter().position(|fd| fd.is_none()) {
+            Some(min + fd)
+        } else if self.fd_table.len() < FD_LIMIT {
             self.fd_table.push(None);
-            self.fd_table.len() - 1
+            Some(self.fd_table.len() - 1)
+        } else {
+            None
         }
     }
 }
@@ -77,16 +94,17 @@ impl TaskControlBlock {
     pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
         self.inner.borrow_mut()
     }
-    pub fn new(elf_data: &[u8]) -> Self {
+    pub fn new(elf: &dyn FilePages) -> Self {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) =
+            MemorySet::from_elf(elf).expect("cannot load the elf");
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new(&pid_handle).expect("no frame for the kernel stack");
         let kernel_stack_top = kernel_stack.get_top();
         // push a task context which goes to trap_return to the top of kernel stack
         let task_control_block = Self {
@@ -104,11 +122,14 @@ impl TaskControlBlock {
                     exit_code: 0,
                     fd_table: vec![
                         // 0 -> stdin
-                        Some(Arc::new(Stdin)),
+                        Some(FileDescriptor::new(
+                            Arc::new(Stdin::new()),
+                            OpenFlag::empty(),
+                        )),
                         // 1 -> stdout
-                        Some(Arc::new(Stdout)),
+                        Some(FileDescriptor::new(Arc::new(Stdout), OpenFlag::empty())),
                         // 2 -> stderr
-                        Some(Arc::new(Stderr)),
+                        Some(FileDescriptor::new(Arc::new(Stderr), OpenFlag::empty())),
                     ],
                     signals: SignalFlags::empty(),
                     signal_mask: SignalFlags::empty(),
@@ -133,9 +154,11 @@ impl TaskControlBlock {
         // trap_cx.x[10] = argv_ptr;
         task_control_block
     }
-    pub fn exec(&self, elf_data: &[u8], args: Vec<Vec<u8>>) {
+    /// Run the program in `elf` with `args`, the error of [`MemorySet::from_elf`]
+    /// if it cannot be loaded
+    pub fn exec(&self, elf: &dyn FilePages, args: Vec<Vec<u8>>) -> Result<(), isize> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, mut user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, mut user_sp, entry_point) = MemorySet::from_elf(elf)?;
 
         // argv[0] : str  <-----|
         // ...     : str  <---| |
@@ -151,13 +174,13 @@ impl TaskControlBlock {
             // copying bytes from kernel space to user space
             let mut arg_slice = arg.as_slice();
 
-            memory::translate_sized(memory_set.token(), user_sp as *mut u8, arg.len())
-                .into_iter()
-                .for_each(|dst| {
-                    let (src, remain) = arg_slice.split_at(dst.len());
-                    dst.copy_from_slice(src);
-                    arg_slice = remain;
-                });
+            let buffer = memory::translate_sized(memory_set.token(), user_sp as *mut u8, arg.len())
+                .ok_or(ENOMEM)?;
+            for dst in buffer.0 {
+                let (src, remain) = arg_slice.split_at(dst.len());
+                dst.copy_from_slice(src);
+                arg_slice = remain;
+            }
         }
         let mut arg_ptr = user_sp;
 
@@ -165,10 +188,12 @@ impl TaskControlBlock {
         user_sp -= user_sp % align_of::<&str>();
         for arg in args.iter().rev() {
             user_sp -= core::mem::size_of::<usize>();
-            let len = memory::translate_ref_mut(memory_set.token(), user_sp as *mut usize);
+            let len = memory::translate_ref_mut(memory_set.token(), user_sp as *mut usize)
+                .ok_or(ENOMEM)?;
             *len = arg.len();
             user_sp -= core::mem::size_of::<&u8>();
-            let ptr = memory::translate_ref_mut(memory_set.token(), user_sp as *mut *const u8);
+            let ptr = memory::translate_ref_mut(memory_set.token(), user_sp as *mut *const u8)
+                .ok_or(ENOMEM)?;
             *ptr = arg_ptr as _;
             arg_ptr += arg.len() as usize;
         }
@@ -177,10 +202,12 @@ impl TaskControlBlock {
         // align to &[&str]
         user_sp -= user_sp % align_of::<&[&str]>();
         user_sp -= core::mem::size_of::<usize>();
-        let len = memory::translate_ref_mut(memory_set.token(), user_sp as *mut usize);
+        let len =
+            memory::translate_ref_mut(memory_set.token(), user_sp as *mut usize).ok_or(ENOMEM)?;
         *len = args.len();
         user_sp -= core::mem::size_of::<&u8>();
-        let ptr = memory::translate_ref_mut(memory_set.token(), user_sp as *mut *const u8);
+        let ptr = memory::translate_ref_mut(memory_set.token(), user_sp as *mut *const u8)
+            .ok_or(ENOMEM)?;
         *ptr = args_ptr as _;
         let argv_ptr = user_sp;
 
@@ -199,6 +226,12 @@ impl TaskControlBlock {
         inner.memory_set = memory_set;
         // update trap_cx ppn
         inner.trap_cx_ppn = trap_cx_ppn;
+        // close descriptors marked close-on-exec
+        for fd in inner.fd_table.iter_mut() {
+            if fd.as_ref().is_some_and(|fd| fd.cloexec) {
+                fd.take();
+            }
+        }
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
             entry_point,
@@ -210,25 +243,23 @@ impl TaskControlBlock {
         trap_cx.x[10] = argv_ptr;
         *inner.get_trap_cx() = trap_cx;
         // **** release current PCB
+        Ok(())
     }
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// A child running a copy of this task, `ENOMEM` if there are not enough frames
+    pub fn fork(self: &Arc<Self>) -> Result<Arc<Self>, isize> {
         // ---- access parent PCB exclusively
         let mut parent_inner = self.inner_exclusive_access();
         // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set).ok_or(ENOMEM)?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack = KernelStack::new(&pid_handle).ok_or(ENOMEM)?;
         let kernel_stack_top = kernel_stack.get_top();
-        let new_fd_table = parent_inner
-            .fd_table
-            .iter()
-            .map(|fd| fd.as_ref().map(|fd| fd.clone()))
-            .collect();
+        let new_fd_table = parent_inner.fd_table.clone();
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
             kernel_stack,
@@ -260,7 +291,7 @@ impl TaskControlBlock {
         let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
         trap_cx.kernel_sp = kernel_stack_top;
         // return
-        task_control_block
+        Ok(task_control_block)
         // ---- release parent PCB automatically
         // **** release children PCB automatically
     }
@@ -14,7 +14,7 @@
 
 mod context;
 
-use crate::task;
+use crate::{fs, memory, task};
 use crate::{syscall::syscall, timer};
 
 mod cfg {
@@ -93,6 +93,7 @@ pub(crate) fn trap_handler() -> ! {
                 Interrupt::MachineSoft => todo!(),
                 Interrupt::SupervisorTimer => {
                     timer::set_next_trigger();
+                    fs::flush_periodically(timer::get_time_ms());
                     task::suspend_current_and_run_next();
                 }
                 Interrupt::MachineTimer => todo!(),
@@ -105,6 +106,20 @@ pub(crate) fn trap_handler() -> ! {
                     task::current_add_signal(cfg::SignalID::ILL);
                 }
                 Exception::Breakpoint => todo!(),
+                // a page swapped out
+                Exception::LoadPageFault
+                | Exception::StorePageFault
+                | Exception::InstructionPageFault
+                    if memory::swapped_out(task::current_user_token(), stval.into()) =>
+                {
+                    if !memory::swap_in_at(task::current_user_token(), stval.into()) {
+                        // no frame for it: free some, then fault again
+                        if !task::oom_kill() {
+                            task::current_add_signal(cfg::SignalID::KILL);
+                        }
+                        task::suspend_current_and_run_next();
+                    }
+                }
                 exception @ (Exception::LoadFault
                 | Exception::StoreFault
                 | Exception::LoadPageFault
@@ -112,7 +127,8 @@ pub(crate) fn trap_handler() -> ! {
                 | Exception::LoadMisaligned
                 | Exception::StoreMisaligned
                 | Exception::InstructionMisaligned
-                | Exception::InstructionFault) => {
+                | Exception::InstructionFault
+                | Exception::InstructionPageFault) => {
                     log::error!(
                         "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}.",
                         exception,
@@ -130,7 +146,6 @@ pub(crate) fn trap_handler() -> ! {
                 }
                 Exception::SupervisorEnvCall => todo!(),
                 Exception::MachineEnvCall => todo!(),
-                Exception::InstructionPageFault => todo!(),
             },
         }
     } else {
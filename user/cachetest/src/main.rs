@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::errno::{EBUSY, EFAULT};
+use libr::{OpenFlag, close, exec, fork, open, read, unlink, waitpid, write};
+
+const FILE: &str = "/cachetest.txt";
+/// Over a few pages, and not a whole number of them
+const LEN: usize = 3 * 4096 + 100;
+
+fn byte(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+/// Read all of `FILE`, checking each byte against `expected`, return its length
+fn check_file(expected: impl Fn(usize) -> u8) -> usize {
+    let fd = open(FILE, OpenFlag::RDONLY);
+    assert!(fd > 0);
+    let mut len = 0;
+    // not a whole number of pages, so reads cross them
+    let mut buffer = [0u8; 1000];
+    loop {
+        let ret = read(fd as usize, &mut buffer);
+        assert!(ret >= 0);
+        if ret == 0 {
+            break;
+        }
+        for (i, &b) in buffer[..ret as usize].iter().enumerate() {
+            assert_eq!(b, expected(len + i));
+        }
+        len += ret as usize;
+    }
+    close(fd as usize);
+    len
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let fd = open(FILE, OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 1000];
+    for start in (0..LEN).step_by(buffer.len()) {
+        let chunk = &mut buffer[..(LEN - start).min(1000)];
+        chunk
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = byte(start + i));
+        assert_eq!(write(fd as usize, chunk), chunk.len() as isize);
+    }
+    close(fd as usize);
+
+    // read twice, the second time from the cache
+    for _ in 0..2 {
+        assert_eq!(check_file(byte), LEN);
+    }
+
+    // a write into a cached page is seen by the next read
+    let fd = open(FILE, OpenFlag::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, b"written"), 7);
+    close(fd as usize);
+    let written = |i: usize| b"written".get(i).copied().unwrap_or(byte(i));
+    assert_eq!(check_file(written), LEN);
+
+    // so is truncating it
+    let fd = open(FILE, OpenFlag::WRONLY | OpenFlag::TRUNC);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, b"short"), 5);
+    close(fd as usize);
+    assert_eq!(check_file(|i| b"short"[i]), 5);
+
+    // the code of programs may be shared with the page cache, so it cannot be read into
+    let fd = open(FILE, OpenFlag::RDONLY);
+    let code = main as fn() -> i32 as usize as *mut u8;
+    let code = unsafe { core::slice::from_raw_parts_mut(code, 5) };
+    assert_eq!(read(fd as usize, code), -EFAULT);
+    // its inode would be freed under the opened file
+    assert_eq!(unlink(FILE), -EBUSY);
+    close(fd as usize);
+    assert_eq!(unlink(FILE), 0);
+
+    // a program run again is loaded from the cache
+    for _ in 0..3 {
+        let pid = fork();
+        if pid == 0 {
+            exec("hello_world", &[]);
+            panic!("cannot run hello_world");
+        }
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    println!("cachetest passed!");
+    0
+}
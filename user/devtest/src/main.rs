@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::dirent::{DT_BLK, DT_CHR, Dirent};
+use libr::errno::EBUSY;
+use libr::{OpenFlag, close, getdents, mkdir, open, read, write};
+
+/// Type of the entry called `name` in /dev
+fn type_of(name: &str) -> Option<u8> {
+    let fd = open("/dev", OpenFlag::RDONLY | OpenFlag::DIRECTORY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 512];
+    let mut found = None;
+    loop {
+        let len = getdents(fd as usize, &mut buffer);
+        if len <= 0 {
+            break;
+        }
+        let mut records = &buffer[..len as usize];
+        while let Some((dirent, reclen)) = Dirent::decode(records) {
+            if dirent.name == name {
+                found = Some(dirent.r#type);
+            }
+            records = &records[reclen..];
+        }
+    }
+    close(fd as usize);
+    found
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let mut buffer = [0xffu8; 64];
+    let null = open("/dev/null", OpenFlag::RDWR);
+    assert!(null > 0);
+    assert_eq!(write(null as usize, b"gone"), 4);
+    assert_eq!(read(null as usize, &mut buffer), 0);
+    close(null as usize);
+
+    let zero = open("/dev/zero", OpenFlag::RDONLY);
+    assert_eq!(read(zero as usize, &mut buffer), 64);
+    assert!(buffer.iter().all(|&b| b == 0));
+    close(zero as usize);
+
+    let urandom = open("/dev/urandom", OpenFlag::RDWR);
+    let mut other = [0u8; 64];
+    assert_eq!(read(urandom as usize, &mut buffer), 64);
+    assert_eq!(write(urandom as usize, b"seed"), 4);
+    assert_eq!(read(urandom as usize, &mut other), 64);
+    assert!(buffer != other && buffer.iter().any(|&b| b != 0));
+    close(urandom as usize);
+
+    let console = open("/dev/console", OpenFlag::WRONLY);
+    let line = b"devtest: written to /dev/console\n";
+    assert_eq!(write(console as usize, line), line.len() as isize);
+    close(console as usize);
+
+    // the disk holds the root filesystem, its first block is the super block
+    let vda = open("/dev/vda", OpenFlag::RDONLY);
+    assert!(vda > 0);
+    let mut block = [0u8; 512];
+    assert_eq!(read(vda as usize, &mut block), 512);
+    assert!(block.iter().any(|&b| b != 0));
+    close(vda as usize);
+    // but it is mounted, so it cannot be written
+    assert_eq!(open("/dev/vda", OpenFlag::RDWR), -EBUSY);
+
+    assert_eq!(type_of("tty"), Some(DT_CHR));
+    assert_eq!(type_of("vda"), Some(DT_BLK));
+    // the swap device has no node
+    assert_eq!(type_of("vdc"), None);
+    assert!(open("/dev/vdc", OpenFlag::RDONLY) < 0);
+    // nothing can be made there
+    assert_eq!(open("/dev/new", OpenFlag::CREATE | OpenFlag::WRONLY), -1);
+    assert!(mkdir("/dev/new") < 0);
+    println!("devtest passed!");
+    0
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::errno::{EAGAIN, EBADF, EINVAL};
+use libr::fcntl::*;
+use libr::{FD_LIMIT, OpenFlag, close, dup3, fcntl, pipe, read, write};
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let mut buffer = [0u8; 8];
+    let (read_end, write_end) = pipe().unwrap();
+
+    // dup3 onto a chosen fd beyond the end of the table
+    assert_eq!(dup3(write_end, 10, OpenFlag::CLOEXEC), 10);
+    assert_eq!(dup3(write_end, write_end, OpenFlag::empty()), -EINVAL);
+    assert_eq!(dup3(write_end, 11, OpenFlag::NONBLOCK), -EINVAL);
+    assert_eq!(dup3(write_end, FD_LIMIT, OpenFlag::empty()), -EBADF);
+    assert_eq!(write(10, b"dup3"), 4);
+    assert_eq!(read(read_end, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"dup3");
+
+    // descriptor flags
+    assert_eq!(fcntl(10, F_GETFD, 0), FD_CLOEXEC as isize);
+    assert_eq!(fcntl(10, F_SETFD, 0), 0);
+    assert_eq!(fcntl(10, F_GETFD, 0), 0);
+    assert_eq!(fcntl(write_end, F_GETFD, 0), 0);
+    close(10);
+    assert_eq!(fcntl(10, F_GETFD, 0), -EBADF);
+    assert_eq!(dup3(10, 12, OpenFlag::empty()), -EBADF);
+
+    // access mode and status flags
+    assert_eq!(
+        fcntl(write_end, F_GETFL, 0),
+        OpenFlag::WRONLY.bits() as isize
+    );
+    assert_eq!(
+        fcntl(read_end, F_GETFL, 0),
+        OpenFlag::RDONLY.bits() as isize
+    );
+    assert_eq!(fcntl(read_end, F_SETFL, OpenFlag::NONBLOCK.bits()), 0);
+    assert_eq!(
+        fcntl(read_end, F_GETFL, 0),
+        OpenFlag::NONBLOCK.bits() as isize
+    );
+    assert_eq!(read(read_end, &mut buffer), -EAGAIN);
+    // stdin keeps its status flags too
+    assert_eq!(fcntl(0, F_SETFL, OpenFlag::NONBLOCK.bits()), 0);
+    assert_eq!(fcntl(0, F_GETFL, 0), OpenFlag::NONBLOCK.bits() as isize);
+    assert_eq!(fcntl(0, F_SETFL, 0), 0);
+
+    // F_DUPFD takes the lowest free fd not less than the argument
+    let fd = fcntl(read_end, F_DUPFD_CLOEXEC, 20);
+    assert_eq!(fd, 20);
+    assert_eq!(fcntl(20, F_GETFD, 0), FD_CLOEXEC as isize);
+    assert_eq!(fcntl(read_end, F_DUPFD, 20), 21);
+    // the status flags are shared by both descriptors
+    assert_eq!(read(21, &mut buffer), -EAGAIN);
+    assert_eq!(fcntl(read_end, F_DUPFD, FD_LIMIT), -EINVAL);
+    assert_eq!(fcntl(read_end, 99, 0), -EINVAL);
+
+    close(20);
+    close(21);
+    close(read_end);
+    close(write_end);
+    println!("duptest passed!");
+    0
+}
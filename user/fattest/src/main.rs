@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::errno::{EEXIST, EINVAL, ENODEV};
+use libr::{OpenFlag, close, mkdir, mount, open, read, umount, write};
+
+const DIR: &str = "/mnt";
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let ret = mkdir(DIR);
+    assert!(ret == 0 || ret == -EEXIST);
+    // the root disk holds easy-fs
+    assert_eq!(mount("vda", DIR, "vfat"), -EINVAL);
+    // the image made by scripts/build-fat is the second disk
+    match mount("vdb", DIR, "vfat") {
+        0 => {}
+        ret if ret == -ENODEV => {
+            println!("fattest skipped: no second disk");
+            return 0;
+        }
+        ret => panic!("cannot mount vdb: {ret}"),
+    }
+
+    // a long name, looked up in another case
+    let fd = open("/mnt/docs/a LONG file name.txt", OpenFlag::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 64];
+    let len = read(fd as usize, &mut buffer) as usize;
+    assert_eq!(&buffer[..len], b"hello from FAT32\n");
+    close(fd as usize);
+
+    // lines 1 to 10000, over many clusters
+    let fd = open("/mnt/numbers.txt", OpenFlag::RDWR);
+    assert!(fd > 0);
+    let mut lines = 0;
+    let mut buffer = [0u8; 700];
+    loop {
+        let len = read(fd as usize, &mut buffer);
+        if len <= 0 {
+            break;
+        }
+        lines += buffer[..len as usize]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count();
+    }
+    assert_eq!(lines, 10000);
+    // the volume is read only
+    assert_eq!(write(fd as usize, b"more"), 0);
+    close(fd as usize);
+    assert_eq!(open("/mnt/new", OpenFlag::CREATE | OpenFlag::WRONLY), -1);
+
+    assert_eq!(umount(DIR), 0);
+    println!("fattest passed!");
+    0
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+extern crate alloc;
+use alloc::format;
+use libr::errno::{EAGAIN, ENAMETOOLONG};
+use libr::{OpenFlag, close, exec, fork, open, pipe2, read, waitpid, write};
+
+/// A scratch file, in memory
+const NAME: &str = "/tmp/flagsf";
+
+fn read_all(buffer: &mut [u8]) -> &str {
+    let fd = open(NAME, OpenFlag::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, buffer) as usize;
+    close(fd as usize);
+    core::str::from_utf8(&buffer[..len]).unwrap()
+}
+
+#[unsafe(no_mangle)]
+fn main(args: &[&str]) -> i32 {
+    // re-executed by ourselves: the inherited fd must have been closed by exec
+    if let ["filetest_flags", "cloexec", fd] = args {
+        let fd = fd.parse::<usize>().unwrap();
+        return if write(fd, b"leak") == -1 { 0 } else { 1 };
+    }
+    let mut buffer = [0u8; 32];
+
+    let fd = open(NAME, OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC);
+    assert!(fd > 0);
+    write(fd as usize, b"hello");
+    close(fd as usize);
+
+    // EXCL refuses an existing file
+    assert_eq!(open(NAME, OpenFlag::CREATE | OpenFlag::EXCL), -1);
+
+    // CREATE alone keeps existing data
+    let fd = open(NAME, OpenFlag::CREATE | OpenFlag::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"J");
+    close(fd as usize);
+    assert_eq!(read_all(&mut buffer), "Jello");
+
+    // APPEND writes at the end whatever the offset is
+    let fd = open(NAME, OpenFlag::WRONLY | OpenFlag::APPEND);
+    assert!(fd > 0);
+    write(fd as usize, b" world");
+    close(fd as usize);
+    assert_eq!(read_all(&mut buffer), "Jello world");
+
+    // names are up to 255 bytes
+    let long = format!("/tmp/{}", "l".repeat(255));
+    let fd = open(&long, OpenFlag::CREATE | OpenFlag::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    let fd = open(&long, OpenFlag::RDONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    let too_long = format!("/tmp/{}", "l".repeat(256));
+    assert_eq!(open(&too_long, OpenFlag::CREATE), -ENAMETOOLONG);
+
+    // NONBLOCK pipe reports EAGAIN instead of waiting
+    let (read_end, write_end) = pipe2(OpenFlag::NONBLOCK).unwrap();
+    assert_eq!(read(read_end, &mut buffer), -EAGAIN);
+    assert_eq!(write(write_end, b"ping"), 4);
+    assert_eq!(read(read_end, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"ping");
+    close(read_end);
+    close(write_end);
+
+    // CLOEXEC descriptors do not survive exec
+    let fd = open(NAME, OpenFlag::WRONLY | OpenFlag::CLOEXEC);
+    assert!(fd > 0);
+    let pid = fork();
+    if pid == 0 {
+        let fd = format!("{}", fd);
+        exec("filetest_flags", &["filetest_flags", "cloexec", &fd]);
+        unreachable!();
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    close(fd as usize);
+
+    println!("filetest_flags passed!");
+    0
+}
@@ -3,13 +3,15 @@
 
 #[macro_use]
 extern crate libr;
-use libr::{OpenFlag, close, open, read, write};
+use libr::errno::{EINVAL, ESPIPE};
+use libr::seek::{SEEK_CUR, SEEK_END, SEEK_SET};
+use libr::{OpenFlag, close, lseek, open, pipe, read, write};
 
 #[unsafe(no_mangle)]
 fn main() -> i32 {
     let test_str = "I'm going write some 💩 in your disk🥵";
     let filea = "filea";
-    let fd = open(filea, OpenFlag::CREATE | OpenFlag::WRONLY);
+    let fd = open(filea, OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC);
     assert!(fd > 0);
     let fd = fd as usize;
     write(fd, test_str.as_bytes());
@@ -21,9 +23,19 @@ fn main() -> i32 {
     let fd = fd as usize;
     let mut buffer = [0u8; 100];
     let read_len = read(fd, &mut buffer) as usize;
-    close(fd);
 
     assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap(),);
+    // seeking back reads the same again
+    assert_eq!(lseek(fd, 0, SEEK_END), test_str.len() as isize);
+    assert_eq!(lseek(fd, -4, SEEK_CUR), test_str.len() as isize - 4);
+    assert_eq!(lseek(fd, -1, SEEK_SET), -EINVAL);
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(read(fd, &mut buffer) as usize, read_len);
+    close(fd);
+    let (read_end, write_end) = pipe().unwrap();
+    assert_eq!(lseek(read_end, 0, SEEK_SET), -ESPIPE);
+    close(read_end);
+    close(write_end);
     println!("file_test passed!");
     0
 }
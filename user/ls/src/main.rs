@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+extern crate alloc;
+use alloc::format;
+use alloc::string::ToString;
+use libr::dirent::{DT_BLK, DT_CHR, DT_DIR, Dirent};
+use libr::seek::SEEK_END;
+use libr::{OpenFlag, close, getdents, lseek, open};
+
+/// Size of the entry `name` of the directory `dir`, which a directory
+/// counts in its own unit, `None` if it cannot be opened or seeked
+fn size(dir: &str, name: &str) -> Option<usize> {
+    let path = if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    };
+    let fd = open(&path, OpenFlag::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let size = lseek(fd as usize, 0, SEEK_END);
+    close(fd as usize);
+    (size >= 0).then_some(size as usize)
+}
+
+/// List the directory `path`, return false if it cannot be opened
+fn list(path: &str, long: bool) -> bool {
+    let fd = match open(path, OpenFlag::RDONLY | OpenFlag::DIRECTORY) {
+        fd if fd >= 0 => fd as usize,
+        _ => return false,
+    };
+    // room for an entry of the longest name
+    let mut buf = [0u8; 512];
+    loop {
+        let len = getdents(fd, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        let mut records = &buf[..len as usize];
+        while let Some((dirent, reclen)) = Dirent::decode(records) {
+            if long {
+                let kind = match dirent.r#type {
+                    DT_DIR => 'd',
+                    DT_CHR => 'c',
+                    DT_BLK => 'b',
+                    _ => '-',
+                };
+                let size = size(path, dirent.name)
+                    .map_or_else(|| "?".to_string(), |size| size.to_string());
+                println!("{} {:>5} {:>8} {}", kind, dirent.ino, size, dirent.name);
+            } else {
+                println!("{}", dirent.name);
+            }
+            records = &records[reclen..];
+        }
+    }
+    close(fd);
+    true
+}
+
+#[unsafe(no_mangle)]
+fn main(args: &[&str]) -> i32 {
+    let long = args.iter().skip(1).any(|arg| *arg == "-l");
+    let paths = args.iter().skip(1).filter(|arg| !arg.starts_with('-'));
+    let mut status = 0;
+    let mut listed = false;
+    for path in paths {
+        listed = true;
+        if list(path, long) {
+            continue;
+        }
+        // not a directory, show the file itself if it exists
+        match open(path, OpenFlag::RDONLY) {
+            fd if fd >= 0 => {
+                close(fd as usize);
+                println!("{}", path);
+            }
+            _ => {
+                println!("ls: cannot access {}", path);
+                status = 1;
+            }
+        }
+    }
+    if !listed && !list(".", long) {
+        status = 1;
+    }
+    status
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::errno::{EBUSY, EEXIST, EINVAL, ENODEV, ENOTDIR};
+use libr::{OpenFlag, close, mkdir, mount, open, read, umount, write};
+
+const DIR: &str = "mountd";
+const FILE: &str = "mountd/sub/f";
+
+/// Read a whole small file, `None` if it cannot be opened
+fn read_file<'a>(path: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let fd = open(path, OpenFlag::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let len = read(fd as usize, buffer) as usize;
+    close(fd as usize);
+    Some(core::str::from_utf8(&buffer[..len]).unwrap())
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let mut buffer = [0u8; 32];
+    // the directories are left by an earlier run on the same image
+    for dir in [DIR, "mountd/sub"] {
+        let ret = mkdir(dir);
+        assert!(ret == 0 || ret == -EEXIST);
+    }
+    let fd = open(FILE, OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC);
+    assert!(fd > 0);
+    write(fd as usize, b"nested");
+    close(fd as usize);
+    assert_eq!(
+        read_file("/mountd/./sub/../sub/f", &mut buffer),
+        Some("nested")
+    );
+    assert_eq!(open("mountd/sub/f/x", OpenFlag::RDONLY), -1);
+    assert_eq!(mkdir("mountd/sub/f/x"), -ENOTDIR);
+
+    // mounting the root device again shows the same tree under the directory
+    assert_eq!(mount("vda", DIR, "easy-fs"), 0);
+    assert_eq!(read_file(FILE, &mut buffer), None);
+    assert_eq!(
+        read_file("mountd/mountd/sub/f", &mut buffer),
+        Some("nested")
+    );
+    assert_eq!(
+        read_file("mountd/../mountd/mountd/sub/f", &mut buffer),
+        Some("nested")
+    );
+
+    // a filesystem with another mounted inside it is busy
+    assert_eq!(mount("vda", "mountd/mountd", "easy-fs"), 0);
+    assert_eq!(umount(DIR), -EBUSY);
+    assert_eq!(umount("mountd/mountd"), 0);
+    assert_eq!(umount(DIR), 0);
+    assert_eq!(read_file(FILE, &mut buffer), Some("nested"));
+
+    assert_eq!(umount(DIR), -EINVAL);
+    assert_eq!(umount("/"), -EBUSY);
+    assert_eq!(mount("vda", DIR, "nofs"), -ENODEV);
+    assert_eq!(mount("vdz", DIR, "easy-fs"), -ENODEV);
+    assert_eq!(mount("vda", FILE, "easy-fs"), -ENOTDIR);
+    println!("mounttest passed!");
+    0
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::errno::ENOMEM;
+use libr::{close, exit, fork, pipe, read, waitpid};
+
+/// Memory of each task, copied by every fork
+const SIZE: usize = 32 * 1024 * 1024;
+/// More children than the memory and the swap area may hold
+const MAX_CHILDREN: usize = 64;
+
+static mut MEMORY: [u8; SIZE] = [0; SIZE];
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    // keep MEMORY in the program
+    unsafe { (&raw mut MEMORY as *mut u8).write_volatile(1) };
+    let (read_end, write_end) = pipe().unwrap();
+    let mut children = [0; MAX_CHILDREN];
+    let mut forked = 0;
+    // fork until there are not enough frames for a copy
+    let error = loop {
+        assert!(forked < MAX_CHILDREN, "fork never ran out of memory");
+        let pid = fork();
+        if pid == 0 {
+            // wait for the parent to close the pipe
+            close(write_end);
+            let mut buffer = [0u8; 1];
+            read(read_end, &mut buffer);
+            exit(0);
+        }
+        if pid < 0 {
+            break pid;
+        }
+        children[forked] = pid as usize;
+        forked += 1;
+    };
+    assert_eq!(error, -ENOMEM);
+    assert!(forked > 0);
+    println!("oomtest: fork failed after {} children", forked);
+    close(write_end);
+    for &pid in &children[..forked] {
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    }
+    // the frames of a failed fork and of the children are back
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    assert!(pid > 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("oomtest passed!");
+    0
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libr::dirent::Dirent;
+use libr::{OpenFlag, close, fork, getdents, getpid, open, pipe, read, waitpid, write};
+
+/// Read a whole file, `None` if it cannot be opened
+fn read_file(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlag::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut text = Vec::new();
+    let mut buffer = [0u8; 64];
+    loop {
+        let len = read(fd as usize, &mut buffer);
+        if len <= 0 {
+            break;
+        }
+        text.extend_from_slice(&buffer[..len as usize]);
+    }
+    close(fd as usize);
+    Some(String::from_utf8(text).unwrap())
+}
+
+/// Names in a directory
+fn list(path: &str) -> Vec<String> {
+    let fd = open(path, OpenFlag::RDONLY | OpenFlag::DIRECTORY);
+    assert!(fd > 0);
+    let mut names = Vec::new();
+    let mut buffer = vec![0u8; 512];
+    loop {
+        let len = getdents(fd as usize, &mut buffer);
+        if len <= 0 {
+            break;
+        }
+        let mut records = &buffer[..len as usize];
+        while let Some((dirent, reclen)) = Dirent::decode(records) {
+            names.push(String::from(dirent.name));
+            records = &records[reclen..];
+        }
+    }
+    close(fd as usize);
+    names
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let pid = getpid();
+    let meminfo = read_file("/proc/meminfo").unwrap();
+    assert!(meminfo.starts_with("MemTotal:") && meminfo.contains("\nMemFree:"));
+    let buddyinfo = read_file("/proc/buddyinfo").unwrap();
+    assert!(buddyinfo.starts_with("Node 0, zone") && buddyinfo.ends_with('\n'));
+    let slabinfo = read_file("/proc/slabinfo").unwrap();
+    assert!(slabinfo.starts_with("# name") && slabinfo.contains("\nsize-64 "));
+    let mounts = read_file("/proc/mounts").unwrap();
+    assert!(mounts.starts_with("vda / easy-fs\n"));
+    assert!(mounts.contains("\nproc /proc procfs\n"));
+
+    // the task itself, by its pid and as `self`
+    let status = read_file("/proc/self/status").unwrap();
+    assert!(status.starts_with(&format!("Pid:\t{}\n", pid)));
+    assert!(status.contains("\nState:\tR (running)\n"));
+    assert_eq!(read_file(&format!("/proc/{}/status", pid)), Some(status));
+    let maps = read_file("/proc/self/maps").unwrap();
+    assert!(maps.lines().any(|area| area.ends_with(" r-xu")));
+    assert!(list("/proc").contains(&format!("{}", pid)));
+
+    // opened files
+    let fd = open("/tmp/procf", OpenFlag::CREATE | OpenFlag::WRONLY);
+    assert!(fd > 0);
+    assert!(list("/proc/self/fd").contains(&format!("{}", fd)));
+    assert_eq!(
+        read_file(&format!("/proc/self/fd/{}", fd)).as_deref(),
+        Some("/tmp/procf\n")
+    );
+    assert_eq!(read_file("/proc/self/fd/0").as_deref(), Some("stdin\n"));
+    close(fd as usize);
+    assert_eq!(read_file(&format!("/proc/self/fd/{}", fd)), None);
+
+    // a child shows up until it is waited for
+    let (read_end, write_end) = pipe().unwrap();
+    let child = fork();
+    if child == 0 {
+        let mut buffer = [0u8; 1];
+        read(read_end, &mut buffer);
+        return 0;
+    }
+    let status = read_file(&format!("/proc/{}/status", child)).unwrap();
+    assert!(status.contains(&format!("\nPPid:\t{}\n", pid)));
+    write(write_end, b"x");
+    let mut exit_code = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(read_file(&format!("/proc/{}/status", child)), None);
+    assert!(!list("/proc").contains(&format!("{}", child)));
+    println!("proctest passed!");
+    0
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+extern crate alloc;
+use alloc::format;
+use libr::dirent::{DT_DIR, Dirent};
+use libr::{OpenFlag, close, getdents, open, read};
+
+/// Print the pid, parent and state of a task from its status file
+fn show(pid: &str) {
+    let fd = open(&format!("/proc/{}/status", pid), OpenFlag::RDONLY);
+    if fd < 0 {
+        // it has exited since
+        return;
+    }
+    let mut buf = [0u8; 256];
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    let status = core::str::from_utf8(&buf[..len.max(0) as usize]).unwrap_or("");
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(":\t"))
+            .unwrap_or("?")
+    };
+    println!("{:>5} {:>5} {}", pid, field("PPid"), field("State"));
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let fd = match open("/proc", OpenFlag::RDONLY | OpenFlag::DIRECTORY) {
+        fd if fd >= 0 => fd as usize,
+        _ => {
+            println!("ps: /proc is not mounted");
+            return 1;
+        }
+    };
+    println!("{:>5} {:>5} STATE", "PID", "PPID");
+    let mut buf = [0u8; 512];
+    loop {
+        let len = getdents(fd, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        let mut records = &buf[..len as usize];
+        while let Some((dirent, reclen)) = Dirent::decode(records) {
+            // the directories of tasks are named by their pid
+            if dirent.r#type == DT_DIR && dirent.name.parse::<usize>().is_ok() {
+                show(dirent.name);
+            }
+            records = &records[reclen..];
+        }
+    }
+    close(fd);
+    0
+}
@@ -16,7 +16,7 @@ const LINE_START: &str = "bish > ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use libr::console::getchar;
-use libr::{OpenFlag, close, dup, exec, fork, open, pipe, waitpid};
+use libr::{OpenFlag, close, dup3, exec, fork, open, pipe, waitpid};
 
 #[derive(Debug)]
 struct ProcessArguments<'a> {
@@ -116,34 +116,32 @@ pub fn main() -> i32 {
                                         return -4;
                                     }
                                     let input_fd = input_fd as usize;
-                                    close(0);
-                                    assert_eq!(dup(input_fd), 0);
+                                    assert_eq!(dup3(input_fd, 0, OpenFlag::empty()), 0);
                                     close(input_fd);
                                 }
                                 // redirect output
                                 if let Some(output) = output {
-                                    let output_fd =
-                                        open(output, OpenFlag::CREATE | OpenFlag::WRONLY);
+                                    let output_fd = open(
+                                        output,
+                                        OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC,
+                                    );
                                     if output_fd == -1 {
                                         println!("Error when opening file {}", output);
                                         return -4;
                                     }
                                     let output_fd = output_fd as usize;
-                                    close(1);
-                                    assert_eq!(dup(output_fd), 1);
+                                    assert_eq!(dup3(output_fd, 1, OpenFlag::empty()), 1);
                                     close(output_fd);
                                 }
                                 // receive input from the previous process
                                 if i > 0 {
-                                    close(0);
                                     let read_end = pipes_fd.get(i - 1).unwrap().0;
-                                    assert_eq!(dup(read_end), 0);
+                                    assert_eq!(dup3(read_end, 0, OpenFlag::empty()), 0);
                                 }
                                 // send output to the next process
                                 if i < process_arguments_list.len() - 1 {
-                                    close(1);
                                     let write_end = pipes_fd.get(i).unwrap().1;
-                                    assert_eq!(dup(write_end), 1);
+                                    assert_eq!(dup3(write_end, 1, OpenFlag::empty()), 1);
                                 }
                                 // close all pipe ends inherited from the parent process
                                 for pipe_fd in pipes_fd.iter() {
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libr::errno::{EEXIST, EINVAL, ENOENT};
+use libr::{IPC_PRIVATE, IPC_RMID, ShmFlag};
+use libr::{exit, fork, shmat, shmctl, shmdt, shmget, waitpid, r#yield};
+
+/// Bytes going from the producer to the consumer, far more than the ring holds
+const TOTAL: usize = 100_000;
+const RING_SIZE: usize = 4000;
+const KEY: usize = 0x5348;
+/// An address chosen by the program
+const ADDR: usize = 0x30_0000_0000;
+
+/// A ring buffer in shared memory, written by one task and read by another
+#[repr(C)]
+struct Ring {
+    /// Bytes written in all
+    head: AtomicUsize,
+    /// Bytes read in all
+    tail: AtomicUsize,
+    data: [u8; RING_SIZE],
+}
+
+fn byte(i: usize) -> u8 {
+    (i % 253) as u8
+}
+
+fn produce(ring: &mut Ring) {
+    for i in 0..TOTAL {
+        while ring.head.load(Ordering::Acquire) - ring.tail.load(Ordering::Acquire) == RING_SIZE {
+            r#yield();
+        }
+        ring.data[i % RING_SIZE] = byte(i);
+        ring.head.store(i + 1, Ordering::Release);
+    }
+}
+
+fn consume(ring: &mut Ring) {
+    for i in 0..TOTAL {
+        while ring.head.load(Ordering::Acquire) == i {
+            r#yield();
+        }
+        assert_eq!(ring.data[i % RING_SIZE], byte(i));
+        ring.tail.store(i + 1, Ordering::Release);
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    // a private segment, attached where the kernel chooses, shared with a child by fork
+    let id = shmget(IPC_PRIVATE, size_of::<Ring>(), ShmFlag::CREAT);
+    assert!(id >= 0);
+    let addr = shmat(id as usize, 0, ShmFlag::empty());
+    assert!(addr > 0);
+    let ring = unsafe { &mut *(addr as *mut Ring) };
+    assert_eq!(ring.head.load(Ordering::Relaxed), 0);
+    let pid = fork();
+    if pid == 0 {
+        consume(ring);
+        exit(0);
+    }
+    produce(ring);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(ring.tail.load(Ordering::Relaxed), TOTAL);
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    // still attached after it is removed
+    assert_eq!(ring.head.load(Ordering::Relaxed), TOTAL);
+    assert_eq!(shmdt(addr as usize), 0);
+    assert_eq!(shmdt(addr as usize), -EINVAL);
+
+    // a segment of a key, attached at a chosen address by two tasks in turn
+    let id = shmget(KEY, 8192, ShmFlag::CREAT | ShmFlag::EXCL);
+    assert!(id >= 0);
+    assert_eq!(shmget(KEY, 8192, ShmFlag::CREAT | ShmFlag::EXCL), -EEXIST);
+    assert_eq!(shmget(KEY, 8193, ShmFlag::empty()), -EINVAL);
+    assert_eq!(shmat(id as usize, ADDR + 1, ShmFlag::empty()), -EINVAL);
+    let pid = fork();
+    if pid == 0 {
+        let id = shmget(KEY, 0, ShmFlag::empty());
+        assert!(id >= 0);
+        assert_eq!(shmat(id as usize, ADDR, ShmFlag::empty()), ADDR as isize);
+        let words = unsafe { core::slice::from_raw_parts_mut(ADDR as *mut usize, 1024) };
+        words.iter_mut().enumerate().for_each(|(i, word)| *word = i);
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(shmat(id as usize, ADDR, ShmFlag::RDONLY), ADDR as isize);
+    // overlapping an attached segment
+    assert_eq!(shmat(id as usize, ADDR + 4096, ShmFlag::empty()), -EINVAL);
+    let words = unsafe { core::slice::from_raw_parts(ADDR as *const usize, 1024) };
+    assert!(words.iter().enumerate().all(|(i, &word)| word == i));
+    assert_eq!(shmdt(ADDR), 0);
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    assert_eq!(shmget(KEY, 8192, ShmFlag::empty()), -ENOENT);
+    println!("shmtest passed!");
+    0
+}
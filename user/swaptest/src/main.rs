@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::{OpenFlag, close, exit, fork, getpid, open, read, waitpid, r#yield};
+
+const PAGE_SIZE: usize = 4096;
+/// Memory of each task, all of them together take more than the 128 MiB of the machine
+const PAGES: usize = 40 * 1024 * 1024 / PAGE_SIZE;
+const TASKS: usize = 4;
+
+static mut MEMORY: [u8; PAGES * PAGE_SIZE] = [0; PAGES * PAGE_SIZE];
+
+/// Size of the swap area in kB, from `/proc/meminfo`
+fn swap_total() -> usize {
+    let fd = open("/proc/meminfo", OpenFlag::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 512];
+    let len = read(fd as usize, &mut buffer);
+    close(fd as usize);
+    let text = core::str::from_utf8(&buffer[..len as usize]).unwrap();
+    text.lines()
+        .find_map(|line| line.strip_prefix("SwapTotal:"))
+        .and_then(|rest| rest.trim().trim_end_matches(" kB").parse().ok())
+        .unwrap_or(0)
+}
+
+/// What the task `pid` writes at both ends of page `i`
+fn mark(pid: usize, i: usize) -> [u8; 8] {
+    ((pid << 32 | i) as u64).to_le_bytes()
+}
+
+/// Write a mark on every page, yield so others run, then check them all
+fn run(pid: usize) {
+    let memory =
+        unsafe { core::slice::from_raw_parts_mut(&raw mut MEMORY as *mut u8, PAGES * PAGE_SIZE) };
+    for (i, page) in memory.chunks_mut(PAGE_SIZE).enumerate() {
+        page[..8].copy_from_slice(&mark(pid, i));
+        page[PAGE_SIZE - 8..].copy_from_slice(&mark(pid, i));
+    }
+    r#yield();
+    for (i, page) in memory.chunks(PAGE_SIZE).enumerate() {
+        assert_eq!(page[..8], mark(pid, i));
+        assert_eq!(page[PAGE_SIZE - 8..], mark(pid, i));
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    if swap_total() == 0 {
+        println!("swaptest skipped: no swap disk");
+        return 0;
+    }
+    let mut children = [0; TASKS - 1];
+    for child in children.iter_mut() {
+        let pid = fork();
+        if pid == 0 {
+            run(getpid());
+            exit(0);
+        }
+        *child = pid as usize;
+    }
+    run(getpid());
+    for pid in children {
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+        assert_eq!(exit_code, 0);
+    }
+    println!("swaptest passed!");
+    0
+}
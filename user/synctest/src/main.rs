@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::errno::EINVAL;
+use libr::{OpenFlag, close, fsync, open, pipe, read, sync, syncfs, write};
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let data = [0x5au8; 2048];
+    let fd = open(
+        "syncf",
+        OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    for _ in 0..8 {
+        assert_eq!(write(fd, &data), data.len() as isize);
+    }
+    assert_eq!(fsync(fd), 0);
+    assert_eq!(syncfs(fd), 0);
+    close(fd);
+    assert_eq!(sync(), 0);
+
+    let fd = open("syncf", OpenFlag::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 2048];
+    let mut total = 0;
+    loop {
+        let len = read(fd as usize, &mut buffer);
+        if len == 0 {
+            break;
+        }
+        assert!(buffer[..len as usize].iter().all(|&b| b == 0x5a));
+        total += len;
+    }
+    assert_eq!(total, 8 * 2048);
+    close(fd as usize);
+
+    // a pipe has nothing to sync
+    let (read_end, write_end) = pipe().unwrap();
+    assert_eq!(fsync(read_end), -EINVAL);
+    close(read_end);
+    close(write_end);
+    // closed descriptors are rejected
+    assert_eq!(fsync(read_end), -1);
+    assert_eq!(syncfs(read_end), -1);
+    println!("synctest passed!");
+    0
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate libr;
+use libr::dirent::Dirent;
+use libr::errno::{EEXIST, EINVAL, EISDIR, ENOENT, ENOSPC};
+use libr::{OpenFlag, close, getdents, mkdir, mount, open, read, umount, unlink, write};
+
+/// Read a whole small file, `None` if it cannot be opened
+fn read_file<'a>(path: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let fd = open(path, OpenFlag::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let len = read(fd as usize, buffer) as usize;
+    close(fd as usize);
+    Some(core::str::from_utf8(&buffer[..len]).unwrap())
+}
+
+/// Create or truncate a file and write `data` to it
+fn write_file(path: &str, data: &[u8]) {
+    let fd = open(path, OpenFlag::CREATE | OpenFlag::WRONLY | OpenFlag::TRUNC);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, data), data.len() as isize);
+    close(fd as usize);
+}
+
+/// Write `data` to `fd` over and over until it fails for want of space,
+/// return how many bytes were written
+fn fill(fd: usize, data: &[u8]) -> usize {
+    let mut total = 0;
+    loop {
+        let len = write(fd, data);
+        if len < 0 {
+            assert_eq!(len, -ENOSPC);
+            return total;
+        }
+        total += len as usize;
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let mut buffer = [0u8; 512];
+    write_file("/tmp/a", b"hello tmpfs");
+    assert_eq!(read_file("/tmp/a", &mut buffer), Some("hello tmpfs"));
+    // truncated on open
+    let fd = open("/tmp/a", OpenFlag::WRONLY | OpenFlag::TRUNC);
+    close(fd as usize);
+    assert_eq!(read_file("/tmp/a", &mut buffer), Some(""));
+
+    // directories
+    assert_eq!(mkdir("/tmp/d"), 0);
+    assert_eq!(mkdir("/tmp/d"), -EEXIST);
+    write_file("/tmp/d/x", b"x");
+    let fd = open("/tmp/d", OpenFlag::RDONLY | OpenFlag::DIRECTORY);
+    assert!(fd > 0);
+    let len = getdents(fd as usize, &mut buffer);
+    close(fd as usize);
+    let (dirent, reclen) = Dirent::decode(&buffer[..len as usize]).unwrap();
+    assert_eq!((dirent.name, reclen), ("x", len as usize));
+
+    // unlink
+    assert_eq!(unlink("/tmp/d/x"), 0);
+    assert_eq!(read_file("/tmp/d/x", &mut buffer), None);
+    assert_eq!(unlink("/tmp/d/x"), -ENOENT);
+    assert_eq!(unlink("/tmp/d"), -EISDIR);
+    // the data of an opened file outlives its name
+    let writer = open("/tmp/b", OpenFlag::CREATE | OpenFlag::WRONLY);
+    let reader = open("/tmp/b", OpenFlag::RDONLY);
+    assert!(writer > 0 && reader > 0);
+    assert_eq!(unlink("/tmp/b"), 0);
+    assert_eq!(write(writer as usize, b"kept"), 4);
+    assert_eq!(read(reader as usize, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"kept");
+    close(writer as usize);
+    close(reader as usize);
+
+    // writes stop at the size limit, and unlinking gives the room back
+    let fd = open("/tmp/big", OpenFlag::CREATE | OpenFlag::WRONLY);
+    assert!(fd > 0);
+    assert!(fill(fd as usize, &buffer) > 0);
+    assert_eq!(write(fd as usize, &buffer), -ENOSPC);
+    close(fd as usize);
+    assert_eq!(unlink("/tmp/big"), 0);
+    write_file("/tmp/a", &buffer);
+    assert_eq!(unlink("/tmp/a"), 0);
+
+    // the limit can be set when mounting, in whole pages
+    assert_eq!(mkdir("/tmp/small"), 0);
+    assert_eq!(mount("size=lots", "/tmp/small", "tmpfs"), -EINVAL);
+    assert_eq!(mount("size=5000", "/tmp/small", "tmpfs"), 0);
+    let fd = open("/tmp/small/f", OpenFlag::CREATE | OpenFlag::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(fill(fd as usize, &buffer), 2 * 4096);
+    close(fd as usize);
+    assert_eq!(umount("/tmp/small"), 0);
+    println!("tmpfstest passed!");
+    0
+}
@@ -10,7 +10,20 @@ extern crate libr;
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&[&str], i32)] = &[
     (&["filetest_simple"], 0),
+    (&["filetest_flags"], 0),
+    (&["duptest"], 0),
+    (&["synctest"], 0),
+    (&["mounttest"], 0),
+    (&["tmpfstest"], 0),
+    (&["proctest"], 0),
+    (&["devtest"], 0),
+    (&["fattest"], 0),
+    (&["cachetest"], 0),
+    (&["swaptest"], 0),
+    (&["oomtest"], 0),
+    (&["shmtest"], 0),
     (&["cat_filea"], 0),
+    (&["ls", "-l"], 0),
     (&["exit"], 0),
     (&["fantastic_text", "0"], 0),
     (&["forktest"], 0),